@@ -0,0 +1,354 @@
+use crate::format_timestamp;
+use crate::print_structs::{Length, Weight};
+use crate::units::Units;
+use crate::watch_bambu;
+use clap::ValueEnum;
+use rusqlite::{Connection, Result};
+use serde_json::{Map, Value};
+use std::env;
+use uuid::Uuid;
+
+const DAY: i64 = 24 * 60 * 60;
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum AuditKind {
+    Spool,
+    Print,
+    /// The spool loaded in a printer
+    Printer,
+    /// The spool loaded in an AMS tray
+    Tray,
+    /// The whole database, when it is restored from a backup
+    Database,
+}
+
+impl AuditKind {
+    //Every table whose changes are logged
+    const AUDITED: [AuditKind; 4] = [
+        AuditKind::Spool,
+        AuditKind::Print,
+        AuditKind::Printer,
+        AuditKind::Tray,
+    ];
+
+    fn table(self) -> &'static str {
+        match self {
+            AuditKind::Spool => "spool",
+            AuditKind::Print => "filament",
+            AuditKind::Printer => "printer",
+            AuditKind::Tray => "ams_tray",
+            AuditKind::Database => "database",
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AuditKind::Spool => "spool",
+            AuditKind::Print => "print",
+            AuditKind::Printer => "printer",
+            AuditKind::Tray => "tray",
+            AuditKind::Database => "database",
+        }
+    }
+
+    fn from_table(table: &str) -> AuditKind {
+        AuditKind::AUDITED
+            .into_iter()
+            .find(|kind| kind.table() == table)
+            .unwrap_or(AuditKind::Database)
+    }
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum AuditAction {
+    Create,
+    Update,
+    Delete,
+    Restore,
+}
+
+impl AuditAction {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AuditAction::Create => "create",
+            AuditAction::Update => "update",
+            AuditAction::Delete => "delete",
+            AuditAction::Restore => "restore",
+        }
+    }
+
+    fn parse(action: &str) -> AuditAction {
+        match action {
+            "create" => AuditAction::Create,
+            "delete" => AuditAction::Delete,
+            "restore" => AuditAction::Restore,
+            _ => AuditAction::Update,
+        }
+    }
+}
+
+//One change, with the whole row before and after it as JSON
+#[derive(Debug, PartialEq)]
+pub struct AuditEntry {
+    pub timestamp: i64,
+    pub user: String,
+    pub kind: AuditKind,
+    pub action: AuditAction,
+    pub roll_id: Option<Uuid>,
+    pub spool_name: Option<String>,
+    pub before: Option<Map<String, Value>>,
+    pub after: Option<Map<String, Value>>,
+}
+
+#[derive(Debug, Default)]
+pub struct HistoryFilter {
+    pub roll_id: Option<Uuid>,
+    pub user: Option<String>,
+    pub kind: Option<AuditKind>,
+    pub action: Option<AuditAction>,
+    //First and last day to include, as the start of each day
+    pub from: Option<i64>,
+    pub to: Option<i64>,
+    pub limit: u32,
+}
+
+//The login name, for when no user is configured
+pub fn os_user() -> Option<String> {
+    env::var("USER")
+        .or_else(|_| env::var("USERNAME"))
+        .ok()
+        .filter(|user| !user.trim().is_empty())
+}
+
+//Logs every change made through this connection as the user. The triggers are
+//temporary, so they only exist while this connection is open
+pub fn start(conn: &Connection, user: &str) -> Result<()> {
+    conn.execute_batch(
+        "CREATE TEMP TABLE IF NOT EXISTS audit_user(user_name TEXT NOT NULL);
+        DELETE FROM temp.audit_user;",
+    )?;
+    conn.execute(
+        "INSERT INTO temp.audit_user (user_name) VALUES (?1)",
+        [user.trim()],
+    )?;
+    for kind in AuditKind::AUDITED {
+        conn.execute_batch(&audit_triggers(conn, kind.table())?)?;
+    }
+    Ok(())
+}
+
+fn audit_triggers(conn: &Connection, table: &str) -> Result<String> {
+    let columns_query = format!("SELECT name, type FROM pragma_table_info('{}')", table);
+    let mut stmt = conn.prepare(&columns_query)?;
+    let columns: Vec<(String, String)> = stmt
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<Result<_>>()?;
+    let old = row_json("OLD", &columns);
+    let new = row_json("NEW", &columns);
+    let insert = format!(
+        "INSERT INTO audit_log (audit_timestamp, audit_user, audit_table, audit_action,
+            roll_id, audit_before, audit_after)
+        VALUES (CAST(strftime('%s', 'now') AS INTEGER),
+            (SELECT user_name FROM audit_user), '{}'",
+        table
+    );
+    Ok(format!(
+        "CREATE TEMP TRIGGER IF NOT EXISTS audit_{table}_insert AFTER INSERT ON {table}
+        BEGIN {insert}, 'create', NEW.roll_id, NULL, {new}); END;
+        CREATE TEMP TRIGGER IF NOT EXISTS audit_{table}_update AFTER UPDATE ON {table}
+            WHEN {old} IS NOT {new}
+        BEGIN {insert}, 'update', COALESCE(NEW.roll_id, OLD.roll_id), {old}, {new}); END;
+        CREATE TEMP TRIGGER IF NOT EXISTS audit_{table}_delete AFTER DELETE ON {table}
+        BEGIN {insert}, 'delete', OLD.roll_id, {old}, NULL); END;"
+    ))
+}
+
+//The row as a JSON object, ids are BLOBs which JSON cannot hold so they are
+//written out the way Uuid shows them
+fn row_json(row: &str, columns: &[(String, String)]) -> String {
+    let fields: Vec<String> = columns
+        .iter()
+        .map(|(name, kind)| {
+            let value = format!("{}.{}", row, name);
+            if kind.eq_ignore_ascii_case("BLOB") {
+                format!(
+                    "'{name}', CASE WHEN typeof({value}) = 'blob' THEN lower(substr(hex({value}), 1, 8)
+                        || '-' || substr(hex({value}), 9, 4) || '-' || substr(hex({value}), 13, 4)
+                        || '-' || substr(hex({value}), 17, 4) || '-' || substr(hex({value}), 21))
+                        ELSE {value} END"
+                )
+            } else {
+                format!("'{}', {}", name, value)
+            }
+        })
+        .collect();
+    format!("json_object({})", fields.join(", "))
+}
+
+//Keeps a copy of the log outside the main database, which a restore replaces
+pub fn save_log(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "DROP TABLE IF EXISTS temp.audit_saved;
+        CREATE TEMP TABLE audit_saved AS SELECT * FROM main.audit_log;",
+    )
+}
+
+//Adds the saved entries the restored log does not have, so changes made since the
+//backup stay in the history, and records the restore itself
+pub fn carry_over_log(conn: &Connection, user: &str, backup: &str, version: i32) -> Result<()> {
+    conn.execute_batch(
+        "INSERT INTO main.audit_log (audit_timestamp, audit_user, audit_table, audit_action,
+                roll_id, audit_before, audit_after)
+            SELECT s.audit_timestamp, s.audit_user, s.audit_table, s.audit_action,
+                s.roll_id, s.audit_before, s.audit_after
+            FROM temp.audit_saved s
+            WHERE NOT EXISTS (SELECT 1 FROM main.audit_log a
+                WHERE a.audit_id = s.audit_id AND a.audit_timestamp = s.audit_timestamp
+                    AND a.audit_user = s.audit_user AND a.audit_table = s.audit_table
+                    AND a.audit_action = s.audit_action AND a.roll_id IS s.roll_id
+                    AND a.audit_before IS s.audit_before AND a.audit_after IS s.audit_after)
+            ORDER BY s.audit_id;
+        DROP TABLE temp.audit_saved;",
+    )?;
+    let after = serde_json::json!({"backup": backup, "schema_version": version});
+    conn.execute(
+        "INSERT INTO audit_log (audit_timestamp, audit_user, audit_table, audit_action,
+                audit_after)
+            VALUES (?1, ?2, 'database', 'restore', ?3)",
+        (crate::get_timestamp(), user.trim(), after.to_string()),
+    )?;
+    Ok(())
+}
+
+//The newest changes first
+pub fn history(conn: &Connection, filter: &HistoryFilter) -> Result<Vec<AuditEntry>> {
+    let history_query = "SELECT a.audit_timestamp, a.audit_user, a.audit_table, a.audit_action,
+                a.roll_id, (SELECT s.roll_name FROM spool s WHERE s.roll_id = a.roll_id),
+                a.audit_before, a.audit_after
+            FROM audit_log a
+            WHERE (?1 IS NULL OR a.roll_id = ?1)
+                AND (?2 IS NULL OR a.audit_user = ?2 COLLATE NOCASE)
+                AND (?3 IS NULL OR a.audit_table = ?3)
+                AND (?4 IS NULL OR a.audit_action = ?4)
+                AND (?5 IS NULL OR a.audit_timestamp >= ?5)
+                AND (?6 IS NULL OR a.audit_timestamp < ?6)
+            ORDER BY a.audit_id DESC LIMIT ?7";
+    let mut stmt = conn.prepare(history_query)?;
+    let rows = stmt.query_map(
+        (
+            filter.roll_id,
+            filter.user.as_deref().map(str::trim),
+            filter.kind.map(AuditKind::table),
+            filter.action.map(AuditAction::as_str),
+            filter.from,
+            filter.to.map(|to| to + DAY),
+            filter.limit,
+        ),
+        |row| {
+            Ok(AuditEntry {
+                timestamp: row.get(0)?,
+                user: row.get(1)?,
+                kind: AuditKind::from_table(&row.get::<_, String>(2)?),
+                action: AuditAction::parse(&row.get::<_, String>(3)?),
+                roll_id: row.get(4)?,
+                spool_name: row.get(5)?,
+                before: parse_row(row.get(6)?),
+                after: parse_row(row.get(7)?),
+            })
+        },
+    )?;
+    rows.collect()
+}
+
+fn parse_row(json: Option<String>) -> Option<Map<String, Value>> {
+    serde_json::from_str(&json?).ok()
+}
+
+impl AuditEntry {
+    //What was changed, e.g. the print's name or the printer's
+    pub fn subject(&self) -> String {
+        let row = self.after.as_ref().or(self.before.as_ref());
+        let field = |name: &str| {
+            row.and_then(|row| row.get(name))
+                .and_then(Value::as_str)
+                .map(str::to_string)
+        };
+        let subject = match self.kind {
+            AuditKind::Spool => field("roll_name").or_else(|| self.spool_name.clone()),
+            AuditKind::Print => field("print_name"),
+            AuditKind::Printer => field("printer_name"),
+            AuditKind::Database => field("backup"),
+            AuditKind::Tray => field("printer_name").map(|printer| {
+                let tray = row
+                    .and_then(|row| row.get("tray_id"))
+                    .and_then(Value::as_u64);
+                match tray {
+                    Some(tray) => format!("{} {}", printer, watch_bambu::tray_name(tray as u32)),
+                    None => printer,
+                }
+            }),
+        };
+        subject.unwrap_or_default()
+    }
+
+    //The fields that changed as before -> after, or every field that is set when the
+    //row was created or deleted
+    pub fn changes(&self, units: &Units) -> Vec<String> {
+        let empty = Map::new();
+        let before = self.before.as_ref().unwrap_or(&empty);
+        let after = self.after.as_ref().unwrap_or(&empty);
+        let mut changes = Vec::new();
+        for name in before
+            .keys()
+            .chain(after.keys().filter(|name| !before.contains_key(*name)))
+        {
+            //The spool is shown with every entry, so only loads list it as a change
+            let loaded = matches!(self.kind, AuditKind::Printer | AuditKind::Tray);
+            if name == "print_id" || (name == "roll_id" && !loaded) {
+                continue;
+            }
+            let (old, new) = (before.get(name), after.get(name));
+            match self.action {
+                AuditAction::Update if old != new => changes.push(format!(
+                    "{}: {} -> {}",
+                    name,
+                    format_value(name, old, units),
+                    format_value(name, new, units)
+                )),
+                AuditAction::Create | AuditAction::Delete | AuditAction::Restore => {
+                    let value = new.or(old).filter(|value| !value.is_null());
+                    if value.is_some() {
+                        changes.push(format!("{}: {}", name, format_value(name, value, units)));
+                    }
+                }
+                AuditAction::Update => {}
+            }
+        }
+        changes
+    }
+}
+
+//Weights are milligrams and lengths millimetres in the table, see print_structs
+fn format_value(name: &str, value: Option<&Value>, units: &Units) -> String {
+    match value {
+        None | Some(Value::Null) => String::from("none"),
+        //Ids are shortened the way list-prints shows them, they still find the spool
+        Some(Value::String(value)) if name.ends_with("_id") && Uuid::parse_str(value).is_ok() => {
+            value[..8].to_string()
+        }
+        Some(Value::String(value)) => value.clone(),
+        Some(Value::Number(number)) => match number.as_i64() {
+            Some(value) if name.ends_with("_weight") || name.ends_with("_tare") => {
+                units.weight(Weight::from_milligrams(value))
+            }
+            Some(value) if name.ends_with("_length") => {
+                units.length(Length::from_millimetres(value))
+            }
+            Some(value) if name.ends_with("_timestamp") || name == "roll_opened" => {
+                format_timestamp(value)
+            }
+            _ => number.to_string(),
+        },
+        Some(value) => value.to_string(),
+    }
+}
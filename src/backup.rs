@@ -0,0 +1,180 @@
+use crate::audit;
+use crate::tbl_creation;
+use chrono::DateTime;
+use rusqlite::{Connection, DatabaseName, OpenFlags};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+//How many automatic backups are kept before the oldest are removed
+pub const MAX_AUTO_BACKUPS: usize = 10;
+const AUTO_PREFIX: &str = "auto-";
+
+//Copies the database using SQLite's online backup, so it is consistent even while in use
+pub fn backup_to(conn: &Connection, path: &Path) -> Result<(), String> {
+    if path.exists() {
+        return Err(format!("{} already exists", path.display()));
+    }
+    conn.backup(DatabaseName::Main, path, None)
+        .map_err(|e| e.to_string())
+}
+
+//Backs up into the backup directory before something which changes a lot of data,
+//only the newest MAX_AUTO_BACKUPS automatic backups are kept
+pub fn auto_backup(conn: &Connection, backup_dir: &Path, reason: &str) -> Result<PathBuf, String> {
+    fs::create_dir_all(backup_dir).map_err(|e| e.to_string())?;
+    let stamp = DateTime::from_timestamp(crate::get_timestamp(), 0)
+        .unwrap()
+        .format("%Y%m%d-%H%M%S");
+    //Numbered within the second so the names still sort oldest first
+    let second = format!("{}{}-", AUTO_PREFIX, stamp);
+    let path = backup_dir.join(format!(
+        "{}{:02}-{}.db",
+        second,
+        last_number(backup_dir, &second)? + 1,
+        reason
+    ));
+    backup_to(conn, &path)?;
+    rotate(backup_dir, MAX_AUTO_BACKUPS)?;
+    Ok(path)
+}
+
+fn last_number(backup_dir: &Path, second: &str) -> Result<u32, String> {
+    let last = fs::read_dir(backup_dir)
+        .map_err(|e| e.to_string())?
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            name.strip_prefix(second)?.get(..2)?.parse::<u32>().ok()
+        })
+        .max();
+    Ok(last.unwrap_or(0))
+}
+
+//Removes the oldest automatic backups, backups made by hand are left alone
+pub fn rotate(backup_dir: &Path, keep: usize) -> Result<Vec<PathBuf>, String> {
+    let mut backups: Vec<PathBuf> = fs::read_dir(backup_dir)
+        .map_err(|e| e.to_string())?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with(AUTO_PREFIX) && name.ends_with(".db"))
+        })
+        .collect();
+    //The timestamp in the name sorts them oldest first
+    backups.sort();
+    let excess = backups.len().saturating_sub(keep);
+    let removed: Vec<PathBuf> = backups.into_iter().take(excess).collect();
+    for path in &removed {
+        fs::remove_file(path).map_err(|e| e.to_string())?;
+    }
+    Ok(removed)
+}
+
+//Makes sure a file is a healthy database of ours that this version can read,
+//giving back its schema version
+pub fn check_backup(path: &Path) -> Result<i32, String> {
+    if !path.is_file() {
+        return Err(format!("{} does not exist", path.display()));
+    }
+    let backup = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .map_err(|e| e.to_string())?;
+    let problems = integrity_check(&backup)
+        .map_err(|e| format!("{} is not a database: {}", path.display(), e))?;
+    if !problems.is_empty() {
+        return Err(format!(
+            "{} is damaged: {}",
+            path.display(),
+            problems.join(", ")
+        ));
+    }
+    let tables: i32 = backup
+        .query_row(
+            "SELECT count(name) FROM sqlite_master WHERE type='table' AND name IN ('spool', 'filament')",
+            [],
+            |row| row.get(0),
+        )
+        .map_err(|e| e.to_string())?;
+    if tables != 2 {
+        return Err(format!("{} is not a remainder database", path.display()));
+    }
+    let version = tbl_creation::schema_version(&backup)?;
+    if version > tbl_creation::SCHEMA_VERSION {
+        return Err(format!(
+            "{} has schema version {} but this version of remainder only knows up to {}",
+            path.display(),
+            version,
+            tbl_creation::SCHEMA_VERSION
+        ));
+    }
+    Ok(version)
+}
+
+//Replaces the database with the backup and upgrades it to the current schema
+//The audit log is not rolled back with the rest, it keeps everything since the backup
+//and gains an entry for the restore made by the user
+pub fn restore_from(conn: &mut Connection, path: &Path, user: &str) -> Result<i32, String> {
+    let version = check_backup(path)?;
+    audit::save_log(conn).map_err(|e| e.to_string())?;
+    conn.restore(
+        DatabaseName::Main,
+        path,
+        None::<fn(rusqlite::backup::Progress)>,
+    )
+    .map_err(|e| e.to_string())?;
+    tbl_creation::setup_database(conn)?;
+    audit::carry_over_log(conn, user, &path.display().to_string(), version)
+        .map_err(|e| e.to_string())?;
+    Ok(version)
+}
+
+//Problems SQLite finds in the file plus prints left without a spool, empty when healthy
+pub fn integrity_check(conn: &Connection) -> Result<Vec<String>, String> {
+    let mut stmt = conn
+        .prepare("PRAGMA integrity_check")
+        .map_err(|e| e.to_string())?;
+    let mut problems = stmt
+        .query_map([], |row| row.get::<_, String>(0))
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<String>, _>>()
+        .map_err(|e| e.to_string())?;
+    if problems == ["ok"] {
+        problems.clear();
+    }
+
+    let has_tables: i32 = conn
+        .query_row(
+            "SELECT count(name) FROM sqlite_master WHERE type='table' AND name IN ('spool', 'filament')",
+            [],
+            |row| row.get(0),
+        )
+        .map_err(|e| e.to_string())?;
+    if has_tables == 2 {
+        let orphans: i32 = conn
+            .query_row(
+                "SELECT count(*) FROM filament WHERE roll_id NOT IN (SELECT roll_id FROM spool)",
+                [],
+                |row| row.get(0),
+            )
+            .map_err(|e| e.to_string())?;
+        if orphans > 0 {
+            problems.push(format!(
+                "{} prints belong to a spool which does not exist",
+                orphans
+            ));
+        }
+    }
+    Ok(problems)
+}
+
+//Rebuilds the file to give back the space left by deleted rows, returning the
+//page count before and after
+pub fn vacuum(conn: &Connection) -> Result<(i64, i64), String> {
+    let pages = |conn: &Connection| -> Result<i64, String> {
+        conn.query_row("PRAGMA page_count", [], |row| row.get(0))
+            .map_err(|e| e.to_string())
+    };
+    let before = pages(conn)?;
+    conn.execute("VACUUM", ()).map_err(|e| e.to_string())?;
+    Ok((before, pages(conn)?))
+}
@@ -0,0 +1,162 @@
+use crate::get_timestamp;
+use crate::print_structs::*;
+use rusqlite::{Connection, OptionalExtension, Result, Row};
+use serde_json::Value;
+use std::io::Read;
+use uuid::Uuid;
+
+const PRODUCT_COLUMNS: &str = "product_name, product_vendor, product_material, product_colour,
+    product_colour_hex, product_diameter, product_density, product_weight, product_tare,
+    product_price";
+
+fn product_from_row(row: &Row) -> Result<Product> {
+    Ok(Product {
+        product_name: row.get(0)?,
+        product_vendor: row.get(1)?,
+        product_material: row.get(2)?,
+        product_colour: row.get(3)?,
+        product_colour_hex: row.get(4)?,
+        product_diameter: row.get(5)?,
+        product_density: row.get(6)?,
+        product_weight: row.get(7)?,
+        product_tare: row.get(8)?,
+        product_price: row.get(9)?,
+    })
+}
+
+impl Product {
+    //The given name, otherwise the vendor, material and colour, e.g. Polymaker PLA Black
+    pub fn display_name(&self) -> String {
+        if let Some(name) = &self.product_name {
+            return name.clone();
+        }
+        [
+            &self.product_vendor,
+            &self.product_material,
+            &self.product_colour,
+        ]
+        .into_iter()
+        .flatten()
+        .cloned()
+        .collect::<Vec<String>>()
+        .join(" ")
+    }
+
+    //A new spool of this product, with the length worked out from the density and
+    //diameter when both are known
+    pub fn new_spool(&self) -> Spool {
+        let diameter = self.product_diameter.unwrap_or(FILAMENT_DIAMETER);
+        let length = match (self.product_weight, self.product_density) {
+            (Some(weight), Some(density)) if density > 0.0 => {
+                let area = std::f32::consts::PI * (diameter / 20.0).powi(2);
+                Some(Length::from_metres(
+                    weight.grams() / (density * area) / 100.0,
+                ))
+            }
+            _ => None,
+        };
+        Spool {
+            roll_id: Some(Uuid::new_v4()),
+            roll_name: Some(self.display_name()),
+            roll_weight: self.product_weight,
+            roll_length: length,
+            timestamp: Some(get_timestamp()),
+            roll_material: self.product_material.clone(),
+            roll_price: self.product_price,
+            roll_tare: self.product_tare,
+            roll_colour: self.product_colour.clone(),
+            roll_diameter: Some(diameter).filter(|diameter| *diameter != FILAMENT_DIAMETER),
+            roll_colour_hex: self
+                .product_colour_hex
+                .as_deref()
+                .and_then(hex_colour)
+                .map(|hex| format!("#{}", hex)),
+            roll_notes: None,
+        }
+    }
+}
+
+//Adds a product or replaces the one with the same name
+pub fn save_product(conn: &Connection, product: &Product) -> Result<usize> {
+    let save_query = format!(
+        "INSERT OR REPLACE INTO product ({}) VALUES (?1,?2,?3,?4,?5,?6,?7,?8,?9,?10)",
+        PRODUCT_COLUMNS
+    );
+    conn.execute(
+        &save_query,
+        (
+            product.display_name(),
+            &product.product_vendor,
+            &product.product_material,
+            &product.product_colour,
+            &product.product_colour_hex,
+            product.product_diameter,
+            product.product_density,
+            product.product_weight,
+            product.product_tare,
+            product.product_price,
+        ),
+    )
+}
+
+pub fn list_products(conn: &Connection) -> Result<Vec<Product>> {
+    let products_query = format!(
+        "SELECT {} FROM product ORDER BY product_name",
+        PRODUCT_COLUMNS
+    );
+    let mut stmt = conn.prepare(&products_query)?;
+    let rows = stmt.query_map([], product_from_row)?;
+    rows.collect()
+}
+
+//Product names are compared without caring about case
+pub fn find_product(conn: &Connection, name: &str) -> Result<Option<Product>> {
+    let product_query = format!(
+        "SELECT {} FROM product WHERE product_name = ?1",
+        PRODUCT_COLUMNS
+    );
+    conn.query_row(&product_query, [name.trim()], product_from_row)
+        .optional()
+}
+
+pub fn remove_product(conn: &Connection, name: &str) -> Result<usize> {
+    conn.execute("DELETE FROM product WHERE product_name = ?1", [name.trim()])
+}
+
+//Reads a JSON list of products, or an object with the list under `products`
+pub fn read_products<R: Read>(reader: R) -> Result<Vec<Product>, String> {
+    let value: Value = serde_json::from_reader(reader).map_err(|e| e.to_string())?;
+    let list = match value {
+        Value::Object(mut object) => object
+            .remove("products")
+            .ok_or("expected a list of products")?,
+        list => list,
+    };
+    let products: Vec<Product> = serde_json::from_value(list).map_err(|e| e.to_string())?;
+    for (index, product) in products.iter().enumerate() {
+        if product.display_name().is_empty() {
+            return Err(format!(
+                "product {} has no name, vendor, material or colour",
+                index + 1
+            ));
+        }
+    }
+    Ok(products)
+}
+
+//Saves every product in one transaction, returning how many were new and how many replaced
+pub fn import_products(conn: &Connection, products: &[Product]) -> Result<(usize, usize), String> {
+    let tx = conn.unchecked_transaction().map_err(|e| e.to_string())?;
+    let mut added = 0;
+    let mut replaced = 0;
+    for product in products {
+        let existing = find_product(&tx, &product.display_name()).map_err(|e| e.to_string())?;
+        save_product(&tx, product).map_err(|e| e.to_string())?;
+        match existing {
+            Some(_) => replaced += 1,
+            None => added += 1,
+        }
+    }
+    tx.commit().map_err(|e| e.to_string())?;
+    Ok((added, replaced))
+}
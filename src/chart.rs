@@ -0,0 +1,110 @@
+use terminal_size::{terminal_size, Width};
+
+const BAR_BLOCKS: [char; 8] = ['▏', '▎', '▍', '▌', '▋', '▊', '▉', '█'];
+const SPARK_BLOCKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+const ASCII_SPARKS: [char; 8] = ['_', '.', '-', '~', '=', '+', '*', '#'];
+const DEFAULT_WIDTH: usize = 80;
+const MIN_BAR_WIDTH: usize = 10;
+
+//Width of the terminal, falling back to $COLUMNS and then 80 when not attached to one
+pub fn terminal_width() -> usize {
+    if let Some((Width(width), _)) = terminal_size() {
+        return width as usize;
+    }
+    std::env::var("COLUMNS")
+        .ok()
+        .and_then(|columns| columns.parse().ok())
+        .unwrap_or(DEFAULT_WIDTH)
+}
+
+//Horizontal bar chart, one row per (label, value, annotation), scaled to the largest value
+pub fn bar_chart(rows: &[(String, f32, String)], width: usize, ascii: bool) -> String {
+    let label_width = rows
+        .iter()
+        .map(|row| row.0.chars().count())
+        .max()
+        .unwrap_or(0);
+    let note_width = rows
+        .iter()
+        .map(|row| row.2.chars().count())
+        .max()
+        .unwrap_or(0);
+    let bar_width = width
+        .saturating_sub(label_width + note_width + 4)
+        .max(MIN_BAR_WIDTH);
+    let max_value = rows.iter().map(|row| row.1).fold(0.0, f32::max);
+    let separator = if ascii { '|' } else { '│' };
+
+    let mut chart = String::new();
+    for (label, value, note) in rows {
+        let scaled = if max_value > 0.0 {
+            value.max(0.0) / max_value * bar_width as f32
+        } else {
+            0.0
+        };
+        let bar = render_bar(scaled, ascii);
+        let padding = bar_width - bar.chars().count();
+        chart.push_str(&format!(
+            "{:<label_width$} {} {}{} {}\n",
+            label,
+            separator,
+            bar,
+            " ".repeat(padding),
+            note
+        ));
+    }
+    chart
+}
+
+//Bar filled to the given fraction of the width, padded so gauges line up
+pub fn gauge(fraction: f32, width: usize, ascii: bool) -> String {
+    let bar = render_bar(fraction.clamp(0.0, 1.0) * width as f32, ascii);
+    let padding = width.saturating_sub(bar.chars().count());
+    format!("{}{}", bar, " ".repeat(padding))
+}
+
+fn render_bar(scaled: f32, ascii: bool) -> String {
+    let full = scaled.floor() as usize;
+    if ascii {
+        return "#".repeat(full);
+    }
+    let mut bar = BAR_BLOCKS[7].to_string().repeat(full);
+    let eighths = ((scaled - full as f32) * 8.0).round() as usize;
+    if eighths > 0 {
+        bar.push(BAR_BLOCKS[eighths.min(8) - 1]);
+    }
+    bar
+}
+
+//One character per value, values are averaged together when they do not fit the width
+pub fn sparkline(values: &[f32], width: usize, ascii: bool) -> String {
+    let blocks = if ascii { ASCII_SPARKS } else { SPARK_BLOCKS };
+    let values = squash(values, width.max(1));
+    let min = values.iter().cloned().fold(f32::INFINITY, f32::min);
+    let max = values.iter().cloned().fold(f32::NEG_INFINITY, f32::max);
+    values
+        .iter()
+        .map(|value| {
+            if max - min <= f32::EPSILON {
+                blocks[blocks.len() / 2]
+            } else {
+                let level = (value - min) / (max - min) * (blocks.len() - 1) as f32;
+                blocks[level.round() as usize]
+            }
+        })
+        .collect()
+}
+
+fn squash(values: &[f32], width: usize) -> Vec<f32> {
+    if values.len() <= width {
+        return values.to_vec();
+    }
+    (0..width)
+        .map(|bucket| {
+            let start = bucket * values.len() / width;
+            let end = ((bucket + 1) * values.len() / width).max(start + 1);
+            let slice = &values[start..end];
+            slice.iter().sum::<f32>() / slice.len() as f32
+        })
+        .collect()
+}
@@ -0,0 +1,53 @@
+use crate::config::{self, Config};
+use clap_complete::engine::CompletionCandidate;
+use rusqlite::{Connection, OpenFlags, Result};
+
+//The shell runs remainder again for every completion, so the names are always the
+//ones in the database at the time
+pub const SPOOL_NAMES: &str =
+    "SELECT DISTINCT roll_name FROM spool WHERE roll_name IS NOT NULL ORDER BY roll_name";
+
+//Printers a spool was loaded into and locations that are printers
+pub const PRINTER_NAMES: &str = "SELECT printer_name FROM printer
+    UNION SELECT printer_name FROM ams_tray
+    UNION SELECT location_name FROM location WHERE location_kind = 'printer'
+    ORDER BY 1";
+
+pub const PROJECT_NAMES: &str = "SELECT project_name FROM project ORDER BY project_name";
+
+pub fn names(conn: &Connection, names_query: &str) -> Result<Vec<String>> {
+    let mut stmt = conn.prepare(names_query)?;
+    let rows = stmt.query_map([], |row| row.get(0))?;
+    rows.collect()
+}
+
+pub fn spool_names() -> Vec<CompletionCandidate> {
+    candidates(SPOOL_NAMES)
+}
+
+pub fn printer_names() -> Vec<CompletionCandidate> {
+    candidates(PRINTER_NAMES)
+}
+
+pub fn project_names() -> Vec<CompletionCandidate> {
+    candidates(PROJECT_NAMES)
+}
+
+//A broken config or a missing database only means there is nothing to offer
+fn candidates(names_query: &str) -> Vec<CompletionCandidate> {
+    let config = config::default_path()
+        .and_then(|path| Config::load(&path).ok())
+        .unwrap_or_default();
+    let Some(path) = config.database.or_else(config::default_database) else {
+        return Vec::new();
+    };
+    //Read only so completing never creates or migrates a database
+    let Ok(conn) = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY) else {
+        return Vec::new();
+    };
+    names(&conn, names_query)
+        .unwrap_or_default()
+        .into_iter()
+        .map(CompletionCandidate::new)
+        .collect()
+}
@@ -0,0 +1,223 @@
+use crate::print_structs::Weight;
+use crate::units::{self, LengthUnit, UnitSystem, WeightUnit};
+use clap::ValueEnum;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+//Every setting with what it is for, each can also be set with REMAINDER_ and the
+//key in upper case, e.g. REMAINDER_PRINTER
+pub const KEYS: [(&str, &str); 10] = [
+    ("material", "Material of new spools, e.g. PLA"),
+    ("diameter", "Filament diameter of new spools in millimetres"),
+    ("currency", "Shown with prices and costs, e.g. € or USD"),
+    ("printer", "Printer used when no --printer is given"),
+    ("low_weight", "Warn when less than this is left, e.g. 100g"),
+    ("units", "metric or imperial"),
+    ("weight_unit", "g, kg, oz or lb, over the unit system's"),
+    ("length_unit", "m, mm, ft or in, over the unit system's"),
+    ("database", "Path of the database"),
+    (
+        "user",
+        "Name the history of changes records, defaults to the login name",
+    ),
+];
+
+const ENV_PREFIX: &str = "REMAINDER_";
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Config {
+    pub material: Option<String>,
+    pub diameter: Option<f32>,
+    pub currency: Option<String>,
+    pub printer: Option<String>,
+    pub low_weight: Option<Weight>,
+    pub units: Option<UnitSystem>,
+    pub weight_unit: Option<WeightUnit>,
+    pub length_unit: Option<LengthUnit>,
+    pub database: Option<PathBuf>,
+    pub user: Option<String>,
+}
+
+//Where a setting's value came from
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Source {
+    File,
+    Environment,
+}
+
+impl Source {
+    pub fn name(self) -> &'static str {
+        match self {
+            Source::File => "config file",
+            Source::Environment => "environment",
+        }
+    }
+}
+
+impl Config {
+    //Reads the config file, a missing file is the same as an empty one, then lets
+    //the environment override it
+    pub fn load(path: &Path) -> Result<Config, String> {
+        let values = layered_values(&read_file(path)?, |name| env::var(name).ok())?;
+        Config::from_values(&values)
+    }
+
+    pub fn from_values(values: &[(&str, String, Source)]) -> Result<Config, String> {
+        let mut config = Config::default();
+        for (key, value, source) in values {
+            config
+                .set(key, value)
+                .map_err(|e| format!("{} in the {}: {}", key, source.name(), e))?;
+        }
+        Ok(config)
+    }
+
+    fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        let value = value.trim();
+        match key {
+            "material" => self.material = Some(value.to_string()),
+            "diameter" => self.diameter = Some(units::parse_diameter(value)?),
+            "currency" => self.currency = Some(value.to_string()),
+            "printer" => self.printer = Some(value.to_string()),
+            "low_weight" => self.low_weight = Some(units::parse_weight(value)?),
+            "units" => {
+                self.units = Some(
+                    UnitSystem::from_str(value, true)
+                        .map_err(|_| format!("{} is not metric or imperial", value))?,
+                )
+            }
+            "weight_unit" => {
+                self.weight_unit = Some(
+                    WeightUnit::from_str(value, true)
+                        .map_err(|_| format!("{} is not g, kg, oz or lb", value))?,
+                )
+            }
+            "length_unit" => {
+                self.length_unit = Some(
+                    LengthUnit::from_str(value, true)
+                        .map_err(|_| format!("{} is not m, mm, ft or in", value))?,
+                )
+            }
+            "database" => {
+                self.database = Some(match (value.strip_prefix("~/"), env::home_dir()) {
+                    (Some(path), Some(home)) => home.join(path),
+                    _ => PathBuf::from(value),
+                })
+            }
+            "user" if value.is_empty() => return Err(String::from("the user cannot be empty")),
+            "user" => self.user = Some(value.to_string()),
+            _ => return Err(unknown_key(key)),
+        }
+        Ok(())
+    }
+
+    //A price with the currency, symbols go in front and codes such as USD after
+    pub fn price(&self, price: f32) -> String {
+        match self.currency.as_deref() {
+            None => format!("{:.2}", price),
+            Some(code) if code.chars().all(char::is_alphabetic) => {
+                format!("{:.2} {}", price, code)
+            }
+            Some(symbol) => format!("{}{:.2}", symbol, price),
+        }
+    }
+}
+
+//The XDG config directory, ~/.config/remainder/config.toml when XDG_CONFIG_HOME is not set
+pub fn default_path() -> Option<PathBuf> {
+    let config_home = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .filter(|path| path.is_absolute())
+        .or_else(|| env::home_dir().map(|home| home.join(".config")))?;
+    Some(config_home.join("remainder").join("config.toml"))
+}
+
+//Where the database is kept when neither --database nor the config says
+pub fn default_database() -> Option<PathBuf> {
+    env::home_dir().map(|home| home.join(".remainder").join("3d_print_db.db"))
+}
+
+pub fn read_file(path: &Path) -> Result<toml::Table, String> {
+    match fs::read_to_string(path) {
+        Ok(text) => text
+            .parse::<toml::Table>()
+            .map_err(|e| format!("{} is not valid TOML: {}", path.display(), e)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(toml::Table::new()),
+        Err(e) => Err(format!("Could not read {}: {}", path.display(), e)),
+    }
+}
+
+//The value of each setting that is set, from the environment when it is set there
+//and from the file otherwise, in the order of KEYS
+pub fn layered_values(
+    file: &toml::Table,
+    env: impl Fn(&str) -> Option<String>,
+) -> Result<Vec<(&'static str, String, Source)>, String> {
+    if let Some(key) = file.keys().find(|key| !is_key(key)) {
+        return Err(unknown_key(key));
+    }
+    let mut values = Vec::new();
+    for (key, _) in KEYS {
+        if let Some(value) = env(&format!("{}{}", ENV_PREFIX, key.to_uppercase())) {
+            values.push((key, value, Source::Environment));
+        } else if let Some(value) = file.get(key) {
+            let value = match value {
+                toml::Value::String(value) => value.clone(),
+                toml::Value::Integer(_) | toml::Value::Float(_) => value.to_string(),
+                _ => return Err(format!("{} should be a string or a number", key)),
+            };
+            values.push((key, value, Source::File));
+        }
+    }
+    Ok(values)
+}
+
+//Settings saved as numbers, every other one is a string so names such as 007 stay as typed
+const NUMBER_KEYS: [&str; 1] = ["diameter"];
+
+//Checks the value and saves it in the file, keeping the other settings in it
+pub fn set_value(path: &Path, key: &str, value: &str) -> Result<(), String> {
+    Config::default().set(key, value)?;
+    let mut file = read_file(path)?;
+    let value = value.trim();
+    let value = match value.parse::<f64>() {
+        Ok(number) if NUMBER_KEYS.contains(&key) => toml::Value::Float(number),
+        _ => toml::Value::String(value.to_string()),
+    };
+    file.insert(key.to_string(), value);
+    write_file(path, &file)
+}
+
+//Takes a setting out of the file, true when it was there
+pub fn unset_value(path: &Path, key: &str) -> Result<bool, String> {
+    if !is_key(key) {
+        return Err(unknown_key(key));
+    }
+    let mut file = read_file(path)?;
+    let removed = file.remove(key).is_some();
+    if removed {
+        write_file(path, &file)?;
+    }
+    Ok(removed)
+}
+
+fn write_file(path: &Path, file: &toml::Table) -> Result<(), String> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    }
+    let text = toml::to_string(file).map_err(|e| e.to_string())?;
+    fs::write(path, text).map_err(|e| format!("Could not write {}: {}", path.display(), e))
+}
+
+pub fn is_key(key: &str) -> bool {
+    KEYS.iter().any(|(known, _)| *known == key)
+}
+
+fn unknown_key(key: &str) -> String {
+    format!(
+        "{} is not a setting, use one of {}",
+        key,
+        KEYS.map(|(key, _)| key).join(", ")
+    )
+}
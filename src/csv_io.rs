@@ -0,0 +1,410 @@
+use crate::print_add;
+use crate::print_stats;
+use crate::print_structs::*;
+use crate::{format_iso_timestamp, get_timestamp, parse_timestamp};
+use clap::ValueEnum;
+use rusqlite::{Connection, OptionalExtension};
+use std::collections::{HashMap, HashSet};
+use std::io::{Read, Write};
+use uuid::Uuid;
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum Table {
+    Spool,
+    Filament,
+}
+
+pub const SPOOL_HEADERS: [&str; 12] = [
+    "id",
+    "name",
+    "material",
+    "colour",
+    "colour_hex",
+    "weight_g",
+    "length_m",
+    "diameter_mm",
+    "price",
+    "tare_g",
+    "created",
+    "notes",
+];
+pub const FILAMENT_HEADERS: [&str; 10] = [
+    "id", "spool_id", "name", "status", "weight_g", "length_m", "time_s", "printed", "notes",
+    "project",
+];
+
+//Other header names spreadsheets tend to use for each column, compared after
+//normalise_header so `Weight (g)` matches weight_g
+const SPOOL_ALIASES: [(&str, &[&str]); 12] = [
+    ("id", &["uuid", "roll_id"]),
+    ("name", &["roll_name", "spool", "spool_name"]),
+    ("material", &["roll_material", "type"]),
+    ("colour", &["roll_colour", "color", "color_name"]),
+    ("colour_hex", &["roll_colour_hex", "color_hex", "hex"]),
+    ("weight_g", &["weight", "roll_weight", "grams"]),
+    ("length_m", &["length", "roll_length", "meters", "metres"]),
+    ("diameter_mm", &["diameter", "roll_diameter"]),
+    ("price", &["roll_price", "cost"]),
+    (
+        "tare_g",
+        &["tare", "roll_tare", "spool_weight", "empty_weight"],
+    ),
+    (
+        "created",
+        &["timestamp", "roll_timestamp", "date", "opened"],
+    ),
+    ("notes", &["roll_notes", "note", "comment", "comments"]),
+];
+const FILAMENT_ALIASES: [(&str, &[&str]); 10] = [
+    ("id", &["uuid", "print_id"]),
+    ("spool_id", &["spool", "roll_id", "roll_name"]),
+    ("name", &["print_name", "job"]),
+    ("status", &["print_status", "result"]),
+    ("weight_g", &["weight", "print_weight", "grams"]),
+    ("length_m", &["length", "print_length", "meters", "metres"]),
+    ("time_s", &["time", "print_time", "duration", "seconds"]),
+    ("printed", &["timestamp", "print_timestamp", "date"]),
+    ("notes", &["print_notes", "note", "comment", "comments"]),
+    ("project", &["print_project"]),
+];
+
+pub struct ImportSummary {
+    pub inserted: usize,
+    pub duplicates: Vec<Uuid>,
+}
+
+pub fn export_csv<W: Write>(conn: &Connection, table: Table, writer: W) -> Result<usize, String> {
+    let mut writer = csv::Writer::from_writer(writer);
+    let mut count = 0;
+    match table {
+        Table::Spool => {
+            writer
+                .write_record(SPOOL_HEADERS)
+                .map_err(|e| e.to_string())?;
+            for spool in print_stats::list_spools(conn).map_err(|e| e.to_string())? {
+                writer
+                    .write_record([
+                        spool.roll_id.map(|id| id.to_string()).unwrap_or_default(),
+                        spool.roll_name.unwrap_or_default(),
+                        spool.roll_material.unwrap_or_default(),
+                        spool.roll_colour.unwrap_or_default(),
+                        spool.roll_colour_hex.unwrap_or_default(),
+                        format_number(spool.roll_weight.map(Weight::grams)),
+                        format_number(spool.roll_length.map(Length::metres)),
+                        format_number(spool.roll_diameter),
+                        format_number(spool.roll_price),
+                        format_number(spool.roll_tare.map(Weight::grams)),
+                        spool
+                            .timestamp
+                            .map(format_iso_timestamp)
+                            .unwrap_or_default(),
+                        spool.roll_notes.unwrap_or_default(),
+                    ])
+                    .map_err(|e| e.to_string())?;
+                count += 1;
+            }
+        }
+        Table::Filament => {
+            writer
+                .write_record(FILAMENT_HEADERS)
+                .map_err(|e| e.to_string())?;
+            for print in print_stats::list_prints(conn).map_err(|e| e.to_string())? {
+                writer
+                    .write_record([
+                        print.print_id.map(|id| id.to_string()).unwrap_or_default(),
+                        print.roll_id.map(|id| id.to_string()).unwrap_or_default(),
+                        print.print_name.unwrap_or_default(),
+                        print.print_status.unwrap_or_default(),
+                        format_number(print.print_weight.map(Weight::grams)),
+                        format_number(print.print_length.map(Length::metres)),
+                        print
+                            .print_time
+                            .map(|time| time.to_string())
+                            .unwrap_or_default(),
+                        print
+                            .timestamp
+                            .map(format_iso_timestamp)
+                            .unwrap_or_default(),
+                        print.print_notes.unwrap_or_default(),
+                        print.print_project.unwrap_or_default(),
+                    ])
+                    .map_err(|e| e.to_string())?;
+                count += 1;
+            }
+        }
+    }
+    writer.flush().map_err(|e| e.to_string())?;
+    Ok(count)
+}
+
+fn format_number(value: Option<f32>) -> String {
+    value.map(|value| value.to_string()).unwrap_or_default()
+}
+
+//Works out which column holds which field, explicit mappings are given as source=field
+fn map_columns(
+    headers: &csv::StringRecord,
+    table: Table,
+    mappings: &[String],
+) -> Result<HashMap<String, usize>, String> {
+    let aliases: &[(&str, &[&str])] = match table {
+        Table::Spool => &SPOOL_ALIASES,
+        Table::Filament => &FILAMENT_ALIASES,
+    };
+    let fields: Vec<&str> = aliases.iter().map(|(field, _)| *field).collect();
+    let mut explicit = HashMap::new();
+    for mapping in mappings {
+        let (source, field) = mapping
+            .split_once('=')
+            .ok_or_else(|| format!("Column mapping {} should look like source=field", mapping))?;
+        if !fields.contains(&field.trim()) {
+            return Err(format!(
+                "Unknown field {} in mapping, expected one of {}",
+                field,
+                fields.join(", ")
+            ));
+        }
+        explicit.insert(normalise_header(source), field.trim().to_string());
+    }
+
+    let mut columns = HashMap::new();
+    for (index, header) in headers.iter().enumerate() {
+        let header = normalise_header(header);
+        let field = match explicit.get(&header) {
+            Some(field) => Some(field.clone()),
+            None => aliases
+                .iter()
+                .find(|(field, names)| *field == header || names.contains(&header.as_str()))
+                .map(|(field, _)| field.to_string()),
+        };
+        if let Some(field) = field {
+            columns.entry(field).or_insert(index);
+        }
+    }
+    Ok(columns)
+}
+
+//Lower case with every run of other characters turned into a single underscore
+fn normalise_header(header: &str) -> String {
+    header
+        .to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<&str>>()
+        .join("_")
+}
+
+fn field<'a>(
+    record: &'a csv::StringRecord,
+    columns: &HashMap<String, usize>,
+    name: &str,
+) -> Option<&'a str> {
+    columns
+        .get(name)
+        .and_then(|index| record.get(*index))
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+}
+
+fn parse_number(value: Option<&str>, name: &str) -> Result<Option<f32>, String> {
+    match value {
+        None => Ok(None),
+        Some(value) => match value.parse::<f32>() {
+            Ok(number) if number.is_finite() && number >= 0.0 => Ok(Some(number)),
+            _ => Err(format!("{} {} is not a positive number", name, value)),
+        },
+    }
+}
+
+fn parse_id(value: Option<&str>) -> Result<Uuid, String> {
+    match value {
+        None => Ok(Uuid::new_v4()),
+        Some(value) => Uuid::parse_str(value).map_err(|_| format!("id {} is not a UUID", value)),
+    }
+}
+
+fn parse_date(value: Option<&str>) -> Result<Option<i64>, String> {
+    match value {
+        None => Ok(None),
+        Some(value) => parse_timestamp(value)
+            .map(Some)
+            .ok_or_else(|| format!("{} is not an ISO 8601 date", value)),
+    }
+}
+
+fn spool_from_record(
+    record: &csv::StringRecord,
+    columns: &HashMap<String, usize>,
+) -> Result<Spool, String> {
+    let spool = Spool {
+        roll_id: Some(parse_id(field(record, columns, "id"))?),
+        roll_name: Some(
+            field(record, columns, "name")
+                .ok_or("name is missing")?
+                .to_string(),
+        ),
+        roll_weight: parse_number(field(record, columns, "weight_g"), "weight")?
+            .map(Weight::from_grams),
+        roll_length: parse_number(field(record, columns, "length_m"), "length")?
+            .map(Length::from_metres),
+        timestamp: Some(
+            parse_date(field(record, columns, "created"))?.unwrap_or_else(get_timestamp),
+        ),
+        roll_material: field(record, columns, "material").map(str::to_string),
+        roll_colour: field(record, columns, "colour").map(str::to_string),
+        roll_price: parse_number(field(record, columns, "price"), "price")?,
+        roll_tare: parse_number(field(record, columns, "tare_g"), "tare")?.map(Weight::from_grams),
+        roll_diameter: parse_number(field(record, columns, "diameter_mm"), "diameter")?,
+        roll_colour_hex: match field(record, columns, "colour_hex") {
+            None => None,
+            Some(hex) => Some(format!(
+                "#{}",
+                hex_colour(hex).ok_or_else(|| format!("colour {} is not a hex code", hex))?
+            )),
+        },
+        roll_notes: field(record, columns, "notes").map(str::to_string),
+    };
+    if spool.roll_weight.is_none() && spool.roll_length.is_none() {
+        return Err(String::from("either a weight or a length is needed"));
+    }
+    Ok(spool)
+}
+
+fn print_from_record(
+    conn: &Connection,
+    record: &csv::StringRecord,
+    columns: &HashMap<String, usize>,
+) -> Result<Filament, String> {
+    let spool = field(record, columns, "spool_id").ok_or("spool_id is missing")?;
+    let roll_id = print_add::find_spool(conn, spool)
+        .map_err(|_| format!("spool {} does not exist", spool))?
+        .roll_id;
+    let status = match field(record, columns, "status") {
+        None => PRINT_SUCCESS,
+        Some(status) => [PRINT_SUCCESS, PRINT_FAILED, PRINT_CANCELLED, PRINT_WEIGH_IN]
+            .into_iter()
+            .find(|known| known.eq_ignore_ascii_case(status))
+            .ok_or_else(|| {
+                format!(
+                    "status {} is not success, failed, cancelled or weigh-in",
+                    status
+                )
+            })?,
+    };
+    let print_time = match field(record, columns, "time_s") {
+        None => None,
+        Some(time) => Some(
+            time.parse::<i32>()
+                .ok()
+                .filter(|time| *time >= 0)
+                .ok_or_else(|| format!("time {} is not a whole number of seconds", time))?,
+        ),
+    };
+    let print = Filament {
+        print_id: Some(parse_id(field(record, columns, "id"))?),
+        print_weight: parse_number(field(record, columns, "weight_g"), "weight")?
+            .map(Weight::from_grams),
+        print_length: parse_number(field(record, columns, "length_m"), "length")?
+            .map(Length::from_metres),
+        print_time,
+        roll_id: Some(roll_id),
+        timestamp: parse_date(field(record, columns, "printed"))?,
+        print_name: field(record, columns, "name").map(str::to_string),
+        print_status: Some(String::from(status)),
+        print_notes: field(record, columns, "notes").map(str::to_string),
+        print_project: field(record, columns, "project").map(str::to_string),
+    };
+    if print.print_weight.is_none() && print.print_length.is_none() {
+        return Err(String::from("either a weight or a length is needed"));
+    }
+    Ok(print)
+}
+
+fn id_exists(conn: &Connection, table: Table, id: Uuid) -> Result<bool, String> {
+    let exists_query = match table {
+        Table::Spool => "SELECT 1 FROM spool WHERE roll_id = ?1",
+        Table::Filament => "SELECT 1 FROM filament WHERE print_id = ?1",
+    };
+    conn.query_row(exists_query, [id], |_| Ok(()))
+        .optional()
+        .map(|found| found.is_some())
+        .map_err(|e| e.to_string())
+}
+
+//Imports every row in one transaction, rows whose id is already stored are skipped.
+//When any row is invalid nothing is imported and every problem is returned
+pub fn import_csv<R: Read>(
+    conn: &Connection,
+    table: Table,
+    reader: R,
+    mappings: &[String],
+    dry_run: bool,
+) -> Result<ImportSummary, Vec<String>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(reader);
+    let headers = reader.headers().map_err(|e| vec![e.to_string()])?.clone();
+    let columns = map_columns(&headers, table, mappings).map_err(|e| vec![e])?;
+
+    let tx = conn
+        .unchecked_transaction()
+        .map_err(|e| vec![e.to_string()])?;
+    let mut errors = Vec::new();
+    let mut seen = HashSet::new();
+    let mut summary = ImportSummary {
+        inserted: 0,
+        duplicates: Vec::new(),
+    };
+    for (index, record) in reader.records().enumerate() {
+        //Header is the first line
+        let line = index + 2;
+        let record = match record {
+            Ok(record) => record,
+            Err(e) => {
+                errors.push(format!("line {}: {}", line, e));
+                continue;
+            }
+        };
+        let inserted = match table {
+            Table::Spool => spool_from_record(&record, &columns).and_then(|mut spool| {
+                let id = spool.roll_id.unwrap();
+                if !seen.insert(id) {
+                    return Err(format!("id {} appears more than once", id));
+                }
+                if id_exists(&tx, table, id)? {
+                    summary.duplicates.push(id);
+                    return Ok(());
+                }
+                print_add::insert_spool(&tx, &mut spool)
+                    .map(|_| summary.inserted += 1)
+                    .map_err(|e| e.to_string())
+            }),
+            Table::Filament => print_from_record(&tx, &record, &columns).and_then(|mut print| {
+                let id = print.print_id.unwrap();
+                if !seen.insert(id) {
+                    return Err(format!("id {} appears more than once", id));
+                }
+                if id_exists(&tx, table, id)? {
+                    summary.duplicates.push(id);
+                    return Ok(());
+                }
+                print_add::insert_print(&tx, &mut print)
+                    .map(|_| summary.inserted += 1)
+                    .map_err(|e| e.to_string())
+            }),
+        };
+        if let Err(e) = inserted {
+            errors.push(format!("line {}: {}", line, e));
+        }
+    }
+
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    if dry_run {
+        tx.rollback().map_err(|e| vec![e.to_string()])?;
+    } else {
+        tx.commit().map_err(|e| vec![e.to_string()])?;
+    }
+    Ok(summary)
+}
@@ -0,0 +1,173 @@
+use crate::print_stats;
+use rusqlite::{Connection, OptionalExtension, Result};
+use uuid::Uuid;
+
+//Days a spool of each hygroscopic material can be open before it wants drying,
+//matched against the start of the spool's material so PA12-CF counts as PA
+const DEFAULT_THRESHOLDS: [(&str, f32); 8] = [
+    ("PA", 2.0),
+    ("NYLON", 2.0),
+    ("PVA", 1.0),
+    ("BVOH", 1.0),
+    ("TPU", 3.0),
+    ("TPE", 3.0),
+    ("PC", 7.0),
+    ("PETG", 14.0),
+];
+
+const DAY: i64 = 24 * 60 * 60;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DryingSession {
+    //When the spool came out of the dryer
+    pub timestamp: i64,
+    pub temperature: f32,
+    pub minutes: i32,
+}
+
+//Records when a spool's bag was opened, keeping the first date unless replace is set
+pub fn set_opened(
+    conn: &Connection,
+    roll_id: Uuid,
+    timestamp: i64,
+    replace: bool,
+) -> Result<usize> {
+    let opened_query = if replace {
+        "UPDATE spool SET roll_opened = ?2 WHERE roll_id = ?1"
+    } else {
+        "UPDATE spool SET roll_opened = ?2 WHERE roll_id = ?1 AND roll_opened IS NULL"
+    };
+    conn.execute(opened_query, (roll_id, timestamp))
+}
+
+pub fn opened(conn: &Connection, roll_id: Uuid) -> Result<Option<i64>> {
+    conn.query_row(
+        "SELECT roll_opened FROM spool WHERE roll_id = ?1",
+        [roll_id],
+        |row| row.get(0),
+    )
+    .optional()
+    .map(Option::flatten)
+}
+
+//A spool that was dried has been opened, so it is marked opened if it was not yet
+pub fn record_drying(conn: &Connection, roll_id: Uuid, session: &DryingSession) -> Result<usize> {
+    let tx = conn.unchecked_transaction()?;
+    let drying_rt = tx.execute(
+        "INSERT INTO spool_drying (roll_id, drying_timestamp, drying_temperature, drying_minutes)
+            VALUES (?1, ?2, ?3, ?4)",
+        (
+            roll_id,
+            session.timestamp,
+            session.temperature,
+            session.minutes,
+        ),
+    )?;
+    set_opened(
+        &tx,
+        roll_id,
+        session.timestamp - i64::from(session.minutes) * 60,
+        false,
+    )?;
+    tx.commit()?;
+    Ok(drying_rt)
+}
+
+//Every drying session of a spool, oldest first
+pub fn drying_sessions(conn: &Connection, roll_id: Uuid) -> Result<Vec<DryingSession>> {
+    let sessions_query = "SELECT drying_timestamp, drying_temperature, drying_minutes
+            FROM spool_drying WHERE roll_id = ?1 ORDER BY drying_timestamp, rowid";
+    let mut stmt = conn.prepare(sessions_query)?;
+    let rows = stmt.query_map([roll_id], |row| {
+        Ok(DryingSession {
+            timestamp: row.get(0)?,
+            temperature: row.get(1)?,
+            minutes: row.get(2)?,
+        })
+    })?;
+    rows.collect()
+}
+
+//Sets how many days a material can be open, replacing the built in threshold
+pub fn set_threshold(conn: &Connection, material: &str, days: f32) -> Result<usize> {
+    conn.execute(
+        "INSERT OR REPLACE INTO drying_threshold (material, threshold_days) VALUES (?1, ?2)",
+        (material.trim(), days),
+    )
+}
+
+//The thresholds that were set, followed by the built in ones they do not replace
+pub fn list_thresholds(conn: &Connection) -> Result<Vec<(String, f32)>> {
+    let mut stmt =
+        conn.prepare("SELECT material, threshold_days FROM drying_threshold ORDER BY material")?;
+    let mut thresholds: Vec<(String, f32)> = stmt
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<Result<_>>()?;
+    for (material, days) in DEFAULT_THRESHOLDS {
+        if !thresholds
+            .iter()
+            .any(|(set, _)| set.eq_ignore_ascii_case(material))
+        {
+            thresholds.push((String::from(material), days));
+        }
+    }
+    Ok(thresholds)
+}
+
+//Days a material can be open for, None when it does not take on moisture.
+//The longest threshold the material starts with applies, and a set threshold beats
+//a built in one of the same length, so setting PA also covers PA12-CF
+pub fn threshold_days(conn: &Connection, material: &str) -> Result<Option<f32>> {
+    let material = material.trim().to_uppercase();
+    let mut best: Option<(usize, f32)> = None;
+    for (prefix, days) in list_thresholds(conn)? {
+        let prefix = prefix.trim().to_uppercase();
+        if material.starts_with(&prefix) && best.is_none_or(|(len, _)| prefix.len() > len) {
+            best = Some((prefix.len(), days));
+        }
+    }
+    Ok(best.map(|(_, days)| days))
+}
+
+//When the spool started taking on moisture, the later of it being opened and last dried.
+//None for a spool still sealed in its bag
+pub fn exposed_since(conn: &Connection, roll_id: Uuid) -> Result<Option<i64>> {
+    let last_dried: Option<i64> = conn.query_row(
+        "SELECT max(drying_timestamp) FROM spool_drying WHERE roll_id = ?1",
+        [roll_id],
+        |row| row.get(0),
+    )?;
+    Ok(opened(conn, roll_id)?.max(last_dried))
+}
+
+//A warning for a hygroscopic spool that has been open longer than its material allows
+pub fn exposure_warning(conn: &Connection, roll_id: Uuid, now: i64) -> Result<Option<String>> {
+    let spool = print_stats::get_spool(conn, roll_id)?;
+    let threshold = match &spool.roll_material {
+        Some(material) => threshold_days(conn, material)?,
+        None => None,
+    };
+    let (Some(threshold), Some(since)) = (threshold, exposed_since(conn, roll_id)?) else {
+        return Ok(None);
+    };
+    let days = (now - since) as f32 / DAY as f32;
+    if days <= threshold {
+        return Ok(None);
+    }
+    let last_dried = drying_sessions(conn, roll_id)?
+        .last()
+        .map(|session| session.timestamp);
+    let dried = if last_dried == Some(since) {
+        "last dried"
+    } else {
+        "opened"
+    };
+    Ok(Some(format!(
+        "{} was {} {:.1} days ago, {} should be dried after {} days",
+        spool.roll_name.unwrap_or_else(|| roll_id.to_string()),
+        dried,
+        days,
+        spool.roll_material.unwrap_or_default(),
+        threshold
+    )))
+}
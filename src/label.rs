@@ -0,0 +1,631 @@
+use crate::format_timestamp;
+use crate::print_structs::*;
+use clap::ValueEnum;
+use embedded_graphics::mono_font::iso_8859_1::{FONT_7X13, FONT_7X13_BOLD};
+use embedded_graphics::mono_font::MonoTextStyle;
+use embedded_graphics::pixelcolor::BinaryColor;
+use embedded_graphics::prelude::*;
+use embedded_graphics::text::{Baseline, Text};
+use qrcode::{Color, QrCode};
+use std::path::{Path, PathBuf};
+
+//PNG labels are rendered for a 300 DPI printer
+const DOTS_PER_MM: f32 = 300.0 / 25.4;
+const POINTS_PER_MM: f32 = 72.0 / 25.4;
+//Rough width of an average character relative to the font size, used to shorten long names
+const CHAR_WIDTH: f32 = 0.56;
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum LabelFormat {
+    Svg,
+    Png,
+    Pdf,
+}
+
+impl LabelFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            LabelFormat::Svg => "svg",
+            LabelFormat::Png => "png",
+            LabelFormat::Pdf => "pdf",
+        }
+    }
+}
+
+//Single labels for the common roll label printers
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum LabelSize {
+    /// Dymo 99012 address label, 89 x 36 mm
+    Dymo,
+    /// Brother DK-11209 small address label, 62 x 29 mm
+    Brother,
+    /// Zebra 2 x 1 inch direct thermal label
+    Zebra,
+}
+
+impl LabelSize {
+    //Width and height in millimetres
+    pub fn dimensions(self) -> (f32, f32) {
+        match self {
+            LabelSize::Dymo => (89.0, 36.0),
+            LabelSize::Brother => (62.0, 29.0),
+            LabelSize::Zebra => (50.8, 25.4),
+        }
+    }
+}
+
+//Sheets of labels for office printers
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum LabelSheet {
+    /// Avery L7160, A4 with 3 x 7 labels of 63.5 x 38.1 mm
+    AveryL7160,
+    /// Avery 5160, US Letter with 3 x 10 labels of 2.625 x 1 inch
+    Avery5160,
+}
+
+struct SheetLayout {
+    page: (f32, f32),
+    label: (f32, f32),
+    columns: usize,
+    rows: usize,
+    //Top left corner of the first label
+    margin: (f32, f32),
+    //Distance between the left or top edges of neighbouring labels
+    pitch: (f32, f32),
+}
+
+impl LabelSheet {
+    fn layout(self) -> SheetLayout {
+        match self {
+            LabelSheet::AveryL7160 => SheetLayout {
+                page: (210.0, 297.0),
+                label: (63.5, 38.1),
+                columns: 3,
+                rows: 7,
+                margin: (7.2, 15.1),
+                pitch: (66.0, 38.1),
+            },
+            LabelSheet::Avery5160 => SheetLayout {
+                page: (215.9, 279.4),
+                label: (66.7, 25.4),
+                columns: 3,
+                rows: 10,
+                margin: (4.8, 12.7),
+                pitch: (69.85, 25.4),
+            },
+        }
+    }
+
+    pub fn labels_per_page(self) -> usize {
+        let layout = self.layout();
+        layout.columns * layout.rows
+    }
+}
+
+//Everything on a page is either filled rectangles or a line of text, positioned in millimetres
+#[derive(Debug, Clone, PartialEq)]
+enum Mark {
+    Rect {
+        x: f32,
+        y: f32,
+        width: f32,
+        height: f32,
+        colour: [u8; 3],
+    },
+    //y is the baseline of the text
+    Text {
+        x: f32,
+        y: f32,
+        size: f32,
+        bold: bool,
+        text: String,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Page {
+    width: f32,
+    height: f32,
+    marks: Vec<Mark>,
+}
+
+//One page per spool, sized for a roll label printer
+pub fn label_pages(spools: &[Spool], size: LabelSize) -> Result<Vec<Page>, String> {
+    let (width, height) = size.dimensions();
+    spools
+        .iter()
+        .map(|spool| {
+            let mut marks = Vec::new();
+            draw_label(&mut marks, spool, 0.0, 0.0, width, height)?;
+            Ok(Page {
+                width,
+                height,
+                marks,
+            })
+        })
+        .collect()
+}
+
+//As many pages as needed to fit the spools onto a label sheet, filled row by row
+pub fn sheet_pages(spools: &[Spool], sheet: LabelSheet) -> Result<Vec<Page>, String> {
+    let layout = sheet.layout();
+    let mut pages = Vec::new();
+    for chunk in spools.chunks(sheet.labels_per_page()) {
+        let mut marks = Vec::new();
+        for (index, spool) in chunk.iter().enumerate() {
+            let x = layout.margin.0 + (index % layout.columns) as f32 * layout.pitch.0;
+            let y = layout.margin.1 + (index / layout.columns) as f32 * layout.pitch.1;
+            draw_label(&mut marks, spool, x, y, layout.label.0, layout.label.1)?;
+        }
+        pages.push(Page {
+            width: layout.page.0,
+            height: layout.page.1,
+            marks,
+        });
+    }
+    Ok(pages)
+}
+
+//Lays out one label with the QR code on the left and the details next to it
+fn draw_label(
+    marks: &mut Vec<Mark>,
+    spool: &Spool,
+    x: f32,
+    y: f32,
+    width: f32,
+    height: f32,
+) -> Result<(), String> {
+    let roll_id = spool.roll_id.ok_or("spool has no id")?;
+    let padding = height * 0.08;
+    //Squarer labels keep some room for the text
+    let qr_size = (height - 2.0 * padding).min(width * 0.4);
+    draw_qr_code(
+        marks,
+        &roll_id.to_string(),
+        x + padding,
+        y + padding,
+        qr_size,
+    )?;
+
+    let text_x = x + 2.0 * padding + qr_size;
+    let text_width = x + width - padding - text_x;
+    let name_size = height * 0.16;
+    let detail_size = height * 0.11;
+    let mut baseline = y + padding + name_size * 0.8;
+    let name = spool.roll_name.as_deref().unwrap_or("Unnamed spool");
+    //Long names get a smaller font before they are cut short
+    let fitted_size = text_width / (name.chars().count() as f32 * CHAR_WIDTH);
+    let name_size = fitted_size.clamp(detail_size, name_size);
+    marks.push(text_mark(
+        text_x, baseline, name_size, true, name, text_width,
+    ));
+
+    let material = spool.roll_material.as_deref().unwrap_or("Unknown material");
+    let mut details = vec![match spool
+        .roll_colour
+        .as_ref()
+        .or(spool.roll_colour_hex.as_ref())
+    {
+        Some(colour) => format!("{} {}", material, colour),
+        None => material.to_string(),
+    }];
+    details.push(format!("{:.0} g", spool.roll_weight.unwrap_or_default()));
+    if let Some(timestamp) = spool.timestamp {
+        details.push(format_timestamp(timestamp)[..10].to_string());
+    }
+    details.push(roll_id.to_string()[..8].to_string());
+
+    for (index, detail) in details.iter().enumerate() {
+        baseline += detail_size * 1.35;
+        let mut detail_x = text_x;
+        //A swatch in front of the material line when the colour can be shown
+        if index == 0 {
+            let colour = spool.colour_hex().or_else(|| spool.roll_colour.clone());
+            if let Some(rgb) = colour.as_deref().and_then(parse_colour) {
+                let swatch = detail_size * 0.8;
+                marks.push(Mark::Rect {
+                    x: detail_x - 0.15,
+                    y: baseline - swatch - 0.15,
+                    width: swatch + 0.3,
+                    height: swatch + 0.3,
+                    colour: [0, 0, 0],
+                });
+                marks.push(Mark::Rect {
+                    x: detail_x,
+                    y: baseline - swatch,
+                    width: swatch,
+                    height: swatch,
+                    colour: rgb,
+                });
+                detail_x += swatch * 1.5;
+            }
+        }
+        let available = text_width - (detail_x - text_x);
+        marks.push(text_mark(
+            detail_x,
+            baseline,
+            detail_size,
+            false,
+            detail,
+            available,
+        ));
+    }
+    Ok(())
+}
+
+//Dark modules next to each other in a row are joined into one rectangle to keep the files small
+fn draw_qr_code(
+    marks: &mut Vec<Mark>,
+    payload: &str,
+    x: f32,
+    y: f32,
+    size: f32,
+) -> Result<(), String> {
+    let code = QrCode::new(payload).map_err(|e| e.to_string())?;
+    let modules = code.width();
+    let colours = code.to_colors();
+    let module = size / modules as f32;
+    for row in 0..modules {
+        let mut column = 0;
+        while column < modules {
+            if colours[row * modules + column] == Color::Light {
+                column += 1;
+                continue;
+            }
+            let start = column;
+            while column < modules && colours[row * modules + column] == Color::Dark {
+                column += 1;
+            }
+            marks.push(Mark::Rect {
+                x: x + start as f32 * module,
+                y: y + row as f32 * module,
+                width: (column - start) as f32 * module,
+                height: module,
+                colour: [0, 0, 0],
+            });
+        }
+    }
+    Ok(())
+}
+
+fn text_mark(x: f32, y: f32, size: f32, bold: bool, text: &str, width: f32) -> Mark {
+    let fits = (width / (size * CHAR_WIDTH)).max(3.0) as usize;
+    let text = if text.chars().count() > fits {
+        let mut shortened: String = text.chars().take(fits - 3).collect();
+        shortened.push_str("...");
+        shortened
+    } else {
+        text.to_string()
+    };
+    Mark::Text {
+        x,
+        y,
+        size,
+        bold,
+        text,
+    }
+}
+
+//Hex codes such as #1A1A1A or a handful of plain colour names
+fn parse_colour(colour: &str) -> Option<[u8; 3]> {
+    let hex = colour.trim().trim_start_matches('#');
+    if hex.len() == 6 && hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+        return Some([channel(0)?, channel(2)?, channel(4)?]);
+    }
+    let rgb = match colour.trim().to_lowercase().as_str() {
+        "black" => [0x1a, 0x1a, 0x1a],
+        "white" => [0xff, 0xff, 0xff],
+        "grey" | "gray" => [0x8a, 0x8a, 0x8a],
+        "silver" => [0xc0, 0xc0, 0xc0],
+        "red" => [0xd0, 0x21, 0x21],
+        "orange" => [0xf2, 0x7a, 0x1a],
+        "yellow" => [0xf5, 0xd0, 0x20],
+        "green" => [0x2f, 0x9e, 0x44],
+        "blue" => [0x1f, 0x5f, 0xbf],
+        "purple" => [0x7b, 0x3f, 0xa8],
+        "pink" => [0xf2, 0x8a, 0xb8],
+        "brown" => [0x7a, 0x4b, 0x2a],
+        _ => return None,
+    };
+    Some(rgb)
+}
+
+//labels.svg becomes labels-1.svg, labels-2.svg and so on
+pub fn numbered_path(path: &Path, number: usize) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(extension) => format!("{}-{}.{}", stem, number, extension.to_string_lossy()),
+        None => format!("{}-{}", stem, number),
+    };
+    path.with_file_name(name)
+}
+
+//SVG and PNG give one file per page, PDF keeps all pages in one file
+pub fn render(pages: &[Page], format: LabelFormat) -> Result<Vec<Vec<u8>>, String> {
+    match format {
+        LabelFormat::Svg => Ok(pages
+            .iter()
+            .map(|page| render_svg(page).into_bytes())
+            .collect()),
+        LabelFormat::Png => pages.iter().map(render_png).collect(),
+        LabelFormat::Pdf => Ok(vec![render_pdf(pages)]),
+    }
+}
+
+fn render_svg(page: &Page) -> String {
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}mm\" height=\"{h}mm\" viewBox=\"0 0 {w} {h}\">\n<rect width=\"{w}\" height=\"{h}\" fill=\"#ffffff\"/>\n",
+        w = page.width,
+        h = page.height
+    );
+    for mark in &page.marks {
+        match mark {
+            Mark::Rect {
+                x,
+                y,
+                width,
+                height,
+                colour,
+            } => svg.push_str(&format!(
+                "<rect x=\"{:.3}\" y=\"{:.3}\" width=\"{:.3}\" height=\"{:.3}\" fill=\"#{:02x}{:02x}{:02x}\"/>\n",
+                x, y, width, height, colour[0], colour[1], colour[2]
+            )),
+            Mark::Text {
+                x,
+                y,
+                size,
+                bold,
+                text,
+            } => svg.push_str(&format!(
+                "<text x=\"{:.3}\" y=\"{:.3}\" font-family=\"Helvetica, Arial, sans-serif\" font-size=\"{:.3}\"{}>{}</text>\n",
+                x,
+                y,
+                size,
+                if *bold { " font-weight=\"bold\"" } else { "" },
+                crate::report::escape(text)
+            )),
+        }
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
+
+//A minimal PDF using the built in Helvetica fonts, so nothing has to be embedded
+fn render_pdf(pages: &[Page]) -> Vec<u8> {
+    //Objects 1 to 4 are the catalogue, page tree and fonts, then a page and its contents per page
+    let mut objects: Vec<Vec<u8>> = Vec::new();
+    let kids: Vec<String> = (0..pages.len())
+        .map(|index| format!("{} 0 R", 5 + index * 2))
+        .collect();
+    objects.push(b"<< /Type /Catalog /Pages 2 0 R >>".to_vec());
+    objects.push(
+        format!(
+            "<< /Type /Pages /Kids [{}] /Count {} >>",
+            kids.join(" "),
+            pages.len()
+        )
+        .into_bytes(),
+    );
+    objects.push(
+        b"<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica /Encoding /WinAnsiEncoding >>"
+            .to_vec(),
+    );
+    objects.push(
+        b"<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica-Bold /Encoding /WinAnsiEncoding >>"
+            .to_vec(),
+    );
+    for (index, page) in pages.iter().enumerate() {
+        let content = pdf_content(page);
+        objects.push(
+            format!(
+                "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {:.2} {:.2}] /Resources << /Font << /F1 3 0 R /F2 4 0 R >> >> /Contents {} 0 R >>",
+                page.width * POINTS_PER_MM,
+                page.height * POINTS_PER_MM,
+                6 + index * 2
+            )
+            .into_bytes(),
+        );
+        let mut stream = format!("<< /Length {} >>\nstream\n", content.len()).into_bytes();
+        stream.extend_from_slice(&content);
+        stream.extend_from_slice(b"\nendstream");
+        objects.push(stream);
+    }
+
+    let mut pdf = b"%PDF-1.4\n".to_vec();
+    let mut offsets = Vec::new();
+    for (index, object) in objects.iter().enumerate() {
+        offsets.push(pdf.len());
+        pdf.extend_from_slice(format!("{} 0 obj\n", index + 1).as_bytes());
+        pdf.extend_from_slice(object);
+        pdf.extend_from_slice(b"\nendobj\n");
+    }
+    let xref = pdf.len();
+    pdf.extend_from_slice(
+        format!("xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1).as_bytes(),
+    );
+    for offset in offsets {
+        pdf.extend_from_slice(format!("{:010} 00000 n \n", offset).as_bytes());
+    }
+    pdf.extend_from_slice(
+        format!(
+            "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{}\n%%EOF\n",
+            objects.len() + 1,
+            xref
+        )
+        .as_bytes(),
+    );
+    pdf
+}
+
+//PDF measures from the bottom left in points, the marks from the top left in millimetres
+fn pdf_content(page: &Page) -> Vec<u8> {
+    let mut content = Vec::new();
+    for mark in &page.marks {
+        match mark {
+            Mark::Rect {
+                x,
+                y,
+                width,
+                height,
+                colour,
+            } => content.extend_from_slice(
+                format!(
+                    "{:.3} {:.3} {:.3} rg {:.2} {:.2} {:.2} {:.2} re f\n",
+                    colour[0] as f32 / 255.0,
+                    colour[1] as f32 / 255.0,
+                    colour[2] as f32 / 255.0,
+                    x * POINTS_PER_MM,
+                    (page.height - y - height) * POINTS_PER_MM,
+                    width * POINTS_PER_MM,
+                    height * POINTS_PER_MM
+                )
+                .as_bytes(),
+            ),
+            Mark::Text {
+                x,
+                y,
+                size,
+                bold,
+                text,
+            } => {
+                content.extend_from_slice(
+                    format!(
+                        "0 g BT /{} {:.2} Tf {:.2} {:.2} Td (",
+                        if *bold { "F2" } else { "F1" },
+                        size * POINTS_PER_MM,
+                        x * POINTS_PER_MM,
+                        (page.height - y) * POINTS_PER_MM
+                    )
+                    .as_bytes(),
+                );
+                for byte in latin1(text) {
+                    if matches!(byte, b'\\' | b'(' | b')') {
+                        content.push(b'\\');
+                    }
+                    content.push(byte);
+                }
+                content.extend_from_slice(b") Tj ET\n");
+            }
+        }
+    }
+    content
+}
+
+//Both the PDF fonts and the bitmap fonts only cover Latin-1
+fn latin1(text: &str) -> Vec<u8> {
+    text.chars()
+        .map(|c| if (c as u32) < 256 { c as u8 } else { b'?' })
+        .collect()
+}
+
+struct Canvas {
+    width: u32,
+    height: u32,
+    pixels: Vec<u8>,
+}
+
+impl Canvas {
+    fn fill(&mut self, x0: i32, y0: i32, x1: i32, y1: i32, colour: [u8; 3]) {
+        for y in y0.max(0)..y1.min(self.height as i32) {
+            for x in x0.max(0)..x1.min(self.width as i32) {
+                let start = (y as usize * self.width as usize + x as usize) * 3;
+                self.pixels[start..start + 3].copy_from_slice(&colour);
+            }
+        }
+    }
+}
+
+//Draws the fixed size bitmap font scaled up by whole pixels, starting at an origin on the canvas
+struct ScaledText<'a> {
+    canvas: &'a mut Canvas,
+    origin: (i32, i32),
+    scale: i32,
+}
+
+impl OriginDimensions for ScaledText<'_> {
+    fn size(&self) -> Size {
+        Size::new(self.canvas.width, self.canvas.height)
+    }
+}
+
+impl DrawTarget for ScaledText<'_> {
+    type Color = BinaryColor;
+    type Error = std::convert::Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        for Pixel(point, colour) in pixels {
+            if colour.is_on() {
+                let x = self.origin.0 + point.x * self.scale;
+                let y = self.origin.1 + point.y * self.scale;
+                self.canvas
+                    .fill(x, y, x + self.scale, y + self.scale, [0, 0, 0]);
+            }
+        }
+        Ok(())
+    }
+}
+
+fn render_png(page: &Page) -> Result<Vec<u8>, String> {
+    let dots = |mm: f32| (mm * DOTS_PER_MM).round() as i32;
+    let mut canvas = Canvas {
+        width: dots(page.width) as u32,
+        height: dots(page.height) as u32,
+        pixels: vec![0xff; (dots(page.width) * dots(page.height) * 3) as usize],
+    };
+    for mark in &page.marks {
+        match mark {
+            Mark::Rect {
+                x,
+                y,
+                width,
+                height,
+                colour,
+            } => canvas.fill(
+                dots(*x),
+                dots(*y),
+                dots(x + width),
+                dots(y + height),
+                *colour,
+            ),
+            Mark::Text {
+                x,
+                y,
+                size,
+                bold,
+                text,
+            } => {
+                let font = if *bold { &FONT_7X13_BOLD } else { &FONT_7X13 };
+                let scale = (dots(*size) / font.character_size.height as i32).max(1);
+                let text: String = latin1(text).into_iter().map(char::from).collect();
+                let mut target = ScaledText {
+                    canvas: &mut canvas,
+                    origin: (dots(*x), dots(*y)),
+                    scale,
+                };
+                let style = MonoTextStyle::new(font, BinaryColor::On);
+                let Ok(_) = Text::with_baseline(&text, Point::zero(), style, Baseline::Alphabetic)
+                    .draw(&mut target);
+            }
+        }
+    }
+
+    let mut png = Vec::new();
+    let mut encoder = png::Encoder::new(&mut png, canvas.width, canvas.height);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_pixel_dims(Some(png::PixelDimensions {
+        xppu: (DOTS_PER_MM * 1000.0).round() as u32,
+        yppu: (DOTS_PER_MM * 1000.0).round() as u32,
+        unit: png::Unit::Meter,
+    }));
+    let mut writer = encoder.write_header().map_err(|e| e.to_string())?;
+    writer
+        .write_image_data(&canvas.pixels)
+        .map_err(|e| e.to_string())?;
+    writer.finish().map_err(|e| e.to_string())?;
+    Ok(png)
+}
@@ -0,0 +1,157 @@
+use crate::get_timestamp;
+use crate::print_add;
+use crate::watch_bambu;
+use clap::ValueEnum;
+use rusqlite::{Connection, OptionalExtension, Result};
+use uuid::Uuid;
+
+//Correlated query for where spool s was moved to last
+pub const LATEST_MOVE: &str =
+    "(SELECT m.location_name FROM spool_move m WHERE m.roll_id = s.roll_id
+    ORDER BY m.move_timestamp DESC, m.rowid DESC LIMIT 1)";
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum LocationKind {
+    Shelf,
+    Drybox,
+    /// Moving a spool here loads it into the printer of the same name
+    Printer,
+}
+
+impl LocationKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LocationKind::Shelf => "shelf",
+            LocationKind::Drybox => "drybox",
+            LocationKind::Printer => "printer",
+        }
+    }
+
+    fn parse(kind: &str) -> LocationKind {
+        match kind {
+            "drybox" => LocationKind::Drybox,
+            "printer" => LocationKind::Printer,
+            _ => LocationKind::Shelf,
+        }
+    }
+}
+
+//Adds a location, or changes the kind of the one with the same name
+pub fn add_location(conn: &Connection, name: &str, kind: LocationKind) -> Result<usize> {
+    conn.execute(
+        "INSERT INTO location (location_name, location_kind) VALUES (?1, ?2)
+            ON CONFLICT(location_name) DO UPDATE SET location_kind = excluded.location_kind",
+        (name.trim(), kind.as_str()),
+    )
+}
+
+//The move history keeps the name, so spools moved there before still say where they were
+pub fn remove_location(conn: &Connection, name: &str) -> Result<usize> {
+    conn.execute(
+        "DELETE FROM location WHERE location_name = ?1",
+        [name.trim()],
+    )
+}
+
+//Every location with its kind and how many spools are in it now
+pub fn list_locations(conn: &Connection) -> Result<Vec<(String, String, usize)>> {
+    let locations_query = format!(
+        "SELECT l.location_name, l.location_kind,
+                (SELECT count(*) FROM spool s WHERE {} = l.location_name)
+            FROM location l ORDER BY l.location_name",
+        LATEST_MOVE
+    );
+    let mut stmt = conn.prepare(&locations_query)?;
+    let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?;
+    rows.collect()
+}
+
+//Looks up a location by name, printers count even when they were never added as a location
+pub fn find_location(conn: &Connection, name: &str) -> Result<Option<(String, LocationKind)>> {
+    let location_query =
+        "SELECT location_name, location_kind FROM location WHERE location_name = ?1";
+    let location_rt = conn
+        .query_row(location_query, [name.trim()], |row| {
+            Ok((row.get(0)?, LocationKind::parse(&row.get::<_, String>(1)?)))
+        })
+        .optional()?;
+    if location_rt.is_some() {
+        return Ok(location_rt);
+    }
+    let printer_query = "SELECT printer_name FROM printer WHERE printer_name = ?1";
+    conn.query_row(printer_query, [name.trim()], |row| {
+        Ok((row.get(0)?, LocationKind::Printer))
+    })
+    .optional()
+}
+
+pub fn current_location(conn: &Connection, roll_id: Uuid) -> Result<Option<String>> {
+    let current_query = format!("SELECT {} FROM spool s WHERE s.roll_id = ?1", LATEST_MOVE);
+    conn.query_row(&current_query, [roll_id], |row| row.get(0))
+        .optional()
+        .map(Option::flatten)
+}
+
+//Adds to the history unless the spool is already there, None is a spool taken out
+//of a printer without saying where it went
+pub fn record_move(conn: &Connection, roll_id: Uuid, location: Option<&str>) -> Result<bool> {
+    if current_location(conn, roll_id)?.as_deref() == location {
+        return Ok(false);
+    }
+    conn.execute(
+        "INSERT INTO spool_move (roll_id, location_name, move_timestamp) VALUES (?1, ?2, ?3)",
+        (roll_id, location, get_timestamp()),
+    )?;
+    Ok(true)
+}
+
+//Where a printer's tray is recorded as, e.g. x1c B3
+pub fn tray_location(printer: &str, tray: u32) -> String {
+    format!("{} {}", printer, watch_bambu::tray_name(tray))
+}
+
+//Puts a spool in a location, which loads it when the location is a printer and takes
+//it out of any printer when it is not. Returns where the spool ended up
+pub fn move_spool(
+    conn: &Connection,
+    roll_id: Uuid,
+    location: &str,
+    tray: Option<u32>,
+) -> Result<String, String> {
+    let (name, kind) = find_location(conn, location)
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| format!("No location called {}, add it with location add", location))?;
+    match (kind, tray) {
+        (LocationKind::Printer, Some(tray)) => {
+            print_add::load_tray(conn, &name, tray, roll_id).map_err(|e| e.to_string())?;
+            Ok(tray_location(&name, tray))
+        }
+        (LocationKind::Printer, None) => {
+            print_add::load_spool(conn, &name, roll_id).map_err(|e| e.to_string())?;
+            Ok(name)
+        }
+        (_, Some(_)) => Err(format!("{} is not a printer, it has no trays", name)),
+        (_, None) => {
+            let tx = conn.unchecked_transaction().map_err(|e| e.to_string())?;
+            tx.execute(
+                "UPDATE printer SET roll_id = NULL WHERE roll_id = ?1",
+                [roll_id],
+            )
+            .map_err(|e| e.to_string())?;
+            tx.execute("DELETE FROM ams_tray WHERE roll_id = ?1", [roll_id])
+                .map_err(|e| e.to_string())?;
+            record_move(&tx, roll_id, Some(&name)).map_err(|e| e.to_string())?;
+            tx.commit().map_err(|e| e.to_string())?;
+            Ok(name)
+        }
+    }
+}
+
+//Where a spool has been, oldest first
+pub fn spool_moves(conn: &Connection, roll_id: Uuid) -> Result<Vec<(Option<String>, i64)>> {
+    let moves_query = "SELECT location_name, move_timestamp FROM spool_move WHERE roll_id = ?1
+            ORDER BY move_timestamp, rowid";
+    let mut stmt = conn.prepare(moves_query)?;
+    let rows = stmt.query_map([roll_id], |row| Ok((row.get(0)?, row.get(1)?)))?;
+    rows.collect()
+}
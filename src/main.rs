@@ -1,13 +1,51 @@
-use clap::{Parser, Subcommand};
+use chrono::{DateTime, NaiveDate, NaiveDateTime};
+use clap::{ArgGroup, CommandFactory, Parser, Subcommand, ValueEnum};
+use clap_complete::env::Shells;
+use clap_complete::{ArgValueCandidates, CompleteEnv, Shell};
 use rusqlite::Connection;
-use std::time::{SystemTime, UNIX_EPOCH};
 use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use uuid::Uuid;
+mod audit;
+mod backup;
+mod catalogue;
+mod chart;
+mod completion;
+mod config;
+mod csv_io;
+mod drying;
+mod label;
+mod locations;
+mod nfc;
 mod print_add;
 mod print_stats;
 mod print_structs;
+mod projects;
+mod report;
+mod scan;
+mod server;
+mod spoolman;
+mod sync_moonraker;
+mod sync_octoprint;
+mod sync_state;
+mod tags;
 mod tbl_creation;
+mod tui;
+mod units;
+mod watch_bambu;
+use audit::{AuditAction, AuditKind};
+use config::Config;
+use csv_io::Table;
+use label::{LabelFormat, LabelSheet, LabelSize};
+use locations::LocationKind;
+use nfc::TagFormat;
+use print_stats::Period;
 use print_structs::*;
+use scan::ScanAction;
+use spoolman::SpoolmanFormat;
+use units::{LengthUnit, UnitSystem, Units, WeightUnit};
 
 /// CLI to keep track and know levels of a 3D printers filament levels
 #[derive(Parser, Debug)]
@@ -16,154 +54,2377 @@ struct Args {
     #[command(subcommand)]
     cmd: Commands,
 
-    /// The weight of the spool or the print.
-    #[arg(short, long)]
-    weight: Option<f32>,
+    /// Deprecated, give the weight after the command instead
+    #[arg(short, long, hide = true, value_parser = units::parse_positive_weight)]
+    weight: Option<Weight>,
 
-    /// The lenght of the spool or the print.
-    #[arg(short, long)]
-    length: Option<f32>,
+    /// Deprecated, give the length after the command instead
+    #[arg(short, long, hide = true, value_parser = units::parse_positive_length)]
+    length: Option<Length>,
+
+    /// Show weights and lengths in metric or imperial units
+    #[arg(long, global = true, value_enum)]
+    units: Option<UnitSystem>,
+
+    /// Show weights in this unit instead of the unit system's
+    #[arg(long, global = true, value_enum)]
+    weight_unit: Option<WeightUnit>,
+
+    /// Show lengths in this unit instead of the unit system's
+    #[arg(long, global = true, value_enum)]
+    length_unit: Option<LengthUnit>,
+
+    /// Config file to use instead of the one in the config directory
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
+
+    /// Database file to use instead of the configured one
+    #[arg(long, global = true)]
+    database: Option<PathBuf>,
+}
+
+//The weight and length of a spool or a print
+#[derive(clap::Args, Debug, Clone, Default)]
+struct Quantity {
+    /// The weight, in grams unless a unit is given, e.g. 3.2oz or 1kg
+    #[arg(short, long, value_parser = units::parse_positive_weight)]
+    weight: Option<Weight>,
+
+    /// The length, in metres unless a unit is given, e.g. 100ft
+    #[arg(short, long, value_parser = units::parse_positive_length)]
+    length: Option<Length>,
+}
+
+impl Quantity {
+    fn is_empty(&self) -> bool {
+        self.weight.is_none() && self.length.is_none()
+    }
 }
 
 #[derive(Subcommand, Debug, Clone)]
 enum Commands {
     CreateSpool {
-        /// Spools Name
-        spool_name: String,
+        /// Spools Name, defaults to the product's name
+        #[arg(required_unless_present = "product")]
+        spool_name: Option<String>,
+
+        #[command(flatten)]
+        quantity: Quantity,
+
+        /// Product from the catalogue to fill in the details from
+        #[arg(long)]
+        product: Option<String>,
+
+        /// Material of the filament, e.g. PLA or PETG
+        #[arg(short, long)]
+        material: Option<String>,
+
+        /// What the whole spool cost
+        #[arg(short, long, value_parser = units::parse_positive::<f32>)]
+        price: Option<f32>,
+
+        /// Filament diameter in millimetres, when it is not 1.75
+        #[arg(long, value_parser = units::parse_diameter)]
+        diameter: Option<f32>,
+
+        /// Weight of the empty spool, for weighing it later
+        #[arg(long, value_parser = units::parse_weight)]
+        tare: Option<Weight>,
+
+        /// Colour name, e.g. Black
+        #[arg(short, long, visible_alias = "color")]
+        colour: Option<String>,
+
+        /// Colour as a hex code, e.g. #1A1A1A
+        #[arg(long, visible_alias = "color-hex", value_parser = parse_colour_hex)]
+        colour_hex: Option<String>,
+
+        /// Tag the spool, e.g. customer:acme, can be given more than once
+        #[arg(long, value_parser = tags::parse_tag)]
+        tag: Vec<String>,
+
+        /// Free form note about the spool
+        #[arg(long)]
+        note: Option<String>,
     },
     AddPrint {
+        #[arg(value_parser = units::parse_positive::<i32>)]
         print_time: i32,
+
+        #[command(flatten)]
+        quantity: Quantity,
+
+        /// Name of the job
+        #[arg(short, long)]
+        name: Option<String>,
+
+        /// The print failed part way through
+        #[arg(long)]
+        failed: bool,
+
+        /// Printer the job ran on, its loaded spool is used instead of the newest spool
+        #[arg(short, long, add = ArgValueCandidates::new(completion::printer_names))]
+        printer: Option<String>,
+
+        /// Tag the print, e.g. project:jig, can be given more than once
+        #[arg(long, value_parser = tags::parse_tag)]
+        tag: Vec<String>,
+
+        /// Free form note about the print
+        #[arg(long)]
+        note: Option<String>,
+
+        /// Project or customer to charge the print to
+        #[arg(long, add = ArgValueCandidates::new(completion::project_names))]
+        project: Option<String>,
+    },
+    CheckRemaining {
+        /// Printer to check the loaded spool of
+        #[arg(short, long, add = ArgValueCandidates::new(completion::printer_names))]
+        printer: Option<String>,
     },
-    CheckRemaining,
     LifetimeStats,
+    /// Load a spool into a printer
+    LoadSpool {
+        /// Spool name, id or scanned label
+        #[arg(add = ArgValueCandidates::new(completion::spool_names))]
+        spool: String,
+
+        /// Printer to load it into, defaults to the configured printer
+        #[arg(short, long, add = ArgValueCandidates::new(completion::printer_names))]
+        printer: Option<String>,
+
+        /// AMS tray to load it into, A1 to D4 or Ext
+        #[arg(short, long, value_parser = watch_bambu::parse_tray)]
+        tray: Option<u32>,
+    },
+    /// Correct what is left on a spool by weighing it
+    Weigh {
+        /// Spool name, id or scanned label
+        #[arg(add = ArgValueCandidates::new(completion::spool_names))]
+        spool: String,
+
+        /// What the scale shows, in grams unless a unit is given. The empty spool's weight is
+        /// taken off when it is known
+        #[arg(value_parser = units::parse_weight)]
+        scale_weight: Weight,
+    },
+    /// Put a spool in a shelf, drybox or printer
+    Move {
+        /// Spool name, id or scanned label
+        #[arg(add = ArgValueCandidates::new(completion::spool_names))]
+        spool: String,
+
+        /// Location to move it to
+        location: String,
+
+        /// AMS tray when the location is a printer, A1 to D4 or Ext
+        #[arg(short, long, value_parser = watch_bambu::parse_tray)]
+        tray: Option<u32>,
+    },
+    /// Manage where spools are kept
+    Location {
+        #[command(subcommand)]
+        action: LocationAction,
+    },
+    /// Record when spools were opened and dried
+    Drying {
+        #[command(subcommand)]
+        action: DryingAction,
+    },
+    /// Read spool labels from a barcode scanner, one per line, and act on each
+    Scan {
+        #[arg(short, long, value_enum, default_value = "show")]
+        action: ScanAction,
+
+        /// Printer to load scanned spools into
+        #[arg(short, long, required_if_eq("action", "load"), add = ArgValueCandidates::new(completion::printer_names))]
+        printer: Option<String>,
+
+        /// AMS tray to load scanned spools into, A1 to D4 or Ext
+        #[arg(short, long, value_parser = watch_bambu::parse_tray)]
+        tray: Option<u32>,
+    },
+    /// Take the spool out of a printer
+    UnloadSpool {
+        /// Printer to unload, defaults to the configured printer
+        #[arg(short, long, add = ArgValueCandidates::new(completion::printer_names))]
+        printer: Option<String>,
+
+        /// AMS tray to empty, A1 to D4 or Ext
+        #[arg(short, long, value_parser = watch_bambu::parse_tray)]
+        tray: Option<u32>,
+    },
+    /// List every spool with what is left on it
+    ListSpools {
+        /// Only list spools with this tag, all of them when given more than once
+        #[arg(long, value_parser = tags::parse_tag)]
+        tag: Vec<String>,
+    },
+    /// List every print, oldest first
+    ListPrints {
+        /// Only list prints with this tag on them or their spool
+        #[arg(long, value_parser = tags::parse_tag)]
+        tag: Vec<String>,
+    },
+    /// Show who created, changed or deleted spools and prints and loaded spools, newest first
+    History {
+        /// Only changes to this spool and its prints, a deleted spool by its id
+        #[arg(long, add = ArgValueCandidates::new(completion::spool_names))]
+        spool: Option<String>,
+
+        /// Only changes made by this user
+        #[arg(long)]
+        user: Option<String>,
+
+        #[arg(long, value_enum)]
+        kind: Option<AuditKind>,
+
+        #[arg(long, value_enum)]
+        action: Option<AuditAction>,
+
+        /// First day to include (YYYY-MM-DD)
+        #[arg(long, value_parser = parse_date)]
+        from: Option<i64>,
+
+        /// Last day to include (YYYY-MM-DD)
+        #[arg(long, value_parser = parse_date)]
+        to: Option<i64>,
+
+        /// Most changes to show
+        #[arg(short = 'n', long, default_value_t = 50)]
+        limit: u32,
+    },
+    /// Tag spools and prints and see the filament used per tag
+    Tag {
+        #[command(subcommand)]
+        action: TagAction,
+    },
+    /// Show, write or clear the note on a spool or print
+    Note {
+        #[command(subcommand)]
+        target: NoteTarget,
+    },
+    /// Pull finished jobs from a printer's own print history
+    Sync {
+        #[command(subcommand)]
+        source: SyncSource,
+    },
+    /// Full screen dashboard of spools, prints and totals
+    Tui,
+    /// Serve a JSON API for spools, prints and statistics
+    Serve {
+        /// Address to listen on
+        #[arg(long, default_value = "127.0.0.1:8080")]
+        bind: String,
+
+        /// Require this bearer token on every request
+        #[arg(long)]
+        token: Option<String>,
+    },
+    /// Record prints as a printer reports them, running until stopped
+    Watch {
+        #[command(subcommand)]
+        source: WatchSource,
+    },
+    /// Manage the catalogue of filament products spools are made from
+    Product {
+        #[command(subcommand)]
+        action: ProductAction,
+    },
+    /// Read spools from NFC tag dumps or write tags for our spools
+    Nfc {
+        #[command(subcommand)]
+        action: NfcAction,
+    },
+    /// Draw charts of the filament usage history
+    Chart {
+        #[command(subcommand)]
+        kind: ChartKind,
+
+        /// Only use plain ASCII characters
+        #[arg(long)]
+        ascii: bool,
+    },
+    /// Manage the projects and customers prints are charged to
+    Project {
+        #[command(subcommand)]
+        action: ProjectAction,
+    },
+    /// Total the filament, time and cost per project
+    ProjectReport {
+        /// First day to include (YYYY-MM-DD)
+        #[arg(long, value_parser = parse_date)]
+        from: Option<i64>,
+
+        /// Last day to include (YYYY-MM-DD)
+        #[arg(long, value_parser = parse_date)]
+        to: Option<i64>,
+
+        /// Write the report to a CSV file instead of the terminal
+        #[arg(long)]
+        csv: Option<PathBuf>,
+    },
+    /// Write a report of the inventory and usage
+    Report {
+        /// HTML file to write the report to
+        #[arg(long)]
+        html: PathBuf,
+
+        /// Only report costs, jobs and failures for this month (YYYY-MM)
+        #[arg(long, value_parser = report::parse_month)]
+        month: Option<String>,
+    },
+    /// Make printable labels with a QR code for spools
+    #[command(group(ArgGroup::new("spools").required(true).args(["spool", "all"])))]
+    Label {
+        /// Spool names, ids or scanned labels
+        #[arg(add = ArgValueCandidates::new(completion::spool_names))]
+        spool: Vec<String>,
+
+        /// Label every spool
+        #[arg(long)]
+        all: bool,
+
+        #[arg(short, long, value_enum, default_value = "pdf")]
+        format: LabelFormat,
+
+        /// Label printer to size single labels for
+        #[arg(long, value_enum, default_value = "dymo")]
+        size: LabelSize,
+
+        /// Lay the labels out on sheets for an office printer instead
+        #[arg(long, value_enum, conflicts_with = "size")]
+        sheet: Option<LabelSheet>,
+
+        /// File to write to, numbered when SVG or PNG need more than one
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Export a table or the inventory for Spoolman
+    #[command(group(ArgGroup::new("format").required(true).args(["csv", "spoolman"])))]
+    Export {
+        /// Table to write out as CSV
+        #[arg(long, value_enum)]
+        csv: Option<Table>,
+
+        /// Write the spools in Spoolman's export format
+        #[arg(long, value_enum)]
+        spoolman: Option<SpoolmanFormat>,
+
+        /// File to write to, defaults to the terminal
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Import rows into a table or spools from Spoolman
+    #[command(group(ArgGroup::new("format").required(true).args(["csv", "spoolman"])))]
+    Import {
+        /// Table to read CSV rows into
+        #[arg(long, value_enum)]
+        csv: Option<Table>,
+
+        /// Read spools from a Spoolman export
+        #[arg(long, value_enum)]
+        spoolman: Option<SpoolmanFormat>,
+
+        /// File to read the rows from
+        file: PathBuf,
+
+        /// Read a column into a field, given as column=field
+        #[arg(long = "map", conflicts_with = "spoolman")]
+        mappings: Vec<String>,
+
+        /// Check the rows and show what would be imported without saving
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Copy the database to a file while it is in use
+    Backup {
+        /// File to write the backup to
+        path: PathBuf,
+    },
+    /// Replace the database with a backup
+    Restore {
+        /// Backup file to restore
+        path: PathBuf,
+    },
+    /// Compact the database file
+    Vacuum,
+    /// Check the database for damage
+    IntegrityCheck,
+    /// Show or change the defaults in the config file
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+    /// Print the script that sets up completion in a shell, spool, printer and
+    /// project names are completed from the database
+    Completions {
+        shell: Shell,
+    },
+    /// Write a man page for remainder and each of its commands
+    ManPages {
+        /// Directory to write the pages to
+        dir: PathBuf,
+    },
+}
+
+#[derive(Subcommand, Debug, Clone)]
+enum ConfigAction {
+    /// Show the value of a setting
+    Get { key: String },
+    /// Save a setting in the config file
+    Set { key: String, value: String },
+    /// Take a setting out of the config file
+    Unset { key: String },
+    /// Show every setting and where its value comes from
+    List,
+}
+
+impl Commands {
+    //The weight and length the command takes, so the old -w and -l before it still work
+    fn quantity_mut(&mut self) -> Option<&mut Quantity> {
+        match self {
+            Commands::CreateSpool { quantity, .. } | Commands::AddPrint { quantity, .. } => {
+                Some(quantity)
+            }
+            Commands::Nfc {
+                action: NfcAction::Read { quantity, .. },
+            } => Some(quantity),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Subcommand, Debug, Clone)]
+enum SyncSource {
+    /// Klipper printers through Moonraker's job history
+    Moonraker {
+        /// Address of Moonraker, e.g. http://voron.local:7125
+        #[arg(long)]
+        url: String,
+
+        /// Printer whose loaded spool the jobs used, defaults to the newest spool
+        #[arg(short, long, add = ArgValueCandidates::new(completion::printer_names))]
+        printer: Option<String>,
+    },
+    /// OctoPrint printers through the print history kept for each file
+    Octoprint {
+        /// Address of OctoPrint, e.g. http://octopi.local
+        #[arg(long)]
+        url: String,
+
+        /// Application key from OctoPrint's settings
+        #[arg(long)]
+        api_key: String,
+
+        /// Printer whose loaded spool the prints used, defaults to the newest spool
+        #[arg(short, long, add = ArgValueCandidates::new(completion::printer_names))]
+        printer: Option<String>,
+    },
+}
+
+#[derive(Subcommand, Debug, Clone)]
+enum LocationAction {
+    /// Add a location, or change the kind of an existing one
+    Add {
+        name: String,
+
+        #[arg(short, long, value_enum, default_value = "shelf")]
+        kind: LocationKind,
+    },
+    /// List the locations and how many spools are in each
+    List,
+    /// Remove a location, the spools' history keeps its name
+    Remove { name: String },
+    /// Show everywhere a spool has been
+    History {
+        /// Spool name, id or scanned label
+        #[arg(add = ArgValueCandidates::new(completion::spool_names))]
+        spool: String,
+    },
+}
+
+#[derive(Subcommand, Debug, Clone)]
+enum ProjectAction {
+    /// Add a project, or change the customer of an existing one
+    Add {
+        name: String,
+
+        /// Customer the project is for
+        #[arg(short, long)]
+        customer: Option<String>,
+    },
+    /// List the projects and how many prints each has
+    List,
+    /// Remove a project, its prints keep the name
+    Remove {
+        #[arg(add = ArgValueCandidates::new(completion::project_names))]
+        name: String,
+    },
+    /// Charge a print to a project
+    Assign {
+        /// Print name or id, the newest print wins when names repeat
+        print: String,
+
+        /// Project to charge it to, left out to take the print out of its project
+        #[arg(add = ArgValueCandidates::new(completion::project_names))]
+        project: Option<String>,
+    },
+}
+
+#[derive(Subcommand, Debug, Clone)]
+enum TagAction {
+    /// Add tags to a spool, or take them off with --remove
+    Spool {
+        /// Spool name, id or scanned label
+        #[arg(add = ArgValueCandidates::new(completion::spool_names))]
+        spool: String,
+
+        #[arg(required = true, value_parser = tags::parse_tag)]
+        tags: Vec<String>,
+
+        #[arg(long)]
+        remove: bool,
+    },
+    /// Add tags to a print, or take them off with --remove
+    Print {
+        /// Print name or id, the newest print wins when names repeat
+        print: String,
+
+        #[arg(required = true, value_parser = tags::parse_tag)]
+        tags: Vec<String>,
+
+        #[arg(long)]
+        remove: bool,
+    },
+    /// List every tag with how many spools and prints have it
+    List,
+    /// Filament and time used per tag, counting prints from tagged spools too
+    Stats {
+        /// Only tags starting with this, e.g. project:
+        #[arg(long)]
+        prefix: Option<String>,
+    },
+}
+
+#[derive(Subcommand, Debug, Clone)]
+enum NoteTarget {
+    Spool {
+        /// Spool name, id or scanned label
+        #[arg(add = ArgValueCandidates::new(completion::spool_names))]
+        spool: String,
+
+        /// New note, the current one is shown when left out
+        note: Option<String>,
+
+        #[arg(long, conflicts_with = "note")]
+        clear: bool,
+    },
+    Print {
+        /// Print name or id, the newest print wins when names repeat
+        print: String,
+
+        /// New note, the current one is shown when left out
+        note: Option<String>,
+
+        #[arg(long, conflicts_with = "note")]
+        clear: bool,
+    },
+}
+
+#[derive(Subcommand, Debug, Clone)]
+enum DryingAction {
+    /// Record a spool coming out of the dryer
+    Log {
+        /// Spool name, id or scanned label
+        #[arg(add = ArgValueCandidates::new(completion::spool_names))]
+        spool: String,
+
+        /// Temperature it was dried at in degrees Celsius
+        #[arg(short, long, value_parser = units::parse_positive::<f32>)]
+        temperature: f32,
+
+        /// How long it was dried for
+        #[arg(long, value_parser = units::parse_positive::<f32>)]
+        hours: f32,
+
+        /// When it came out of the dryer (YYYY-MM-DD), defaults to now
+        #[arg(long, value_parser = parse_date)]
+        date: Option<i64>,
+    },
+    /// Record when a spool was taken out of its sealed bag
+    Opened {
+        /// Spool name, id or scanned label
+        #[arg(add = ArgValueCandidates::new(completion::spool_names))]
+        spool: String,
+
+        /// When it was opened (YYYY-MM-DD), defaults to now
+        #[arg(long, value_parser = parse_date)]
+        date: Option<i64>,
+    },
+    /// Show when a spool was opened and every time it was dried
+    History {
+        /// Spool name, id or scanned label
+        #[arg(add = ArgValueCandidates::new(completion::spool_names))]
+        spool: String,
+    },
+    /// Set how many days a material can be open before it needs drying, or list them
+    Threshold {
+        #[arg(requires = "days")]
+        material: Option<String>,
+
+        #[arg(value_parser = units::parse_positive::<f32>)]
+        days: Option<f32>,
+    },
+}
+
+#[derive(Subcommand, Debug, Clone)]
+enum ProductAction {
+    /// Add a product, or replace the one with the same name
+    Add {
+        /// Name to pick the product by, defaults to the vendor, material and colour
+        name: Option<String>,
+
+        #[arg(long)]
+        vendor: Option<String>,
+
+        /// Material of the filament, e.g. PLA or PETG
+        #[arg(short, long)]
+        material: Option<String>,
+
+        /// Colour name, e.g. Black
+        #[arg(short, long, visible_alias = "color")]
+        colour: Option<String>,
+
+        /// Colour as a hex code, e.g. #1A1A1A
+        #[arg(long, visible_alias = "color-hex", value_parser = parse_colour_hex)]
+        colour_hex: Option<String>,
+
+        /// Filament diameter in millimetres
+        #[arg(long, value_parser = units::parse_diameter)]
+        diameter: Option<f32>,
+
+        /// Density in grams per cubic centimetre, e.g. 1.24 for PLA
+        #[arg(long, value_parser = units::parse_positive::<f32>)]
+        density: Option<f32>,
+
+        /// Filament on a new spool, in grams unless a unit is given
+        #[arg(long, value_parser = units::parse_weight)]
+        net_weight: Option<Weight>,
+
+        /// Weight of the empty spool
+        #[arg(long, value_parser = units::parse_weight)]
+        tare: Option<Weight>,
+
+        /// What a spool costs
+        #[arg(short, long, value_parser = units::parse_positive::<f32>)]
+        price: Option<f32>,
+    },
+    /// List the products in the catalogue
+    List,
+    /// Take a product out of the catalogue
+    Remove { name: String },
+    /// Add or replace products from a JSON file
+    Import { file: PathBuf },
+}
+
+#[derive(Subcommand, Debug, Clone)]
+enum NfcAction {
+    /// Create the spool a tag dump describes, or find it if it was read before
+    Read {
+        /// OpenSpool JSON, a Proxmark JSON or binary dump, or a Flipper .nfc file
+        file: PathBuf,
+
+        /// Name for the new spool instead of the brand and material on the tag
+        #[arg(short, long)]
+        name: Option<String>,
+
+        /// Used when the tag does not say
+        #[command(flatten)]
+        quantity: Quantity,
+
+        /// Only show what is on the tag
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Write an OpenSpool tag payload for a spool
+    Write {
+        /// Spool name, id or scanned label
+        #[arg(add = ArgValueCandidates::new(completion::spool_names))]
+        spool: String,
+
+        #[arg(short, long, value_enum, default_value = "json")]
+        format: TagFormat,
+
+        /// File to write the payload to
+        #[arg(short, long)]
+        output: PathBuf,
+    },
+}
+
+#[derive(Subcommand, Debug, Clone)]
+enum WatchSource {
+    /// Bambu Lab printers in LAN mode.
+    ///
+    /// The printer's TLS certificate is pinned by serial the first time it is watched and
+    /// any other certificate is refused after that. Nothing authenticates the printer on
+    /// that first connection, so make it on a network you trust
+    Bambu {
+        /// Address of the printer
+        #[arg(long)]
+        host: String,
+
+        /// Serial number of the printer
+        #[arg(long)]
+        serial: String,
+
+        /// LAN access code from the printer's network settings
+        #[arg(long)]
+        access_code: String,
+
+        /// Printer whose trays the spools were loaded into
+        #[arg(short, long, add = ArgValueCandidates::new(completion::printer_names))]
+        printer: String,
+
+        #[arg(long, default_value_t = 8883)]
+        port: u16,
+
+        /// Connect without TLS, for a local broker bridging the printer
+        #[arg(long)]
+        plain: bool,
+
+        /// Forget the pinned certificate and pin the one the printer shows now,
+        /// for after a firmware update replaced it
+        #[arg(long, conflicts_with = "plain")]
+        trust_new_certificate: bool,
+    },
+}
+
+#[derive(Subcommand, Debug, Clone)]
+enum ChartKind {
+    /// Filament used per period
+    Usage {
+        #[arg(short, long, value_enum, default_value_t = Period::Month)]
+        period: Period,
+    },
+    /// Remaining filament on a spool after each print
+    Burndown {
+        /// Spool name, id or scanned label, defaults to the current spool
+        #[arg(add = ArgValueCandidates::new(completion::spool_names))]
+        spool: Option<String>,
+    },
+    /// Share of the filament used per material
+    Materials,
 }
 
 fn main() {
-    let db_path = match env::home_dir() {
-        Some(mut path) => {
-            //println!("Your home directory, probably: {}", path.display());
-            path.push(".remainder/3d_print_db.db");
-            path
+    //Answers the shell when it runs remainder to complete a command line
+    CompleteEnv::with_factory(Args::command).complete();
+    let mut args = Args::parse();
+    match move_old_quantity(&mut args) {
+        Ok(true) => eprintln!(
+            "Warning: -w and -l before the command are deprecated, give them after it, e.g. remainder add-print 3600 -w 12"
+        ),
+        Ok(false) => {}
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+    let config_path = match args.config.clone().or_else(config::default_path) {
+        Some(path) => path,
+        None => {
+            eprintln!("Impossible to find the config directory, give a file with --config");
+            std::process::exit(1);
+        }
+    };
+    //The config file can be fixed without opening the database it points to
+    match &args.cmd {
+        Commands::Config { action } => {
+            config_command(action, &config_path);
+            return;
         }
+        Commands::Completions { shell } => {
+            completions_command(*shell);
+            return;
+        }
+        Commands::ManPages { dir } => {
+            man_pages_command(dir);
+            return;
+        }
+        _ => {}
+    }
+    let config = match Config::load(&config_path) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let db_path = match args
+        .database
+        .clone()
+        .or(config.database.clone())
+        .or_else(config::default_database)
+    {
+        Some(path) => path,
         None => {
             println!("Impossible to get your home dir!");
             panic!("Failed");
         }
     };
-    let db = Connection::open(db_path).unwrap();
+    let backup_dir = db_path.with_file_name("backups");
+    let mut db = Connection::open(&db_path).unwrap();
     //println!("Connection to database has been established");
-    //Create test spool
-    tbl_creation::create_new_spool_tbl(&db).unwrap();
-    tbl_creation::create_new_filament_tbl(&db).unwrap();
-    let args = Args::parse();
+    if tbl_creation::needs_migration(&db).unwrap() {
+        backup_before(&db, &backup_dir, "migration");
+    }
+    tbl_creation::setup_database(&db).unwrap();
+    let user = config
+        .user
+        .clone()
+        .or_else(audit::os_user)
+        .unwrap_or_else(|| String::from("unknown"));
+    audit::start(&db, &user).unwrap();
+    let units = Units::new(
+        args.units.or(config.units).unwrap_or_default(),
+        args.weight_unit.or(config.weight_unit),
+        args.length_unit.or(config.length_unit),
+    );
 
     match args.cmd {
-        Commands::AddPrint { print_time } => {
+        Commands::AddPrint {
+            print_time,
+            quantity,
+            name,
+            failed,
+            printer,
+            tag,
+            note,
+            project,
+        } => {
+            if quantity.is_empty() {
+                eprintln!("Give the print's weight with -w or its length with -l");
+                std::process::exit(1);
+            }
+            let project = project.map(|project| known_project(&db, &project));
             println!("Adding New Print: Print Time {}", print_time);
+            let status = if failed { PRINT_FAILED } else { PRINT_SUCCESS };
+            let printer = printer.or_else(|| config.printer.clone());
+            let loaded = current_spool(&db, printer.as_deref());
+            warn_if_exposed(&db, loaded.roll_id);
             let mut new_print = Filament {
                 print_id: Some(Uuid::new_v4()),
-                print_weight: args.weight,
-                print_length: args.length,
+                print_weight: quantity.weight,
+                print_length: quantity.length,
                 print_time: Some(print_time),
-                roll_id: None,
+                roll_id: Some(loaded.roll_id),
+                print_name: name,
+                print_status: Some(String::from(status)),
+                print_notes: note,
+                print_project: project,
+                ..Default::default()
             };
+            if let Err(e) = new_print.validate() {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
 
             let print_rt = print_add::add_new_print(&db, &mut new_print).unwrap();
             if print_rt != 1 {
                 panic!("Didnt Successfully Create Print");
             }
+            tags::tag_print(&db, new_print.print_id.unwrap(), &tag).unwrap();
+            println!("New print created");
+            let (weight, _) = print_stats::remaining_on_spool(&db, loaded.roll_id);
+            warn_if_low(weight, &config, &units);
         }
-        Commands::CreateSpool { spool_name } => {
-            println!("Creating New spool: {}", spool_name);
-            let mut new_spool = Spool {
-                roll_id: Some(Uuid::new_v4()),
-                roll_name: Some(spool_name),
-                roll_weight: args.weight,
-                roll_length: args.length,
-                timestamp: Some(get_timestamp()),
+        Commands::CreateSpool {
+            spool_name,
+            quantity,
+            product,
+            material,
+            price,
+            diameter,
+            tare,
+            colour,
+            colour_hex,
+            tag,
+            note,
+        } => {
+            let mut new_spool = match product {
+                Some(product) => match catalogue::find_product(&db, &product).unwrap() {
+                    Some(product) => product.new_spool(),
+                    None => {
+                        eprintln!("No product called {} in the catalogue", product);
+                        std::process::exit(1);
+                    }
+                },
+                None if quantity.is_empty() => {
+                    eprintln!("Give the spool's weight with -w or its length with -l");
+                    std::process::exit(1);
+                }
+                None => Spool {
+                    roll_id: Some(Uuid::new_v4()),
+                    timestamp: Some(get_timestamp()),
+                    roll_material: config.material.clone(),
+                    roll_diameter: config.diameter,
+                    ..Default::default()
+                },
             };
+            //Anything given on the command line wins over the product's details
+            if !quantity.is_empty() {
+                new_spool.roll_weight = quantity.weight;
+                new_spool.roll_length = quantity.length;
+            }
+            new_spool.roll_name = spool_name.or(new_spool.roll_name);
+            new_spool.roll_material = material.or(new_spool.roll_material);
+            new_spool.roll_price = price.or(new_spool.roll_price);
+            new_spool.roll_diameter = diameter
+                .or(new_spool.roll_diameter)
+                .filter(|diameter| *diameter != FILAMENT_DIAMETER);
+            new_spool.roll_tare = tare.or(new_spool.roll_tare);
+            new_spool.roll_colour = colour.or(new_spool.roll_colour);
+            new_spool.roll_colour_hex = colour_hex.or(new_spool.roll_colour_hex);
+            new_spool.roll_notes = note;
+            if let Err(e) = new_spool.validate() {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+            println!(
+                "Creating New spool: {}",
+                new_spool.roll_name.as_deref().unwrap_or_default()
+            );
             let spool_rt = print_add::open_new_spool(&db, &mut new_spool).unwrap();
             if spool_rt != 1 {
                 panic!("Didnt Successfully Create Spool");
             }
+            tags::tag_spool(&db, new_spool.roll_id.unwrap(), &tag).unwrap();
+            println!("New spool created");
         }
-        Commands::CheckRemaining => {
+        Commands::CheckRemaining { printer } => {
             println!("Checking Remaining levels of Printer");
-            let (weight, length) = print_stats::check_remaining(&db);
-            println!("Estimated REMAINING Weight: {} gram", weight);
-            println!("Estimated REMAINING Lenght: {} meters", length);
+            let (weight, length) = match printer.or_else(|| config.printer.clone()) {
+                Some(printer) => {
+                    let loaded = current_spool(&db, Some(&printer));
+                    print_stats::remaining_on_spool(&db, loaded.roll_id)
+                }
+                None => print_stats::check_remaining(&db),
+            };
+            println!("Estimated REMAINING Weight: {}", units.weight(weight));
+            println!("Estimated REMAINING Lenght: {}", units.length(length));
+            warn_if_low(weight, &config, &units);
         }
         Commands::LifetimeStats => {
             println!("Lifetime Stats for printer:");
             let (total_weight, total_length, total_time) = print_stats::lifetime_statistics(&db);
-            println!("Total Amount of Fillament used: {} grams", total_weight);
-            println!("Total Length of Fillament used: {} meters", total_length);
-            let time_converted = total_time / 60 as i32;
+            println!(
+                "Total Amount of Fillament used: {}",
+                units.weight(total_weight)
+            );
+            println!(
+                "Total Length of Fillament used: {}",
+                units.length(total_length)
+            );
+            let time_converted = total_time / 60;
             println!("Total Printing Time: {} min", time_converted);
         }
-    }
-
-    let rt = db.close();
-    rt.unwrap();
-}
-
-//Function to get the current timestamp
-pub fn get_timestamp() -> i64 {
-    let start = SystemTime::now();
-    let timestamp_current = i64::try_from(
-        start
-            .duration_since(UNIX_EPOCH)
-            .expect("Time went backwards")
-            .as_secs(),
-    )
-    .unwrap();
-    timestamp_current
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[test]
-    fn test_spool_tbl_creation() {
-        let conn = Connection::open_in_memory().unwrap();
-        tbl_creation::create_new_spool_tbl(&conn).unwrap();
-        tbl_creation::create_new_spool_tbl(&conn).unwrap();
-    }
-
-    #[test]
-    fn test_filament_tbl_creation() {
-        let conn = Connection::open_in_memory().unwrap();
-        tbl_creation::create_new_filament_tbl(&conn).unwrap();
-        tbl_creation::create_new_filament_tbl(&conn).unwrap();
-    }
-
-    #[test]
-    fn test_spool_weight_1() {
-        let mut test_spool = Spool {
-            roll_id: Some(Uuid::new_v4()),
-            roll_name: Some(String::from("crealtivity")),
-            roll_weight: Some(1000.0),
-            roll_length: None,
-            timestamp: Some(get_timestamp()),
-        };
-
-        let ans = test_spool.get_weight();
-        assert_eq!(ans, 1000.0);
-    }
-
-    #[test]
-    fn test_spool_weight_2() {
-        let mut test_spool = Spool {
-            roll_id: Some(Uuid::new_v4()),
-            roll_name: Some(String::from("crealtivity")),
-            roll_weight: None,
-            roll_length: Some(330.0),
+        Commands::LoadSpool {
+            spool,
+            printer,
+            tray,
+        } => {
+            let printer = configured_printer(printer, &config);
+            let found = match print_add::find_spool(&db, &spool) {
+                Ok(found) => found,
+                Err(_) => {
+                    eprintln!("No spool called {}", spool);
+                    std::process::exit(1);
+                }
+            };
+            match tray {
+                Some(tray) => {
+                    print_add::load_tray(&db, &printer, tray, found.roll_id).unwrap();
+                    println!(
+                        "Loaded {} into {} {}",
+                        spool,
+                        printer,
+                        watch_bambu::tray_name(tray)
+                    );
+                }
+                None => {
+                    print_add::load_spool(&db, &printer, found.roll_id).unwrap();
+                    println!("Loaded {} into {}", spool, printer);
+                }
+            }
+            warn_if_exposed(&db, found.roll_id);
+        }
+        Commands::Weigh {
+            spool,
+            scale_weight,
+        } => {
+            let found = match print_add::find_spool(&db, &spool) {
+                Ok(found) => found,
+                Err(_) => {
+                    eprintln!("No spool called {}", spool);
+                    std::process::exit(1);
+                }
+            };
+            let weigh_in = print_add::weigh_spool(&db, found.roll_id, scale_weight).unwrap();
+            let (weight, _) = print_stats::remaining_on_spool(&db, found.roll_id);
+            println!(
+                "Adjusted {} by {}, {} left",
+                spool,
+                units.weight(-weigh_in.print_weight.unwrap()),
+                units.weight(weight)
+            );
+        }
+        Commands::Move {
+            spool,
+            location,
+            tray,
+        } => {
+            let found = match print_add::find_spool(&db, &spool) {
+                Ok(found) => found,
+                Err(_) => {
+                    eprintln!("No spool called {}", spool);
+                    std::process::exit(1);
+                }
+            };
+            match locations::move_spool(&db, found.roll_id, &location, tray) {
+                Ok(place) => println!("Moved {} to {}", spool, place),
+                Err(e) => {
+                    eprintln!("{}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Commands::Location { action } => match action {
+            LocationAction::Add { name, kind } => {
+                locations::add_location(&db, &name, kind).unwrap();
+                println!("Saved {} {}", kind.as_str(), name);
+            }
+            LocationAction::List => {
+                let places = locations::list_locations(&db).unwrap();
+                if places.is_empty() {
+                    println!("No locations yet, add one with location add");
+                }
+                for (name, kind, spools) in places {
+                    println!("{:<24} {:<8} {} spools", name, kind, spools);
+                }
+            }
+            LocationAction::Remove { name } => {
+                if locations::remove_location(&db, &name).unwrap() == 0 {
+                    eprintln!("No location called {}", name);
+                    std::process::exit(1);
+                }
+                println!("Removed location {}", name);
+            }
+            LocationAction::History { spool } => {
+                let found = match print_add::find_spool(&db, &spool) {
+                    Ok(found) => found,
+                    Err(_) => {
+                        eprintln!("No spool called {}", spool);
+                        std::process::exit(1);
+                    }
+                };
+                let moves = locations::spool_moves(&db, found.roll_id).unwrap();
+                if moves.is_empty() {
+                    println!("{} has not been moved", spool);
+                }
+                for (location, timestamp) in moves {
+                    println!(
+                        "{}  {}",
+                        format_timestamp(timestamp),
+                        location.as_deref().unwrap_or("taken out of the printer")
+                    );
+                }
+            }
+        },
+        Commands::Drying { action } => match action {
+            DryingAction::Log {
+                spool,
+                temperature,
+                hours,
+                date,
+            } => {
+                let found = match print_add::find_spool(&db, &spool) {
+                    Ok(found) => found,
+                    Err(_) => {
+                        eprintln!("No spool called {}", spool);
+                        std::process::exit(1);
+                    }
+                };
+                let session = drying::DryingSession {
+                    timestamp: date.unwrap_or_else(get_timestamp),
+                    temperature,
+                    minutes: (hours * 60.0).round() as i32,
+                };
+                drying::record_drying(&db, found.roll_id, &session).unwrap();
+                println!("Dried {} at {} C for {} hours", spool, temperature, hours);
+            }
+            DryingAction::Opened { spool, date } => {
+                let found = match print_add::find_spool(&db, &spool) {
+                    Ok(found) => found,
+                    Err(_) => {
+                        eprintln!("No spool called {}", spool);
+                        std::process::exit(1);
+                    }
+                };
+                let opened = date.unwrap_or_else(get_timestamp);
+                drying::set_opened(&db, found.roll_id, opened, true).unwrap();
+                println!("{} opened {}", spool, format_timestamp(opened));
+            }
+            DryingAction::History { spool } => {
+                let found = match print_add::find_spool(&db, &spool) {
+                    Ok(found) => found,
+                    Err(_) => {
+                        eprintln!("No spool called {}", spool);
+                        std::process::exit(1);
+                    }
+                };
+                match drying::opened(&db, found.roll_id).unwrap() {
+                    Some(opened) => println!("Opened {}", format_timestamp(opened)),
+                    None => println!("{} is still sealed", spool),
+                }
+                for session in drying::drying_sessions(&db, found.roll_id).unwrap() {
+                    println!(
+                        "Dried {}  {} C for {:.1} hours",
+                        format_timestamp(session.timestamp),
+                        session.temperature,
+                        session.minutes as f32 / 60.0
+                    );
+                }
+                warn_if_exposed(&db, found.roll_id);
+            }
+            DryingAction::Threshold { material, days } => match (material, days) {
+                (Some(material), Some(days)) => {
+                    drying::set_threshold(&db, &material, days).unwrap();
+                    println!("{} should be dried after {} days open", material, days);
+                }
+                _ => {
+                    for (material, days) in drying::list_thresholds(&db).unwrap() {
+                        println!("{:<12} {} days", material, days);
+                    }
+                }
+            },
+        },
+        Commands::Scan {
+            action,
+            printer,
+            tray,
+        } => {
+            let options = scan::ScanOptions {
+                action,
+                printer,
+                tray,
+                units,
+            };
+            eprintln!("Waiting for scans, press Ctrl-D to stop");
+            let stdin = std::io::stdin();
+            match scan::scan(&db, stdin.lock(), std::io::stdout(), &options) {
+                Ok(count) => eprintln!("Handled {} scans", count),
+                Err(e) => {
+                    eprintln!("Scanning stopped: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Commands::UnloadSpool { printer, tray } => {
+            let printer = configured_printer(printer, &config);
+            match tray {
+                Some(tray) => {
+                    print_add::unload_tray(&db, &printer, tray).unwrap();
+                    println!("Emptied {} {}", printer, watch_bambu::tray_name(tray));
+                }
+                None => {
+                    print_add::unload_spool(&db, &printer).unwrap();
+                    println!("Unloaded {}", printer);
+                }
+            }
+        }
+        Commands::ListSpools { tag } => {
+            let loaded = print_stats::loaded_printers(&db).unwrap();
+            let trays = print_stats::loaded_trays(&db).unwrap();
+            for spool in print_stats::spool_inventory(&db).unwrap() {
+                if !tag.iter().all(|tag| spool.tags.contains(tag)) {
+                    continue;
+                }
+                let mut printers: Vec<String> = loaded
+                    .iter()
+                    .filter(|(_, roll_id)| *roll_id == spool.roll_id)
+                    .map(|(printer, _)| printer.clone())
+                    .collect();
+                printers.extend(
+                    trays
+                        .iter()
+                        .filter(|(_, _, roll_id)| *roll_id == spool.roll_id)
+                        .map(|(printer, tray, _)| {
+                            format!("{} {}", printer, watch_bambu::tray_name(*tray))
+                        }),
+                );
+                let place = if !printers.is_empty() {
+                    format!("loaded in {}", printers.join(", "))
+                } else if let Some(location) = &spool.location {
+                    format!("in {}", location)
+                } else {
+                    String::new()
+                };
+                let tagged = if spool.tags.is_empty() {
+                    String::new()
+                } else {
+                    format!("  [{}]", spool.tags.join(" "))
+                };
+                println!(
+                    "{}  {:<24} {:<8} {:>10} left  {}{}",
+                    &spool.roll_id.simple().to_string()[..8],
+                    spool.roll_name,
+                    spool.roll_material.as_deref().unwrap_or("-"),
+                    units.weight(spool.remaining_weight()),
+                    place,
+                    tagged
+                );
+            }
+        }
+        Commands::History {
+            spool,
+            user,
+            kind,
+            action,
+            from,
+            to,
+            limit,
+        } => {
+            let roll_id = spool.map(|spool| match print_add::find_spool(&db, &spool) {
+                Ok(found) => found.roll_id,
+                Err(_) => match Uuid::parse_str(spool.trim()) {
+                    Ok(roll_id) => roll_id,
+                    Err(_) => {
+                        eprintln!("No spool called {}", spool);
+                        std::process::exit(1);
+                    }
+                },
+            });
+            let filter = audit::HistoryFilter {
+                roll_id,
+                user,
+                kind,
+                action,
+                from,
+                to,
+                limit,
+            };
+            let entries = audit::history(&db, &filter).unwrap();
+            if entries.is_empty() {
+                println!("No changes recorded");
+            }
+            for entry in entries {
+                let spool = match (entry.kind, entry.spool_name.as_deref()) {
+                    (AuditKind::Spool, _) | (_, None) => String::new(),
+                    (_, Some(spool)) => format!("  (spool {})", spool),
+                };
+                println!(
+                    "{}  {:<12} {} {} {}{}",
+                    format_timestamp(entry.timestamp),
+                    entry.user,
+                    entry.action.as_str(),
+                    entry.kind.as_str(),
+                    entry.subject(),
+                    spool
+                );
+                for change in entry.changes(&units) {
+                    println!("    {}", change);
+                }
+            }
+        }
+        Commands::ListPrints { tag } => {
+            let spools = print_stats::spool_inventory(&db).unwrap();
+            let print_tags = tags::print_tags(&db).unwrap();
+            for print in print_stats::list_prints(&db).unwrap() {
+                let spool = spools
+                    .iter()
+                    .find(|spool| Some(spool.roll_id) == print.roll_id);
+                //A print carries its spool's tags as well as its own
+                let mut tagged: Vec<String> = print
+                    .print_id
+                    .and_then(|print_id| print_tags.get(&print_id))
+                    .cloned()
+                    .unwrap_or_default();
+                for spool_tag in spool.map(|spool| &spool.tags).into_iter().flatten() {
+                    if !tagged.contains(spool_tag) {
+                        tagged.push(spool_tag.clone());
+                    }
+                }
+                if !tag.iter().all(|tag| tagged.contains(tag)) {
+                    continue;
+                }
+                println!(
+                    "{}  {}  {:<24} {:>10}  {:<9} {:<16}{}",
+                    &print.print_id.unwrap_or_default().simple().to_string()[..8],
+                    print
+                        .timestamp
+                        .map(format_timestamp)
+                        .unwrap_or_else(|| String::from("unknown")),
+                    print.print_name.as_deref().unwrap_or("-"),
+                    units.weight(print.print_weight.unwrap_or_default()),
+                    print.print_status.as_deref().unwrap_or(PRINT_SUCCESS),
+                    spool.map(|spool| spool.roll_name.as_str()).unwrap_or("-"),
+                    if tagged.is_empty() {
+                        String::new()
+                    } else {
+                        format!("  [{}]", tagged.join(" "))
+                    }
+                );
+            }
+        }
+        Commands::Project { action } => match action {
+            ProjectAction::Add { name, customer } => {
+                projects::add_project(&db, &name, customer.as_deref()).unwrap();
+                println!("Saved project {}", name);
+            }
+            ProjectAction::List => {
+                let all = projects::list_projects(&db).unwrap();
+                if all.is_empty() {
+                    println!("No projects yet, add one with project add");
+                }
+                for (name, customer, prints) in all {
+                    println!(
+                        "{:<24} {:<20} {} prints",
+                        name,
+                        customer.as_deref().unwrap_or("-"),
+                        prints
+                    );
+                }
+            }
+            ProjectAction::Remove { name } => {
+                if projects::remove_project(&db, &name).unwrap() == 0 {
+                    eprintln!("No project called {}", name);
+                    std::process::exit(1);
+                }
+                println!("Removed project {}", name);
+            }
+            ProjectAction::Assign { print, project } => {
+                let print_id = match print_add::find_print(&db, &print) {
+                    Ok(print_id) => print_id,
+                    Err(_) => {
+                        eprintln!("No print called {}", print);
+                        std::process::exit(1);
+                    }
+                };
+                let project = project.map(|project| known_project(&db, &project));
+                projects::assign_print(&db, print_id, project.as_deref()).unwrap();
+                match project {
+                    Some(project) => println!("Charged {} to {}", print, project),
+                    None => println!("Took {} out of its project", print),
+                }
+            }
+        },
+        Commands::ProjectReport { from, to, csv } => {
+            let usage = projects::project_usage(&db, from, to).unwrap();
+            match csv {
+                Some(path) => {
+                    let written = fs::File::create(&path)
+                        .map_err(|e| e.to_string())
+                        .and_then(|file| projects::write_report_csv(&usage, file));
+                    match written {
+                        Ok(()) => println!("Wrote {} projects to {}", usage.len(), path.display()),
+                        Err(e) => {
+                            eprintln!("Could not write {}: {}", path.display(), e);
+                            std::process::exit(1);
+                        }
+                    }
+                }
+                None => {
+                    if usage.is_empty() {
+                        println!("No prints in that time");
+                    }
+                    for project in usage {
+                        println!(
+                            "{:<24} {:<20} {:>4} prints {:>11} {:>10} {:>6} min {:>9}",
+                            project.project.as_deref().unwrap_or("No project"),
+                            project.customer.as_deref().unwrap_or("-"),
+                            project.prints,
+                            units.weight(Weight::from_grams(project.weight)),
+                            units.length(Length::from_metres(project.length)),
+                            project.time / 60,
+                            project
+                                .cost
+                                .map(|cost| config.price(cost))
+                                .unwrap_or_else(|| String::from("-"))
+                        );
+                    }
+                }
+            }
+        }
+        Commands::Tag { action } => match action {
+            TagAction::Spool {
+                spool,
+                tags,
+                remove,
+            } => {
+                let found = match print_add::find_spool(&db, &spool) {
+                    Ok(found) => found,
+                    Err(_) => {
+                        eprintln!("No spool called {}", spool);
+                        std::process::exit(1);
+                    }
+                };
+                if remove {
+                    tags::untag_spool(&db, found.roll_id, &tags).unwrap();
+                    println!("Took {} off {}", tags.join(", "), spool);
+                } else {
+                    tags::tag_spool(&db, found.roll_id, &tags).unwrap();
+                    println!("Tagged {} with {}", spool, tags.join(", "));
+                }
+            }
+            TagAction::Print {
+                print,
+                tags,
+                remove,
+            } => {
+                let print_id = match print_add::find_print(&db, &print) {
+                    Ok(print_id) => print_id,
+                    Err(_) => {
+                        eprintln!("No print called {}", print);
+                        std::process::exit(1);
+                    }
+                };
+                if remove {
+                    tags::untag_print(&db, print_id, &tags).unwrap();
+                    println!("Took {} off {}", tags.join(", "), print);
+                } else {
+                    tags::tag_print(&db, print_id, &tags).unwrap();
+                    println!("Tagged {} with {}", print, tags.join(", "));
+                }
+            }
+            TagAction::List => {
+                let all = tags::list_tags(&db).unwrap();
+                if all.is_empty() {
+                    println!("No tags yet, add them with tag spool or tag print");
+                }
+                for (tag, spools, prints) in all {
+                    println!("{:<24} {} spools  {} prints", tag, spools, prints);
+                }
+            }
+            TagAction::Stats { prefix } => {
+                for usage in tags::tag_usage(&db, prefix.as_deref()).unwrap() {
+                    println!(
+                        "{:<24} {:>4} prints {:>11} {:>10} {:>6} min",
+                        usage.tag,
+                        usage.prints,
+                        units.weight(Weight::from_grams(usage.weight)),
+                        units.length(Length::from_metres(usage.length)),
+                        usage.time / 60
+                    );
+                }
+            }
+        },
+        Commands::Note { target } => match target {
+            NoteTarget::Spool { spool, note, clear } => {
+                let found = match print_add::find_spool(&db, &spool) {
+                    Ok(found) => found,
+                    Err(_) => {
+                        eprintln!("No spool called {}", spool);
+                        std::process::exit(1);
+                    }
+                };
+                if note.is_some() || clear {
+                    print_add::set_spool_notes(&db, found.roll_id, note.as_deref()).unwrap();
+                    println!(
+                        "{} the note on {}",
+                        if clear { "Cleared" } else { "Saved" },
+                        spool
+                    );
+                } else {
+                    let spool_info = print_stats::get_spool(&db, found.roll_id).unwrap();
+                    println!("{}", spool_info.roll_notes.as_deref().unwrap_or("No note"));
+                }
+            }
+            NoteTarget::Print { print, note, clear } => {
+                let print_id = match print_add::find_print(&db, &print) {
+                    Ok(print_id) => print_id,
+                    Err(_) => {
+                        eprintln!("No print called {}", print);
+                        std::process::exit(1);
+                    }
+                };
+                if note.is_some() || clear {
+                    print_add::set_print_notes(&db, print_id, note.as_deref()).unwrap();
+                    println!(
+                        "{} the note on {}",
+                        if clear { "Cleared" } else { "Saved" },
+                        print
+                    );
+                } else {
+                    let found = print_stats::list_prints(&db)
+                        .unwrap()
+                        .into_iter()
+                        .find(|found| found.print_id == Some(print_id));
+                    println!(
+                        "{}",
+                        found
+                            .and_then(|found| found.print_notes)
+                            .as_deref()
+                            .unwrap_or("No note")
+                    );
+                }
+            }
+        },
+        Commands::Sync { source } => match source {
+            SyncSource::Moonraker { url, printer } => {
+                let loaded = current_spool(&db, printer.as_deref());
+                match sync_moonraker::sync_moonraker(&db, &url, loaded.roll_id) {
+                    Ok(summary) => println!(
+                        "Imported {} jobs from Moonraker, {} were already known",
+                        summary.imported, summary.skipped
+                    ),
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        std::process::exit(1);
+                    }
+                }
+            }
+            SyncSource::Octoprint {
+                url,
+                api_key,
+                printer,
+            } => {
+                let loaded = current_spool(&db, printer.as_deref());
+                match sync_octoprint::sync_octoprint(&db, &url, &api_key, loaded.roll_id) {
+                    Ok(summary) => println!(
+                        "Imported {} prints from OctoPrint, {} were already known",
+                        summary.imported, summary.skipped
+                    ),
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        std::process::exit(1);
+                    }
+                }
+            }
+        },
+        Commands::Tui => {
+            if let Err(e) = tui::run(&db, units) {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        }
+        Commands::Serve { bind, token } => {
+            let server = match tiny_http::Server::http(&bind) {
+                Ok(server) => server,
+                Err(e) => {
+                    eprintln!("Could not listen on {}: {}", bind, e);
+                    std::process::exit(1);
+                }
+            };
+            println!("Serving on http://{}", bind);
+            if let Err(e) = server::serve(server, &db_path, token, &user) {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        }
+        Commands::Product { action } => match action {
+            ProductAction::Add {
+                name,
+                vendor,
+                material,
+                colour,
+                colour_hex,
+                diameter,
+                density,
+                net_weight,
+                tare,
+                price,
+            } => {
+                let product = Product {
+                    product_name: name,
+                    product_vendor: vendor,
+                    product_material: material,
+                    product_colour: colour,
+                    product_colour_hex: colour_hex,
+                    product_diameter: diameter,
+                    product_density: density,
+                    product_weight: net_weight,
+                    product_tare: tare,
+                    product_price: price,
+                };
+                if product.display_name().is_empty() {
+                    eprintln!("Give the product a name, or a vendor, material or colour");
+                    std::process::exit(1);
+                }
+                catalogue::save_product(&db, &product).unwrap();
+                println!("Saved product {}", product.display_name());
+            }
+            ProductAction::List => {
+                let products = catalogue::list_products(&db).unwrap();
+                if products.is_empty() {
+                    println!("No products in the catalogue");
+                }
+                for product in products {
+                    let weight = product
+                        .product_weight
+                        .map(|weight| units.weight(weight))
+                        .unwrap_or_default();
+                    let price = product
+                        .product_price
+                        .map(|price| config.price(price))
+                        .unwrap_or_default();
+                    println!(
+                        "{:<30} {:<10} {:<10} {:>10} {:>8}",
+                        product.display_name(),
+                        product.product_material.as_deref().unwrap_or_default(),
+                        product
+                            .product_colour_hex
+                            .as_deref()
+                            .or(product.product_colour.as_deref())
+                            .unwrap_or_default(),
+                        weight,
+                        price
+                    );
+                }
+            }
+            ProductAction::Remove { name } => {
+                if catalogue::remove_product(&db, &name).unwrap() == 0 {
+                    eprintln!("No product called {} in the catalogue", name);
+                    std::process::exit(1);
+                }
+                println!("Removed product {}", name);
+            }
+            ProductAction::Import { file } => {
+                let imported = fs::File::open(&file)
+                    .map_err(|e| e.to_string())
+                    .and_then(catalogue::read_products)
+                    .and_then(|products| catalogue::import_products(&db, &products));
+                match imported {
+                    Ok((added, replaced)) => {
+                        println!("Added {} products and replaced {}", added, replaced)
+                    }
+                    Err(e) => {
+                        eprintln!("Could not import products: {}", e);
+                        std::process::exit(1);
+                    }
+                }
+            }
+        },
+        Commands::Nfc {
+            action:
+                NfcAction::Read {
+                    file,
+                    name,
+                    quantity,
+                    dry_run,
+                },
+        } => {
+            let tag = match fs::read(&file)
+                .map_err(|e| e.to_string())
+                .and_then(|contents| nfc::read_tag(&contents))
+            {
+                Ok(tag) => tag,
+                Err(e) => {
+                    eprintln!("Could not read the tag: {}", e);
+                    std::process::exit(1);
+                }
+            };
+            let mut spool = tag.to_spool();
+            let roll_id = spool.roll_id.unwrap();
+            if let Ok(existing) = print_stats::get_spool(&db, roll_id) {
+                let (weight, length) = print_stats::remaining_on_spool(&db, roll_id);
+                println!(
+                    "Tag belongs to {}, {} and {} left",
+                    existing.roll_name.unwrap_or_default(),
+                    units.weight(weight),
+                    units.length(length)
+                );
+            } else {
+                spool.roll_name = name.or(spool.roll_name);
+                spool.roll_weight = spool.roll_weight.or(quantity.weight);
+                spool.roll_length = spool.roll_length.or(quantity.length);
+                println!("Name: {}", spool.roll_name.as_deref().unwrap_or("unknown"));
+                println!(
+                    "Material: {}",
+                    spool.roll_material.as_deref().unwrap_or("unknown")
+                );
+                println!(
+                    "Colour: {}",
+                    spool
+                        .roll_colour
+                        .as_ref()
+                        .or(spool.roll_colour_hex.as_ref())
+                        .map_or("unknown", String::as_str)
+                );
+                println!(
+                    "Diameter: {} mm",
+                    spool.roll_diameter.unwrap_or(FILAMENT_DIAMETER)
+                );
+                match spool.roll_weight {
+                    Some(weight) => println!("Weight: {}", units.weight(weight)),
+                    None => println!("Weight: unknown"),
+                }
+                if !dry_run {
+                    if spool.roll_name.is_none() {
+                        eprintln!(
+                            "The tag has nothing to name the spool after, give one with --name"
+                        );
+                        std::process::exit(1);
+                    }
+                    if spool.roll_weight.is_none() && spool.roll_length.is_none() {
+                        eprintln!("The tag has no weight, give one with -w");
+                        std::process::exit(1);
+                    }
+                    if let Err(e) = spool.validate() {
+                        eprintln!("{}", e);
+                        std::process::exit(1);
+                    }
+                    print_add::open_new_spool(&db, &mut spool).unwrap();
+                    println!("Created spool {}", spool.roll_name.unwrap());
+                }
+            }
+        }
+        Commands::Nfc {
+            action:
+                NfcAction::Write {
+                    spool,
+                    format,
+                    output,
+                },
+        } => {
+            let found = match print_add::find_spool(&db, &spool) {
+                Ok(found) => found,
+                Err(_) => {
+                    eprintln!("No spool called {}", spool);
+                    std::process::exit(1);
+                }
+            };
+            let spool = print_stats::get_spool(&db, found.roll_id).unwrap();
+            write_file(&output, nfc::write_tag(&spool, format));
+            println!("Tag payload written to {}", output.display());
+        }
+        Commands::Watch { source } => match source {
+            WatchSource::Bambu {
+                host,
+                serial,
+                access_code,
+                printer,
+                port,
+                plain,
+                trust_new_certificate,
+            } => {
+                if trust_new_certificate {
+                    watch_bambu::unpin_certificate(&db, &serial).unwrap();
+                }
+                let options = watch_bambu::BambuOptions {
+                    host,
+                    port,
+                    serial,
+                    access_code,
+                    plain,
+                };
+                let mut tracker = watch_bambu::BambuTracker::default();
+                println!("Watching {} for prints, Ctrl-C to stop", options.host);
+                //Keep listening through the printer going to sleep or the network dropping
+                loop {
+                    if let Err(e) = watch_bambu::watch_bambu(&db, &printer, &options, &mut tracker)
+                    {
+                        eprintln!("Lost connection to {}: {}", options.host, e);
+                    }
+                    std::thread::sleep(Duration::from_secs(10));
+                }
+            }
+        },
+        Commands::Chart { kind, ascii } => {
+            let width = chart::terminal_width();
+            match kind {
+                ChartKind::Usage { period } => {
+                    let period_name = period.to_possible_value().unwrap();
+                    println!("Filament used per {}:", period_name.get_name());
+                    let usage = print_stats::usage_per_period(&db, period).unwrap();
+                    if usage.is_empty() {
+                        println!("No prints recorded yet");
+                    } else {
+                        let weights: Vec<f32> = usage.iter().map(|row| row.1).collect();
+                        println!("{}", chart::sparkline(&weights, width, ascii));
+                        let rows: Vec<(String, f32, String)> = usage
+                            .into_iter()
+                            .map(|(label, weight, length)| {
+                                let note = format!(
+                                    "{} / {}",
+                                    units.weight(Weight::from_grams(weight)),
+                                    units.length(Length::from_metres(length))
+                                );
+                                (label, weight, note)
+                            })
+                            .collect();
+                        print!("{}", chart::bar_chart(&rows, width, ascii));
+                    }
+                }
+                ChartKind::Burndown { spool } => {
+                    let current = match &spool {
+                        Some(spool) => print_add::find_spool(&db, spool),
+                        None => print_add::get_current_spool(&db),
+                    };
+                    let roll_id = match current {
+                        Ok(current) => current.roll_id,
+                        Err(_) => {
+                            match spool {
+                                Some(spool) => eprintln!("No spool called {}", spool),
+                                None => eprintln!("No spools have been created"),
+                            }
+                            std::process::exit(1);
+                        }
+                    };
+                    let name = print_stats::get_spool(&db, roll_id).unwrap().roll_name;
+                    println!(
+                        "Remaining filament on spool {}:",
+                        name.unwrap_or_else(|| roll_id.to_string())
+                    );
+                    let burndown = print_stats::spool_burndown(&db, roll_id).unwrap();
+                    let remaining: Vec<f32> = burndown.iter().map(|row| row.1).collect();
+                    println!("{}", chart::sparkline(&remaining, width, ascii));
+                    let rows: Vec<(String, f32, String)> = burndown
+                        .into_iter()
+                        .map(|(label, weight)| {
+                            (label, weight, units.weight(Weight::from_grams(weight)))
+                        })
+                        .collect();
+                    print!("{}", chart::bar_chart(&rows, width, ascii));
+                }
+                ChartKind::Materials => {
+                    println!("Filament used per material:");
+                    let shares = print_stats::material_share(&db).unwrap();
+                    let total: f32 = shares.iter().map(|row| row.1).sum();
+                    if total <= 0.0 {
+                        println!("No prints recorded yet");
+                    } else {
+                        let rows: Vec<(String, f32, String)> = shares
+                            .into_iter()
+                            .map(|(material, weight)| {
+                                let note = format!(
+                                    "{} ({:.0}%)",
+                                    units.weight(Weight::from_grams(weight)),
+                                    weight / total * 100.0
+                                );
+                                (material, weight, note)
+                            })
+                            .collect();
+                        print!("{}", chart::bar_chart(&rows, width, ascii));
+                    }
+                }
+            }
+        }
+        Commands::Report { html, month } => {
+            let report = match report::html_report(&db, month.as_deref(), &config) {
+                Ok(report) => report,
+                Err(e) => {
+                    eprintln!("Could not build the report: {}", e);
+                    std::process::exit(1);
+                }
+            };
+            write_file(&html, report);
+            println!("Report written to {}", html.display());
+        }
+        Commands::Label {
+            spool,
+            all,
+            format,
+            size,
+            sheet,
+            output,
+        } => {
+            let spools = if all {
+                print_stats::list_spools(&db).unwrap()
+            } else {
+                spool
+                    .iter()
+                    .map(|name| match print_add::find_spool(&db, name) {
+                        Ok(found) => print_stats::get_spool(&db, found.roll_id).unwrap(),
+                        Err(_) => {
+                            eprintln!("No spool called {}", name);
+                            std::process::exit(1);
+                        }
+                    })
+                    .collect()
+            };
+            if spools.is_empty() {
+                eprintln!("No spools to label");
+                std::process::exit(1);
+            }
+            let pages = match sheet {
+                Some(sheet) => label::sheet_pages(&spools, sheet),
+                None => label::label_pages(&spools, size),
+            };
+            let files = match pages.and_then(|pages| label::render(&pages, format)) {
+                Ok(files) => files,
+                Err(e) => {
+                    eprintln!("Could not make labels: {}", e);
+                    std::process::exit(1);
+                }
+            };
+            let output =
+                output.unwrap_or_else(|| PathBuf::from(format!("labels.{}", format.extension())));
+            for (index, file) in files.iter().enumerate() {
+                let path = if files.len() == 1 {
+                    output.clone()
+                } else {
+                    label::numbered_path(&output, index + 1)
+                };
+                write_file(&path, file);
+                println!("Labels written to {}", path.display());
+            }
+        }
+        Commands::Export {
+            csv,
+            spoolman,
+            output,
+        } => {
+            let writer: Box<dyn std::io::Write> = match &output {
+                Some(path) => Box::new(create_file(path)),
+                None => Box::new(std::io::stdout()),
+            };
+            let count = match (csv, spoolman) {
+                (Some(table), _) => csv_io::export_csv(&db, table, writer).unwrap(),
+                (_, Some(format)) => spoolman::export_spoolman(&db, format, writer).unwrap(),
+                (None, None) => unreachable!("clap requires a format"),
+            };
+            if let Some(path) = output {
+                println!("Exported {} rows to {}", count, path.display());
+            }
+            if count == 0 {
+                eprintln!("Nothing to export");
+            }
+        }
+        Commands::Import {
+            csv: None,
+            spoolman: Some(format),
+            file,
+            dry_run,
+            ..
+        } => {
+            let reader = open_file(&file);
+            if !dry_run {
+                backup_before(&db, &backup_dir, "import");
+            }
+            let imported = spoolman::read_spoolman(format, reader)
+                .and_then(|spools| spoolman::import_spoolman(&db, &spools, dry_run));
+            match imported {
+                Ok(summary) => {
+                    for id in &summary.duplicates {
+                        println!("Skipped {} as it already exists", id);
+                    }
+                    let verb = if dry_run { "Would import" } else { "Imported" };
+                    println!(
+                        "{} {} spools with {} usage records",
+                        verb, summary.spools, summary.usage_records
+                    );
+                }
+                Err(error) => {
+                    eprintln!("{}", error);
+                    eprintln!("Nothing was imported");
+                    std::process::exit(1);
+                }
+            }
+        }
+        Commands::Import {
+            csv,
+            file,
+            mappings,
+            dry_run,
+            ..
+        } => {
+            let csv = csv.expect("clap requires a format");
+            let reader = open_file(&file);
+            if !dry_run {
+                backup_before(&db, &backup_dir, "import");
+            }
+            match csv_io::import_csv(&db, csv, reader, &mappings, dry_run) {
+                Ok(summary) => {
+                    for id in &summary.duplicates {
+                        println!("Skipped {} as it already exists", id);
+                    }
+                    if dry_run {
+                        println!("Dry run, would import {} rows", summary.inserted);
+                    } else {
+                        println!("Imported {} rows", summary.inserted);
+                    }
+                }
+                Err(errors) => {
+                    for error in errors {
+                        eprintln!("{}", error);
+                    }
+                    eprintln!("Nothing was imported");
+                    std::process::exit(1);
+                }
+            }
+        }
+        Commands::Backup { path } => match backup::backup_to(&db, &path) {
+            Ok(()) => println!("Database backed up to {}", path.display()),
+            Err(e) => {
+                eprintln!("Backup failed: {}", e);
+                std::process::exit(1);
+            }
+        },
+        Commands::Restore { path } => {
+            if let Err(e) = backup::check_backup(&path) {
+                eprintln!("Can not restore: {}", e);
+                std::process::exit(1);
+            }
+            backup_before(&db, &backup_dir, "restore");
+            match backup::restore_from(&mut db, &path, &user) {
+                Ok(version) => println!("Restored {} (schema version {})", path.display(), version),
+                Err(e) => {
+                    eprintln!("Restore failed: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Commands::Config { .. } | Commands::Completions { .. } | Commands::ManPages { .. } => {
+            unreachable!("handled before the database is opened")
+        }
+        Commands::Vacuum => {
+            let (before, after) = backup::vacuum(&db).unwrap();
+            println!("Database compacted from {} to {} pages", before, after);
+        }
+        Commands::IntegrityCheck => {
+            let problems = backup::integrity_check(&db).unwrap();
+            if problems.is_empty() {
+                println!("Database is healthy");
+            } else {
+                for problem in problems {
+                    println!("{}", problem);
+                }
+                std::process::exit(1);
+            }
+        }
+    }
+
+    let rt = db.close();
+    rt.unwrap();
+}
+
+//Function to find the spool a printer is using, the newest spool when no printer is given
+fn current_spool(db: &Connection, printer: Option<&str>) -> print_add::RollId {
+    let spool = match printer {
+        Some(printer) => print_add::spool_for_printer(db, printer),
+        None => print_add::get_current_spool(db),
+    };
+    match spool {
+        Ok(spool) => spool,
+        Err(_) => {
+            eprintln!("No spool found, create one with create-spool first");
+            std::process::exit(1);
+        }
+    }
+}
+
+//Function to move -w and -l given before the command, the way they used to be given,
+//onto the command. True when they were there
+fn move_old_quantity(args: &mut Args) -> Result<bool, String> {
+    let (weight, length) = (args.weight, args.length);
+    if weight.is_none() && length.is_none() {
+        return Ok(false);
+    }
+    let Some(quantity) = args.cmd.quantity_mut() else {
+        return Err(String::from(
+            "This command does not take a weight or a length",
+        ));
+    };
+    if (weight.is_some() && quantity.weight.is_some())
+        || (length.is_some() && quantity.length.is_some())
+    {
+        return Err(String::from(
+            "Give the weight and length once, after the command",
+        ));
+    }
+    quantity.weight = quantity.weight.or(weight);
+    quantity.length = quantity.length.or(length);
+    Ok(true)
+}
+
+//Function to use the configured printer when none is given
+fn configured_printer(printer: Option<String>, config: &Config) -> String {
+    match printer.or_else(|| config.printer.clone()) {
+        Some(printer) => printer,
+        None => {
+            eprintln!("No printer given, use --printer or set one with config set printer");
+            std::process::exit(1);
+        }
+    }
+}
+
+//Function to warn when a spool is running low, below the configured low_weight
+fn warn_if_low(weight: Weight, config: &Config, units: &Units) {
+    if config
+        .low_weight
+        .is_some_and(|low_weight| weight < low_weight)
+    {
+        eprintln!("Warning: only {} left on this spool", units.weight(weight));
+    }
+}
+
+fn config_command(action: &ConfigAction, path: &Path) {
+    let file = match config::read_file(path) {
+        Ok(file) => file,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let values = match config::layered_values(&file, |name| env::var(name).ok()) {
+        Ok(values) => values,
+        Err(e) => {
+            eprintln!("{} in {}", e, path.display());
+            std::process::exit(1);
+        }
+    };
+    match action {
+        ConfigAction::Get { key } => {
+            if !config::is_key(key) {
+                eprintln!("{} is not a setting, see config list", key);
+                std::process::exit(1);
+            }
+            match values.iter().find(|(set, _, _)| set == key) {
+                Some((_, value, _)) => println!("{}", value),
+                None => {
+                    eprintln!("{} is not set", key);
+                    std::process::exit(1);
+                }
+            }
+        }
+        ConfigAction::Set { key, value } => match config::set_value(path, key, value) {
+            Ok(()) => {
+                println!("Set {} to {} in {}", key, value, path.display());
+                if let Some((_, _, config::Source::Environment)) =
+                    values.iter().find(|(set, _, _)| set == key)
+                {
+                    eprintln!(
+                        "Warning: REMAINDER_{} is set and overrides the config file",
+                        key.to_uppercase()
+                    );
+                }
+            }
+            Err(e) => {
+                eprintln!("Could not set {}: {}", key, e);
+                std::process::exit(1);
+            }
+        },
+        ConfigAction::Unset { key } => match config::unset_value(path, key) {
+            Ok(true) => println!("Removed {} from {}", key, path.display()),
+            Ok(false) => println!("{} is not in {}", key, path.display()),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        },
+        ConfigAction::List => {
+            println!("# {}", path.display());
+            for (key, about) in config::KEYS {
+                match values.iter().find(|(set, _, _)| *set == key) {
+                    Some((_, value, source)) => {
+                        println!("{} = {:?}  # from the {}", key, value, source.name())
+                    }
+                    None => println!("# {}: {}", key, about),
+                }
+            }
+        }
+    }
+}
+
+//Function to print the completion script for a shell. Completion runs remainder
+//again from the script, so it points at this binary when it is not run from the PATH
+fn completions_command(shell: Shell) {
+    let shells = Shells::builtins();
+    let completer = shells
+        .completer(&shell.to_string())
+        .expect("every shell clap knows has a completer");
+    let bin = env::current_exe()
+        .map(|path| path.display().to_string())
+        .unwrap_or_else(|_| String::from("remainder"));
+    if let Err(e) = completer.write_registration(
+        "COMPLETE",
+        "remainder",
+        "remainder",
+        &bin,
+        &mut std::io::stdout(),
+    ) {
+        eprintln!("Could not write the completion script: {}", e);
+        std::process::exit(1);
+    }
+}
+
+fn man_pages_command(dir: &Path) {
+    match fs::create_dir_all(dir).and_then(|()| clap_mangen::generate_to(Args::command(), dir)) {
+        Ok(()) => println!("Man pages written to {}", dir.display()),
+        Err(e) => {
+            eprintln!("Could not write the man pages: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+//Function to look up a project given on the command line, which has to be added first
+fn known_project(db: &Connection, project: &str) -> String {
+    match projects::find_project(db, project).unwrap() {
+        Some(project) => project,
+        None => {
+            eprintln!("No project called {}, add it with project add", project);
+            std::process::exit(1);
+        }
+    }
+}
+
+//Function to warn before printing with a spool that has taken on moisture
+fn warn_if_exposed(db: &Connection, roll_id: Uuid) {
+    if let Some(warning) = drying::exposure_warning(db, roll_id, get_timestamp()).unwrap() {
+        eprintln!("Warning: {}", warning);
+    }
+}
+
+//Function to open a file given on the command line, stopping when it can not be read
+fn open_file(path: &Path) -> fs::File {
+    match fs::File::open(path) {
+        Ok(file) => file,
+        Err(e) => {
+            eprintln!("Can not read {}: {}", path.display(), e);
+            std::process::exit(1);
+        }
+    }
+}
+
+//Function to create a file given on the command line, stopping when it can not be written
+fn create_file(path: &Path) -> fs::File {
+    match fs::File::create(path) {
+        Ok(file) => file,
+        Err(e) => {
+            eprintln!("Can not write {}: {}", path.display(), e);
+            std::process::exit(1);
+        }
+    }
+}
+
+//Function to write a file given on the command line, stopping when it can not be written
+fn write_file(path: &Path, contents: impl AsRef<[u8]>) {
+    if let Err(e) = fs::write(path, contents) {
+        eprintln!("Can not write {}: {}", path.display(), e);
+        std::process::exit(1);
+    }
+}
+
+//Function to take an automatic backup before changing a lot of data
+fn backup_before(db: &Connection, backup_dir: &Path, reason: &str) {
+    match backup::auto_backup(db, backup_dir, reason) {
+        Ok(path) => println!("Backed up database to {}", path.display()),
+        Err(e) => {
+            eprintln!(
+                "Could not back up the database before the {}: {}",
+                reason, e
+            );
+            std::process::exit(1);
+        }
+    }
+}
+
+//Function to get the current timestamp
+pub fn get_timestamp() -> i64 {
+    let start = SystemTime::now();
+    i64::try_from(
+        start
+            .duration_since(UNIX_EPOCH)
+            .expect("Time went backwards")
+            .as_secs(),
+    )
+    .unwrap()
+}
+
+//Function to format a timestamp as a UTC date and time
+pub fn format_timestamp(timestamp: i64) -> String {
+    match DateTime::from_timestamp(timestamp, 0) {
+        Some(date) => date.format("%Y-%m-%d %H:%M").to_string(),
+        None => String::from("unknown"),
+    }
+}
+
+//Function to format a timestamp as an ISO 8601 UTC date and time
+pub fn format_iso_timestamp(timestamp: i64) -> String {
+    match DateTime::from_timestamp(timestamp, 0) {
+        Some(date) => date.format("%Y-%m-%dT%H:%M:%SZ").to_string(),
+        None => String::new(),
+    }
+}
+
+//Function to read an ISO 8601 date, with or without a time, as a timestamp
+pub fn parse_timestamp(date: &str) -> Option<i64> {
+    let date = date.trim();
+    if let Ok(date_time) = DateTime::parse_from_rfc3339(date) {
+        return Some(date_time.timestamp());
+    }
+    for format in ["%Y-%m-%dT%H:%M:%S", "%Y-%m-%d %H:%M:%S", "%Y-%m-%d %H:%M"] {
+        if let Ok(date_time) = NaiveDateTime::parse_from_str(date, format) {
+            return Some(date_time.and_utc().timestamp());
+        }
+    }
+    NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .ok()
+        .and_then(|day| day.and_hms_opt(0, 0, 0))
+        .map(|day| day.and_utc().timestamp())
+}
+
+//Function to read a colour hex code given on the command line
+fn parse_colour_hex(hex: &str) -> Result<String, String> {
+    hex_colour(hex)
+        .map(|hex| format!("#{}", hex))
+        .ok_or_else(|| format!("{} is not a hex colour such as #1A1A1A", hex))
+}
+
+//Function to read a date given on the command line
+fn parse_date(date: &str) -> Result<i64, String> {
+    parse_timestamp(date).ok_or_else(|| format!("{} is not a YYYY-MM-DD date", date))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_spool_tbl_creation() {
+        let conn = Connection::open_in_memory().unwrap();
+        tbl_creation::create_new_spool_tbl(&conn).unwrap();
+        tbl_creation::create_new_spool_tbl(&conn).unwrap();
+    }
+
+    #[test]
+    fn test_filament_tbl_creation() {
+        let conn = Connection::open_in_memory().unwrap();
+        tbl_creation::create_new_filament_tbl(&conn).unwrap();
+        tbl_creation::create_new_filament_tbl(&conn).unwrap();
+    }
+
+    #[test]
+    fn test_spool_weight_1() {
+        let mut test_spool = Spool {
+            roll_id: Some(Uuid::new_v4()),
+            roll_name: Some(String::from("crealtivity")),
+            roll_weight: Some(Weight::from_grams(1000.0)),
+            roll_length: None,
+            timestamp: Some(get_timestamp()),
+            ..Default::default()
+        };
+
+        let ans = test_spool.get_weight();
+        assert_eq!(ans, Weight::from_grams(1000.0));
+    }
+
+    #[test]
+    fn test_spool_weight_2() {
+        let mut test_spool = Spool {
+            roll_id: Some(Uuid::new_v4()),
+            roll_name: Some(String::from("crealtivity")),
+            roll_weight: None,
+            roll_length: Some(Length::from_metres(330.0)),
             timestamp: Some(get_timestamp()),
+            ..Default::default()
         };
 
         let ans = test_spool.get_weight();
-        assert_eq!(ans, 999.99896);
+        assert_eq!(ans, Weight::from_grams(999.99896));
     }
 
     #[test]
@@ -175,6 +2436,7 @@ mod tests {
             roll_weight: None,
             roll_length: None,
             timestamp: Some(get_timestamp()),
+            ..Default::default()
         };
 
         let _ans = test_spool.get_weight();
@@ -185,13 +2447,14 @@ mod tests {
         let mut test_spool = Spool {
             roll_id: Some(Uuid::new_v4()),
             roll_name: Some(String::from("crealtivity")),
-            roll_weight: Some(1000.0),
+            roll_weight: Some(Weight::from_grams(1000.0)),
             roll_length: None,
             timestamp: Some(get_timestamp()),
+            ..Default::default()
         };
 
         let ans = test_spool.get_length();
-        assert_eq!(ans, 330.0);
+        assert_eq!(ans, Length::from_metres(330.0));
     }
 
     #[test]
@@ -200,12 +2463,13 @@ mod tests {
             roll_id: Some(Uuid::new_v4()),
             roll_name: Some(String::from("crealtivity")),
             roll_weight: None,
-            roll_length: Some(330.0),
+            roll_length: Some(Length::from_metres(330.0)),
             timestamp: Some(get_timestamp()),
+            ..Default::default()
         };
 
         let ans = test_spool.get_length();
-        assert_eq!(ans, 330.0);
+        assert_eq!(ans, Length::from_metres(330.0));
     }
 
     #[test]
@@ -217,254 +2481,2678 @@ mod tests {
             roll_weight: None,
             roll_length: None,
             timestamp: Some(get_timestamp()),
+            ..Default::default()
+        };
+
+        let _ans = test_spool.get_length();
+    }
+
+    #[test]
+    fn test_create_new_spool() {
+        let conn = Connection::open_in_memory().unwrap();
+        tbl_creation::create_new_spool_tbl(&conn).unwrap();
+        let mut test_spool = Spool {
+            roll_id: Some(Uuid::new_v4()),
+            roll_name: Some(String::from("crealtivity")),
+            roll_weight: Some(Weight::from_grams(1000.0)),
+            roll_length: Some(Length::from_metres(330.0)),
+            timestamp: Some(get_timestamp()),
+            ..Default::default()
         };
+        let rt = print_add::open_new_spool(&conn, &mut test_spool).unwrap();
+        assert_eq!(rt, 1);
+        let check_query = "SELECT * FROM spool";
+        let exists_rt = conn
+            .query_row(check_query, [], |row| {
+                Ok(Spool {
+                    roll_id: row.get(0)?,
+                    roll_name: row.get(1)?,
+                    roll_weight: row.get(2)?,
+                    roll_length: row.get(3)?,
+                    timestamp: row.get(4)?,
+                    ..Default::default()
+                })
+            })
+            .unwrap();
+
+        assert_eq!(exists_rt.roll_id.unwrap(), test_spool.roll_id.unwrap());
+        assert_eq!(exists_rt.roll_name.unwrap(), "crealtivity".to_string());
+        assert_eq!(exists_rt.roll_weight.unwrap(), Weight::from_grams(1000.0));
+        assert_eq!(exists_rt.roll_length.unwrap(), Length::from_metres(330.0));
+        assert!(exists_rt.timestamp.unwrap() > 1734209754);
+    }
+
+    #[test]
+    fn test_create_new_print() {
+        //Create in memory DB
+        let conn = Connection::open_in_memory().unwrap();
+
+        //Create test spool
+        tbl_creation::create_new_spool_tbl(&conn).unwrap();
+        tbl_creation::create_new_filament_tbl(&conn).unwrap();
+
+        let mut test_spool = Spool {
+            roll_id: Some(Uuid::new_v4()),
+            roll_name: Some(String::from("crealtivity")),
+            roll_weight: Some(Weight::from_grams(1000.0)),
+            roll_length: Some(Length::from_metres(330.0)),
+            timestamp: Some(get_timestamp()),
+            ..Default::default()
+        };
+        let rt = print_add::open_new_spool(&conn, &mut test_spool).unwrap();
+        assert_eq!(rt, 1);
+        let check_query = "SELECT * FROM spool";
+        let exists_rt = conn
+            .query_row(check_query, [], |row| {
+                Ok(Spool {
+                    roll_id: row.get(0)?,
+                    roll_name: row.get(1)?,
+                    roll_weight: row.get(2)?,
+                    roll_length: row.get(3)?,
+                    timestamp: row.get(4)?,
+                    ..Default::default()
+                })
+            })
+            .unwrap();
+
+        assert_eq!(exists_rt.roll_id.unwrap(), test_spool.roll_id.unwrap());
+        assert_eq!(exists_rt.roll_name.unwrap(), "crealtivity".to_string());
+        assert_eq!(exists_rt.roll_weight.unwrap(), Weight::from_grams(1000.0));
+        assert_eq!(exists_rt.roll_length.unwrap(), Length::from_metres(330.0));
+        assert!(exists_rt.timestamp.unwrap() > 1734209754);
+        let mut second_test_spool = Spool {
+            roll_id: Some(Uuid::new_v4()),
+            roll_name: Some(String::from("crealtivity")),
+            roll_weight: Some(Weight::from_grams(1000.0)),
+            roll_length: Some(Length::from_metres(330.0)),
+            timestamp: Some(get_timestamp() + 5),
+            ..Default::default()
+        };
+        let rt_second_spool = print_add::open_new_spool(&conn, &mut second_test_spool).unwrap();
+        assert_eq!(rt_second_spool, 1);
+
+        //Test print creation
+        let mut test_print = Filament {
+            print_id: Some(Uuid::new_v4()),
+            print_weight: None,
+            print_length: Some(Length::from_metres(2.31)),
+            print_time: Some(1125),
+            ..Default::default()
+        };
+
+        let _rt2 = print_add::add_new_print(&conn, &mut test_print).unwrap();
+        let check_query = "SELECT * FROM filament";
+        let exists_rt2 = conn
+            .query_row(check_query, [], |row| {
+                Ok(Filament {
+                    print_id: row.get(0)?,
+                    print_weight: row.get(1)?,
+                    print_length: row.get(2)?,
+                    print_time: row.get(3)?,
+                    roll_id: row.get(4)?,
+                    ..Default::default()
+                })
+            })
+            .unwrap();
+
+        assert_eq!(exists_rt2.print_id.unwrap(), test_print.print_id.unwrap());
+        assert_eq!(exists_rt2.print_weight.unwrap(), Weight::from_grams(7.0));
+        assert_eq!(exists_rt2.print_length.unwrap(), Length::from_metres(2.31));
+        assert_eq!(exists_rt2.print_time.unwrap(), 1125);
+        assert_eq!(
+            exists_rt2.roll_id.unwrap(),
+            second_test_spool.roll_id.unwrap()
+        );
+    }
+
+    #[test]
+    fn test_create_remaining() {
+        //Create in memory DB
+        let conn = Connection::open_in_memory().unwrap();
+
+        //Create test spool
+        tbl_creation::create_new_spool_tbl(&conn).unwrap();
+        tbl_creation::create_new_filament_tbl(&conn).unwrap();
+
+        let mut test_spool = Spool {
+            roll_id: Some(Uuid::new_v4()),
+            roll_name: Some(String::from("crealtivity")),
+            roll_weight: Some(Weight::from_grams(1000.0)),
+            roll_length: Some(Length::from_metres(330.0)),
+            timestamp: Some(get_timestamp()),
+            ..Default::default()
+        };
+        let rt = print_add::open_new_spool(&conn, &mut test_spool).unwrap();
+        assert_eq!(rt, 1);
+        //Test print creation
+        let mut test_print = Filament {
+            print_id: Some(Uuid::new_v4()),
+            print_weight: None,
+            print_length: Some(Length::from_metres(2.31)),
+            print_time: Some(1125),
+            ..Default::default()
+        };
+
+        let mut second_test_print = Filament {
+            print_id: Some(Uuid::new_v4()),
+            print_weight: Some(Weight::from_grams(89.6)),
+            print_length: None,
+            print_time: Some(2700),
+            ..Default::default()
+        };
+        let _rt2 = print_add::add_new_print(&conn, &mut test_print).unwrap();
+        let ans = print_stats::check_remaining(&conn);
+        assert_eq!(ans.0, Weight::from_grams(993.0));
+        assert_eq!(ans.1, Length::from_metres(327.69));
+
+        let _rt3 = print_add::add_new_print(&conn, &mut second_test_print).unwrap();
+        let ans = print_stats::check_remaining(&conn);
+        assert_eq!(ans.0, Weight::from_grams(903.4));
+        assert_eq!(ans.1, Length::from_metres(298.122));
+    }
+
+    #[test]
+    fn test_check_lifetime() {
+        //Create in memory DB
+        let conn = Connection::open_in_memory().unwrap();
+
+        //Create test spool
+        tbl_creation::create_new_spool_tbl(&conn).unwrap();
+        tbl_creation::create_new_filament_tbl(&conn).unwrap();
+
+        let mut test_spool = Spool {
+            roll_id: Some(Uuid::new_v4()),
+            roll_name: Some(String::from("crealtivity")),
+            roll_weight: Some(Weight::from_grams(1000.0)),
+            roll_length: Some(Length::from_metres(330.0)),
+            timestamp: Some(get_timestamp()),
+            ..Default::default()
+        };
+        let rt = print_add::open_new_spool(&conn, &mut test_spool).unwrap();
+        assert_eq!(rt, 1);
+        //Test print creation
+        let mut test_print = Filament {
+            print_id: Some(Uuid::new_v4()),
+            print_weight: None,
+            print_length: Some(Length::from_metres(2.31)),
+            print_time: Some(1125),
+            ..Default::default()
+        };
+
+        let mut second_test_print = Filament {
+            print_id: Some(Uuid::new_v4()),
+            print_weight: Some(Weight::from_grams(89.6)),
+            print_length: None,
+            print_time: Some(2700),
+            ..Default::default()
+        };
+        let _rt2 = print_add::add_new_print(&conn, &mut test_print).unwrap();
+        let _rt3 = print_add::add_new_print(&conn, &mut second_test_print).unwrap();
+        let ans = print_stats::lifetime_statistics(&conn);
+        assert_eq!(ans.0, Weight::from_grams(96.6));
+        assert_eq!(ans.1, Length::from_metres(31.878));
+        assert_eq!(ans.2, 3825);
+    }
+
+    #[test]
+    fn test_check_lifetime_empty() {
+        //Create in memory DB
+        let conn = Connection::open_in_memory().unwrap();
+
+        //Create test spool
+        tbl_creation::create_new_spool_tbl(&conn).unwrap();
+        tbl_creation::create_new_filament_tbl(&conn).unwrap();
+
+        let mut test_spool = Spool {
+            roll_id: Some(Uuid::new_v4()),
+            roll_name: Some(String::from("crealtivity")),
+            roll_weight: Some(Weight::from_grams(1000.0)),
+            roll_length: Some(Length::from_metres(330.0)),
+            timestamp: Some(get_timestamp()),
+            ..Default::default()
+        };
+        let rt = print_add::open_new_spool(&conn, &mut test_spool).unwrap();
+        assert_eq!(rt, 1);
+        let ans = print_stats::lifetime_statistics(&conn);
+        assert_eq!(ans.0, Weight::from_grams(0.0));
+        assert_eq!(ans.1, Length::from_metres(0.0));
+        assert_eq!(ans.2, 0);
+    }
+
+    #[test]
+    fn test_check_remaining_empty() {
+        //Create in memory DB
+        let conn = Connection::open_in_memory().unwrap();
+
+        //Create test spool
+        tbl_creation::create_new_spool_tbl(&conn).unwrap();
+        tbl_creation::create_new_filament_tbl(&conn).unwrap();
+
+        let mut test_spool = Spool {
+            roll_id: Some(Uuid::new_v4()),
+            roll_name: Some(String::from("crealtivity")),
+            roll_weight: Some(Weight::from_grams(1000.0)),
+            roll_length: Some(Length::from_metres(330.0)),
+            timestamp: Some(get_timestamp()),
+            ..Default::default()
+        };
+        let rt = print_add::open_new_spool(&conn, &mut test_spool).unwrap();
+        assert_eq!(rt, 1);
+        let ans = print_stats::check_remaining(&conn);
+        assert_eq!(ans.0, Weight::from_grams(1000.0));
+        assert_eq!(ans.1, Length::from_metres(330.0));
+    }
+
+    #[test]
+    fn test_old_tables_get_new_columns() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute(
+            "CREATE TABLE filament(
+                print_id BLOB PRIMARY KEY,
+                print_weight REAL,
+                print_length REAL,
+                print_time INTEGER,
+                roll_id BLOB NOT NULL)",
+            (),
+        )
+        .unwrap();
+        tbl_creation::create_new_filament_tbl(&conn).unwrap();
+        let columns: i32 = conn
+            .query_row(
+                "SELECT count(name) FROM pragma_table_info('filament') WHERE name = 'print_timestamp'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(columns, 1);
+    }
+
+    #[test]
+    fn test_weights_converted_to_integers() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE spool (
+                roll_id BLOB PRIMARY KEY,
+                roll_name TEXT,
+                roll_weight REAL,
+                roll_length REAL,
+                roll_timestamp INTEGER NOT NULL,
+                roll_tare REAL);
+            CREATE TABLE filament(
+                print_id BLOB PRIMARY KEY,
+                print_weight REAL,
+                print_length REAL,
+                print_time INTEGER,
+                roll_id BLOB NOT NULL);
+            PRAGMA user_version = 11;",
+        )
+        .unwrap();
+        let roll_id = Uuid::new_v4();
+        conn.execute(
+            "INSERT INTO spool (roll_id, roll_name, roll_weight, roll_length, roll_timestamp, roll_tare)
+                VALUES (?1, 'PLA Black', 1000.0, 330.0, 1735689600, 182.5)",
+            [roll_id],
+        )
+        .unwrap();
+        for weight in [0.1, 0.2, 12.3456] {
+            conn.execute(
+                "INSERT INTO filament (print_id, print_weight, print_length, print_time, roll_id)
+                    VALUES (?1, ?2, 1.5, 60, ?3)",
+                (Uuid::new_v4(), weight, roll_id),
+            )
+            .unwrap();
+        }
+
+        tbl_creation::setup_database(&conn).unwrap();
+        let (weight, tare, kind): (i64, i64, String) = conn
+            .query_row(
+                "SELECT roll_weight, roll_tare, typeof(roll_weight) FROM spool",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .unwrap();
+        assert_eq!(
+            (weight, tare, kind.as_str()),
+            (1_000_000, 182_500, "integer")
+        );
+        let (used, length): (i64, i64) = conn
+            .query_row(
+                "SELECT SUM(print_weight), SUM(print_length) FROM filament",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!((used, length), (12_646, 4_500));
+        let (remaining, _) = print_stats::remaining_on_spool(&conn, roll_id);
+        assert_eq!(remaining, Weight::from_milligrams(987_354));
+        assert_eq!(format!("{:.1}", remaining), "987.4");
+
+        //Running it again leaves the converted values alone
+        tbl_creation::setup_database(&conn).unwrap();
+        assert_eq!(
+            print_stats::remaining_on_spool(&conn, roll_id).0,
+            Weight::from_milligrams(987_354)
+        );
+    }
+
+    #[test]
+    fn test_usage_statistics() {
+        //Create in memory DB
+        let conn = Connection::open_in_memory().unwrap();
+        tbl_creation::create_new_spool_tbl(&conn).unwrap();
+        tbl_creation::create_new_filament_tbl(&conn).unwrap();
+
+        let mut pla_spool = Spool {
+            roll_id: Some(Uuid::new_v4()),
+            roll_name: Some(String::from("PLA Black")),
+            roll_weight: Some(Weight::from_grams(1000.0)),
+            roll_length: Some(Length::from_metres(330.0)),
+            timestamp: Some(1735689600),
+            roll_material: Some(String::from("PLA")),
+            roll_price: Some(20.0),
+            ..Default::default()
+        };
+        print_add::open_new_spool(&conn, &mut pla_spool).unwrap();
+        //2025-01-05 and 2025-01-20
+        for (weight, timestamp) in [(100.0, 1736035200), (50.0, 1737331200)] {
+            let mut print = Filament {
+                print_id: Some(Uuid::new_v4()),
+                print_weight: Some(Weight::from_grams(weight)),
+                print_time: Some(600),
+                timestamp: Some(timestamp),
+                ..Default::default()
+            };
+            print_add::add_new_print(&conn, &mut print).unwrap();
+        }
+        let mut petg_spool = Spool {
+            roll_id: Some(Uuid::new_v4()),
+            roll_name: Some(String::from("PETG Blue")),
+            roll_weight: Some(Weight::from_grams(1000.0)),
+            timestamp: Some(1738368000),
+            roll_material: Some(String::from("PETG")),
+            ..Default::default()
+        };
+        print_add::open_new_spool(&conn, &mut petg_spool).unwrap();
+        //2025-02-02
+        let mut print = Filament {
+            print_id: Some(Uuid::new_v4()),
+            print_weight: Some(Weight::from_grams(25.0)),
+            print_time: Some(600),
+            timestamp: Some(1738454400),
+            ..Default::default()
+        };
+        print_add::add_new_print(&conn, &mut print).unwrap();
+
+        let usage = print_stats::usage_per_period(&conn, Period::Month).unwrap();
+        assert_eq!(usage.len(), 2);
+        assert_eq!(usage[0].0, "2025-01");
+        assert_eq!(usage[0].1, 150.0);
+        assert_eq!(usage[1].0, "2025-02");
+        assert_eq!(usage[1].1, 25.0);
+
+        let burndown = print_stats::spool_burndown(&conn, pla_spool.roll_id.unwrap()).unwrap();
+        let remaining: Vec<f32> = burndown.iter().map(|row| row.1).collect();
+        assert_eq!(remaining, vec![1000.0, 900.0, 850.0]);
+        assert_eq!(burndown[1].0, "2025-01-05 00:00");
+
+        let shares = print_stats::material_share(&conn).unwrap();
+        assert_eq!(shares[0], (String::from("PLA"), 150.0));
+        assert_eq!(shares[1], (String::from("PETG"), 25.0));
+
+        let found = print_add::find_spool(&conn, "PLA Black").unwrap();
+        assert_eq!(found.roll_id, pla_spool.roll_id.unwrap());
+    }
+
+    #[test]
+    fn test_bar_chart_fits_width() {
+        let rows = vec![
+            (String::from("2025-01"), 150.0, String::from("150.0 g")),
+            (String::from("2025-02"), 75.0, String::from("75.0 g")),
+        ];
+        let rendered = chart::bar_chart(&rows, 40, true);
+        let lines: Vec<&str> = rendered.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines.iter().all(|line| line.chars().count() <= 40));
+        let full = lines[0].matches('#').count();
+        let half = lines[1].matches('#').count();
+        assert_eq!(full, 2 * half);
+    }
+
+    #[test]
+    fn test_sparkline() {
+        assert_eq!(
+            chart::sparkline(&[0.0, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0], 80, false),
+            "▁▂▃▄▅▆▇█"
+        );
+        assert_eq!(chart::sparkline(&[1.0, 1.0], 80, true), "==");
+        assert_eq!(chart::sparkline(&[0.0, 0.0, 7.0, 7.0], 2, true), "_#");
+    }
+
+    fn report_test_db() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        tbl_creation::create_new_spool_tbl(&conn).unwrap();
+        tbl_creation::create_new_filament_tbl(&conn).unwrap();
+        tbl_creation::create_new_move_tbl(&conn).unwrap();
+        tbl_creation::create_new_tag_tbls(&conn).unwrap();
+        let mut test_spool = Spool {
+            roll_id: Some(Uuid::new_v4()),
+            roll_name: Some(String::from("PLA <Black>")),
+            roll_weight: Some(Weight::from_grams(1000.0)),
+            timestamp: Some(1735689600),
+            roll_material: Some(String::from("PLA")),
+            roll_price: Some(20.0),
+            ..Default::default()
+        };
+        print_add::open_new_spool(&conn, &mut test_spool).unwrap();
+        //2025-01-05, 2025-01-20 and 2025-02-02
+        let prints = [
+            ("Benchy", 15.0, 1736035200, PRINT_SUCCESS),
+            ("Bracket", 150.0, 1737331200, PRINT_FAILED),
+            ("Vase", 100.0, 1738454400, PRINT_SUCCESS),
+        ];
+        for (name, weight, timestamp, status) in prints {
+            let mut print = Filament {
+                print_id: Some(Uuid::new_v4()),
+                print_weight: Some(Weight::from_grams(weight)),
+                print_time: Some(3600),
+                timestamp: Some(timestamp),
+                print_name: Some(String::from(name)),
+                print_status: Some(String::from(status)),
+                ..Default::default()
+            };
+            print_add::add_new_print(&conn, &mut print).unwrap();
+        }
+        conn
+    }
+
+    #[test]
+    fn test_report_statistics() {
+        let conn = report_test_db();
+
+        let costs = print_stats::cost_per_spool(&conn, None).unwrap();
+        assert_eq!(costs.len(), 1);
+        assert_eq!(costs[0].1, 265.0);
+        assert_eq!(costs[0].2, 5.3);
+
+        let top = print_stats::top_prints(&conn, 2, None).unwrap();
+        assert_eq!(top.len(), 2);
+        assert_eq!(top[0].0.print_name.as_deref(), Some("Bracket"));
+        assert_eq!(top[0].1, Some(3.0));
+        assert_eq!(top[1].0.print_name.as_deref(), Some("Vase"));
+
+        let january = print_stats::top_prints(&conn, 10, Some("2025-01")).unwrap();
+        assert_eq!(january.len(), 2);
+
+        let failures = print_stats::failure_rates(&conn, None).unwrap();
+        assert_eq!(
+            failures,
+            vec![
+                (String::from("2025-01"), 2, 1),
+                (String::from("2025-02"), 1, 0)
+            ]
+        );
+
+        let inventory = print_stats::spool_inventory(&conn).unwrap();
+        assert_eq!(inventory.len(), 1);
+        assert_eq!(inventory[0].remaining_weight(), Weight::from_grams(735.0));
+    }
+
+    #[test]
+    fn test_html_report() {
+        let conn = report_test_db();
+        let config = Config {
+            currency: Some(String::from("€")),
+            ..Default::default()
+        };
+        let html = report::html_report(&conn, Some("2025-01"), &config).unwrap();
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert!(html.contains("<title>Filament report 2025-01</title>"));
+        for section in [
+            "Inventory",
+            "Usage per month",
+            "Cost breakdown",
+            "Top jobs by filament",
+            "Failure rates",
+        ] {
+            assert!(html.contains(&format!("<h2>{}</h2>", section)));
+        }
+        assert!(html.contains("<svg"));
+        assert!(html.contains("PLA &lt;Black&gt;"));
+        assert!(html.contains("Bracket"));
+        assert!(!html.contains("Vase"));
+        assert!(!html.contains("<link") && !html.contains("src="));
+        //Prices and costs use the configured currency like the rest of the program
+        assert!(html.contains("<td class=\"num\">€20.00</td>"));
+        assert!(!html.contains("<td class=\"num\">20.00</td>"));
+    }
+
+    #[test]
+    fn test_parse_month() {
+        assert_eq!(report::parse_month("2025-01").unwrap(), "2025-01");
+        assert!(report::parse_month("2025-13").is_err());
+        assert!(report::parse_month("2025-1").is_err());
+        assert!(report::parse_month("January").is_err());
+    }
+
+    #[test]
+    fn test_csv_round_trip() {
+        let conn = report_test_db();
+        let mut spools = Vec::new();
+        csv_io::export_csv(&conn, Table::Spool, &mut spools).unwrap();
+        let mut prints = Vec::new();
+        let count = csv_io::export_csv(&conn, Table::Filament, &mut prints).unwrap();
+        assert_eq!(count, 3);
+        let spools_text = String::from_utf8(spools.clone()).unwrap();
+        assert!(spools_text.starts_with(
+            "id,name,material,colour,colour_hex,weight_g,length_m,diameter_mm,price,tare_g,created,notes\n"
+        ));
+        assert!(spools_text.contains(",2025-01-01T00:00:00Z"));
+
+        let copy = Connection::open_in_memory().unwrap();
+        tbl_creation::create_new_spool_tbl(&copy).unwrap();
+        tbl_creation::create_new_filament_tbl(&copy).unwrap();
+        let summary =
+            csv_io::import_csv(&copy, Table::Spool, spools.as_slice(), &[], false).unwrap();
+        assert_eq!(summary.inserted, 1);
+        let summary =
+            csv_io::import_csv(&copy, Table::Filament, prints.as_slice(), &[], false).unwrap();
+        assert_eq!(summary.inserted, 3);
+        assert_eq!(
+            print_stats::lifetime_statistics(&copy),
+            print_stats::lifetime_statistics(&conn)
+        );
+
+        //Importing the same rows again only finds duplicates
+        let summary =
+            csv_io::import_csv(&copy, Table::Filament, prints.as_slice(), &[], false).unwrap();
+        assert_eq!(summary.inserted, 0);
+        assert_eq!(summary.duplicates.len(), 3);
+    }
+
+    #[test]
+    fn test_csv_import_mapping_and_dry_run() {
+        let conn = Connection::open_in_memory().unwrap();
+        tbl_creation::create_new_spool_tbl(&conn).unwrap();
+        tbl_creation::create_new_filament_tbl(&conn).unwrap();
+        let sheet = "Spool Name,Weight (g),Bought,Type\nPLA Black,1000,2025-01-01,PLA\n";
+        let mappings = vec![String::from("Bought=created")];
+
+        let summary =
+            csv_io::import_csv(&conn, Table::Spool, sheet.as_bytes(), &mappings, true).unwrap();
+        assert_eq!(summary.inserted, 1);
+        assert!(print_stats::list_spools(&conn).unwrap().is_empty());
+
+        csv_io::import_csv(&conn, Table::Spool, sheet.as_bytes(), &mappings, false).unwrap();
+        let spools = print_stats::list_spools(&conn).unwrap();
+        assert_eq!(spools.len(), 1);
+        assert_eq!(spools[0].roll_name.as_deref(), Some("PLA Black"));
+        assert_eq!(spools[0].roll_material.as_deref(), Some("PLA"));
+        assert_eq!(spools[0].roll_length, Some(Length::from_metres(330.0)));
+        assert_eq!(spools[0].timestamp, Some(1735689600));
+    }
+
+    #[test]
+    fn test_csv_import_invalid_rows() {
+        let conn = Connection::open_in_memory().unwrap();
+        tbl_creation::create_new_spool_tbl(&conn).unwrap();
+        tbl_creation::create_new_filament_tbl(&conn).unwrap();
+        let sheet =
+            "name,weight_g,created\nGood,1000,2025-01-01\nBad,-5,2025-01-01\nLate,1000,yesterday\n";
+        let errors = csv_io::import_csv(&conn, Table::Spool, sheet.as_bytes(), &[], false)
+            .err()
+            .unwrap();
+        assert_eq!(errors.len(), 2);
+        assert!(errors[0].starts_with("line 3:"));
+        assert!(errors[1].starts_with("line 4:"));
+        assert!(print_stats::list_spools(&conn).unwrap().is_empty());
+
+        let prints = "spool,weight\nMissing,10\n";
+        let errors = csv_io::import_csv(&conn, Table::Filament, prints.as_bytes(), &[], false)
+            .err()
+            .unwrap();
+        assert_eq!(
+            errors,
+            vec![String::from("line 2: spool Missing does not exist")]
+        );
+    }
+
+    #[test]
+    fn test_parse_timestamp() {
+        assert_eq!(parse_timestamp("2025-01-01"), Some(1735689600));
+        assert_eq!(parse_timestamp("2025-01-01T01:00:00Z"), Some(1735693200));
+        assert_eq!(
+            parse_timestamp("2025-01-01T02:00:00+01:00"),
+            Some(1735693200)
+        );
+        assert_eq!(parse_timestamp("2025-01-01 01:00"), Some(1735693200));
+        assert_eq!(parse_timestamp("not a date"), None);
+        assert_eq!(format_iso_timestamp(1735693200), "2025-01-01T01:00:00Z");
+    }
+
+    #[test]
+    fn test_spoolman_json_import() {
+        let conn = Connection::open_in_memory().unwrap();
+        tbl_creation::create_new_spool_tbl(&conn).unwrap();
+        tbl_creation::create_new_filament_tbl(&conn).unwrap();
+        let export = r#"[{
+            "id": 4,
+            "registered": "2024-06-01T10:00:00Z",
+            "last_used": "2024-07-01T10:00:00Z",
+            "filament": {
+                "id": 2,
+                "name": "PLA Basic Black",
+                "vendor": {"id": 1, "name": "Bambu"},
+                "material": "PLA",
+                "price": 25.0,
+                "density": 1.24,
+                "diameter": 1.75,
+                "weight": 1000.0
+            },
+            "remaining_weight": 750.0,
+            "used_weight": 250.0,
+            "used_length": 83000.0,
+            "archived": false,
+            "extra": {}
+        }]"#;
+        let spools = spoolman::read_spoolman(SpoolmanFormat::Json, export.as_bytes()).unwrap();
+        let summary = spoolman::import_spoolman(&conn, &spools, false).unwrap();
+        assert_eq!(summary.spools, 1);
+        assert_eq!(summary.usage_records, 1);
+
+        let imported = print_stats::list_spools(&conn).unwrap();
+        assert_eq!(
+            imported[0].roll_name.as_deref(),
+            Some("Bambu PLA Basic Black")
+        );
+        assert_eq!(imported[0].roll_price, Some(25.0));
+        assert_eq!(
+            print_stats::check_remaining(&conn),
+            (Weight::from_grams(750.0), Length::from_metres(247.0))
+        );
+
+        //The same export is recognised by the Spoolman id
+        let summary = spoolman::import_spoolman(&conn, &spools, false).unwrap();
+        assert_eq!(summary.spools, 0);
+        assert_eq!(summary.duplicates.len(), 1);
+    }
+
+    #[test]
+    fn test_spoolman_round_trip() {
+        let conn = report_test_db();
+        for format in [SpoolmanFormat::Json, SpoolmanFormat::Csv] {
+            let mut export = Vec::new();
+            let count = spoolman::export_spoolman(&conn, format, &mut export).unwrap();
+            assert_eq!(count, 1);
+
+            let copy = Connection::open_in_memory().unwrap();
+            tbl_creation::create_new_spool_tbl(&copy).unwrap();
+            tbl_creation::create_new_filament_tbl(&copy).unwrap();
+            let spools = spoolman::read_spoolman(format, export.as_slice()).unwrap();
+            assert_eq!(spools[0].used_weight, 265.0);
+            assert_eq!(spools[0].remaining_weight, Some(735.0));
+            let dry = spoolman::import_spoolman(&copy, &spools, true).unwrap();
+            assert_eq!(dry.spools, 1);
+            assert!(print_stats::list_spools(&copy).unwrap().is_empty());
+
+            spoolman::import_spoolman(&copy, &spools, false).unwrap();
+            let original = print_stats::list_spools(&conn).unwrap();
+            let imported = print_stats::list_spools(&copy).unwrap();
+            assert_eq!(imported[0].roll_id, original[0].roll_id);
+            assert_eq!(imported[0].roll_name, original[0].roll_name);
+            assert_eq!(
+                print_stats::check_remaining(&copy).0,
+                Weight::from_grams(735.0)
+            );
+        }
+    }
+
+    fn temp_dir() -> PathBuf {
+        let dir = env::temp_dir().join(format!("remainder-test-{}", Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_schema_version() {
+        let conn = Connection::open_in_memory().unwrap();
+        assert!(!tbl_creation::needs_migration(&conn).unwrap());
+        tbl_creation::setup_database(&conn).unwrap();
+        assert_eq!(
+            tbl_creation::schema_version(&conn).unwrap(),
+            tbl_creation::SCHEMA_VERSION
+        );
+
+        //Tables made before there was a schema version
+        let old = Connection::open_in_memory().unwrap();
+        tbl_creation::create_new_spool_tbl(&old).unwrap();
+        assert!(tbl_creation::needs_migration(&old).unwrap());
+        tbl_creation::setup_database(&old).unwrap();
+        assert!(!tbl_creation::needs_migration(&old).unwrap());
+    }
+
+    #[test]
+    fn test_backup_and_restore() {
+        let dir = temp_dir();
+        let mut conn = Connection::open(dir.join("live.db")).unwrap();
+        tbl_creation::setup_database(&conn).unwrap();
+        audit::start(&conn, "alice").unwrap();
+        let mut test_spool = Spool {
+            roll_id: Some(Uuid::new_v4()),
+            roll_name: Some(String::from("crealtivity")),
+            roll_weight: Some(Weight::from_grams(1000.0)),
+            timestamp: Some(get_timestamp()),
+            ..Default::default()
+        };
+        print_add::open_new_spool(&conn, &mut test_spool).unwrap();
+
+        let backup_path = dir.join("backup.db");
+        backup::backup_to(&conn, &backup_path).unwrap();
+        assert!(backup::backup_to(&conn, &backup_path).is_err());
+        assert_eq!(
+            backup::check_backup(&backup_path).unwrap(),
+            tbl_creation::SCHEMA_VERSION
+        );
+
+        let mut test_print = Filament {
+            print_id: Some(Uuid::new_v4()),
+            print_weight: Some(Weight::from_grams(100.0)),
+            print_time: Some(60),
+            ..Default::default()
+        };
+        print_add::add_new_print(&conn, &mut test_print).unwrap();
+        assert_eq!(
+            print_stats::check_remaining(&conn).0,
+            Weight::from_grams(900.0)
+        );
+
+        backup::restore_from(&mut conn, &backup_path, "alice").unwrap();
+        assert_eq!(
+            print_stats::check_remaining(&conn).0,
+            Weight::from_grams(1000.0)
+        );
+        //The print is gone but the log still says it was added, and who restored
+        let all = audit::HistoryFilter {
+            limit: 10,
+            ..Default::default()
+        };
+        let actions: Vec<_> = audit::history(&conn, &all)
+            .unwrap()
+            .into_iter()
+            .map(|entry| (entry.action, entry.kind))
+            .collect();
+        assert_eq!(
+            actions,
+            [
+                (AuditAction::Restore, AuditKind::Database),
+                (AuditAction::Create, AuditKind::Print),
+                (AuditAction::Create, AuditKind::Spool),
+            ]
+        );
+        //Changes after the restore are still logged
+        print_add::add_new_print(&conn, &mut test_print).unwrap();
+        assert_eq!(audit::history(&conn, &all).unwrap().len(), 4);
+
+        //Backups from a newer schema and files which are not databases are refused
+        let newer_path = dir.join("newer.db");
+        backup::backup_to(&conn, &newer_path).unwrap();
+        let newer = Connection::open(&newer_path).unwrap();
+        newer
+            .pragma_update(None, "user_version", tbl_creation::SCHEMA_VERSION + 1)
+            .unwrap();
+        newer.close().unwrap();
+        assert!(backup::restore_from(&mut conn, &newer_path, "alice").is_err());
+        let text_path = dir.join("notes.txt");
+        fs::write(&text_path, "not a database at all").unwrap();
+        assert!(backup::check_backup(&text_path).is_err());
+        assert!(backup::check_backup(&dir.join("missing.db")).is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_auto_backup_rotation() {
+        let dir = temp_dir();
+        let conn = Connection::open(dir.join("live.db")).unwrap();
+        tbl_creation::setup_database(&conn).unwrap();
+        let backup_dir = dir.join("backups");
+        fs::create_dir_all(&backup_dir).unwrap();
+        backup::backup_to(&conn, &backup_dir.join("manual.db")).unwrap();
+        let mut made = Vec::new();
+        for _ in 0..backup::MAX_AUTO_BACKUPS + 2 {
+            made.push(backup::auto_backup(&conn, &backup_dir, "test").unwrap());
+        }
+        let remaining = fs::read_dir(&backup_dir).unwrap().count();
+        assert_eq!(remaining, backup::MAX_AUTO_BACKUPS + 1);
+        assert!(backup_dir.join("manual.db").exists());
+        assert!(made.last().unwrap().exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_integrity_check() {
+        let conn = report_test_db();
+        assert!(backup::integrity_check(&conn).unwrap().is_empty());
+        conn.execute("DELETE FROM spool", ()).unwrap();
+        let problems = backup::integrity_check(&conn).unwrap();
+        assert_eq!(
+            problems,
+            vec![String::from(
+                "3 prints belong to a spool which does not exist"
+            )]
+        );
+        assert!(backup::vacuum(&conn).is_ok());
+    }
+
+    //Answers one request per body in order, handing back the request heads it saw
+    fn mock_http_server(bodies: Vec<String>) -> (String, std::thread::JoinHandle<Vec<String>>) {
+        mock_http_responses(bodies.into_iter().map(|body| (200, body)).collect())
+    }
+
+    //Answers each request with the next status and body, then hands back the requests
+    fn mock_http_responses(
+        responses: Vec<(u16, String)>,
+    ) -> (String, std::thread::JoinHandle<Vec<String>>) {
+        use std::io::{BufRead, BufReader, Write};
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let handle = std::thread::spawn(move || {
+            let mut requests = Vec::new();
+            for (status, body) in responses {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                let mut header = String::new();
+                while reader.read_line(&mut header).unwrap() > 2 {
+                    request_line.push_str(&header);
+                    header.clear();
+                }
+                requests.push(request_line.trim().to_string());
+                write!(
+                    stream,
+                    "HTTP/1.1 {} OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                )
+                .unwrap();
+            }
+            requests
+        });
+        (url, handle)
+    }
+
+    fn printer_test_db() -> (Connection, Uuid, Uuid) {
+        let conn = Connection::open_in_memory().unwrap();
+        tbl_creation::setup_database(&conn).unwrap();
+        let mut ids = Vec::new();
+        for (name, timestamp) in [("PLA Black", 1735689600), ("PETG Blue", 1735776000)] {
+            let mut spool = Spool {
+                roll_id: Some(Uuid::new_v4()),
+                roll_name: Some(String::from(name)),
+                roll_weight: Some(Weight::from_grams(1000.0)),
+                timestamp: Some(timestamp),
+                ..Default::default()
+            };
+            print_add::open_new_spool(&conn, &mut spool).unwrap();
+            ids.push(spool.roll_id.unwrap());
+        }
+        (conn, ids[0], ids[1])
+    }
+
+    #[test]
+    fn test_load_spool() {
+        let (conn, older, newer) = printer_test_db();
+        assert_eq!(
+            print_add::spool_for_printer(&conn, "voron")
+                .unwrap()
+                .roll_id,
+            newer
+        );
+        print_add::load_spool(&conn, "voron", older).unwrap();
+        assert_eq!(
+            print_add::spool_for_printer(&conn, "voron")
+                .unwrap()
+                .roll_id,
+            older
+        );
+        assert_eq!(
+            print_add::spool_for_printer(&conn, "prusa")
+                .unwrap()
+                .roll_id,
+            newer
+        );
+        print_add::load_spool(&conn, "voron", newer).unwrap();
+        assert_eq!(
+            print_add::spool_for_printer(&conn, "voron")
+                .unwrap()
+                .roll_id,
+            newer
+        );
+        assert_eq!(
+            print_stats::loaded_printers(&conn).unwrap(),
+            vec![(String::from("voron"), newer)]
+        );
+        print_add::load_spool(&conn, "voron", older).unwrap();
+        print_add::unload_spool(&conn, "voron").unwrap();
+        assert_eq!(
+            print_add::spool_for_printer(&conn, "voron")
+                .unwrap()
+                .roll_id,
+            newer
+        );
+        assert!(print_stats::loaded_printers(&conn).unwrap().is_empty());
+    }
+
+    fn moonraker_job(job_id: &str, status: &str, start_time: f64, filament_used: f64) -> String {
+        format!(
+            r#"{{"job_id": "{}", "exists": true, "filename": "part_{}.gcode", "status": "{}", "start_time": {}, "end_time": {}, "print_duration": 3600.0, "total_duration": 3700.0, "filament_used": {}, "metadata": {{}}}}"#,
+            job_id,
+            job_id,
+            status,
+            start_time,
+            start_time + 3700.0,
+            filament_used
+        )
+    }
+
+    #[test]
+    fn test_moonraker_sync() {
+        let (conn, older, _) = printer_test_db();
+        print_add::load_spool(&conn, "voron", older).unwrap();
+        let roll_id = print_add::spool_for_printer(&conn, "voron")
+            .unwrap()
+            .roll_id;
+        let first = format!(
+            r#"{{"result": {{"count": 3, "jobs": [{}, {}, {}]}}}}"#,
+            moonraker_job("00000A", "completed", 1736035200.0, 3300.0),
+            moonraker_job("00000B", "cancelled", 1736121600.0, 330.0),
+            moonraker_job("00000C", "in_progress", 1736208000.0, 100.0)
+        );
+        let second = format!(
+            r#"{{"result": {{"count": 2, "jobs": [{}, {}]}}}}"#,
+            moonraker_job("00000B", "cancelled", 1736121600.0, 330.0),
+            moonraker_job("00000C", "completed", 1736208000.0, 6600.0)
+        );
+        let third = String::from(r#"{"result": {"count": 0, "jobs": []}}"#);
+        let (url, server) = mock_http_server(vec![first, second, third]);
+
+        let summary = sync_moonraker::sync_moonraker(&conn, &url, roll_id).unwrap();
+        assert_eq!(summary.imported, 2);
+        let prints = print_stats::list_prints(&conn).unwrap();
+        assert_eq!(prints.len(), 2);
+        assert_eq!(prints[0].print_length, Some(Length::from_metres(3.3)));
+        assert_eq!(prints[0].print_time, Some(3600));
+        assert_eq!(prints[0].print_name.as_deref(), Some("part_00000A.gcode"));
+        assert_eq!(prints[0].timestamp, Some(1736038900));
+        assert_eq!(prints[1].print_status.as_deref(), Some(PRINT_CANCELLED));
+        assert!(prints.iter().all(|print| print.roll_id == Some(older)));
+
+        //The job which was still printing is picked up once it has finished
+        let summary = sync_moonraker::sync_moonraker(&conn, &url, roll_id).unwrap();
+        assert_eq!(summary.imported, 1);
+        assert_eq!(summary.skipped, 1);
+        let summary = sync_moonraker::sync_moonraker(&conn, &url, roll_id).unwrap();
+        assert_eq!(summary.imported, 0);
+        assert_eq!(print_stats::list_prints(&conn).unwrap().len(), 3);
+
+        let requests = server.join().unwrap();
+        assert!(requests[0].starts_with("GET /server/history/list?order=asc"));
+        assert!(!requests[0].contains("since"));
+        assert!(requests[1].contains("since=1736121600"));
+        assert!(requests[2].contains("since=1736208000"));
+    }
+
+    fn octoprint_file(path: &str, analysis: &str, prints: &str) -> String {
+        format!(
+            r#"{{"name": "{}", "path": "{}", "type": "machinecode", "origin": "local", "gcodeAnalysis": {}, "prints": {}}}"#,
+            path.rsplit('/').next().unwrap(),
+            path,
+            analysis,
+            prints
+        )
+    }
+
+    #[test]
+    fn test_octoprint_sync() {
+        let (conn, older, _) = printer_test_db();
+        let bracket = |prints: &str| {
+            octoprint_file(
+                "brackets/bracket.gcode",
+                r#"{"estimatedPrintTime": 3000.0, "filament": {"tool0": {"length": 4000.0, "volume": 9.6}}}"#,
+                prints,
+            )
+        };
+        //Only the volume is known and the print failed half way through
+        let vase = |prints: &str| {
+            octoprint_file(
+                "vase.gcode",
+                r#"{"estimatedPrintTime": 7200.0, "filament": {"tool0": {"volume": 24.0532}}}"#,
+                prints,
+            )
+        };
+        let unprinted = octoprint_file("new.gcode", r#"{"filament": {}}"#, "null");
+        let files = |bracket: &str, vase: &str| {
+            let body = format!(
+                r#"{{"files": [{{"name": "brackets", "path": "brackets", "type": "folder", "children": [{}]}}, {}, {}], "free": 1000}}"#,
+                bracket, vase, unprinted
+            );
+            (200, body)
+        };
+        let once = bracket(
+            r#"{"success": 2, "failure": 0, "last": {"date": 1736035200.0, "printTime": 2950.0, "success": true}}"#,
+        );
+        //Printed twice more between syncs, only the last of them has its details
+        let twice_more = bracket(
+            r#"{"success": 4, "failure": 0, "last": {"date": 1736294400.0, "printTime": 2900.0, "success": true}}"#,
+        );
+        let failed = vase(
+            r#"{"success": 0, "failure": 1, "last": {"date": 1736121600.0, "printTime": 3600.0, "success": false}}"#,
+        );
+        let reprinted = vase(
+            r#"{"success": 1, "failure": 1, "last": {"date": 1736208000.0, "printTime": 7100.0, "success": true}}"#,
+        );
+        //Without the plugin there is no history to fetch
+        let no_plugin = (404, String::from(r#"{"error": "Not found"}"#));
+        let (url, server) = mock_http_responses(vec![
+            files(&once, &failed),
+            no_plugin.clone(),
+            files(&once, &failed),
+            no_plugin.clone(),
+            files(&twice_more, &reprinted),
+            no_plugin.clone(),
+        ]);
+
+        //Both of the bracket's prints are counted, the first with the slicer's estimate
+        let summary = sync_octoprint::sync_octoprint(&conn, &url, "secret", older).unwrap();
+        assert_eq!(summary.imported, 3);
+        let prints = print_stats::list_prints(&conn).unwrap();
+        assert_eq!(prints.len(), 3);
+        assert_eq!(prints[0].print_name.as_deref(), Some("bracket.gcode"));
+        assert_eq!(prints[0].print_time, Some(3000));
+        assert_eq!(prints[1].print_name.as_deref(), Some("bracket.gcode"));
+        assert_eq!(prints[1].print_length, Some(Length::from_metres(4.0)));
+        assert_eq!(prints[1].print_time, Some(2950));
+        assert_eq!(prints[1].timestamp, Some(1736035200));
+        assert_eq!(prints[2].print_status.as_deref(), Some(PRINT_FAILED));
+        assert!((prints[2].print_length.unwrap().metres() - 5.0).abs() < 0.01);
+
+        let summary = sync_octoprint::sync_octoprint(&conn, &url, "secret", older).unwrap();
+        assert_eq!(summary.imported, 0);
+        assert_eq!(summary.skipped, 2);
+        let summary = sync_octoprint::sync_octoprint(&conn, &url, "secret", older).unwrap();
+        assert_eq!(summary.imported, 3);
+        let prints = print_stats::list_prints(&conn).unwrap();
+        assert_eq!(prints.len(), 6);
+        assert!((prints[3].print_length.unwrap().metres() - 10.0).abs() < 0.01);
+        let brackets: Vec<&Filament> = prints
+            .iter()
+            .filter(|print| print.print_name.as_deref() == Some("bracket.gcode"))
+            .collect();
+        assert_eq!(brackets.len(), 4);
+        assert_eq!(brackets[3].print_time, Some(2900));
+        assert_eq!(brackets[3].timestamp, Some(1736294400));
+
+        let requests = server.join().unwrap();
+        assert!(requests[0].starts_with("GET /api/files?recursive=true"));
+        assert!(requests[0].to_lowercase().contains("x-api-key: secret"));
+        assert!(requests[1].starts_with("GET /plugin/printhistory/history"));
+    }
+
+    #[test]
+    fn test_octoprint_history_plugin() {
+        let (conn, older, _) = printer_test_db();
+        let bracket = octoprint_file(
+            "brackets/bracket.gcode",
+            r#"{"estimatedPrintTime": 3000.0, "filament": {"tool0": {"length": 4000.0}}}"#,
+            r#"{"success": 3, "failure": 0, "last": {"date": 1736294400.0, "printTime": 2900.0, "success": true}}"#,
+        );
+        let files = (200, format!(r#"{{"files": [{}]}}"#, bracket));
+        let job = |start: f64, time: f64, success: bool, length: &str| {
+            format!(
+                r#"{{"fileName": "brackets/bracket.gcode", "timestamp": {}, "printTime": {}, "success": {}, "filamentLength": {}}}"#,
+                start, time, success, length
+            )
+        };
+        let first = job(1736032250.0, 2950.0, true, "3900.0");
+        //The same file printed twice between syncs, the second stopped half way
+        let second = job(1736200000.0, 3000.0, true, "null");
+        let third = job(1736291500.0, 1500.0, false, "null");
+        let history = |jobs: &[&String]| {
+            let jobs: Vec<&str> = jobs.iter().map(|job| job.as_str()).collect();
+            (200, format!(r#"{{"history": [{}]}}"#, jobs.join(", ")))
+        };
+        let (url, server) = mock_http_responses(vec![
+            files.clone(),
+            history(&[&first]),
+            files,
+            history(&[&first, &second, &third]),
+        ]);
+
+        let summary = sync_octoprint::sync_octoprint(&conn, &url, "secret", older).unwrap();
+        assert_eq!(summary.imported, 1);
+        let summary = sync_octoprint::sync_octoprint(&conn, &url, "secret", older).unwrap();
+        assert_eq!(summary.imported, 2);
+        assert_eq!(summary.skipped, 1);
+
+        let prints = print_stats::list_prints(&conn).unwrap();
+        assert_eq!(prints.len(), 3);
+        assert!(prints
+            .iter()
+            .all(|print| print.print_name.as_deref() == Some("bracket.gcode")));
+        //Known by when they finished, with the plugin's length when it measured one
+        assert_eq!(prints[0].timestamp, Some(1736035200));
+        assert_eq!(prints[0].print_length, Some(Length::from_metres(3.9)));
+        assert_eq!(prints[1].timestamp, Some(1736203000));
+        assert_eq!(prints[1].print_length, Some(Length::from_metres(4.0)));
+        assert_eq!(prints[2].print_status.as_deref(), Some(PRINT_FAILED));
+        assert_eq!(prints[2].print_length, Some(Length::from_metres(2.0)));
+
+        let requests = server.join().unwrap();
+        assert!(requests[1].to_lowercase().contains("x-api-key: secret"));
+    }
+
+    #[test]
+    fn test_tray_names() {
+        assert_eq!(watch_bambu::parse_tray("a1"), Ok(0));
+        assert_eq!(watch_bambu::parse_tray("D4"), Ok(15));
+        assert_eq!(
+            watch_bambu::parse_tray("ext"),
+            Ok(watch_bambu::EXTERNAL_TRAY)
+        );
+        assert!(watch_bambu::parse_tray("E1").is_err());
+        assert!(watch_bambu::parse_tray("A5").is_err());
+        for tray in [0, 5, 15, watch_bambu::EXTERNAL_TRAY] {
+            assert_eq!(
+                watch_bambu::parse_tray(&watch_bambu::tray_name(tray)),
+                Ok(tray)
+            );
+        }
+    }
+
+    fn mqtt_read(stream: &mut std::net::TcpStream) -> (u8, Vec<u8>) {
+        use std::io::Read;
+        let mut byte = [0u8; 1];
+        stream.read_exact(&mut byte).unwrap();
+        let packet_type = byte[0];
+        let (mut length, mut shift) = (0usize, 0);
+        loop {
+            stream.read_exact(&mut byte).unwrap();
+            length |= ((byte[0] & 0x7f) as usize) << shift;
+            shift += 7;
+            if byte[0] & 0x80 == 0 {
+                break;
+            }
+        }
+        let mut body = vec![0u8; length];
+        stream.read_exact(&mut body).unwrap();
+        (packet_type, body)
+    }
+
+    fn mqtt_write(stream: &mut std::net::TcpStream, packet_type: u8, body: &[u8]) {
+        use std::io::Write;
+        let mut packet = vec![packet_type];
+        let mut length = body.len();
+        loop {
+            let mut byte = (length % 128) as u8;
+            length /= 128;
+            if length > 0 {
+                byte |= 0x80;
+            }
+            packet.push(byte);
+            if length == 0 {
+                break;
+            }
+        }
+        packet.extend_from_slice(body);
+        stream.write_all(&packet).unwrap();
+    }
+
+    //Stands in for the printer's broker, publishing the reports once the client has
+    //subscribed and then hanging up
+    fn mqtt_broker_stub(
+        serial: &'static str,
+        reports: Vec<String>,
+    ) -> (u16, std::thread::JoinHandle<Vec<Vec<u8>>>) {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let handle = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut received = Vec::new();
+            let (packet_type, connect) = mqtt_read(&mut stream);
+            assert_eq!(packet_type, 0x10);
+            received.push(connect);
+            mqtt_write(&mut stream, 0x20, &[0, 0]);
+            while received.len() < 3 {
+                let (packet_type, body) = mqtt_read(&mut stream);
+                if packet_type & 0xf0 == 0x80 {
+                    mqtt_write(&mut stream, 0x90, &[body[0], body[1], 0]);
+                }
+                if packet_type & 0xf0 != 0xc0 {
+                    received.push(body);
+                }
+            }
+            let topic = format!("device/{}/report", serial);
+            for report in reports {
+                let mut body = (topic.len() as u16).to_be_bytes().to_vec();
+                body.extend_from_slice(topic.as_bytes());
+                body.extend_from_slice(report.as_bytes());
+                mqtt_write(&mut stream, 0x30, &body);
+            }
+            received
+        });
+        (port, handle)
+    }
+
+    #[test]
+    fn test_bambu_watch() {
+        let (conn, older, newer) = printer_test_db();
+        print_add::load_tray(&conn, "x1c", 0, older).unwrap();
+        let full_report = r#"{"print": {"command": "push_status", "gcode_state": "IDLE", "subtask_name": "", "print_error": 0,
+            "ams": {"tray_now": "255", "ams": [{"id": "0", "tray": [
+                {"id": "0", "remain": 80, "tray_weight": "1000", "tray_type": "PLA"},
+                {"id": "1", "remain": 50, "tray_weight": "1000", "tray_type": "PETG"}]}]},
+            "vt_tray": {"id": "254", "remain": -1, "tray_weight": "0"}}}"#;
+        let reports = [
+            full_report,
+            r#"{"print": {"gcode_state": "RUNNING", "subtask_name": "Benchy", "ams": {"tray_now": "0"}}}"#,
+            r#"{"print": {"ams": {"tray_now": "1"}}}"#,
+            r#"{"info": {"command": "get_version"}}"#,
+            r#"{"print": {"ams": {"ams": [{"id": "0", "tray": [{"id": "0", "remain": 78}, {"id": "1", "remain": 49}]}]}}}"#,
+            r#"{"print": {"gcode_state": "FINISH"}}"#,
+            r#"{"print": {"gcode_state": "PREPARE", "subtask_name": "Vase", "ams": {"tray_now": "0"}}}"#,
+            r#"{"print": {"gcode_state": "RUNNING"}}"#,
+            r#"{"print": {"gcode_state": "FAILED", "print_error": 50348044}}"#,
+        ];
+        let (port, broker) =
+            mqtt_broker_stub("01S00A123", reports.iter().map(|r| r.to_string()).collect());
+        let options = watch_bambu::BambuOptions {
+            host: String::from("127.0.0.1"),
+            port,
+            serial: String::from("01S00A123"),
+            access_code: String::from("12345678"),
+            plain: true,
+        };
+        let mut tracker = watch_bambu::BambuTracker::default();
+        //Ends when the broker hangs up
+        assert!(watch_bambu::watch_bambu(&conn, "x1c", &options, &mut tracker).is_err());
+
+        let received = broker.join().unwrap();
+        let contains = |haystack: &[u8], needle: &str| {
+            haystack
+                .windows(needle.len())
+                .any(|window| window == needle.as_bytes())
+        };
+        assert!(contains(&received[0], "bblp") && contains(&received[0], "12345678"));
+        assert!(contains(&received[1], "device/01S00A123/report"));
+        assert!(contains(&received[2], "pushall"));
+
+        let prints = print_stats::list_prints(&conn).unwrap();
+        assert_eq!(prints.len(), 3);
+        let benchy: Vec<&Filament> = prints
+            .iter()
+            .filter(|print| print.print_name.as_deref() == Some("Benchy"))
+            .collect();
+        assert_eq!(benchy.len(), 2);
+        assert!(benchy
+            .iter()
+            .all(|print| print.print_status.as_deref() == Some(PRINT_SUCCESS)));
+        let from_a1 = benchy
+            .iter()
+            .find(|print| print.roll_id == Some(older))
+            .unwrap();
+        assert!((from_a1.print_weight.unwrap().grams() - 20.0).abs() < 0.01);
+        //Nothing was loaded into A2 so it used the printer's spool
+        let from_a2 = benchy
+            .iter()
+            .find(|print| print.roll_id == Some(newer))
+            .unwrap();
+        assert!((from_a2.print_weight.unwrap().grams() - 10.0).abs() < 0.01);
+        let vase = prints
+            .iter()
+            .find(|print| print.print_name.as_deref() == Some("Vase"))
+            .unwrap();
+        assert_eq!(vase.print_status.as_deref(), Some(PRINT_CANCELLED));
+        assert_eq!(vase.roll_id, Some(older));
+        assert_eq!(vase.print_weight, Some(Weight::from_grams(0.0)));
+    }
+
+    #[test]
+    fn test_bambu_certificate_pinning() {
+        let (conn, _, _) = printer_test_db();
+        //Nothing is pinned yet, so the first certificate is taken and kept for pinning
+        assert_eq!(
+            watch_bambu::pinned_certificate(&conn, "01S00A123").unwrap(),
+            None
+        );
+        let first = watch_bambu::PrinterCertificate::new(None);
+        assert!(first.check(b"printer").is_ok());
+        let seen = first.unpinned().unwrap();
+        assert_eq!(first.unpinned(), None);
+        watch_bambu::pin_certificate(&conn, "01S00A123", &seen).unwrap();
+
+        let pinned = watch_bambu::pinned_certificate(&conn, "01S00A123").unwrap();
+        let later = watch_bambu::PrinterCertificate::new(pinned);
+        assert!(later.check(b"printer").is_ok());
+        assert!(later.check(b"impostor").is_err());
+        assert_eq!(later.unpinned(), None);
+        //Each printer has its own certificate
+        assert_eq!(
+            watch_bambu::pinned_certificate(&conn, "01P00C456").unwrap(),
+            None
+        );
+
+        watch_bambu::unpin_certificate(&conn, "01S00A123").unwrap();
+        assert_eq!(
+            watch_bambu::pinned_certificate(&conn, "01S00A123").unwrap(),
+            None
+        );
+    }
+
+    //Status and body of a request, error statuses included
+    fn api_call(request: ureq::Request, body: Option<&str>) -> (u16, String) {
+        let result = match body {
+            Some(body) => request.send_string(body),
+            None => request.call(),
+        };
+        let response = match result {
+            Ok(response) => response,
+            Err(ureq::Error::Status(_, response)) => response,
+            Err(e) => panic!("{}", e),
+        };
+        (response.status(), response.into_string().unwrap())
+    }
+
+    #[test]
+    fn test_serve_api() {
+        let db_path = temp_dir().join("api.db");
+        let (conn, older, _) = printer_test_db();
+        print_add::load_spool(&conn, "voron", older).unwrap();
+        conn.backup(rusqlite::DatabaseName::Main, &db_path, None)
+            .unwrap();
+
+        let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/api", server.server_addr().to_ip().unwrap());
+        let token = Some(String::from("secret"));
+        std::thread::spawn(move || server::serve(server, &db_path, token, "tester"));
+        let get = |path: &str| {
+            let request =
+                ureq::get(&format!("{}{}", url, path)).set("Authorization", "Bearer secret");
+            api_call(request, None)
+        };
+        let post = |path: &str, body: &str| {
+            let request =
+                ureq::post(&format!("{}{}", url, path)).set("Authorization", "Bearer secret");
+            api_call(request, Some(body))
+        };
+
+        assert_eq!(api_call(ureq::get(&format!("{}/spools", url)), None).0, 401);
+        let spools: Vec<Spool> = serde_json::from_str(&get("/spools").1).unwrap();
+        assert_eq!(spools.len(), 2);
+        let spool: Spool = serde_json::from_str(&get("/spools/PLA%20Black").1).unwrap();
+        assert_eq!(spool.roll_id, Some(older));
+        assert_eq!(get("/spools/Missing").0, 404);
+        assert_eq!(get("/nothing").0, 404);
+
+        let (code, body) = post(
+            "/spools",
+            r#"{"roll_name": "ASA Grey", "roll_weight": 500.0, "roll_material": "ASA"}"#,
+        );
+        assert_eq!(code, 201);
+        let created: Spool = serde_json::from_str(&body).unwrap();
+        assert!(created.roll_id.is_some());
+        assert_eq!(created.roll_length, Some(Length::from_metres(165.0)));
+        assert_eq!(post("/spools", r#"{"roll_name": "Nothing"}"#).0, 400);
+        for invalid in [
+            r#"{"roll_name": "X", "roll_weight": 0}"#,
+            r#"{"roll_name": "X", "roll_weight": -500}"#,
+            r#"{"roll_name": "X", "roll_length": 330, "roll_diameter": 0}"#,
+            r#"{"roll_name": "X", "roll_weight": 500, "roll_diameter": -1.75}"#,
+            r#"{"roll_name": "X", "roll_weight": 1e30}"#,
+        ] {
+            assert_eq!(post("/spools", invalid).0, 400, "{} was accepted", invalid);
+        }
+        for invalid in [
+            r#"{"print_weight": 0}"#,
+            r#"{"print_weight": -10}"#,
+            r#"{"print_length": -3}"#,
+            r#"{"print_weight": 10, "print_time": -60}"#,
+            r#"{"print_weight": 10, "print_time": 0}"#,
+            r#"{"print_weight": 1e30, "print_time": 5}"#,
+            r#"{"print_weight": 10, "print_time": 5, "print_project": "Missing"}"#,
+        ] {
+            assert_eq!(post("/prints", invalid).0, 400, "{} was accepted", invalid);
+        }
+        let wrong = ureq::get(&format!("{}/spools", url)).set("Authorization", "Bearer secreT");
+        assert_eq!(api_call(wrong, None).0, 401);
+        let longer = ureq::get(&format!("{}/spools", url)).set("Authorization", "Bearer secrets");
+        assert_eq!(api_call(longer, None).0, 401);
+        assert_eq!(post("/spools", "not json").0, 400);
+
+        //Prints posted at the same time all make it in
+        let posters: Vec<_> = (0..8)
+            .map(|_| {
+                let url = url.clone();
+                std::thread::spawn(move || {
+                    let request = ureq::post(&format!("{}/prints?printer=voron", url))
+                        .set("Authorization", "Bearer secret");
+                    api_call(
+                        request,
+                        Some(r#"{"print_weight": 10.0, "print_time": 600}"#),
+                    )
+                    .0
+                })
+            })
+            .collect();
+        for poster in posters {
+            assert_eq!(poster.join().unwrap(), 201);
+        }
+        let exploded = r#"{"print_weight": 1.0, "print_status": "exploded"}"#;
+        assert_eq!(post("/prints", exploded).0, 400);
+        let prints: Vec<Filament> =
+            serde_json::from_str(&get("/prints?spool=PLA+Black").1).unwrap();
+        assert_eq!(prints.len(), 8);
+        assert!(prints
+            .iter()
+            .all(|print| print.print_status.as_deref() == Some(PRINT_SUCCESS)));
+
+        let remaining: serde_json::Value =
+            serde_json::from_str(&get("/remaining?printer=voron").1).unwrap();
+        assert_eq!(remaining["remaining_weight"], 920.0);
+        let stats: serde_json::Value = serde_json::from_str(&get("/stats").1).unwrap();
+        assert_eq!(stats["total_weight"], 80.0);
+        assert_eq!(stats["total_time"], 4800);
+    }
+
+    #[test]
+    fn test_web_ui() {
+        let db_path = temp_dir().join("web.db");
+        let (conn, older, newer) = printer_test_db();
+        conn.backup(rusqlite::DatabaseName::Main, &db_path, None)
+            .unwrap();
+
+        let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", server.server_addr().to_ip().unwrap());
+        let token = Some(String::from("secret"));
+        std::thread::spawn(move || server::serve(server, &db_path, token, "tester"));
+        let post = |path: &str, body: &str| {
+            let request =
+                ureq::post(&format!("{}/api{}", url, path)).set("Authorization", "Bearer secret");
+            api_call(request, Some(body))
+        };
+        let inventory = || {
+            let request =
+                ureq::get(&format!("{}/api/inventory", url)).set("Authorization", "Bearer secret");
+            serde_json::from_str::<serde_json::Value>(&api_call(request, None).1).unwrap()
+        };
+
+        //The page loads without the token, the data behind it does not
+        let page = ureq::get(&format!("{}/", url)).call().unwrap();
+        assert_eq!(page.content_type(), "text/html");
+        assert!(page.into_string().unwrap().contains("/app.js"));
+        let script = ureq::get(&format!("{}/app.js", url)).call().unwrap();
+        assert_eq!(script.content_type(), "text/javascript");
+        assert_eq!(
+            api_call(ureq::get(&format!("{}/missing.js", url)), None).0,
+            404
+        );
+        assert_eq!(
+            api_call(ureq::get(&format!("{}/api/inventory", url)), None).0,
+            401
+        );
+
+        let body = format!(r#"{{"spool": "{}"}}"#, older);
+        assert_eq!(post("/printers/voron/load", &body).0, 200);
+        assert_eq!(
+            post(
+                "/printers/x1c/load",
+                r#"{"spool": "PETG Blue", "tray": "B2"}"#
+            )
+            .0,
+            200
+        );
+        assert_eq!(
+            post(
+                "/printers/x1c/load",
+                r#"{"spool": "PETG Blue", "tray": "Q9"}"#
+            )
+            .0,
+            400
+        );
+        assert_eq!(post("/printers/x1c/load", r#"{"spool": "Missing"}"#).0, 404);
+        let spools = inventory();
+        assert_eq!(spools[0]["roll_id"], older.to_string());
+        assert_eq!(spools[0]["loaded"][0]["printer"], "voron");
+        assert_eq!(spools[1]["roll_id"], newer.to_string());
+        assert_eq!(spools[1]["loaded"][0]["tray"], "B2");
+        assert_eq!(spools[1]["remaining_weight"], 1000.0);
+
+        assert_eq!(post("/printers/voron/unload", "").0, 200);
+        assert_eq!(post("/printers/x1c/unload", r#"{"tray": "B2"}"#).0, 200);
+        let spools = inventory();
+        assert_eq!(spools[0]["loaded"], serde_json::json!([]));
+        assert_eq!(spools[1]["loaded"], serde_json::json!([]));
+    }
+
+    #[test]
+    fn test_weigh_spool() {
+        let (conn, older, newer) = printer_test_db();
+        conn.execute(
+            "UPDATE spool SET roll_tare = ?2 WHERE roll_id = ?1",
+            (older, Weight::from_grams(250.0)),
+        )
+        .unwrap();
+        let mut print = Filament {
+            print_id: Some(Uuid::new_v4()),
+            print_weight: Some(Weight::from_grams(100.0)),
+            print_time: Some(3600),
+            roll_id: Some(older),
+            ..Default::default()
+        };
+        print_add::add_new_print(&conn, &mut print).unwrap();
+
+        //800 g on the scale is 550 g of filament once the spool is taken off
+        let weigh_in = print_add::weigh_spool(&conn, older, Weight::from_grams(800.0)).unwrap();
+        assert_eq!(weigh_in.print_weight, Some(Weight::from_grams(350.0)));
+        assert_eq!(weigh_in.print_name.as_deref(), Some(print_add::WEIGH_IN));
+        assert_eq!(
+            print_stats::remaining_on_spool(&conn, older).0,
+            Weight::from_grams(550.0)
+        );
+        //Without a tare the reading is taken as the filament alone
+        print_add::weigh_spool(&conn, newer, Weight::from_grams(1010.0)).unwrap();
+        assert_eq!(
+            print_stats::remaining_on_spool(&conn, newer).0,
+            Weight::from_grams(1010.0)
+        );
+
+        //Weigh-ins only change what is left, they are not prints
+        assert_eq!(weigh_in.print_status.as_deref(), Some(PRINT_WEIGH_IN));
+        assert_eq!(
+            print_stats::lifetime_statistics(&conn),
+            (Weight::from_grams(100.0), Length::from_metres(33.0), 3600)
+        );
+        let top = print_stats::top_prints(&conn, 10, None).unwrap();
+        assert_eq!(top.len(), 1);
+        assert_eq!(top[0].0.print_id, print.print_id);
+        let failures = print_stats::failure_rates(&conn, None).unwrap();
+        assert_eq!(failures.iter().map(|month| month.1).sum::<i32>(), 1);
+        let usage = print_stats::material_share(&conn).unwrap();
+        assert_eq!(usage.iter().map(|material| material.1).sum::<f32>(), 100.0);
+        assert_eq!(print_stats::list_prints(&conn).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_weigh_ins_marked_on_upgrade() {
+        let (conn, older, _) = printer_test_db();
+        let weigh_in = print_add::weigh_spool(&conn, older, Weight::from_grams(900.0)).unwrap();
+        //How older versions stored it
+        conn.execute_batch(
+            "UPDATE filament SET print_status = 'success';
+            PRAGMA user_version = 14;",
+        )
+        .unwrap();
+        tbl_creation::setup_database(&conn).unwrap();
+        let prints = print_stats::list_prints(&conn).unwrap();
+        assert_eq!(prints[0].print_id, weigh_in.print_id);
+        assert_eq!(prints[0].print_status.as_deref(), Some(PRINT_WEIGH_IN));
+    }
+
+    #[test]
+    fn test_tui() {
+        use ratatui::crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+        let (conn, older, newer) = printer_test_db();
+        let mut app = tui::App::new(&conn, Units::default()).unwrap();
+        let press = |app: &mut tui::App, keys: &str| {
+            for key in keys.chars() {
+                let code = match key {
+                    '\n' => KeyCode::Enter,
+                    '\x1b' => KeyCode::Esc,
+                    key => KeyCode::Char(key),
+                };
+                app.handle_key(&conn, KeyEvent::new(code, KeyModifiers::NONE));
+            }
+        };
+        let screen = |app: &mut tui::App| {
+            let mut terminal =
+                ratatui::Terminal::new(ratatui::backend::TestBackend::new(110, 30)).unwrap();
+            terminal.draw(|frame| app.render(frame)).unwrap();
+            let buffer = terminal.backend().buffer().clone();
+            buffer
+                .content()
+                .iter()
+                .map(|cell| cell.symbol())
+                .collect::<String>()
+        };
+        assert!(screen(&mut app).contains("PLA Black"));
+
+        //The newest spool starts selected
+        press(&mut app, "a25\n30\nBenchy\n");
+        press(&mut app, "kl");
+        assert!(screen(&mut app).contains("Load spool"));
+        press(&mut app, "voron\n");
+        press(&mut app, "wabc\n");
+        assert!(screen(&mut app).contains("abc is not a number"));
+        press(&mut app, "\x1b");
+        let text = screen(&mut app);
+        assert!(text.contains("Benchy"));
+        assert!(text.contains("voron"));
+        assert!(!text.contains("Weigh spool"));
+
+        let prints = print_stats::list_prints(&conn).unwrap();
+        assert_eq!(prints.len(), 1);
+        assert_eq!(prints[0].roll_id, Some(newer));
+        assert_eq!(prints[0].print_weight, Some(Weight::from_grams(25.0)));
+        assert_eq!(prints[0].print_time, Some(1800));
+        assert_eq!(
+            print_add::spool_for_printer(&conn, "voron")
+                .unwrap()
+                .roll_id,
+            older
+        );
+
+        //Nothing is added for values the command line would refuse
+        for (keys, error) in [
+            ("a-5\n\n\n", "-5 cannot be negative"),
+            ("aNaN\n\n\n", "NaN is not a number"),
+            ("a0\n\n\n", "0 is not more than 0"),
+            ("a5\n-3\n\n", "-3 is not more than 0"),
+            ("a5\ninf\n\n", "inf is not more than 0"),
+        ] {
+            press(&mut app, keys);
+            assert!(screen(&mut app).contains(error), "{} was accepted", keys);
+            press(&mut app, "\x1b");
+        }
+        assert_eq!(print_stats::list_prints(&conn).unwrap().len(), 1);
+
+        //Loading a spool that has been open too long warns about it
+        conn.execute(
+            "UPDATE spool SET roll_material = 'PA12-CF', roll_opened = 0 WHERE roll_id = ?1",
+            [older],
+        )
+        .unwrap();
+        press(&mut app, "lprusa\n");
+        assert!(screen(&mut app).contains("Warning: PLA Black was opened"));
+        press(&mut app, "a5\n10\n\n");
+        assert!(screen(&mut app).contains("PA12-CF should be dried"));
+
+        press(&mut app, "nASA\n\n750\n7oz\n");
+        let spools = print_stats::list_spools(&conn).unwrap();
+        assert_eq!(spools.len(), 3);
+        assert_eq!(spools[2].roll_tare, Some(Weight::from_grams(198.447)));
+        press(&mut app, "q");
+    }
+
+    #[test]
+    fn test_spool_labels() {
+        let (conn, older, _) = printer_test_db();
+        conn.execute(
+            "UPDATE spool SET roll_material = 'PLA', roll_colour = '#1A1A1A' WHERE roll_id = ?1",
+            [older],
+        )
+        .unwrap();
+        let spool = print_stats::get_spool(&conn, older).unwrap();
+        assert_eq!(spool.roll_colour.as_deref(), Some("#1A1A1A"));
+
+        let pages = label::label_pages(&[spool], LabelSize::Dymo).unwrap();
+        let svg =
+            String::from_utf8(label::render(&pages, LabelFormat::Svg).unwrap().remove(0)).unwrap();
+        assert!(svg.starts_with("<svg"));
+        assert!(svg.contains(">PLA Black</text>"));
+        assert!(svg.contains(">PLA #1A1A1A</text>"));
+        assert!(svg.contains(">1000 g</text>"));
+        assert!(svg.contains(">2025-01-01</text>"));
+        assert!(svg.contains("fill=\"#1a1a1a\""));
+
+        let pdf = label::render(&pages, LabelFormat::Pdf).unwrap().remove(0);
+        let pdf = String::from_utf8_lossy(&pdf);
+        assert!(pdf.starts_with("%PDF-1.4"));
+        assert!(pdf.contains("(PLA Black) Tj"));
+        assert!(pdf.trim_end().ends_with("%%EOF"));
+
+        let png = label::render(&pages, LabelFormat::Png).unwrap().remove(0);
+        let mut reader = png::Decoder::new(png.as_slice()).read_info().unwrap();
+        let mut pixels = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut pixels).unwrap();
+        assert_eq!((info.width, info.height), (1051, 425));
+        //The top left of the QR code is a dark finder pattern
+        let corner = (40 * info.width as usize + 40) * 3;
+        assert_eq!(&pixels[corner..corner + 3], &[0, 0, 0]);
+    }
+
+    #[test]
+    fn test_label_sheets() {
+        let (conn, older, _) = printer_test_db();
+        let spools: Vec<Spool> = (0..22)
+            .map(|_| print_stats::get_spool(&conn, older).unwrap())
+            .collect();
+        let pages = label::sheet_pages(&spools, LabelSheet::AveryL7160).unwrap();
+        assert_eq!(pages.len(), 2);
+        let pdf = label::render(&pages, LabelFormat::Pdf).unwrap();
+        assert_eq!(pdf.len(), 1);
+        let pdf = String::from_utf8_lossy(&pdf[0]);
+        assert!(pdf.contains("/Count 2"));
+        assert_eq!(pdf.matches("(PLA Black) Tj").count(), 22);
+        assert_eq!(label::render(&pages, LabelFormat::Svg).unwrap().len(), 2);
+        assert_eq!(
+            label::numbered_path(Path::new("out/labels.svg"), 2),
+            PathBuf::from("out/labels-2.svg")
+        );
+    }
+
+    #[test]
+    fn test_find_spool_by_scan() {
+        let (conn, older, newer) = printer_test_db();
+        let find = |payload: &str| print_add::find_spool(&conn, payload).map(|found| found.roll_id);
+        assert_eq!(find(&older.to_string()).unwrap(), older);
+        assert_eq!(find(&format!("  {}\n", newer)).unwrap(), newer);
+        assert_eq!(
+            find(&older.simple().to_string().to_uppercase()).unwrap(),
+            older
+        );
+        assert_eq!(
+            find(&format!("remainder://spool/{}", older)).unwrap(),
+            older
+        );
+        assert_eq!(
+            find(&format!("REMAINDER://SPOOL/{}", newer)).unwrap(),
+            newer
+        );
+        assert_eq!(find(&older.to_string()[..8]).unwrap(), older);
+        assert_eq!(find(&newer.to_string()[..13]).unwrap(), newer);
+        assert_eq!(find("PETG Blue").unwrap(), newer);
+        //Too short to trust, or not a spool at all
+        assert!(find(&older.to_string()[..3]).is_err());
+        assert!(find(&Uuid::new_v4().to_string()).is_err());
+        assert!(find("remainder://spool/").is_err());
+    }
+
+    #[test]
+    fn test_scan_actions() {
+        let (conn, older, newer) = printer_test_db();
+        conn.execute(
+            "UPDATE spool SET roll_tare = ?2 WHERE roll_id = ?1",
+            (older, Weight::from_grams(200.0)),
+        )
+        .unwrap();
+        let run_in = |input: &str, action: ScanAction, units: Units| {
+            let options = scan::ScanOptions {
+                action,
+                printer: Some(String::from("voron")),
+                tray: None,
+                units,
+            };
+            let mut output = Vec::new();
+            let handled = scan::scan(&conn, input.as_bytes(), &mut output, &options).unwrap();
+            (handled, String::from_utf8(output).unwrap())
+        };
+        let run = |input: &str, action: ScanAction| run_in(input, action, Units::default());
+
+        let (handled, output) = run(&format!("{}\nnot a spool\n", newer), ScanAction::Show);
+        assert_eq!(handled, 1);
+        assert!(output.contains("PETG Blue: 1000.0 g and 330.00 m left"));
+        assert!(output.contains("No spool matches not a spool"));
+        let imperial = Units::new(UnitSystem::Imperial, None, None);
+        let (_, output) = run_in(&format!("{}\n", newer), ScanAction::Show, imperial);
+        assert!(output.contains("PETG Blue: 35.27 oz and 1082.7 ft left"));
+
+        let (handled, _) = run(&format!("remainder://spool/{}\n", older), ScanAction::Load);
+        assert_eq!(handled, 1);
+        assert_eq!(
+            print_add::spool_for_printer(&conn, "voron")
+                .unwrap()
+                .roll_id,
+            older
+        );
+
+        //A scan while waiting for a reading starts over with the new spool
+        let input = format!("{}\n{}\n\n1100\n", &newer.to_string()[..8], older);
+        let (handled, output) = run(&input, ScanAction::Weigh);
+        assert_eq!(handled, 1);
+        assert!(output.contains("Scale reading for PETG Blue, in grams unless a unit is given:"));
+        assert!(output.contains("Adjusted PLA Black by -100.0 g, 900.0 g left"));
+        assert_eq!(
+            print_stats::remaining_on_spool(&conn, newer).0,
+            Weight::from_grams(1000.0)
+        );
+    }
+
+    fn bambu_tag_dump() -> Vec<u8> {
+        let mut dump = vec![0u8; 1024];
+        dump[..4].copy_from_slice(&[0x75, 0x88, 0x6B, 0x1D]);
+        dump[32..35].copy_from_slice(b"PLA");
+        dump[64..73].copy_from_slice(b"PLA Basic");
+        dump[80..84].copy_from_slice(&[0xFF, 0x6A, 0x13, 0xFF]);
+        dump[84..86].copy_from_slice(&1000u16.to_le_bytes());
+        dump[88..92].copy_from_slice(&1.75f32.to_le_bytes());
+        dump[144..160].copy_from_slice(&[0xAB; 16]);
+        dump[228..230].copy_from_slice(&330u16.to_le_bytes());
+        dump
+    }
+
+    #[test]
+    fn test_read_bambu_tag() {
+        let dump = bambu_tag_dump();
+        let tag = nfc::read_tag(&dump).unwrap();
+        assert_eq!(tag.material.as_deref(), Some("PLA"));
+        assert_eq!(tag.variant.as_deref(), Some("Basic"));
+        assert_eq!(tag.colour.as_deref(), Some("#FF6A13"));
+        assert_eq!(tag.weight, Some(1000.0));
+        assert_eq!(tag.length, Some(330.0));
+        assert_eq!(tag.diameter, Some(1.75));
+        let spool = tag.to_spool();
+        assert_eq!(spool.roll_name.as_deref(), Some("Bambu PLA Basic"));
+        assert_eq!(spool.roll_diameter, None);
+
+        //The same tag dumped by a Flipper gives the same spool
+        let mut flipper = String::from("Filetype: Flipper NFC device\nVersion: 4\nDevice type: Mifare Classic\nUID: 75 88 6B 1D\n");
+        for (number, block) in dump.chunks(16).enumerate() {
+            let bytes: Vec<String> = block.iter().map(|byte| format!("{:02X}", byte)).collect();
+            flipper.push_str(&format!("Block {}: {}\n", number, bytes.join(" ")));
+        }
+        let flipper = nfc::read_tag(flipper.as_bytes()).unwrap();
+        assert_eq!(flipper.roll_id(), tag.roll_id());
+        assert!(nfc::read_tag(&[0u8; 1024]).is_err());
+    }
+
+    #[test]
+    fn test_openspool_tags() {
+        let (conn, older, _) = printer_test_db();
+        conn.execute(
+            "UPDATE spool SET roll_material = 'PETG', roll_colour = '#1a1a1a', roll_diameter = 2.85 WHERE roll_id = ?1",
+            [older],
+        )
+        .unwrap();
+        let spool = print_stats::get_spool(&conn, older).unwrap();
+        let payload = nfc::openspool_payload(&spool);
+        assert_eq!(payload["protocol"], "openspool");
+        assert_eq!(payload["type"], "PETG");
+        assert_eq!(payload["color_hex"], "1A1A1A");
+        assert_eq!(payload["weight"], "1000");
+
+        for format in [TagFormat::Json, TagFormat::Ndef] {
+            let tag = nfc::read_tag(&nfc::write_tag(&spool, format)).unwrap();
+            assert_eq!(tag.roll_id(), older);
+            assert_eq!(tag.material.as_deref(), Some("PETG"));
+            assert_eq!(tag.diameter, Some(2.85));
+        }
+
+        //Someone else's tag on an NTAG215, identified by the chip's id
+        let json = br#"{"protocol":"openspool","version":"1.0","type":"PLA","color_hex":"FFAABB","brand":"Elegoo","min_temp":"190","max_temp":"220"}"#;
+        let mut memory = vec![0x04, 0xA1, 0xB2, 0x88, 0xC3, 0xD4, 0xE5, 0xF6];
+        memory.resize(16, 0);
+        memory.extend(nfc::ndef_message(json));
+        memory.resize(540, 0);
+        let tag = nfc::read_tag(&memory).unwrap();
+        assert_eq!(tag.uid.as_deref(), Some("04A1B2C3D4E5F6"));
+        let mut spool = tag.to_spool();
+        assert_eq!(spool.roll_name.as_deref(), Some("Elegoo PLA"));
+        assert_eq!(spool.roll_colour_hex.as_deref(), Some("#FFAABB"));
+        assert_eq!(spool.roll_weight, None);
+        assert_eq!(nfc::read_tag(&memory).unwrap().roll_id(), tag.roll_id());
+
+        //Thicker filament is shorter for the same weight
+        spool.roll_weight = Some(Weight::from_grams(1000.0));
+        spool.roll_diameter = Some(2.85);
+        assert!((spool.get_length().metres() - 330.0 / (2.85f32 / 1.75).powi(2)).abs() < 0.01);
+        assert!(nfc::read_tag(br#"{"protocol":"other"}"#).is_err());
+    }
+
+    #[test]
+    fn test_product_catalogue() {
+        let conn = Connection::open_in_memory().unwrap();
+        tbl_creation::setup_database(&conn).unwrap();
+        let json = r##"{"products": [
+            {"vendor": "Polymaker", "material": "PLA", "color": "Black", "color_hex": "1a1a1a",
+             "density": 1.24, "net_weight": 1000, "spool_weight": 140, "price": 21.5},
+            {"name": "eSun PETG 3mm", "type": "PETG", "diameter": 2.85, "weight": 1000}
+        ]}"##;
+        let products = catalogue::read_products(json.as_bytes()).unwrap();
+        assert_eq!(
+            catalogue::import_products(&conn, &products).unwrap(),
+            (2, 0)
+        );
+        assert_eq!(
+            catalogue::import_products(&conn, &products).unwrap(),
+            (0, 2)
+        );
+        assert_eq!(catalogue::list_products(&conn).unwrap().len(), 2);
+
+        let product = catalogue::find_product(&conn, "polymaker pla black")
+            .unwrap()
+            .unwrap();
+        assert_eq!(product.product_name.as_deref(), Some("Polymaker PLA Black"));
+        assert_eq!(product.product_tare, Some(Weight::from_grams(140.0)));
+        let mut spool = product.new_spool();
+        assert_eq!(spool.roll_name.as_deref(), Some("Polymaker PLA Black"));
+        assert_eq!(spool.roll_colour.as_deref(), Some("Black"));
+        assert_eq!(spool.roll_colour_hex.as_deref(), Some("#1A1A1A"));
+        assert_eq!(spool.roll_price, Some(21.5));
+        assert!((spool.roll_length.unwrap().metres() - 335.3).abs() < 0.1);
+        print_add::open_new_spool(&conn, &mut spool).unwrap();
+        let saved = print_stats::get_spool(&conn, spool.roll_id.unwrap()).unwrap();
+        assert_eq!(saved.roll_tare, Some(Weight::from_grams(140.0)));
+
+        let thick = catalogue::find_product(&conn, "eSun PETG 3mm")
+            .unwrap()
+            .unwrap()
+            .new_spool();
+        assert_eq!(thick.roll_diameter, Some(2.85));
+        assert_eq!(thick.roll_length, None);
+
+        assert_eq!(
+            catalogue::remove_product(&conn, "ESUN petg 3MM").unwrap(),
+            1
+        );
+        assert!(catalogue::find_product(&conn, "eSun PETG 3mm")
+            .unwrap()
+            .is_none());
+        assert!(catalogue::read_products(r#"[{"price": 20}]"#.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_spool_locations() {
+        let (conn, older, newer) = printer_test_db();
+        locations::add_location(&conn, "Drybox 1", LocationKind::Drybox).unwrap();
+        locations::add_location(&conn, "Shelf", LocationKind::Shelf).unwrap();
+        assert!(locations::move_spool(&conn, older, "nowhere", None).is_err());
+        assert!(locations::move_spool(&conn, older, "shelf", Some(0)).is_err());
+
+        assert_eq!(
+            locations::move_spool(&conn, older, "drybox 1", None).unwrap(),
+            "Drybox 1"
+        );
+        locations::move_spool(&conn, newer, "Shelf", None).unwrap();
+        let places = locations::list_locations(&conn).unwrap();
+        assert_eq!(
+            places[0],
+            (String::from("Drybox 1"), String::from("drybox"), 1)
+        );
+        assert_eq!(places[1].2, 1);
+
+        //Loading is a move too, and moving it back out unloads it
+        print_add::load_spool(&conn, "voron", older).unwrap();
+        let inventory = print_stats::spool_inventory(&conn).unwrap();
+        assert_eq!(inventory[0].location.as_deref(), Some("voron"));
+        locations::move_spool(&conn, older, "Drybox 1", None).unwrap();
+        assert_eq!(
+            print_add::spool_for_printer(&conn, "voron")
+                .unwrap()
+                .roll_id,
+            newer
+        );
+        //Printers can be moved to without adding them as locations
+        assert_eq!(
+            locations::move_spool(&conn, older, "voron", None).unwrap(),
+            "voron"
+        );
+        print_add::unload_spool(&conn, "voron").unwrap();
+        assert_eq!(locations::current_location(&conn, older).unwrap(), None);
+
+        print_add::load_tray(&conn, "voron", 6, newer).unwrap();
+        print_add::load_tray(&conn, "voron", 6, newer).unwrap();
+        let moves: Vec<Option<String>> = locations::spool_moves(&conn, newer)
+            .unwrap()
+            .into_iter()
+            .map(|(location, _)| location)
+            .collect();
+        assert_eq!(
+            moves,
+            vec![Some(String::from("Shelf")), Some(String::from("voron B3"))]
+        );
+        let moves = locations::spool_moves(&conn, older).unwrap();
+        assert_eq!(moves.len(), 5);
 
-        let _ans = test_spool.get_length();
+        locations::remove_location(&conn, "Shelf").unwrap();
+        assert_eq!(locations::list_locations(&conn).unwrap().len(), 1);
     }
 
     #[test]
-    fn test_create_new_spool() {
-        let conn = Connection::open_in_memory().unwrap();
-        tbl_creation::create_new_spool_tbl(&conn).unwrap();
-        let mut test_spool = Spool {
-            roll_id: Some(Uuid::new_v4()),
-            roll_name: Some(String::from("crealtivity")),
-            roll_weight: Some(1000.0),
-            roll_length: Some(330.0),
-            timestamp: Some(get_timestamp()),
+    fn test_prints_use_spool_diameter() {
+        let (conn, older, _) = printer_test_db();
+        conn.execute(
+            "UPDATE spool SET roll_diameter = 2.85 WHERE roll_id = ?1",
+            [older],
+        )
+        .unwrap();
+        let factor = (2.85f32 / 1.75).powi(2);
+        let mut by_length = Filament {
+            print_id: Some(Uuid::new_v4()),
+            print_length: Some(Length::from_metres(10.0)),
+            roll_id: Some(older),
+            ..Default::default()
         };
-        let rt = print_add::open_new_spool(&conn, &mut test_spool).unwrap();
-        assert_eq!(rt, 1);
-        let check_query = "SELECT * FROM spool";
-        let exists_rt = conn
-            .query_row(check_query, [], |row| {
-                Ok(Spool {
-                    roll_id: row.get(0)?,
-                    roll_name: row.get(1)?,
-                    roll_weight: row.get(2)?,
-                    roll_length: row.get(3)?,
-                    timestamp: row.get(4)?,
-                })
-            })
-            .unwrap();
+        print_add::insert_print(&conn, &mut by_length).unwrap();
+        let weight = by_length.print_weight.unwrap().grams();
+        assert!((weight - 10.0 * GRAMS_PER_METRE * factor).abs() < 0.01);
 
-        assert_eq!(exists_rt.roll_id.unwrap(), test_spool.roll_id.unwrap());
-        assert_eq!(exists_rt.roll_name.unwrap(), "crealtivity".to_string());
-        assert_eq!(exists_rt.roll_weight.unwrap(), 1000.0);
-        assert_eq!(exists_rt.roll_length.unwrap(), 330.0);
-        assert!(exists_rt.timestamp.unwrap() > 1734209754);
+        let mut by_weight = Filament {
+            print_id: Some(Uuid::new_v4()),
+            print_weight: Some(Weight::from_grams(100.0)),
+            roll_id: Some(older),
+            ..Default::default()
+        };
+        print_add::insert_print(&conn, &mut by_weight).unwrap();
+        let length = by_weight.print_length.unwrap().metres();
+        assert!((length - 100.0 * METRES_PER_GRAM / factor).abs() < 0.01);
+        let stored: Length = conn
+            .query_row(
+                "SELECT print_length FROM filament WHERE print_id = ?1",
+                [by_weight.print_id.unwrap()],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(stored, by_weight.print_length.unwrap());
     }
 
     #[test]
-    fn test_create_new_print() {
-        //Create in memory DB
-        let conn = Connection::open_in_memory().unwrap();
+    fn test_loading_replaces_spool() {
+        let (conn, older, newer) = printer_test_db();
+        print_add::load_spool(&conn, "voron", older).unwrap();
+        print_add::load_spool(&conn, "voron", newer).unwrap();
+        assert_eq!(locations::current_location(&conn, older).unwrap(), None);
+        assert_eq!(
+            locations::current_location(&conn, newer)
+                .unwrap()
+                .as_deref(),
+            Some("voron")
+        );
+        //Loading the same spool again leaves it where it is
+        print_add::load_spool(&conn, "voron", newer).unwrap();
+        assert_eq!(locations::spool_moves(&conn, newer).unwrap().len(), 1);
 
-        //Create test spool
-        tbl_creation::create_new_spool_tbl(&conn).unwrap();
-        tbl_creation::create_new_filament_tbl(&conn).unwrap();
+        print_add::load_tray(&conn, "x1c", 0, older).unwrap();
+        print_add::load_tray(&conn, "x1c", 0, newer).unwrap();
+        assert_eq!(locations::current_location(&conn, older).unwrap(), None);
+        assert_eq!(
+            locations::current_location(&conn, newer)
+                .unwrap()
+                .as_deref(),
+            Some("x1c A1")
+        );
+    }
 
-        let mut test_spool = Spool {
-            roll_id: Some(Uuid::new_v4()),
-            roll_name: Some(String::from("crealtivity")),
-            roll_weight: Some(1000.0),
-            roll_length: Some(330.0),
-            timestamp: Some(get_timestamp()),
-        };
-        let rt = print_add::open_new_spool(&conn, &mut test_spool).unwrap();
-        assert_eq!(rt, 1);
-        let check_query = "SELECT * FROM spool";
-        let exists_rt = conn
-            .query_row(check_query, [], |row| {
-                Ok(Spool {
-                    roll_id: row.get(0)?,
-                    roll_name: row.get(1)?,
-                    roll_weight: row.get(2)?,
-                    roll_length: row.get(3)?,
-                    timestamp: row.get(4)?,
-                })
-            })
+    #[test]
+    fn test_drying_exposure() {
+        let (conn, older, newer) = printer_test_db();
+        conn.execute("UPDATE spool SET roll_material = 'PA12-CF'", ())
             .unwrap();
+        let day = 24 * 60 * 60;
+        let now = 1735689600 + 30 * day;
+        //Sealed spools have not taken on any moisture
+        assert_eq!(drying::exposure_warning(&conn, older, now).unwrap(), None);
 
-        assert_eq!(exists_rt.roll_id.unwrap(), test_spool.roll_id.unwrap());
-        assert_eq!(exists_rt.roll_name.unwrap(), "crealtivity".to_string());
-        assert_eq!(exists_rt.roll_weight.unwrap(), 1000.0);
-        assert_eq!(exists_rt.roll_length.unwrap(), 330.0);
-        assert!(exists_rt.timestamp.unwrap() > 1734209754);
-        let mut second_test_spool = Spool {
-            roll_id: Some(Uuid::new_v4()),
-            roll_name: Some(String::from("crealtivity")),
-            roll_weight: Some(1000.0),
-            roll_length: Some(330.0),
-            timestamp: Some(get_timestamp() + 5),
-        };
-        let rt_second_spool = print_add::open_new_spool(&conn, &mut second_test_spool).unwrap();
-        assert_eq!(rt_second_spool, 1);
+        drying::set_opened(&conn, older, now - 3 * day, true).unwrap();
+        drying::set_opened(&conn, older, now - 10 * day, false).unwrap();
+        assert_eq!(drying::opened(&conn, older).unwrap(), Some(now - 3 * day));
+        assert_eq!(
+            drying::exposure_warning(&conn, older, now)
+                .unwrap()
+                .as_deref(),
+            Some("PLA Black was opened 3.0 days ago, PA12-CF should be dried after 2 days")
+        );
 
-        //Test print creation
-        let mut test_print = Filament {
-            print_id: Some(Uuid::new_v4()),
-            print_weight: None,
-            print_length: Some(2.31),
-            print_time: Some(1125),
-            roll_id: None,
+        let session = drying::DryingSession {
+            timestamp: now - day,
+            temperature: 80.0,
+            minutes: 480,
         };
+        drying::record_drying(&conn, older, &session).unwrap();
+        assert_eq!(drying::exposure_warning(&conn, older, now).unwrap(), None);
+        assert_eq!(
+            drying::drying_sessions(&conn, older).unwrap(),
+            vec![session.clone()]
+        );
 
-        let _rt2 = print_add::add_new_print(&conn, &mut test_print).unwrap();
-        let check_query = "SELECT * FROM filament";
-        let exists_rt2 = conn
-            .query_row(check_query, [], |row| {
-                Ok(Filament {
-                    print_id: row.get(0)?,
-                    print_weight: row.get(1)?,
-                    print_length: row.get(2)?,
-                    print_time: row.get(3)?,
-                    roll_id: row.get(4)?,
-                })
-            })
-            .unwrap();
+        //Drying a sealed spool means it was opened when it went into the dryer
+        drying::record_drying(&conn, newer, &session).unwrap();
+        assert_eq!(
+            drying::opened(&conn, newer).unwrap(),
+            Some(now - day - 8 * 60 * 60)
+        );
 
-        assert_eq!(exists_rt2.print_id.unwrap(), test_print.print_id.unwrap());
-        assert_eq!(exists_rt2.print_weight.unwrap(), 6.9999924);
-        assert_eq!(exists_rt2.print_length.unwrap(), 2.31);
-        assert_eq!(exists_rt2.print_time.unwrap(), 1125);
+        drying::set_threshold(&conn, "pa12-cf", 0.5).unwrap();
+        assert_eq!(drying::threshold_days(&conn, "PA12-CF").unwrap(), Some(0.5));
+        assert_eq!(drying::threshold_days(&conn, "PA6").unwrap(), Some(2.0));
+        assert_eq!(drying::threshold_days(&conn, "PLA").unwrap(), None);
+        assert!(drying::exposure_warning(&conn, older, now)
+            .unwrap()
+            .unwrap()
+            .contains("was last dried 1.0 days ago"));
+        assert_eq!(drying::list_thresholds(&conn).unwrap().len(), 9);
+
+        //A set threshold covers the materials it is the start of, like the built in ones
+        drying::set_threshold(&conn, "pa", 4.0).unwrap();
+        assert_eq!(drying::threshold_days(&conn, "PA6").unwrap(), Some(4.0));
+        assert_eq!(drying::threshold_days(&conn, "PA12-GF").unwrap(), Some(4.0));
+        assert_eq!(drying::threshold_days(&conn, "PA12-CF").unwrap(), Some(0.5));
+        drying::set_threshold(&conn, "PA12-CF", 4.0).unwrap();
+        assert_eq!(drying::exposure_warning(&conn, older, now).unwrap(), None);
+        assert_eq!(drying::list_thresholds(&conn).unwrap().len(), 9);
         assert_eq!(
-            exists_rt2.roll_id.unwrap(),
-            second_test_spool.roll_id.unwrap()
+            drying::threshold_days(&conn, "PETG-CF").unwrap(),
+            Some(14.0)
         );
     }
 
     #[test]
-    fn test_create_remaining() {
-        //Create in memory DB
-        let conn = Connection::open_in_memory().unwrap();
+    fn test_tags_and_notes() {
+        let (conn, older, newer) = printer_test_db();
+        assert_eq!(tags::parse_tag(" Customer:ACME ").unwrap(), "customer:acme");
+        assert!(tags::parse_tag("a,b").is_err());
+        assert!(tags::parse_tag(" ").is_err());
 
-        //Create test spool
-        tbl_creation::create_new_spool_tbl(&conn).unwrap();
-        tbl_creation::create_new_filament_tbl(&conn).unwrap();
+        let acme = vec![String::from("customer:acme")];
+        let jig = vec![String::from("project:jig")];
+        assert_eq!(tags::tag_spool(&conn, older, &acme).unwrap(), 1);
+        assert_eq!(tags::tag_spool(&conn, older, &acme).unwrap(), 0);
+        for (name, weight, roll_id) in [("Bracket", 40.0, older), ("Jig", 25.0, newer)] {
+            let mut print = Filament {
+                print_id: Some(Uuid::new_v4()),
+                print_weight: Some(Weight::from_grams(weight)),
+                print_time: Some(600),
+                roll_id: Some(roll_id),
+                print_name: Some(String::from(name)),
+                print_notes: Some(String::from("0.2mm layers")),
+                ..Default::default()
+            };
+            print_add::insert_print(&conn, &mut print).unwrap();
+        }
+        let jig_print = print_add::find_print(&conn, "Jig").unwrap();
+        assert_eq!(
+            print_add::find_print(&conn, &jig_print.simple().to_string()[..8]).unwrap(),
+            jig_print
+        );
+        assert!(print_add::find_print(&conn, "Nothing").is_err());
+        tags::tag_print(&conn, jig_print, &jig).unwrap();
+        tags::tag_print(&conn, jig_print, &acme).unwrap();
 
-        let mut test_spool = Spool {
-            roll_id: Some(Uuid::new_v4()),
-            roll_name: Some(String::from("crealtivity")),
-            roll_weight: Some(1000.0),
-            roll_length: Some(330.0),
-            timestamp: Some(get_timestamp()),
+        //The bracket counts for acme through its spool, the jig through its own tag
+        let usage = tags::tag_usage(&conn, None).unwrap();
+        assert_eq!(usage[0].tag, "customer:acme");
+        assert_eq!((usage[0].prints, usage[0].weight), (2, 65.0));
+        assert_eq!((usage[1].prints, usage[1].time), (1, 600));
+        let projects = tags::tag_usage(&conn, Some("Project:")).unwrap();
+        assert_eq!(projects.len(), 1);
+        assert_eq!(projects[0].tag, "project:jig");
+        assert_eq!(
+            tags::list_tags(&conn).unwrap(),
+            vec![
+                (String::from("customer:acme"), 1, 1),
+                (String::from("project:jig"), 0, 1)
+            ]
+        );
+
+        let inventory = print_stats::spool_inventory(&conn).unwrap();
+        assert_eq!(inventory[0].tags, acme);
+        assert!(inventory[1].tags.is_empty());
+        tags::untag_print(&conn, jig_print, &acme).unwrap();
+        assert_eq!(tags::print_tags(&conn).unwrap()[&jig_print], jig);
+
+        print_add::set_spool_notes(&conn, older, Some("Keep for acme")).unwrap();
+        let spool = print_stats::get_spool(&conn, older).unwrap();
+        assert_eq!(spool.roll_notes.as_deref(), Some("Keep for acme"));
+        print_add::set_print_notes(&conn, jig_print, None).unwrap();
+        let prints = print_stats::list_prints(&conn).unwrap();
+        assert_eq!(prints[0].print_notes.as_deref(), Some("0.2mm layers"));
+        assert_eq!(prints[1].print_notes, None);
+
+        let hex = Spool {
+            roll_colour: Some(String::from("Galaxy Black")),
+            roll_colour_hex: Some(String::from("#1a1a1a")),
+            ..Default::default()
         };
-        let rt = print_add::open_new_spool(&conn, &mut test_spool).unwrap();
-        assert_eq!(rt, 1);
-        //Test print creation
-        let mut test_print = Filament {
-            print_id: Some(Uuid::new_v4()),
-            print_weight: None,
-            print_length: Some(2.31),
-            print_time: Some(1125),
-            roll_id: None,
+        assert_eq!(hex.colour_hex().as_deref(), Some("1A1A1A"));
+        let legacy = Spool {
+            roll_colour: Some(String::from("#ffaabb")),
+            ..Default::default()
         };
+        assert_eq!(legacy.colour_hex().as_deref(), Some("FFAABB"));
+    }
 
-        let mut second_test_print = Filament {
-            print_id: Some(Uuid::new_v4()),
-            print_weight: Some(89.6),
-            print_length: None,
-            print_time: Some(2700),
-            roll_id: None,
-        };
-        let _rt2 = print_add::add_new_print(&conn, &mut test_print).unwrap();
-        let ans = print_stats::check_remaining(&conn);
-        assert_eq!(ans.0, 993.0);
-        assert_eq!(ans.1, 327.69);
+    #[test]
+    fn test_project_report() {
+        let (conn, older, newer) = printer_test_db();
+        conn.execute("UPDATE spool SET roll_price = 20.0", ())
+            .unwrap();
+        projects::add_project(&conn, "Jig", None).unwrap();
+        projects::add_project(&conn, "jig", Some("Acme")).unwrap();
+        assert_eq!(
+            projects::find_project(&conn, "JIG").unwrap().as_deref(),
+            Some("Jig")
+        );
+        assert_eq!(projects::find_project(&conn, "Vase").unwrap(), None);
 
-        let _rt3 = print_add::add_new_print(&conn, &mut second_test_print).unwrap();
-        let ans = print_stats::check_remaining(&conn);
-        assert_eq!(ans.0, 903.4);
-        assert_eq!(ans.1, 298.122);
+        let day = 24 * 60 * 60;
+        let start = 1735776000;
+        let jobs = [
+            (older, 100.0, 0, Some("Jig")),
+            (newer, 50.0, day, Some("Jig")),
+            (newer, 30.0, 2 * day, None),
+            (older, 10.0, 10 * day, Some("Jig")),
+        ];
+        for (roll_id, weight, offset, project) in jobs {
+            let mut print = Filament {
+                print_id: Some(Uuid::new_v4()),
+                print_weight: Some(Weight::from_grams(weight)),
+                print_time: Some(1800),
+                roll_id: Some(roll_id),
+                timestamp: Some(start + offset),
+                print_project: project.map(String::from),
+                ..Default::default()
+            };
+            print_add::insert_print(&conn, &mut print).unwrap();
+        }
+
+        let usage = projects::project_usage(&conn, Some(start), Some(start + 2 * day)).unwrap();
+        assert_eq!(usage.len(), 2);
+        assert_eq!(usage[0].project.as_deref(), Some("Jig"));
+        assert_eq!(usage[0].customer.as_deref(), Some("Acme"));
+        assert_eq!((usage[0].prints, usage[0].weight), (2, 150.0));
+        assert_eq!(usage[0].time, 3600);
+        assert_eq!(usage[0].cost, Some(3.0));
+        assert_eq!(usage[1].project, None);
+        assert_eq!(usage[1].weight, 30.0);
+        assert_eq!(
+            projects::project_usage(&conn, None, None).unwrap()[0].prints,
+            3
+        );
+
+        let mut csv = Vec::new();
+        projects::write_report_csv(&usage, &mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        assert!(csv.starts_with("project,customer,prints,weight_g,length_m,time_s,cost\n"));
+        assert!(csv.contains("\nJig,Acme,2,150.0,"));
+        assert!(csv.ends_with(",1800,0.60\n"));
+
+        assert_eq!(projects::list_projects(&conn).unwrap()[0].2, 3);
+        projects::remove_project(&conn, "jig").unwrap();
+        assert!(projects::list_projects(&conn).unwrap().is_empty());
+        let usage = projects::project_usage(&conn, None, None).unwrap();
+        assert_eq!(usage[0].project.as_deref(), Some("Jig"));
+        assert_eq!(usage[0].customer, None);
     }
 
     #[test]
-    fn test_check_lifetime() {
-        //Create in memory DB
-        let conn = Connection::open_in_memory().unwrap();
+    fn test_unit_parsing() {
+        assert_eq!(
+            units::parse_weight("250").unwrap(),
+            Weight::from_grams(250.0)
+        );
+        assert_eq!(
+            units::parse_weight("1kg").unwrap(),
+            Weight::from_grams(1000.0)
+        );
+        assert_eq!(
+            units::parse_weight("3.2oz").unwrap(),
+            Weight::from_milligrams(90_718)
+        );
+        assert_eq!(
+            units::parse_weight("2 LB").unwrap(),
+            Weight::from_milligrams(907_185)
+        );
+        assert_eq!(
+            units::parse_length("330").unwrap(),
+            Length::from_metres(330.0)
+        );
+        assert_eq!(
+            units::parse_length("100ft").unwrap(),
+            Length::from_metres(30.48)
+        );
+        assert_eq!(
+            units::parse_length("12in").unwrap(),
+            Length::from_metres(0.3048)
+        );
+        assert_eq!(
+            units::parse_length("1500mm").unwrap(),
+            Length::from_metres(1.5)
+        );
+        assert!(units::parse_weight("3 stone").is_err());
+        assert!(units::parse_weight("oz").is_err());
+        assert!(units::parse_length("10kg").is_err());
+        assert!(units::parse_weight("1e30g").is_err());
+        assert!(units::parse_length("inf").is_err());
+        assert_eq!(units::parse_positive::<f32>("19.99"), Ok(19.99));
+        assert!(units::parse_positive::<f32>("NaN").is_err());
+        assert!(units::parse_positive::<i32>("-50").is_err());
+        assert_eq!(units::parse_diameter("2.85"), Ok(2.85));
+        assert!(units::parse_diameter("0").is_err());
 
-        //Create test spool
-        tbl_creation::create_new_spool_tbl(&conn).unwrap();
-        tbl_creation::create_new_filament_tbl(&conn).unwrap();
+        let args =
+            Args::try_parse_from(["remainder", "-w", "3.2oz", "-l", "100ft", "add-print", "60"])
+                .unwrap();
+        assert_eq!(args.weight, Some(Weight::from_milligrams(90_718)));
+        assert_eq!(args.length, Some(Length::from_millimetres(30_480)));
+        assert!(Args::try_parse_from(["remainder", "-w", "heavy", "lifetime-stats"]).is_err());
+    }
 
-        let mut test_spool = Spool {
-            roll_id: Some(Uuid::new_v4()),
-            roll_name: Some(String::from("crealtivity")),
-            roll_weight: Some(1000.0),
-            roll_length: Some(330.0),
-            timestamp: Some(get_timestamp()),
-        };
-        let rt = print_add::open_new_spool(&conn, &mut test_spool).unwrap();
-        assert_eq!(rt, 1);
-        //Test print creation
-        let mut test_print = Filament {
-            print_id: Some(Uuid::new_v4()),
-            print_weight: None,
-            print_length: Some(2.31),
-            print_time: Some(1125),
-            roll_id: None,
-        };
+    #[test]
+    fn test_unit_display() {
+        let spool = Weight::from_grams(1000.0);
+        let length = Length::from_metres(330.0);
+        let metric = Units::default();
+        assert_eq!(metric.weight(spool), "1000.0 g");
+        assert_eq!(metric.length(length), "330.00 m");
+        let imperial = Units::new(UnitSystem::Imperial, None, None);
+        assert_eq!(imperial.weight(spool), "35.27 oz");
+        assert_eq!(imperial.length(length), "1082.7 ft");
+        let custom = Units::new(
+            UnitSystem::Imperial,
+            Some(WeightUnit::Lb),
+            Some(LengthUnit::M),
+        );
+        assert_eq!(custom.weight(spool), "2.205 lb");
+        assert_eq!(custom.length(length), "330.00 m");
+        assert_eq!(
+            Units::new(UnitSystem::Metric, Some(WeightUnit::Kg), None).weight(spool),
+            "1.000 kg"
+        );
 
-        let mut second_test_print = Filament {
-            print_id: Some(Uuid::new_v4()),
-            print_weight: Some(89.6),
-            print_length: None,
-            print_time: Some(2700),
-            roll_id: None,
+        //What is shown can be typed back in and comes to within a gram of where it started
+        for units in [metric, imperial, custom] {
+            let weight = units::parse_weight(&units.weight(spool)).unwrap();
+            assert!((weight.grams() - spool.grams()).abs() < 1.0);
+            let shown = units::parse_length(&units.length(length)).unwrap();
+            assert!((shown.metres() - length.metres()).abs() < 0.1);
+        }
+
+        let args = Args::try_parse_from([
+            "remainder",
+            "list-spools",
+            "--units",
+            "imperial",
+            "--length-unit",
+            "m",
+        ])
+        .unwrap();
+        assert_eq!(args.units, Some(UnitSystem::Imperial));
+        assert_eq!(args.length_unit, Some(LengthUnit::M));
+    }
+
+    #[test]
+    fn test_config_layers() {
+        let file: toml::Table = r#"
+            material = "PETG"
+            diameter = 2.85
+            printer = "voron"
+            low_weight = "3.5oz"
+            units = "imperial"
+        "#
+        .parse()
+        .unwrap();
+        let env = |name: &str| match name {
+            "REMAINDER_PRINTER" => Some(String::from("prusa")),
+            "REMAINDER_CURRENCY" => Some(String::from("EUR")),
+            _ => None,
         };
-        let _rt2 = print_add::add_new_print(&conn, &mut test_print).unwrap();
-        let _rt3 = print_add::add_new_print(&conn, &mut second_test_print).unwrap();
-        let ans = print_stats::lifetime_statistics(&conn);
-        assert_eq!(ans.0, 96.59999);
-        assert_eq!(ans.1, 31.878);
-        assert_eq!(ans.2, 3825);
+        let values = config::layered_values(&file, env).unwrap();
+        assert!(values.contains(&(
+            "printer",
+            String::from("prusa"),
+            config::Source::Environment
+        )));
+        assert!(values.contains(&("diameter", String::from("2.85"), config::Source::File)));
+        let config = Config::from_values(&values).unwrap();
+        assert_eq!(config.material.as_deref(), Some("PETG"));
+        assert_eq!(config.diameter, Some(2.85));
+        assert_eq!(config.printer.as_deref(), Some("prusa"));
+        assert_eq!(config.low_weight, Some(Weight::from_milligrams(99_223)));
+        assert_eq!(config.units, Some(UnitSystem::Imperial));
+        assert_eq!(config.price(12.5), "12.50 EUR");
+        assert_eq!(Config::default().price(12.5), "12.50");
+
+        //Command line arguments win over both
+        let args = Args::try_parse_from(["remainder", "--units", "metric", "list-spools"]).unwrap();
+        assert_eq!(args.units.or(config.units), Some(UnitSystem::Metric));
+
+        let bad: toml::Table = "colour = \"red\"".parse().unwrap();
+        assert!(config::layered_values(&bad, |_| None).is_err());
+        let bad: toml::Table = "diameter = -1".parse().unwrap();
+        let values = config::layered_values(&bad, |_| None).unwrap();
+        assert!(Config::from_values(&values).is_err());
+        let bad_env = |name: &str| (name == "REMAINDER_UNITS").then(|| String::from("cubits"));
+        let values = config::layered_values(&toml::Table::new(), bad_env).unwrap();
+        assert!(Config::from_values(&values).is_err());
     }
 
     #[test]
-    fn test_check_lifetime_empty() {
-        //Create in memory DB
-        let conn = Connection::open_in_memory().unwrap();
+    fn test_config_file() {
+        let path = temp_dir().join("remainder").join("config.toml");
+        assert_eq!(Config::load(&path).unwrap(), Config::default());
+        config::set_value(&path, "currency", "€").unwrap();
+        config::set_value(&path, "diameter", "2.85").unwrap();
+        config::set_value(&path, "weight_unit", "lb").unwrap();
+        config::set_value(&path, "printer", "007").unwrap();
+        config::set_value(&path, "low_weight", "1e3").unwrap();
+        assert!(config::set_value(&path, "weight_unit", "stone").is_err());
+        assert!(config::set_value(&path, "colour", "red").is_err());
 
-        //Create test spool
-        tbl_creation::create_new_spool_tbl(&conn).unwrap();
-        tbl_creation::create_new_filament_tbl(&conn).unwrap();
+        let file = config::read_file(&path).unwrap();
+        assert_eq!(file.get("diameter"), Some(&toml::Value::Float(2.85)));
+        assert_eq!(
+            file.get("printer"),
+            Some(&toml::Value::String(String::from("007")))
+        );
+        let config =
+            Config::from_values(&config::layered_values(&file, |_| None).unwrap()).unwrap();
+        assert_eq!(config.price(3.0), "€3.00");
+        assert_eq!(config.weight_unit, Some(WeightUnit::Lb));
+        assert_eq!(config.printer.as_deref(), Some("007"));
+        assert_eq!(config.low_weight, Some(Weight::from_grams(1000.0)));
 
-        let mut test_spool = Spool {
+        assert!(config::unset_value(&path, "currency").unwrap());
+        assert!(!config::unset_value(&path, "currency").unwrap());
+        assert!(config::unset_value(&path, "colour").is_err());
+        let file = config::read_file(&path).unwrap();
+        assert_eq!(file.len(), 4);
+        fs::remove_dir_all(path.parent().unwrap().parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_quantity_arguments() {
+        let quantity = |args: &mut Args| args.cmd.quantity_mut().cloned().unwrap();
+        let mut args = Args::try_parse_from([
+            "remainder",
+            "create-spool",
+            "PLA Black",
+            "-w",
+            "1kg",
+            "-l",
+            "330",
+        ])
+        .unwrap();
+        assert!(!move_old_quantity(&mut args).unwrap());
+        assert_eq!(quantity(&mut args).weight, Some(Weight::from_grams(1000.0)));
+        assert_eq!(quantity(&mut args).length, Some(Length::from_metres(330.0)));
+
+        for bad in ["0", "-5", "inf", "NaN", "12 stone"] {
+            assert!(
+                Args::try_parse_from(["remainder", "add-print", "60", "-w", bad]).is_err(),
+                "{} was accepted",
+                bad
+            );
+        }
+        assert!(Args::try_parse_from(["remainder", "add-print", "60", "-l", "0ft"]).is_err());
+        //Weights are only taken by commands that use them
+        assert!(Args::try_parse_from(["remainder", "check-remaining", "-w", "10"]).is_err());
+        let args = Args::try_parse_from(["remainder", "add-print", "60"]).unwrap();
+        assert!(matches!(args.cmd, Commands::AddPrint { quantity, .. } if quantity.is_empty()));
+
+        //The old way round still works
+        let mut args = Args::try_parse_from(["remainder", "-w", "12", "add-print", "60"]).unwrap();
+        assert!(move_old_quantity(&mut args).unwrap());
+        assert_eq!(quantity(&mut args).weight, Some(Weight::from_grams(12.0)));
+        let mut args =
+            Args::try_parse_from(["remainder", "-w", "12", "add-print", "60", "-l", "4"]).unwrap();
+        assert!(move_old_quantity(&mut args).unwrap());
+        assert_eq!(quantity(&mut args).length, Some(Length::from_metres(4.0)));
+        let mut args = Args::try_parse_from(["remainder", "-w", "12", "check-remaining"]).unwrap();
+        assert!(move_old_quantity(&mut args).is_err());
+        let mut args =
+            Args::try_parse_from(["remainder", "-w", "12", "add-print", "60", "-w", "13"]).unwrap();
+        assert!(move_old_quantity(&mut args).is_err());
+        assert!(Args::try_parse_from(["remainder", "-w", "-1", "add-print", "60"]).is_err());
+        for invalid in [
+            vec!["create-spool", "X", "-w", "1000", "--diameter", "0"],
+            vec!["create-spool", "X", "-w", "1000", "--price", "-5"],
+            vec!["add-print", "-w", "5", "--", "-50"],
+            vec!["add-print", "0", "-w", "5"],
+            vec!["drying", "threshold", "PA", "0"],
+            vec!["drying", "log", "X", "-t", "80", "--hours", "-2"],
+            vec!["drying", "log", "X", "-t", "NaN", "--hours", "2"],
+        ] {
+            let args = ["remainder"].into_iter().chain(invalid);
+            assert!(Args::try_parse_from(args).is_err());
+        }
+    }
+
+    #[test]
+    fn test_completion_names() {
+        let (conn, older, _) = printer_test_db();
+        print_add::load_spool(&conn, "voron", older).unwrap();
+        print_add::load_tray(&conn, "x1c", 0, older).unwrap();
+        locations::add_location(&conn, "Prusa", LocationKind::Printer).unwrap();
+        locations::add_location(&conn, "Shelf", LocationKind::Shelf).unwrap();
+        projects::add_project(&conn, "Jig", None).unwrap();
+        let mut spool = Spool {
             roll_id: Some(Uuid::new_v4()),
-            roll_name: Some(String::from("crealtivity")),
-            roll_weight: Some(1000.0),
-            roll_length: Some(330.0),
-            timestamp: Some(get_timestamp()),
+            roll_name: Some(String::from("PLA Black")),
+            roll_weight: Some(Weight::from_grams(1000.0)),
+            timestamp: Some(1735862400),
+            ..Default::default()
         };
-        let rt = print_add::open_new_spool(&conn, &mut test_spool).unwrap();
-        assert_eq!(rt, 1);
-        let ans = print_stats::lifetime_statistics(&conn);
-        assert_eq!(ans.0, 0.0);
-        assert_eq!(ans.1, 0.0);
-        assert_eq!(ans.2, 0);
+        print_add::open_new_spool(&conn, &mut spool).unwrap();
+
+        let names = |query| completion::names(&conn, query).unwrap();
+        assert_eq!(names(completion::SPOOL_NAMES), ["PETG Blue", "PLA Black"]);
+        assert_eq!(names(completion::PRINTER_NAMES), ["Prusa", "voron", "x1c"]);
+        assert_eq!(names(completion::PROJECT_NAMES), ["Jig"]);
     }
 
     #[test]
-    fn test_check_remaining_empty() {
-        //Create in memory DB
-        let conn = Connection::open_in_memory().unwrap();
+    fn test_completion_scripts() {
+        Args::command().debug_assert();
+        for shell in Shell::value_variants() {
+            let mut script = Vec::new();
+            Shells::builtins()
+                .completer(&shell.to_string())
+                .unwrap()
+                .write_registration(
+                    "COMPLETE",
+                    "remainder",
+                    "remainder",
+                    "remainder",
+                    &mut script,
+                )
+                .unwrap();
+            assert!(String::from_utf8(script).unwrap().contains("remainder"));
+        }
+        let args = Args::try_parse_from(["remainder", "completions", "zsh"]).unwrap();
+        assert!(matches!(
+            args.cmd,
+            Commands::Completions { shell: Shell::Zsh }
+        ));
+    }
 
-        //Create test spool
-        tbl_creation::create_new_spool_tbl(&conn).unwrap();
-        tbl_creation::create_new_filament_tbl(&conn).unwrap();
+    #[test]
+    fn test_audit_log() {
+        let (conn, older, newer) = printer_test_db();
+        audit::start(&conn, "alice").unwrap();
+        let mut print = Filament {
+            print_id: Some(Uuid::new_v4()),
+            print_name: Some(String::from("Benchy")),
+            print_weight: Some(Weight::from_grams(12.5)),
+            roll_id: Some(older),
+            timestamp: Some(1735862400),
+            ..Default::default()
+        };
+        print_add::insert_print(&conn, &mut print).unwrap();
+        projects::assign_print(&conn, print.print_id.unwrap(), Some("Jig")).unwrap();
+        //Setting the same value again is not a change
+        projects::assign_print(&conn, print.print_id.unwrap(), Some("Jig")).unwrap();
+        audit::start(&conn, "bob").unwrap();
+        print_add::load_spool(&conn, "voron", newer).unwrap();
+        print_add::load_tray(&conn, "x1c", 0, older).unwrap();
+        print_add::unload_tray(&conn, "x1c", 0).unwrap();
 
-        let mut test_spool = Spool {
-            roll_id: Some(Uuid::new_v4()),
-            roll_name: Some(String::from("crealtivity")),
-            roll_weight: Some(1000.0),
-            roll_length: Some(330.0),
-            timestamp: Some(get_timestamp()),
+        let all = audit::HistoryFilter {
+            limit: 50,
+            ..Default::default()
         };
-        let rt = print_add::open_new_spool(&conn, &mut test_spool).unwrap();
-        assert_eq!(rt, 1);
-        let ans = print_stats::check_remaining(&conn);
-        assert_eq!(ans.0, 1000.0);
-        assert_eq!(ans.1, 330.0);
+        let entries = audit::history(&conn, &all).unwrap();
+        let summary: Vec<_> = entries
+            .iter()
+            .map(|entry| {
+                (
+                    entry.user.as_str(),
+                    entry.action,
+                    entry.kind,
+                    entry.subject(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (
+                    "bob",
+                    AuditAction::Delete,
+                    AuditKind::Tray,
+                    String::from("x1c A1")
+                ),
+                (
+                    "bob",
+                    AuditAction::Create,
+                    AuditKind::Tray,
+                    String::from("x1c A1")
+                ),
+                (
+                    "bob",
+                    AuditAction::Create,
+                    AuditKind::Printer,
+                    String::from("voron")
+                ),
+                (
+                    "alice",
+                    AuditAction::Update,
+                    AuditKind::Print,
+                    String::from("Benchy")
+                ),
+                (
+                    "alice",
+                    AuditAction::Create,
+                    AuditKind::Print,
+                    String::from("Benchy")
+                ),
+            ]
+        );
+        let units = Units::default();
+        assert_eq!(entries[3].changes(&units), ["print_project: none -> Jig"]);
+        assert!(entries[4]
+            .changes(&units)
+            .contains(&String::from("print_weight: 12.5 g")));
+        assert_eq!(entries[4].spool_name.as_deref(), Some("PLA Black"));
+
+        let filter = audit::HistoryFilter {
+            roll_id: Some(older),
+            user: Some(String::from("ALICE")),
+            action: Some(AuditAction::Update),
+            ..all
+        };
+        assert_eq!(audit::history(&conn, &filter).unwrap().len(), 1);
+        let filter = audit::HistoryFilter {
+            kind: Some(AuditKind::Tray),
+            limit: 1,
+            ..Default::default()
+        };
+        assert_eq!(
+            audit::history(&conn, &filter).unwrap()[0].action,
+            AuditAction::Delete
+        );
+
+        assert!(conn.execute("DELETE FROM audit_log", ()).is_err());
+        assert!(conn
+            .execute("UPDATE audit_log SET audit_user = 'eve'", ())
+            .is_err());
+        assert_eq!(audit::history(&conn, &all).unwrap().len(), 5);
     }
 }
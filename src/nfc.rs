@@ -0,0 +1,400 @@
+use crate::get_timestamp;
+use crate::print_structs::*;
+use clap::ValueEnum;
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+//Key we add to OpenSpool payloads so our own tags map back to the same spool
+const EXTRA_ID: &str = "remainder_id";
+const OPENSPOOL_MIME: &str = "application/json";
+//MIFARE Classic 1K has 64 blocks of 16 bytes, Bambu tags are the 1K kind
+const CLASSIC_BLOCK: usize = 16;
+const CLASSIC_1K: usize = 64 * CLASSIC_BLOCK;
+//NTAG pages are 4 bytes and the NDEF data area starts on page 4
+const NTAG_PAGE: usize = 4;
+const NTAG_DATA: usize = 4 * NTAG_PAGE;
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum TagFormat {
+    /// The OpenSpool JSON on its own
+    Json,
+    /// A raw NDEF message ready to write to an NTAG
+    Ndef,
+}
+
+//What a tag dump held, before it is turned into a spool
+#[derive(Debug, Default, PartialEq)]
+pub struct TagData {
+    //Unique id of the chip, or the tray id Bambu writes
+    pub uid: Option<String>,
+    //Our id, only on tags we wrote ourselves
+    pub roll_id: Option<Uuid>,
+    pub brand: Option<String>,
+    pub material: Option<String>,
+    pub variant: Option<String>,
+    pub colour: Option<String>,
+    pub weight: Option<f32>,
+    pub length: Option<f32>,
+    pub diameter: Option<f32>,
+}
+
+impl TagData {
+    //Tags without one of our ids get one from their chip, so the same tag always
+    //finds the same spool
+    pub fn roll_id(&self) -> Uuid {
+        self.roll_id.unwrap_or_else(|| match &self.uid {
+            Some(uid) => Uuid::new_v5(&Uuid::NAMESPACE_OID, format!("nfc:{}", uid).as_bytes()),
+            None => Uuid::new_v4(),
+        })
+    }
+
+    pub fn to_spool(&self) -> Spool {
+        let name = [&self.brand, &self.material, &self.variant]
+            .into_iter()
+            .flatten()
+            .cloned()
+            .collect::<Vec<String>>()
+            .join(" ");
+        Spool {
+            roll_id: Some(self.roll_id()),
+            roll_name: Some(name).filter(|name| !name.is_empty()),
+            roll_weight: self.weight.map(Weight::from_grams),
+            roll_length: self.length.map(Length::from_metres),
+            timestamp: Some(get_timestamp()),
+            roll_material: self.material.clone(),
+            roll_colour_hex: self.colour.clone(),
+            roll_diameter: self
+                .diameter
+                .filter(|diameter| *diameter != FILAMENT_DIAMETER),
+            ..Default::default()
+        }
+    }
+}
+
+//Works out what kind of dump the file is: OpenSpool JSON, a Proxmark JSON dump,
+//a Flipper .nfc file, or a raw binary dump of a MIFARE Classic or NTAG
+pub fn read_tag(contents: &[u8]) -> Result<TagData, String> {
+    if let Ok(text) = std::str::from_utf8(contents) {
+        let text = text.trim_start_matches('\u{feff}').trim();
+        if text.starts_with('{') {
+            let value: Value = serde_json::from_str(text).map_err(|e| e.to_string())?;
+            return match value.get("blocks") {
+                Some(blocks) => read_proxmark(&value, blocks),
+                None => parse_openspool(&value, None),
+            };
+        }
+        if text.starts_with("Filetype: Flipper NFC device") {
+            return read_flipper(text);
+        }
+    }
+    //A bare NDEF message, as written by `nfc write --format ndef`
+    if contents.first() == Some(&0x03) {
+        parse_ndef(contents, None)
+    } else if contents.len() >= CLASSIC_1K && contents.len().is_multiple_of(CLASSIC_1K) {
+        parse_bambu(contents)
+    } else {
+        parse_ntag(contents, None)
+    }
+}
+
+fn read_proxmark(value: &Value, blocks: &Value) -> Result<TagData, String> {
+    let blocks = blocks.as_object().ok_or("blocks is not a list of blocks")?;
+    let mut numbered: Vec<(usize, Vec<u8>)> = blocks
+        .iter()
+        .map(|(number, hex)| {
+            let number = number
+                .parse()
+                .map_err(|_| format!("block {} is not a number", number))?;
+            Ok((number, parse_hex(hex.as_str().unwrap_or_default())?))
+        })
+        .collect::<Result<_, String>>()?;
+    numbered.sort_by_key(|(number, _)| *number);
+    let memory: Vec<u8> = numbered.into_iter().flat_map(|(_, bytes)| bytes).collect();
+    let uid = value
+        .pointer("/Card/UID")
+        .and_then(Value::as_str)
+        .map(str::to_uppercase);
+    //Classic blocks are 16 bytes, NTAG pages only 4
+    let is_classic = blocks
+        .values()
+        .next()
+        .and_then(Value::as_str)
+        .is_some_and(|hex| hex.len() == 2 * CLASSIC_BLOCK);
+    if is_classic {
+        parse_bambu(&memory)
+    } else {
+        parse_ntag(&memory, uid)
+    }
+}
+
+//Flipper writes `Block 4: 50 4C 41 ...` for MIFARE Classic and `Page 4: 03 ...` for
+//NTAG, with ?? for bytes it could not read
+fn read_flipper(text: &str) -> Result<TagData, String> {
+    let mut uid = None;
+    let mut memory = Vec::new();
+    let mut is_classic = false;
+    for line in text.lines() {
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = value.trim().replace("??", "00");
+        if key == "UID" {
+            uid = Some(value.replace(' ', ""));
+        } else if let Some(number) = key.strip_prefix("Block ") {
+            is_classic = true;
+            place(&mut memory, number, CLASSIC_BLOCK, &value)?;
+        } else if let Some(number) = key.strip_prefix("Page ") {
+            place(&mut memory, number, NTAG_PAGE, &value)?;
+        }
+    }
+    if memory.is_empty() {
+        return Err(String::from("the Flipper file has no blocks or pages"));
+    }
+    if is_classic {
+        parse_bambu(&memory)
+    } else {
+        parse_ntag(&memory, uid)
+    }
+}
+
+fn place(memory: &mut Vec<u8>, number: &str, size: usize, hex: &str) -> Result<(), String> {
+    let number: usize = number
+        .trim()
+        .parse()
+        .map_err(|_| format!("{} is not a block number", number))?;
+    let bytes = parse_hex(hex)?;
+    let start = number * size;
+    if memory.len() < start + bytes.len() {
+        memory.resize(start + bytes.len(), 0);
+    }
+    memory[start..start + bytes.len()].copy_from_slice(&bytes);
+    Ok(())
+}
+
+fn parse_hex(hex: &str) -> Result<Vec<u8>, String> {
+    let digits: String = hex.chars().filter(|c| !c.is_whitespace()).collect();
+    if !digits.len().is_multiple_of(2) {
+        return Err(format!("{} is not whole bytes of hex", hex));
+    }
+    (0..digits.len())
+        .step_by(2)
+        .map(|i| {
+            u8::from_str_radix(&digits[i..i + 2], 16).map_err(|_| format!("{} is not hex", hex))
+        })
+        .collect()
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02X}", byte)).collect()
+}
+
+//Bambu Lab spools carry a MIFARE Classic tag, the layout is the one worked out by
+//the Bambu Research Group. Their tray id stays the same for both tags on a spool
+fn parse_bambu(memory: &[u8]) -> Result<TagData, String> {
+    if memory.len() < CLASSIC_1K {
+        return Err(String::from("the dump is too short for a Bambu Lab tag"));
+    }
+    let block = |number: usize| &memory[number * CLASSIC_BLOCK..(number + 1) * CLASSIC_BLOCK];
+    let text = |bytes: &[u8]| {
+        let text = String::from_utf8_lossy(bytes)
+            .trim_matches(char::from(0))
+            .trim()
+            .to_string();
+        Some(text).filter(|text| !text.is_empty())
+    };
+    let u16_at = |bytes: &[u8], at: usize| u16::from_le_bytes([bytes[at], bytes[at + 1]]);
+
+    let material = text(block(2)).ok_or("no filament type on the tag, is it a Bambu Lab tag?")?;
+    //The detailed type is e.g. `PLA Basic`, keep the part after the material
+    let variant = text(block(4)).map(|detail| {
+        detail
+            .strip_prefix(&material)
+            .map(str::trim)
+            .unwrap_or(&detail)
+            .to_string()
+    });
+    let colour = block(5);
+    let weight = u16_at(colour, 4);
+    let diameter = f32::from_le_bytes([colour[8], colour[9], colour[10], colour[11]]);
+    let length = u16_at(block(14), 4);
+    let tray_uid = block(9);
+    let uid = if tray_uid.iter().any(|byte| *byte != 0) {
+        format!("bambu:{}", to_hex(tray_uid))
+    } else {
+        to_hex(&block(0)[..4])
+    };
+    Ok(TagData {
+        uid: Some(uid),
+        brand: Some(String::from("Bambu")),
+        material: Some(material),
+        variant: variant.filter(|variant| !variant.is_empty()),
+        colour: Some(format!("#{}", to_hex(&colour[..3]))),
+        weight: Some(weight as f32).filter(|weight| *weight > 0.0),
+        length: Some(length as f32).filter(|length| *length > 0.0),
+        diameter: Some(diameter).filter(|diameter| diameter.is_finite() && *diameter > 0.0),
+        ..Default::default()
+    })
+}
+
+//Finds the NDEF message in an NTAG's memory and reads the OpenSpool record from it
+fn parse_ntag(memory: &[u8], uid: Option<String>) -> Result<TagData, String> {
+    //Page 0 and 1 hold the 7 byte id, with a check byte after the first three
+    let uid = uid
+        .or_else(|| (memory.len() >= 8).then(|| to_hex(&[&memory[..3], &memory[4..8]].concat())));
+    parse_ndef(memory.get(NTAG_DATA..).unwrap_or_default(), uid)
+}
+
+fn parse_ndef(data: &[u8], uid: Option<String>) -> Result<TagData, String> {
+    let message = find_ndef(data).ok_or("no NDEF message on the tag")?;
+    let payload = find_record(message, OPENSPOOL_MIME).ok_or("no OpenSpool record on the tag")?;
+    let value: Value = serde_json::from_slice(payload).map_err(|e| e.to_string())?;
+    parse_openspool(&value, uid)
+}
+
+//Steps through the TLV blocks for the NDEF message one (type 3)
+fn find_ndef(data: &[u8]) -> Option<&[u8]> {
+    let mut at = 0;
+    while at < data.len() {
+        let kind = data[at];
+        match kind {
+            0x00 => {
+                at += 1;
+                continue;
+            }
+            0xFE => return None,
+            _ => {}
+        }
+        let (length, header) = match *data.get(at + 1)? {
+            0xFF => (
+                u16::from_be_bytes([*data.get(at + 2)?, *data.get(at + 3)?]) as usize,
+                4,
+            ),
+            length => (length as usize, 2),
+        };
+        let value = data.get(at + header..at + header + length)?;
+        if kind == 0x03 {
+            return Some(value);
+        }
+        at += header + length;
+    }
+    None
+}
+
+//Returns the payload of the first media type record with the given MIME type
+fn find_record<'a>(message: &'a [u8], mime: &str) -> Option<&'a [u8]> {
+    let mut at = 0;
+    while at < message.len() {
+        let flags = message[at];
+        let short = flags & 0x10 != 0;
+        let has_id = flags & 0x08 != 0;
+        let type_length = *message.get(at + 1)? as usize;
+        let mut cursor = at + 2;
+        let payload_length = if short {
+            cursor += 1;
+            *message.get(cursor - 1)? as usize
+        } else {
+            cursor += 4;
+            u32::from_be_bytes(message.get(cursor - 4..cursor)?.try_into().ok()?) as usize
+        };
+        let id_length = if has_id {
+            cursor += 1;
+            *message.get(cursor - 1)? as usize
+        } else {
+            0
+        };
+        let record_type = message.get(cursor..cursor + type_length)?;
+        cursor += type_length + id_length;
+        let payload = message.get(cursor..cursor + payload_length)?;
+        if flags & 0x07 == 0x02 && record_type.eq_ignore_ascii_case(mime.as_bytes()) {
+            return Some(payload);
+        }
+        at = cursor + payload_length;
+    }
+    None
+}
+
+//OpenSpool keeps every value as a string, numbers are accepted as well
+fn parse_openspool(value: &Value, uid: Option<String>) -> Result<TagData, String> {
+    if value.get("protocol").and_then(Value::as_str) != Some("openspool") {
+        return Err(String::from("the JSON is not an OpenSpool payload"));
+    }
+    let text = |key: &str| {
+        value
+            .get(key)
+            .and_then(Value::as_str)
+            .map(str::trim)
+            .filter(|text| !text.is_empty())
+            .map(str::to_string)
+    };
+    let number = |key: &str| match value.get(key) {
+        Some(Value::Number(number)) => number.as_f64().map(|number| number as f32),
+        Some(Value::String(text)) => text.trim().parse().ok(),
+        _ => None,
+    };
+    Ok(TagData {
+        uid,
+        roll_id: text(EXTRA_ID).and_then(|id| Uuid::parse_str(&id).ok()),
+        brand: text("brand").filter(|brand| brand != "Generic"),
+        material: text("type"),
+        variant: text("subtype"),
+        colour: text("color_hex")
+            .map(|hex| format!("#{}", hex.trim_start_matches('#').to_uppercase())),
+        weight: number("weight"),
+        length: None,
+        diameter: number("diameter"),
+    })
+}
+
+//An OpenSpool payload for one of our spools, with our id so it is found again
+pub fn openspool_payload(spool: &Spool) -> Value {
+    let mut payload = json!({
+        "protocol": "openspool",
+        "version": "1.0",
+        "type": spool.roll_material.as_deref().unwrap_or("PLA"),
+        "brand": "Generic",
+        "weight": format!("{:.0}", spool.roll_weight.unwrap_or_default()),
+        "diameter": format!("{}", spool.roll_diameter.unwrap_or(FILAMENT_DIAMETER)),
+    });
+    if let Some(hex) = spool.colour_hex() {
+        payload["color_hex"] = json!(hex);
+    }
+    if let Some(roll_id) = spool.roll_id {
+        payload[EXTRA_ID] = json!(roll_id.to_string());
+    }
+    payload
+}
+
+//Wraps the payload in a single MIME record inside an NDEF TLV, the bytes an NFC
+//tool writes from page 4 of an NTAG
+pub fn ndef_message(payload: &[u8]) -> Vec<u8> {
+    let short = payload.len() < 256;
+    //Message begin, message end, short record and the media type name format
+    let mut record = vec![0xC2 | if short { 0x10 } else { 0x00 }];
+    record.push(OPENSPOOL_MIME.len() as u8);
+    if short {
+        record.push(payload.len() as u8);
+    } else {
+        record.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    }
+    record.extend_from_slice(OPENSPOOL_MIME.as_bytes());
+    record.extend_from_slice(payload);
+
+    let mut message = vec![0x03];
+    if record.len() < 0xFF {
+        message.push(record.len() as u8);
+    } else {
+        message.push(0xFF);
+        message.extend_from_slice(&(record.len() as u16).to_be_bytes());
+    }
+    message.extend_from_slice(&record);
+    message.push(0xFE);
+    message
+}
+
+pub fn write_tag(spool: &Spool, format: TagFormat) -> Vec<u8> {
+    let payload = openspool_payload(spool).to_string().into_bytes();
+    match format {
+        TagFormat::Json => payload,
+        TagFormat::Ndef => ndef_message(&payload),
+    }
+}
@@ -1,5 +1,7 @@
+use crate::get_timestamp;
+use crate::locations;
 use crate::print_structs::*;
-use rusqlite::{Connection, Result};
+use rusqlite::{Connection, OptionalExtension, Result};
 use uuid::Uuid;
 
 pub struct RollId {
@@ -10,55 +12,323 @@ pub fn get_current_spool(conn: &Connection) -> Result<RollId> {
     let check_query =
         "SELECT r.roll_id FROM spool r WHERE r.roll_timestamp = (SELECT MAX(roll_timestamp) FROM spool)";
 
-    let exists_rt = conn.query_row(check_query, [], |row| {
+    conn.query_row(check_query, [], |row| {
         Ok(RollId {
             roll_id: row.get(0).unwrap(),
         })
-    });
-    exists_rt
+    })
+}
+
+//Links written for a spool, e.g. by a label printer's own software
+pub const SPOOL_URI: &str = "remainder://spool/";
+//Shorter id prefixes match too many spools to be useful
+const MIN_PREFIX: usize = 4;
+
+//Finds a spool by what was typed or scanned: its full id, a remainder://spool/ link,
+//its name or the start of its id as printed on the label. The newest spool wins
+//when names repeat, a short id has to match exactly one spool
+pub fn find_spool(conn: &Connection, spool: &str) -> Result<RollId> {
+    let spool = spool.trim();
+    let payload = match spool.get(..SPOOL_URI.len()) {
+        Some(scheme) if scheme.eq_ignore_ascii_case(SPOOL_URI) => &spool[SPOOL_URI.len()..],
+        _ => spool,
+    };
+    if let Ok(roll_id) = Uuid::parse_str(payload) {
+        let id_query = "SELECT roll_id FROM spool WHERE roll_id = ?1";
+        let id_rt = conn
+            .query_row(id_query, [roll_id], |row| row.get(0))
+            .optional()?;
+        if let Some(roll_id) = id_rt {
+            return Ok(RollId { roll_id });
+        }
+    }
+    let name_query =
+        "SELECT roll_id FROM spool WHERE roll_name = ?1 ORDER BY roll_timestamp DESC LIMIT 1";
+    let name_rt = conn
+        .query_row(name_query, [spool], |row| row.get(0))
+        .optional()?;
+    if let Some(roll_id) = name_rt {
+        return Ok(RollId { roll_id });
+    }
+
+    let prefix: String = payload.chars().filter(|c| *c != '-').collect();
+    if prefix.len() >= MIN_PREFIX && prefix.chars().all(|c| c.is_ascii_hexdigit()) {
+        let prefix_query = "SELECT roll_id FROM spool WHERE hex(roll_id) LIKE ?1 LIMIT 2";
+        let mut stmt = conn.prepare(prefix_query)?;
+        let matches = stmt
+            .query_map([format!("{}%", prefix.to_uppercase())], |row| row.get(0))?
+            .collect::<Result<Vec<Uuid>>>()?;
+        if let [roll_id] = matches[..] {
+            return Ok(RollId { roll_id });
+        }
+    }
+    Err(rusqlite::Error::QueryReturnedNoRows)
+}
+
+//Finds a print by its full id, the start of its id or its name, the newest print
+//wins when names repeat
+pub fn find_print(conn: &Connection, print: &str) -> Result<Uuid> {
+    let print = print.trim();
+    if let Ok(print_id) = Uuid::parse_str(print) {
+        let id_query = "SELECT print_id FROM filament WHERE print_id = ?1";
+        let id_rt = conn
+            .query_row(id_query, [print_id], |row| row.get(0))
+            .optional()?;
+        if let Some(print_id) = id_rt {
+            return Ok(print_id);
+        }
+    }
+    let name_query = "SELECT print_id FROM filament WHERE print_name = ?1
+            ORDER BY print_timestamp DESC, rowid DESC LIMIT 1";
+    let name_rt = conn
+        .query_row(name_query, [print], |row| row.get(0))
+        .optional()?;
+    if let Some(print_id) = name_rt {
+        return Ok(print_id);
+    }
+
+    let prefix: String = print.chars().filter(|c| *c != '-').collect();
+    if prefix.len() >= MIN_PREFIX && prefix.chars().all(|c| c.is_ascii_hexdigit()) {
+        let prefix_query = "SELECT print_id FROM filament WHERE hex(print_id) LIKE ?1 LIMIT 2";
+        let mut stmt = conn.prepare(prefix_query)?;
+        let matches = stmt
+            .query_map([format!("{}%", prefix.to_uppercase())], |row| row.get(0))?
+            .collect::<Result<Vec<Uuid>>>()?;
+        if let [print_id] = matches[..] {
+            return Ok(print_id);
+        }
+    }
+    Err(rusqlite::Error::QueryReturnedNoRows)
+}
+
+//Sets or clears the note on a spool
+pub fn set_spool_notes(conn: &Connection, roll_id: Uuid, notes: Option<&str>) -> Result<usize> {
+    conn.execute(
+        "UPDATE spool SET roll_notes = ?2 WHERE roll_id = ?1",
+        (roll_id, notes),
+    )
+}
+
+//Sets or clears the note on a print
+pub fn set_print_notes(conn: &Connection, print_id: Uuid, notes: Option<&str>) -> Result<usize> {
+    conn.execute(
+        "UPDATE filament SET print_notes = ?2 WHERE print_id = ?1",
+        (print_id, notes),
+    )
+}
+
+//The spool loaded in a printer, or the newest spool when none has been loaded
+pub fn spool_for_printer(conn: &Connection, printer: &str) -> Result<RollId> {
+    let loaded_query =
+        "SELECT roll_id FROM printer WHERE printer_name = ?1 AND roll_id IS NOT NULL";
+    let loaded_rt = conn
+        .query_row(loaded_query, [printer], |row| row.get(0))
+        .optional()?;
+    match loaded_rt {
+        Some(roll_id) => Ok(RollId { roll_id }),
+        None => get_current_spool(conn),
+    }
+}
+
+//The spool it replaces is taken out of the printer
+pub fn load_spool(conn: &Connection, printer: &str, roll_id: Uuid) -> Result<usize> {
+    let loaded_query =
+        "SELECT roll_id FROM printer WHERE printer_name = ?1 AND roll_id IS NOT NULL";
+    let loaded_rt: Option<Uuid> = conn
+        .query_row(loaded_query, [printer], |row| row.get(0))
+        .optional()?;
+    if let Some(replaced) = loaded_rt.filter(|loaded| *loaded != roll_id) {
+        take_out(conn, replaced, printer)?;
+    }
+    let load_rt = conn.execute(
+        "INSERT INTO printer (printer_name, roll_id) VALUES (?1, ?2)
+            ON CONFLICT(printer_name) DO UPDATE SET roll_id = excluded.roll_id",
+        (printer, roll_id),
+    )?;
+    locations::record_move(conn, roll_id, Some(printer))?;
+    Ok(load_rt)
+}
+
+//Leaves the printer without a spool, so prints go back to using the newest spool
+pub fn unload_spool(conn: &Connection, printer: &str) -> Result<usize> {
+    let loaded_query =
+        "SELECT roll_id FROM printer WHERE printer_name = ?1 AND roll_id IS NOT NULL";
+    let loaded_rt: Option<Uuid> = conn
+        .query_row(loaded_query, [printer], |row| row.get(0))
+        .optional()?;
+    let unload_rt = conn.execute(
+        "UPDATE printer SET roll_id = NULL WHERE printer_name = ?1",
+        [printer],
+    )?;
+    if let Some(roll_id) = loaded_rt {
+        take_out(conn, roll_id, printer)?;
+    }
+    Ok(unload_rt)
+}
+
+//Only a spool still recorded as being in the printer loses its location
+fn take_out(conn: &Connection, roll_id: Uuid, place: &str) -> Result<()> {
+    if locations::current_location(conn, roll_id)?.as_deref() == Some(place) {
+        locations::record_move(conn, roll_id, None)?;
+    }
+    Ok(())
+}
+
+//The spool in one tray of a printer, or the printer's spool when nothing was put in the tray
+pub fn spool_for_tray(conn: &Connection, printer: &str, tray: u32) -> Result<RollId> {
+    let loaded_query = "SELECT roll_id FROM ams_tray WHERE printer_name = ?1 AND tray_id = ?2";
+    let loaded_rt = conn
+        .query_row(loaded_query, (printer, tray), |row| row.get(0))
+        .optional()?;
+    match loaded_rt {
+        Some(roll_id) => Ok(RollId { roll_id }),
+        None => spool_for_printer(conn, printer),
+    }
+}
+
+pub fn load_tray(conn: &Connection, printer: &str, tray: u32, roll_id: Uuid) -> Result<usize> {
+    let loaded_query = "SELECT roll_id FROM ams_tray WHERE printer_name = ?1 AND tray_id = ?2";
+    let loaded_rt: Option<Uuid> = conn
+        .query_row(loaded_query, (printer, tray), |row| row.get(0))
+        .optional()?;
+    if let Some(replaced) = loaded_rt.filter(|loaded| *loaded != roll_id) {
+        take_out(conn, replaced, &locations::tray_location(printer, tray))?;
+    }
+    let load_rt = conn.execute(
+        "INSERT INTO ams_tray (printer_name, tray_id, roll_id) VALUES (?1, ?2, ?3)
+            ON CONFLICT(printer_name, tray_id) DO UPDATE SET roll_id = excluded.roll_id",
+        (printer, tray, roll_id),
+    )?;
+    locations::record_move(
+        conn,
+        roll_id,
+        Some(&locations::tray_location(printer, tray)),
+    )?;
+    Ok(load_rt)
+}
+
+pub fn unload_tray(conn: &Connection, printer: &str, tray: u32) -> Result<usize> {
+    let loaded_query = "SELECT roll_id FROM ams_tray WHERE printer_name = ?1 AND tray_id = ?2";
+    let loaded_rt: Option<Uuid> = conn
+        .query_row(loaded_query, (printer, tray), |row| row.get(0))
+        .optional()?;
+    let unload_rt = conn.execute(
+        "DELETE FROM ams_tray WHERE printer_name = ?1 AND tray_id = ?2",
+        (printer, tray),
+    )?;
+    if let Some(roll_id) = loaded_rt {
+        take_out(conn, roll_id, &locations::tray_location(printer, tray))?;
+    }
+    Ok(unload_rt)
+}
+
+//Name given to the entries weighing a spool adds
+pub const WEIGH_IN: &str = "Weigh-in";
+
+//Puts what the scale says the spool weighs against what the prints add up to, recording
+//the difference as its own entry so the remaining filament matches the spool again
+pub fn weigh_spool(conn: &Connection, roll_id: Uuid, scale_weight: Weight) -> Result<Filament> {
+    let tare: Option<Weight> = conn.query_row(
+        "SELECT roll_tare FROM spool WHERE roll_id = ?1",
+        [roll_id],
+        |row| row.get(0),
+    )?;
+    let (remaining, _) = crate::print_stats::remaining_on_spool(conn, roll_id);
+    let mut weigh_in = Filament {
+        print_id: Some(Uuid::new_v4()),
+        print_weight: Some(remaining - (scale_weight - tare.unwrap_or_default())),
+        print_time: Some(0),
+        roll_id: Some(roll_id),
+        print_name: Some(String::from(WEIGH_IN)),
+        print_status: Some(String::from(PRINT_WEIGH_IN)),
+        ..Default::default()
+    };
+    insert_print(conn, &mut weigh_in)?;
+    Ok(weigh_in)
 }
 
 pub fn add_new_print(conn: &Connection, print: &mut Filament) -> Result<usize> {
-    //Get Spool currently used
-    let exists_rt = get_current_spool(conn).unwrap();
-    print.roll_id = Some(exists_rt.roll_id);
+    //Get Spool currently used, unless the print already says which
+    if print.roll_id.is_none() {
+        let exists_rt = get_current_spool(conn).unwrap();
+        print.roll_id = Some(exists_rt.roll_id);
+    }
 
     //Add print to list
-    let rt = conn.execute(
+    insert_print(conn, print)
+}
+
+//Adds a print against the spool already set on it
+pub fn insert_print(conn: &Connection, print: &mut Filament) -> Result<usize> {
+    let timestamp = *print.timestamp.get_or_insert_with(get_timestamp);
+    let diameter: Option<f32> = conn
+        .query_row(
+            "SELECT roll_diameter FROM spool WHERE roll_id = ?1",
+            [print.roll_id.unwrap()],
+            |row| row.get(0),
+        )
+        .optional()?
+        .flatten();
+    let factor = diameter_factor(diameter);
+    conn.execute(
         "INSERT INTO filament (print_id,
                         print_weight,
                         print_length,
                         print_time,
-                        roll_id)
-            VALUES (?1,?2,?3,?4,?5)",
+                        roll_id,
+                        print_timestamp,
+                        print_name,
+                        print_status,
+                        print_notes,
+                        print_project)
+            VALUES (?1,?2,?3,?4,?5,?6,?7,?8,?9,?10)",
         (
             &print.print_id.unwrap().as_bytes(),
-            print.get_weight(),
-            print.get_length(),
+            print.get_weight(factor),
+            print.get_length(factor),
             print.print_time,
             &print.roll_id.unwrap().as_bytes(),
+            timestamp,
+            print.print_name.clone(),
+            print.print_status.as_deref().unwrap_or(PRINT_SUCCESS),
+            print.print_notes.clone(),
+            print.print_project.clone(),
         ),
-    );
-    println!("New print created");
-    return rt;
+    )
 }
 
 pub fn open_new_spool(conn: &Connection, spool_info: &mut Spool) -> Result<usize> {
-    let rt = conn.execute(
+    insert_spool(conn, spool_info)
+}
+
+pub fn insert_spool(conn: &Connection, spool_info: &mut Spool) -> Result<usize> {
+    conn.execute(
         "INSERT INTO spool (roll_id,
                         roll_name,
                         roll_weight,
                         roll_length,
-                        roll_timestamp)
-            VALUES (?1,?2,?3,?4,?5)",
+                        roll_timestamp,
+                        roll_material,
+                        roll_price,
+                        roll_tare,
+                        roll_colour,
+                        roll_diameter,
+                        roll_colour_hex,
+                        roll_notes)
+            VALUES (?1,?2,?3,?4,?5,?6,?7,?8,?9,?10,?11,?12)",
         (
             &spool_info.roll_id.unwrap().as_bytes(),
             spool_info.roll_name.clone(),
             spool_info.get_weight(),
             spool_info.get_length(),
             spool_info.timestamp,
+            spool_info.roll_material.clone(),
+            spool_info.roll_price,
+            spool_info.roll_tare,
+            spool_info.roll_colour.clone(),
+            spool_info.roll_diameter,
+            spool_info.roll_colour_hex.clone(),
+            spool_info.roll_notes.clone(),
         ),
-    );
-    println!("New spool created");
-    return rt;
+    )
 }
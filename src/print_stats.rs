@@ -1,33 +1,130 @@
-use crate::print_add::get_current_spool;
+use crate::locations;
+use crate::print_add::{get_current_spool, RollId};
 use crate::print_structs::*;
-use rusqlite::Connection;
+use crate::tags;
+use clap::ValueEnum;
+use rusqlite::{Connection, Result, Row};
+use uuid::Uuid;
 
-pub fn lifetime_statistics(conn: &Connection) -> (f32, f32, i32) {
-    let lifetime_query =
-        "SELECT SUM(print_weight), SUM(print_length), SUM(print_time) FROM filament";
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum Period {
+    Day,
+    Week,
+    Month,
+    Year,
+}
+
+impl Period {
+    fn strftime_format(&self) -> &'static str {
+        match self {
+            Period::Day => "%Y-%m-%d",
+            Period::Week => "%Y-W%W",
+            Period::Month => "%Y-%m",
+            Period::Year => "%Y",
+        }
+    }
+}
+
+const SPOOL_COLUMNS: &str =
+    "roll_id, roll_name, roll_weight, roll_length, roll_timestamp, roll_material, roll_price, roll_tare,
+    roll_colour, roll_diameter, roll_colour_hex, roll_notes";
+const FILAMENT_COLUMNS: &str = "print_id, print_weight, print_length, print_time, roll_id,
+    print_timestamp, print_name, print_status, print_notes, print_project";
+
+fn spool_from_row(row: &Row) -> Result<Spool> {
+    Ok(Spool {
+        roll_id: row.get(0)?,
+        roll_name: row.get(1)?,
+        roll_weight: row.get(2)?,
+        roll_length: row.get(3)?,
+        timestamp: row.get(4)?,
+        roll_material: row.get(5)?,
+        roll_price: row.get(6)?,
+        roll_tare: row.get(7)?,
+        roll_colour: row.get(8)?,
+        roll_diameter: row.get(9)?,
+        roll_colour_hex: row.get(10)?,
+        roll_notes: row.get(11)?,
+    })
+}
+
+fn print_from_row(row: &Row) -> Result<Filament> {
+    Ok(Filament {
+        print_id: row.get(0)?,
+        print_weight: row.get(1)?,
+        print_length: row.get(2)?,
+        print_time: row.get(3)?,
+        roll_id: row.get(4)?,
+        timestamp: row.get(5)?,
+        print_name: row.get(6)?,
+        print_status: row.get(7)?,
+        print_notes: row.get(8)?,
+        print_project: row.get(9)?,
+    })
+}
+
+//Every spool, oldest first
+pub fn list_spools(conn: &Connection) -> Result<Vec<Spool>> {
+    let spools_query = format!(
+        "SELECT {} FROM spool ORDER BY roll_timestamp",
+        SPOOL_COLUMNS
+    );
+    let mut stmt = conn.prepare(&spools_query)?;
+    let rows = stmt.query_map([], spool_from_row)?;
+    rows.collect()
+}
+
+//A single spool by its id
+pub fn get_spool(conn: &Connection, roll_id: Uuid) -> Result<Spool> {
+    let spool_query = format!("SELECT {} FROM spool WHERE roll_id = ?1", SPOOL_COLUMNS);
+    conn.query_row(&spool_query, [roll_id], spool_from_row)
+}
+
+//Every print, oldest first
+pub fn list_prints(conn: &Connection) -> Result<Vec<Filament>> {
+    let prints_query = format!(
+        "SELECT {} FROM filament ORDER BY COALESCE(print_timestamp, 0), rowid",
+        FILAMENT_COLUMNS
+    );
+    let mut stmt = conn.prepare(&prints_query)?;
+    let rows = stmt.query_map([], print_from_row)?;
+    rows.collect()
+}
+
+//Leaves out the corrections weighing a spool adds, for queries about what was printed
+pub const COUNTED_PRINTS: &str = "f.print_status IS NOT 'weigh-in'";
+
+pub fn lifetime_statistics(conn: &Connection) -> (Weight, Length, i32) {
+    let lifetime_query = format!(
+        "SELECT SUM(print_weight), SUM(print_length), SUM(print_time) FROM filament f WHERE {}",
+        COUNTED_PRINTS
+    );
 
     let lifetime_rt = conn
-        .query_row(lifetime_query, [], |row| {
+        .query_row(&lifetime_query, [], |row| {
             Ok(Filament {
-                print_id: None,
                 print_weight: row.get(0)?,
                 print_length: row.get(1)?,
                 print_time: row.get(2)?,
-                roll_id: None,
+                ..Default::default()
             })
         })
         .unwrap();
-    let lifetime_output = (
+    (
         lifetime_rt.print_weight.unwrap_or_default(),
         lifetime_rt.print_length.unwrap_or_default(),
         lifetime_rt.print_time.unwrap_or_default(),
-    );
-    lifetime_output
+    )
 }
 
-pub fn check_remaining(conn: &Connection) -> (f32, f32) {
+pub fn check_remaining(conn: &Connection) -> (Weight, Length) {
     //Get Spool currently used
     let current_spool = get_current_spool(conn).unwrap();
+    remaining_on_spool(conn, current_spool.roll_id)
+}
+
+pub fn remaining_on_spool(conn: &Connection, roll_id: Uuid) -> (Weight, Length) {
+    let current_spool = RollId { roll_id };
 
     //Get the sum of weight and length for current spool.
     //Get information for spool.
@@ -36,11 +133,9 @@ pub fn check_remaining(conn: &Connection) -> (f32, f32) {
     let accu_rt = conn
         .query_row(accu_query, [current_spool.roll_id], |row| {
             Ok(Filament {
-                print_id: None,
                 print_weight: row.get(0)?,
                 print_length: row.get(1)?,
-                print_time: None,
-                roll_id: None,
+                ..Default::default()
             })
         })
         .unwrap();
@@ -48,17 +143,228 @@ pub fn check_remaining(conn: &Connection) -> (f32, f32) {
     let original_rt = conn
         .query_row(original_query, [current_spool.roll_id], |row| {
             Ok(Spool {
-                roll_id: None,
-                roll_name: None,
                 roll_weight: row.get(0)?,
                 roll_length: row.get(1)?,
-                timestamp: None,
+                ..Default::default()
             })
         })
         .unwrap();
 
     let remaining_length = original_rt.roll_length.unwrap() - accu_rt.print_length.unwrap_or_default();
     let remaining_weight = original_rt.roll_weight.unwrap() - accu_rt.print_weight.unwrap_or_default();
-    let remaining_rt = (remaining_weight, remaining_length);
-    remaining_rt
+    (remaining_weight, remaining_length)
+}
+
+//Weight and length used per period, prints from before timestamps were
+//recorded are counted on the day their spool was opened
+pub fn usage_per_period(conn: &Connection, period: Period) -> Result<Vec<(String, f32, f32)>> {
+    let usage_query = format!(
+        "SELECT strftime(?1, COALESCE(f.print_timestamp, s.roll_timestamp), 'unixepoch') AS bucket,
+                SUM(f.print_weight), SUM(f.print_length)
+            FROM filament f JOIN spool s ON s.roll_id = f.roll_id
+            WHERE {}
+            GROUP BY bucket ORDER BY bucket",
+        COUNTED_PRINTS
+    );
+    let mut stmt = conn.prepare(&usage_query)?;
+    let rows = stmt.query_map([period.strftime_format()], |row| {
+        Ok((
+            row.get(0)?,
+            row.get::<_, Option<Weight>>(1)?.unwrap_or_default().grams(),
+            row.get::<_, Option<Length>>(2)?
+                .unwrap_or_default()
+                .metres(),
+        ))
+    })?;
+    rows.collect()
+}
+
+//Remaining weight of a spool after each print, starting with the full spool
+pub fn spool_burndown(conn: &Connection, roll_id: Uuid) -> Result<Vec<(String, f32)>> {
+    let original_query =
+        "SELECT strftime('%Y-%m-%d %H:%M', roll_timestamp, 'unixepoch'), roll_weight
+            FROM spool WHERE roll_id = ?1";
+    let (opened, mut remaining): (String, Weight) =
+        conn.query_row(original_query, [roll_id], |row| {
+            Ok((row.get(0)?, row.get(1)?))
+        })?;
+
+    let mut burndown = vec![(opened, remaining.grams())];
+    let prints_query =
+        "SELECT strftime('%Y-%m-%d %H:%M', print_timestamp, 'unixepoch'), print_weight
+            FROM filament WHERE roll_id = ?1
+            ORDER BY COALESCE(print_timestamp, 0), rowid";
+    let mut stmt = conn.prepare(prints_query)?;
+    let rows = stmt.query_map([roll_id], |row| {
+        Ok((
+            row.get::<_, Option<String>>(0)?,
+            row.get::<_, Option<Weight>>(1)?.unwrap_or_default(),
+        ))
+    })?;
+    for row in rows {
+        let (printed, weight) = row?;
+        remaining -= weight;
+        burndown.push((
+            printed.unwrap_or_else(|| String::from("unknown")),
+            remaining.grams(),
+        ));
+    }
+    Ok(burndown)
+}
+
+//Weight used per spool material, largest first
+pub fn material_share(conn: &Connection) -> Result<Vec<(String, f32)>> {
+    let share_query = format!(
+        "SELECT COALESCE(s.roll_material, 'Unknown') AS material, SUM(f.print_weight) AS used
+            FROM filament f JOIN spool s ON s.roll_id = f.roll_id
+            WHERE {}
+            GROUP BY material ORDER BY used DESC",
+        COUNTED_PRINTS
+    );
+    let mut stmt = conn.prepare(&share_query)?;
+    let rows = stmt.query_map([], |row| {
+        Ok((
+            row.get(0)?,
+            row.get::<_, Option<Weight>>(1)?.unwrap_or_default().grams(),
+        ))
+    })?;
+    rows.collect()
+}
+
+pub struct SpoolStatus {
+    pub roll_id: Uuid,
+    pub roll_name: String,
+    pub roll_material: Option<String>,
+    pub roll_weight: Weight,
+    pub used_weight: Weight,
+    pub roll_price: Option<f32>,
+    pub timestamp: i64,
+    //Where the spool was last moved to
+    pub location: Option<String>,
+    pub tags: Vec<String>,
+}
+
+impl SpoolStatus {
+    pub fn remaining_weight(&self) -> Weight {
+        self.roll_weight - self.used_weight
+    }
+}
+
+//Every spool with how much of it has been used, oldest first
+pub fn spool_inventory(conn: &Connection) -> Result<Vec<SpoolStatus>> {
+    let inventory_query = format!(
+        "SELECT s.roll_name, s.roll_material, s.roll_weight,
+                COALESCE(SUM(f.print_weight), 0), s.roll_price, s.roll_timestamp, s.roll_id, {}, {}
+            FROM spool s LEFT JOIN filament f ON f.roll_id = s.roll_id
+            GROUP BY s.roll_id ORDER BY s.roll_timestamp",
+        locations::LATEST_MOVE,
+        tags::SPOOL_TAGS
+    );
+    let mut stmt = conn.prepare(&inventory_query)?;
+    let rows = stmt.query_map([], |row| {
+        Ok(SpoolStatus {
+            roll_name: row.get::<_, Option<String>>(0)?.unwrap_or_default(),
+            roll_material: row.get(1)?,
+            roll_weight: row.get::<_, Option<Weight>>(2)?.unwrap_or_default(),
+            used_weight: row.get(3)?,
+            roll_price: row.get(4)?,
+            timestamp: row.get(5)?,
+            roll_id: row.get(6)?,
+            location: row.get(7)?,
+            tags: tags::split_tags(row.get(8)?),
+        })
+    })?;
+    rows.collect()
+}
+
+//Names of the printers each spool is loaded in
+pub fn loaded_printers(conn: &Connection) -> Result<Vec<(String, Uuid)>> {
+    let loaded_query =
+        "SELECT printer_name, roll_id FROM printer WHERE roll_id IS NOT NULL ORDER BY printer_name";
+    let mut stmt = conn.prepare(loaded_query)?;
+    let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+    rows.collect()
+}
+
+pub fn loaded_trays(conn: &Connection) -> Result<Vec<(String, u32, Uuid)>> {
+    let loaded_query =
+        "SELECT printer_name, tray_id, roll_id FROM ams_tray ORDER BY printer_name, tray_id";
+    let mut stmt = conn.prepare(loaded_query)?;
+    let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?;
+    rows.collect()
+}
+
+//Matches prints in the given YYYY-MM month, or every print when no month is given
+const MONTH_FILTER: &str =
+    "(?1 IS NULL OR strftime('%Y-%m', COALESCE(f.print_timestamp, s.roll_timestamp), 'unixepoch') = ?1)";
+
+//Filament used and what it cost per spool, spools without a price are skipped
+pub fn cost_per_spool(conn: &Connection, month: Option<&str>) -> Result<Vec<(String, f32, f32)>> {
+    let cost_query = format!(
+        "SELECT s.roll_name, SUM(f.print_weight), SUM(f.print_weight) * s.roll_price / s.roll_weight AS cost
+            FROM filament f JOIN spool s ON s.roll_id = f.roll_id
+            WHERE s.roll_price IS NOT NULL AND s.roll_weight > 0 AND {} AND {}
+            GROUP BY s.roll_id ORDER BY cost DESC",
+        MONTH_FILTER, COUNTED_PRINTS
+    );
+    let mut stmt = conn.prepare(&cost_query)?;
+    let rows = stmt.query_map([month], |row| {
+        Ok((
+            row.get::<_, Option<String>>(0)?.unwrap_or_default(),
+            row.get::<_, Weight>(1)?.grams(),
+            row.get(2)?,
+        ))
+    })?;
+    rows.collect()
+}
+
+//The prints that used the most filament, with the cost when the spool has a price
+pub fn top_prints(
+    conn: &Connection,
+    limit: usize,
+    month: Option<&str>,
+) -> Result<Vec<(Filament, Option<f32>)>> {
+    let top_query = format!(
+        "SELECT f.print_id, f.print_weight, f.print_length, f.print_time, f.roll_id,
+                COALESCE(f.print_timestamp, s.roll_timestamp), f.print_name, f.print_status,
+                f.print_weight * s.roll_price / s.roll_weight
+            FROM filament f JOIN spool s ON s.roll_id = f.roll_id
+            WHERE {} AND {}
+            ORDER BY f.print_weight DESC LIMIT ?2",
+        MONTH_FILTER, COUNTED_PRINTS
+    );
+    let mut stmt = conn.prepare(&top_query)?;
+    let rows = stmt.query_map((month, limit as i64), |row| {
+        Ok((
+            Filament {
+                print_id: row.get(0)?,
+                print_weight: row.get(1)?,
+                print_length: row.get(2)?,
+                print_time: row.get(3)?,
+                roll_id: row.get(4)?,
+                timestamp: row.get(5)?,
+                print_name: row.get(6)?,
+                print_status: row.get(7)?,
+                print_notes: None,
+                print_project: None,
+            },
+            row.get(8)?,
+        ))
+    })?;
+    rows.collect()
+}
+
+//Total and failed (or cancelled) prints per month
+pub fn failure_rates(conn: &Connection, month: Option<&str>) -> Result<Vec<(String, i32, i32)>> {
+    let failure_query = format!(
+        "SELECT strftime('%Y-%m', COALESCE(f.print_timestamp, s.roll_timestamp), 'unixepoch') AS bucket,
+                COUNT(*), SUM(CASE WHEN f.print_status IN ('{}', '{}') THEN 1 ELSE 0 END)
+            FROM filament f JOIN spool s ON s.roll_id = f.roll_id
+            WHERE {} AND {}
+            GROUP BY bucket ORDER BY bucket",
+        PRINT_FAILED, PRINT_CANCELLED, MONTH_FILTER, COUNTED_PRINTS
+    );
+    let mut stmt = conn.prepare(&failure_query)?;
+    let rows = stmt.query_map([month], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?;
+    rows.collect()
 }
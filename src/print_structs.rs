@@ -1,75 +1,337 @@
+use crate::units::MAX_QUANTITY;
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSql, ToSqlOutput, ValueRef};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::iter::Sum;
+use std::ops::{Add, AddAssign, Neg, Sub, SubAssign};
 use uuid::Uuid;
 
-#[derive(Debug)]
+//Weights are kept as whole milligrams and lengths as whole millimetres, so adding up
+//thousands of prints gives the same total every time. They are stored in the database
+//as integers and read and written in JSON as grams and metres
+macro_rules! fixed_point {
+    ($name:ident, $from:ident, $to:ident, $from_small:ident, $to_small:ident) => {
+        #[derive(
+            Debug,
+            Default,
+            Clone,
+            Copy,
+            PartialEq,
+            Eq,
+            PartialOrd,
+            Ord,
+            Hash,
+            Serialize,
+            Deserialize,
+        )]
+        #[serde(from = "f64", into = "f64")]
+        pub struct $name(i64);
+
+        impl $name {
+            pub const ZERO: $name = $name(0);
+
+            pub fn $from(value: f32) -> $name {
+                $name((f64::from(value) * 1000.0).round() as i64)
+            }
+
+            pub fn $to(self) -> f32 {
+                (self.0 as f64 / 1000.0) as f32
+            }
+
+            pub fn $from_small(value: i64) -> $name {
+                $name(value)
+            }
+
+            pub fn $to_small(self) -> i64 {
+                self.0
+            }
+        }
+
+        impl From<f64> for $name {
+            fn from(value: f64) -> $name {
+                $name((value * 1000.0).round() as i64)
+            }
+        }
+
+        impl From<$name> for f64 {
+            fn from(value: $name) -> f64 {
+                value.0 as f64 / 1000.0
+            }
+        }
+
+        //Shows the value in grams or metres, keeping any precision such as {:.1}
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                fmt::Display::fmt(&self.$to(), f)
+            }
+        }
+
+        impl Add for $name {
+            type Output = $name;
+            fn add(self, other: $name) -> $name {
+                $name(self.0 + other.0)
+            }
+        }
+
+        impl Sub for $name {
+            type Output = $name;
+            fn sub(self, other: $name) -> $name {
+                $name(self.0 - other.0)
+            }
+        }
+
+        impl Neg for $name {
+            type Output = $name;
+            fn neg(self) -> $name {
+                $name(-self.0)
+            }
+        }
+
+        impl AddAssign for $name {
+            fn add_assign(&mut self, other: $name) {
+                self.0 += other.0;
+            }
+        }
+
+        impl SubAssign for $name {
+            fn sub_assign(&mut self, other: $name) {
+                self.0 -= other.0;
+            }
+        }
+
+        impl Sum for $name {
+            fn sum<I: Iterator<Item = $name>>(iter: I) -> $name {
+                $name(iter.map(|value| value.0).sum())
+            }
+        }
+
+        impl ToSql for $name {
+            fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+                Ok(ToSqlOutput::from(self.0))
+            }
+        }
+
+        //Sums and other expressions can come back as reals, they still hold whole units
+        impl FromSql for $name {
+            fn column_result(value: ValueRef<'_>) -> FromSqlResult<$name> {
+                match value {
+                    ValueRef::Integer(value) => Ok($name(value)),
+                    ValueRef::Real(value) => Ok($name(value.round() as i64)),
+                    _ => Err(FromSqlError::InvalidType),
+                }
+            }
+        }
+    };
+}
+
+fixed_point!(Weight, from_grams, grams, from_milligrams, milligrams);
+fixed_point!(Length, from_metres, metres, from_millimetres, millimetres);
+
+#[derive(Debug, Default, Serialize, Deserialize)]
 pub struct Spool {
     pub roll_id: Option<Uuid>,
     pub roll_name: Option<String>,
-    pub roll_weight: Option<f32>,
-    pub roll_length: Option<f32>,
+    pub roll_weight: Option<Weight>,
+    pub roll_length: Option<Length>,
     pub timestamp: Option<i64>,
+    pub roll_material: Option<String>,
+    pub roll_price: Option<f32>,
+    //Weight of the empty spool, so it can be weighed on a scale
+    pub roll_tare: Option<Weight>,
+    //A name such as Black, older spools may have a hex code here instead
+    pub roll_colour: Option<String>,
+    //Only set for filament other than the standard 1.75mm
+    pub roll_diameter: Option<f32>,
+    //Hex code such as #1A1A1A
+    pub roll_colour_hex: Option<String>,
+    pub roll_notes: Option<String>,
 }
 
-#[derive(Debug)]
+//A filament product as sold, which new spools can be made from. The aliases
+//let catalogues written by hand or by other tools be imported
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Product {
+    #[serde(default, alias = "name")]
+    pub product_name: Option<String>,
+    #[serde(default, alias = "vendor", alias = "manufacturer", alias = "brand")]
+    pub product_vendor: Option<String>,
+    #[serde(default, alias = "material", alias = "type")]
+    pub product_material: Option<String>,
+    #[serde(default, alias = "colour", alias = "color", alias = "color_name")]
+    pub product_colour: Option<String>,
+    #[serde(default, alias = "colour_hex", alias = "color_hex")]
+    pub product_colour_hex: Option<String>,
+    //Millimetres
+    #[serde(default, alias = "diameter")]
+    pub product_diameter: Option<f32>,
+    //Grams per cubic centimetre
+    #[serde(default, alias = "density")]
+    pub product_density: Option<f32>,
+    //Grams of filament on a new spool
+    #[serde(default, alias = "weight", alias = "net_weight")]
+    pub product_weight: Option<Weight>,
+    #[serde(default, alias = "tare", alias = "spool_weight")]
+    pub product_tare: Option<Weight>,
+    #[serde(default, alias = "price")]
+    pub product_price: Option<f32>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
 pub struct Filament {
     pub print_id: Option<Uuid>,
-    pub print_weight: Option<f32>,
-    pub print_length: Option<f32>,
+    pub print_weight: Option<Weight>,
+    pub print_length: Option<Length>,
     pub print_time: Option<i32>,
     pub roll_id: Option<Uuid>,
+    pub timestamp: Option<i64>,
+    pub print_name: Option<String>,
+    pub print_status: Option<String>,
+    pub print_notes: Option<String>,
+    //Name of the project the print is charged to
+    pub print_project: Option<String>,
+}
+
+//Conversions for standard 1.75mm filament
+pub const GRAMS_PER_METRE: f32 = 3.0303;
+pub const METRES_PER_GRAM: f32 = 0.33;
+pub const FILAMENT_DIAMETER: f32 = 1.75;
+
+pub const PRINT_SUCCESS: &str = "success";
+pub const PRINT_FAILED: &str = "failed";
+pub const PRINT_CANCELLED: &str = "cancelled";
+//A correction from weighing the spool, which counts towards what is left but is not a print
+pub const PRINT_WEIGH_IN: &str = "weigh-in";
+
+//Thicker filament weighs more per metre, in proportion to its cross section
+pub fn diameter_factor(diameter: Option<f32>) -> f32 {
+    match diameter {
+        Some(diameter) => (diameter / FILAMENT_DIAMETER).powi(2),
+        None => 1.0,
+    }
+}
+
+//Fails with the field's name unless it is a finite number above the minimum,
+//or at or above it when zero is allowed, and no more than the command line takes
+fn check_number(field: &str, value: Option<f64>, allow_zero: bool) -> Result<(), String> {
+    match value {
+        Some(value) if !value.is_finite() || value < 0.0 || (value == 0.0 && !allow_zero) => {
+            let least = if allow_zero {
+                "0 or more"
+            } else {
+                "more than 0"
+            };
+            Err(format!("{} must be {}", field, least))
+        }
+        Some(value) if value > MAX_QUANTITY => Err(format!("{} is too large", field)),
+        _ => Ok(()),
+    }
 }
 
 impl Spool {
-    pub fn get_weight(&mut self) -> f32 {
-        const CONVERSION_FACTOR: f32 = 3.0303;
+    //The same checks for spools made on the command line and through the API
+    pub fn validate(&self) -> Result<(), String> {
+        if self.roll_weight.is_none() && self.roll_length.is_none() {
+            return Err(String::from("roll_weight or roll_length is required"));
+        }
+        check_number("roll_weight", self.roll_weight.map(f64::from), false)?;
+        check_number("roll_length", self.roll_length.map(f64::from), false)?;
+        check_number("roll_diameter", self.roll_diameter.map(f64::from), false)?;
+        check_number("roll_price", self.roll_price.map(f64::from), true)?;
+        check_number("roll_tare", self.roll_tare.map(f64::from), true)
+    }
+
+    fn diameter_factor(&self) -> f32 {
+        diameter_factor(self.roll_diameter)
+    }
+
+    pub fn get_weight(&mut self) -> Weight {
+        let conversion_factor = GRAMS_PER_METRE * self.diameter_factor();
         match self.roll_weight {
             Some(val) => val,
             None => {
                 let length = match self.roll_length {
-                    Some(val) => val as f32,
+                    Some(val) => val,
                     None => panic!("No Vals Set"),
                 };
-                let weight = length * CONVERSION_FACTOR;
+                let weight = Weight::from_grams(length.metres() * conversion_factor);
                 self.roll_weight = Some(weight);
                 weight
             }
         }
     }
 
-    pub fn get_length(&mut self) -> f32 {
-        const CONVERSION_FACTOR: f32 = 0.33;
+    pub fn get_length(&mut self) -> Length {
+        let conversion_factor = METRES_PER_GRAM / self.diameter_factor();
         match self.roll_length {
             Some(val) => val,
             None => {
-                let weight = self.roll_weight.unwrap() as f32;
-                let length = weight * CONVERSION_FACTOR;
+                let weight = self.roll_weight.unwrap();
+                let length = Length::from_metres(weight.grams() * conversion_factor);
                 self.roll_length = Some(length);
                 length
             }
         }
     }
+
+    //The colour as six hex digits, from the hex code or a colour that was given as one
+    pub fn colour_hex(&self) -> Option<String> {
+        [&self.roll_colour_hex, &self.roll_colour]
+            .into_iter()
+            .flatten()
+            .find_map(|colour| hex_colour(colour))
+    }
 }
 
+//Six hex digits in upper case without the #, None when it is not a hex colour
+pub fn hex_colour(colour: &str) -> Option<String> {
+    let hex = colour.trim().trim_start_matches('#');
+    if hex.len() == 6 && hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        Some(hex.to_uppercase())
+    } else {
+        None
+    }
+}
+
+//Prints convert with the diameter factor of the spool they were printed from
 impl Filament {
-    pub fn get_weight(&mut self) -> f32 {
-        const CONVERSION_FACTOR: f32 = 3.0303;
+    //The same checks for prints added on the command line and through the API
+    pub fn validate(&self) -> Result<(), String> {
+        if self.print_weight.is_none() && self.print_length.is_none() {
+            return Err(String::from("print_weight or print_length is required"));
+        }
+        check_number("print_weight", self.print_weight.map(f64::from), false)?;
+        check_number("print_length", self.print_length.map(f64::from), false)?;
+        check_number("print_time", self.print_time.map(f64::from), false)?;
+        match &self.print_status {
+            Some(status)
+                if ![PRINT_SUCCESS, PRINT_FAILED, PRINT_CANCELLED].contains(&status.as_str()) =>
+            {
+                Err(format!("{} is not a print status", status))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub fn get_weight(&mut self, diameter_factor: f32) -> Weight {
+        let conversion_factor = GRAMS_PER_METRE * diameter_factor;
         match self.print_weight {
             Some(val) => val,
             None => {
                 let length = self.print_length.unwrap();
-                let weight = length * CONVERSION_FACTOR;
+                let weight = Weight::from_grams(length.metres() * conversion_factor);
                 self.print_weight = Some(weight);
                 weight
             }
         }
     }
 
-    pub fn get_length(&mut self) -> f32 {
-        const CONVERSION_FACTOR: f32 = 0.33;
+    pub fn get_length(&mut self, diameter_factor: f32) -> Length {
+        let conversion_factor = METRES_PER_GRAM / diameter_factor;
         match self.print_length {
             Some(val) => val,
             None => {
                 let weight = self.print_weight.unwrap();
-                let length = weight * CONVERSION_FACTOR;
+                let length = Length::from_metres(weight.grams() * conversion_factor);
                 self.print_length = Some(length);
                 length
             }
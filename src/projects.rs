@@ -0,0 +1,126 @@
+use crate::print_stats;
+use crate::print_structs::{Length, Weight};
+use rusqlite::{Connection, OptionalExtension, Result};
+use std::io::Write;
+use uuid::Uuid;
+
+const DAY: i64 = 24 * 60 * 60;
+
+pub const REPORT_HEADERS: [&str; 7] = [
+    "project", "customer", "prints", "weight_g", "length_m", "time_s", "cost",
+];
+
+//What the prints of one project used, prints without a project have no name
+#[derive(Debug, PartialEq)]
+pub struct ProjectUsage {
+    pub project: Option<String>,
+    pub customer: Option<String>,
+    pub prints: i32,
+    pub weight: f32,
+    pub length: f32,
+    pub time: i64,
+    //Only counts prints from spools with a price
+    pub cost: Option<f32>,
+}
+
+//Adds a project, or changes the customer of the one with the same name
+pub fn add_project(conn: &Connection, name: &str, customer: Option<&str>) -> Result<usize> {
+    conn.execute(
+        "INSERT INTO project (project_name, project_customer) VALUES (?1, ?2)
+            ON CONFLICT(project_name) DO UPDATE SET project_customer = excluded.project_customer",
+        (name.trim(), customer),
+    )
+}
+
+//Prints keep the name of a removed project so past reports do not change
+pub fn remove_project(conn: &Connection, name: &str) -> Result<usize> {
+    conn.execute("DELETE FROM project WHERE project_name = ?1", [name.trim()])
+}
+
+//Every project with its customer and how many prints it has
+pub fn list_projects(conn: &Connection) -> Result<Vec<(String, Option<String>, i32)>> {
+    let projects_query = "SELECT p.project_name, p.project_customer,
+                (SELECT count(*) FROM filament f WHERE f.print_project = p.project_name)
+            FROM project p ORDER BY p.project_name";
+    let mut stmt = conn.prepare(projects_query)?;
+    let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?;
+    rows.collect()
+}
+
+//The project's name as it was added, names are compared without caring about case
+pub fn find_project(conn: &Connection, name: &str) -> Result<Option<String>> {
+    conn.query_row(
+        "SELECT project_name FROM project WHERE project_name = ?1",
+        [name.trim()],
+        |row| row.get(0),
+    )
+    .optional()
+}
+
+//Puts a print in a project, None takes it out of its project
+pub fn assign_print(conn: &Connection, print_id: Uuid, project: Option<&str>) -> Result<usize> {
+    conn.execute(
+        "UPDATE filament SET print_project = ?2 WHERE print_id = ?1",
+        (print_id, project),
+    )
+}
+
+//Usage per project for prints from the from date up to the end of the to date,
+//the most used project first and the prints without a project last
+pub fn project_usage(
+    conn: &Connection,
+    from: Option<i64>,
+    to: Option<i64>,
+) -> Result<Vec<ProjectUsage>> {
+    let usage_query = format!(
+        "SELECT f.print_project, p.project_customer, count(*),
+                COALESCE(SUM(f.print_weight), 0), COALESCE(SUM(f.print_length), 0),
+                COALESCE(SUM(f.print_time), 0),
+                SUM(f.print_weight * s.roll_price / s.roll_weight)
+            FROM filament f JOIN spool s ON s.roll_id = f.roll_id
+                LEFT JOIN project p ON p.project_name = f.print_project
+            WHERE (?1 IS NULL OR COALESCE(f.print_timestamp, s.roll_timestamp) >= ?1)
+                AND (?2 IS NULL OR COALESCE(f.print_timestamp, s.roll_timestamp) < ?2)
+                AND {}
+            GROUP BY f.print_project
+            ORDER BY f.print_project IS NULL, SUM(f.print_weight) DESC, f.print_project",
+        print_stats::COUNTED_PRINTS
+    );
+    let mut stmt = conn.prepare(&usage_query)?;
+    let rows = stmt.query_map((from, to.map(|to| to + DAY)), |row| {
+        Ok(ProjectUsage {
+            project: row.get(0)?,
+            customer: row.get(1)?,
+            prints: row.get(2)?,
+            weight: row.get::<_, Weight>(3)?.grams(),
+            length: row.get::<_, Length>(4)?.metres(),
+            time: row.get(5)?,
+            cost: row.get(6)?,
+        })
+    })?;
+    rows.collect()
+}
+
+pub fn write_report_csv<W: Write>(usage: &[ProjectUsage], writer: W) -> Result<(), String> {
+    let mut writer = csv::Writer::from_writer(writer);
+    writer
+        .write_record(REPORT_HEADERS)
+        .map_err(|e| e.to_string())?;
+    for project in usage {
+        writer
+            .write_record([
+                project.project.clone().unwrap_or_default(),
+                project.customer.clone().unwrap_or_default(),
+                project.prints.to_string(),
+                format!("{:.1}", project.weight),
+                format!("{:.2}", project.length),
+                project.time.to_string(),
+                project
+                    .cost
+                    .map(|cost| format!("{:.2}", cost))
+                    .unwrap_or_default(),
+            ])
+            .map_err(|e| e.to_string())?;
+    }
+    writer.flush().map_err(|e| e.to_string())
+}
@@ -0,0 +1,309 @@
+use crate::config::Config;
+use crate::format_timestamp;
+use crate::print_stats;
+use crate::print_structs::*;
+use rusqlite::Connection;
+
+const CHART_WIDTH: f32 = 640.0;
+const CHART_HEIGHT: f32 = 220.0;
+const TOP_PRINTS: usize = 10;
+
+const STYLE: &str = "body { font-family: sans-serif; margin: 2em; color: #222; }
+h1 { margin-bottom: 0; }
+h2 { border-bottom: 1px solid #ccc; margin-top: 2em; }
+table { border-collapse: collapse; margin-top: 1em; }
+th, td { padding: 0.3em 0.8em; border-bottom: 1px solid #eee; text-align: left; }
+td.num { text-align: right; }
+svg text { font-size: 11px; fill: #444; }
+.muted { color: #777; }
+@media print { body { margin: 0; } h2 { page-break-after: avoid; } }";
+
+//Builds a self contained HTML page with inline SVG charts, when a month (YYYY-MM)
+//is given the costs, top jobs and failure rates only cover that month. Prices are
+//shown in the configured currency
+pub fn html_report(
+    conn: &Connection,
+    month: Option<&str>,
+    config: &Config,
+) -> rusqlite::Result<String> {
+    let title = match month {
+        Some(month) => format!("Filament report {}", month),
+        None => String::from("Filament report"),
+    };
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n<style>\n{}\n</style>\n</head>\n<body>\n<h1>{}</h1>\n<p class=\"muted\">Generated {} UTC</p>\n",
+        escape(&title),
+        STYLE,
+        escape(&title),
+        format_timestamp(crate::get_timestamp())
+    );
+    html.push_str(&inventory_section(conn, config)?);
+    html.push_str(&usage_section(conn)?);
+    html.push_str(&cost_section(conn, month, config)?);
+    html.push_str(&top_prints_section(conn, month, config)?);
+    html.push_str(&failure_section(conn, month)?);
+    html.push_str("</body>\n</html>\n");
+    Ok(html)
+}
+
+fn inventory_section(conn: &Connection, config: &Config) -> rusqlite::Result<String> {
+    let inventory = print_stats::spool_inventory(conn)?;
+    let mut section = String::from("<h2>Inventory</h2>\n");
+    if inventory.is_empty() {
+        section.push_str("<p>No spools recorded yet.</p>\n");
+        return Ok(section);
+    }
+    let bars: Vec<(String, f32, f32)> = inventory
+        .iter()
+        .map(|spool| {
+            (
+                spool.roll_name.clone(),
+                spool.remaining_weight().grams(),
+                spool.roll_weight.grams(),
+            )
+        })
+        .collect();
+    section.push_str(&svg_remaining_chart(&bars));
+    section.push_str("<table>\n<tr><th>Spool</th><th>Material</th><th>Opened</th><th>Initial (g)</th><th>Remaining (g)</th><th>Remaining</th><th>Price</th></tr>\n");
+    for spool in &inventory {
+        let percent = if spool.roll_weight > Weight::ZERO {
+            spool.remaining_weight().grams() / spool.roll_weight.grams() * 100.0
+        } else {
+            0.0
+        };
+        section.push_str(&format!(
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td class=\"num\">{:.1}</td><td class=\"num\">{:.1}</td><td class=\"num\">{:.0}%</td><td class=\"num\">{}</td></tr>\n",
+            escape(&spool.roll_name),
+            escape(spool.roll_material.as_deref().unwrap_or("Unknown")),
+            format_timestamp(spool.timestamp),
+            spool.roll_weight,
+            spool.remaining_weight(),
+            percent,
+            spool
+                .roll_price
+                .map(|price| escape(&config.price(price)))
+                .unwrap_or_default()
+        ));
+    }
+    section.push_str("</table>\n");
+    Ok(section)
+}
+
+fn usage_section(conn: &Connection) -> rusqlite::Result<String> {
+    let usage = print_stats::usage_per_period(conn, print_stats::Period::Month)?;
+    let mut section = String::from("<h2>Usage per month</h2>\n");
+    if usage.is_empty() {
+        section.push_str("<p>No prints recorded yet.</p>\n");
+        return Ok(section);
+    }
+    let bars: Vec<(String, f32)> = usage
+        .into_iter()
+        .map(|(label, weight, _)| (label, weight))
+        .collect();
+    section.push_str(&svg_column_chart(&bars, "g"));
+    Ok(section)
+}
+
+fn cost_section(
+    conn: &Connection,
+    month: Option<&str>,
+    config: &Config,
+) -> rusqlite::Result<String> {
+    let costs = print_stats::cost_per_spool(conn, month)?;
+    let mut section = String::from("<h2>Cost breakdown</h2>\n");
+    if costs.is_empty() {
+        section.push_str("<p>No prints on spools with a price.</p>\n");
+        return Ok(section);
+    }
+    let bars: Vec<(String, f32)> = costs
+        .iter()
+        .map(|(name, _, cost)| (name.clone(), *cost))
+        .collect();
+    section.push_str(&svg_column_chart(&bars, ""));
+    section.push_str("<table>\n<tr><th>Spool</th><th>Used (g)</th><th>Cost</th></tr>\n");
+    for (name, used, cost) in &costs {
+        section.push_str(&format!(
+            "<tr><td>{}</td><td class=\"num\">{:.1}</td><td class=\"num\">{}</td></tr>\n",
+            escape(name),
+            used,
+            escape(&config.price(*cost))
+        ));
+    }
+    let total: f32 = costs.iter().map(|row| row.2).sum();
+    section.push_str(&format!(
+        "<tr><th>Total</th><td></td><th class=\"num\">{}</th></tr>\n</table>\n",
+        escape(&config.price(total))
+    ));
+    Ok(section)
+}
+
+fn top_prints_section(
+    conn: &Connection,
+    month: Option<&str>,
+    config: &Config,
+) -> rusqlite::Result<String> {
+    let prints = print_stats::top_prints(conn, TOP_PRINTS, month)?;
+    let mut section = String::from("<h2>Top jobs by filament</h2>\n");
+    if prints.is_empty() {
+        section.push_str("<p>No prints recorded yet.</p>\n");
+        return Ok(section);
+    }
+    section.push_str("<table>\n<tr><th>Job</th><th>Date</th><th>Status</th><th>Weight (g)</th><th>Length (m)</th><th>Time (min)</th><th>Cost</th></tr>\n");
+    for (print, cost) in &prints {
+        section.push_str(&format!(
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td class=\"num\">{:.1}</td><td class=\"num\">{:.1}</td><td class=\"num\">{}</td><td class=\"num\">{}</td></tr>\n",
+            escape(print.print_name.as_deref().unwrap_or("Unnamed print")),
+            print.timestamp.map(format_timestamp).unwrap_or_default(),
+            escape(print.print_status.as_deref().unwrap_or(PRINT_SUCCESS)),
+            print.print_weight.unwrap_or_default(),
+            print.print_length.unwrap_or_default(),
+            print.print_time.unwrap_or_default() / 60,
+            cost.map(|cost| escape(&config.price(cost)))
+                .unwrap_or_default()
+        ));
+    }
+    section.push_str("</table>\n");
+    Ok(section)
+}
+
+fn failure_section(conn: &Connection, month: Option<&str>) -> rusqlite::Result<String> {
+    let failures = print_stats::failure_rates(conn, month)?;
+    let mut section = String::from("<h2>Failure rates</h2>\n");
+    if failures.is_empty() {
+        section.push_str("<p>No prints recorded yet.</p>\n");
+        return Ok(section);
+    }
+    let bars: Vec<(String, f32)> = failures
+        .iter()
+        .map(|(label, total, failed)| (label.clone(), *failed as f32 / *total as f32 * 100.0))
+        .collect();
+    section.push_str(&svg_column_chart(&bars, "%"));
+    section.push_str(
+        "<table>\n<tr><th>Month</th><th>Prints</th><th>Failed</th><th>Failure rate</th></tr>\n",
+    );
+    for ((label, total, failed), (_, rate)) in failures.iter().zip(&bars) {
+        section.push_str(&format!(
+            "<tr><td>{}</td><td class=\"num\">{}</td><td class=\"num\">{}</td><td class=\"num\">{:.0}%</td></tr>\n",
+            escape(label),
+            total,
+            failed,
+            rate
+        ));
+    }
+    section.push_str("</table>\n");
+    Ok(section)
+}
+
+//Vertical bars with the value above and the label below each bar
+pub fn svg_column_chart(bars: &[(String, f32)], unit: &str) -> String {
+    let max_value = bars.iter().map(|bar| bar.1).fold(0.0, f32::max);
+    let slot = CHART_WIDTH / bars.len().max(1) as f32;
+    let plot_height = CHART_HEIGHT - 40.0;
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" viewBox=\"0 0 {} {}\">\n",
+        CHART_WIDTH, CHART_HEIGHT, CHART_WIDTH, CHART_HEIGHT
+    );
+    for (index, (label, value)) in bars.iter().enumerate() {
+        let height = if max_value > 0.0 {
+            value.max(0.0) / max_value * plot_height
+        } else {
+            0.0
+        };
+        let x = index as f32 * slot;
+        let y = 20.0 + plot_height - height;
+        svg.push_str(&format!(
+            "<rect x=\"{:.1}\" y=\"{:.1}\" width=\"{:.1}\" height=\"{:.1}\" fill=\"#4a90d9\"/>\n",
+            x + slot * 0.15,
+            y,
+            slot * 0.7,
+            height
+        ));
+        svg.push_str(&format!(
+            "<text x=\"{:.1}\" y=\"{:.1}\" text-anchor=\"middle\">{:.1}{}</text>\n",
+            x + slot / 2.0,
+            y - 4.0,
+            value,
+            escape(unit)
+        ));
+        svg.push_str(&format!(
+            "<text x=\"{:.1}\" y=\"{:.1}\" text-anchor=\"middle\">{}</text>\n",
+            x + slot / 2.0,
+            CHART_HEIGHT - 6.0,
+            escape(label)
+        ));
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
+
+//One horizontal bar per spool, the filled part is the remaining share of the spool
+fn svg_remaining_chart(bars: &[(String, f32, f32)]) -> String {
+    let row_height = 24.0;
+    let label_width = 180.0;
+    let bar_width = CHART_WIDTH - label_width - 60.0;
+    let height = row_height * bars.len() as f32;
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" viewBox=\"0 0 {} {}\">\n",
+        CHART_WIDTH, height, CHART_WIDTH, height
+    );
+    for (index, (label, remaining, initial)) in bars.iter().enumerate() {
+        let share = if *initial > 0.0 {
+            (remaining / initial).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        let y = index as f32 * row_height;
+        let colour = if share < 0.1 { "#d9534f" } else { "#5cb85c" };
+        svg.push_str(&format!(
+            "<text x=\"0\" y=\"{:.1}\">{}</text>\n",
+            y + 16.0,
+            escape(label)
+        ));
+        svg.push_str(&format!(
+            "<rect x=\"{}\" y=\"{:.1}\" width=\"{}\" height=\"16\" fill=\"#eee\"/>\n",
+            label_width,
+            y + 4.0,
+            bar_width
+        ));
+        svg.push_str(&format!(
+            "<rect x=\"{}\" y=\"{:.1}\" width=\"{:.1}\" height=\"16\" fill=\"{}\"/>\n",
+            label_width,
+            y + 4.0,
+            bar_width * share,
+            colour
+        ));
+        svg.push_str(&format!(
+            "<text x=\"{}\" y=\"{:.1}\">{:.0}%</text>\n",
+            label_width + bar_width + 6.0,
+            y + 16.0,
+            share * 100.0
+        ));
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
+
+pub fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+//Checks a month given on the command line is in the YYYY-MM form
+pub fn parse_month(month: &str) -> Result<String, String> {
+    let valid = match month.split_once('-') {
+        Some((year, month_number)) => {
+            year.len() == 4
+                && year.parse::<u32>().is_ok()
+                && matches!(month_number.parse::<u32>(), Ok(1..=12))
+                && month_number.len() == 2
+        }
+        None => false,
+    };
+    if valid {
+        Ok(month.to_string())
+    } else {
+        Err(format!("{} is not a month in the form YYYY-MM", month))
+    }
+}
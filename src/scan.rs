@@ -0,0 +1,134 @@
+use crate::drying;
+use crate::get_timestamp;
+use crate::print_add;
+use crate::print_stats;
+use crate::units::{self, Units};
+use crate::watch_bambu;
+use clap::ValueEnum;
+use rusqlite::Connection;
+use std::io::{BufRead, Write};
+use uuid::Uuid;
+
+//What to do with each spool that is scanned
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum ScanAction {
+    /// Show what is left on the spool
+    Show,
+    /// Load the spool into the printer
+    Load,
+    /// Ask for the scale reading and weigh the spool in
+    Weigh,
+}
+
+pub struct ScanOptions {
+    pub action: ScanAction,
+    pub printer: Option<String>,
+    pub tray: Option<u32>,
+    pub units: Units,
+}
+
+//Barcode scanners type the payload followed by enter, so each line is one scan.
+//While a weigh-in is waiting a number is taken as the scale reading, anything
+//else is looked up as the next spool. Returns how many scans were acted on
+pub fn scan<R: BufRead, W: Write>(
+    conn: &Connection,
+    input: R,
+    mut output: W,
+    options: &ScanOptions,
+) -> Result<usize, String> {
+    let mut handled = 0;
+    let mut weighing: Option<(Uuid, String)> = None;
+    for line in input.lines() {
+        let line = line.map_err(|e| e.to_string())?;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        if let Some((roll_id, name)) = &weighing {
+            if let Ok(scale_weight) = units::parse_weight(line) {
+                let weigh_in = print_add::weigh_spool(conn, *roll_id, scale_weight)
+                    .map_err(|e| e.to_string())?;
+                let (weight, _) = print_stats::remaining_on_spool(conn, *roll_id);
+                writeln!(
+                    output,
+                    "Adjusted {} by {}, {} left",
+                    name,
+                    options
+                        .units
+                        .weight(-weigh_in.print_weight.unwrap_or_default()),
+                    options.units.weight(weight)
+                )
+                .map_err(|e| e.to_string())?;
+                output.flush().map_err(|e| e.to_string())?;
+                weighing = None;
+                handled += 1;
+                continue;
+            }
+        }
+
+        let roll_id = match print_add::find_spool(conn, line) {
+            Ok(found) => found.roll_id,
+            Err(_) => {
+                writeln!(output, "No spool matches {}", line).map_err(|e| e.to_string())?;
+                continue;
+            }
+        };
+        let spool = print_stats::get_spool(conn, roll_id).map_err(|e| e.to_string())?;
+        let name = spool.roll_name.unwrap_or_else(|| roll_id.to_string());
+        match options.action {
+            ScanAction::Show => {
+                let (weight, length) = print_stats::remaining_on_spool(conn, roll_id);
+                writeln!(
+                    output,
+                    "{}: {} and {} left",
+                    name,
+                    options.units.weight(weight),
+                    options.units.length(length)
+                )
+                .map_err(|e| e.to_string())?;
+                handled += 1;
+            }
+            ScanAction::Load => {
+                let printer = options
+                    .printer
+                    .as_deref()
+                    .ok_or("loading needs a printer")?;
+                match options.tray {
+                    Some(tray) => {
+                        print_add::load_tray(conn, printer, tray, roll_id)
+                            .map_err(|e| e.to_string())?;
+                        writeln!(
+                            output,
+                            "Loaded {} into {} {}",
+                            name,
+                            printer,
+                            watch_bambu::tray_name(tray)
+                        )
+                    }
+                    None => {
+                        print_add::load_spool(conn, printer, roll_id).map_err(|e| e.to_string())?;
+                        writeln!(output, "Loaded {} into {}", name, printer)
+                    }
+                }
+                .map_err(|e| e.to_string())?;
+                if let Some(warning) = drying::exposure_warning(conn, roll_id, get_timestamp())
+                    .map_err(|e| e.to_string())?
+                {
+                    writeln!(output, "Warning: {}", warning).map_err(|e| e.to_string())?;
+                }
+                handled += 1;
+            }
+            ScanAction::Weigh => {
+                writeln!(
+                    output,
+                    "Scale reading for {}, in grams unless a unit is given:",
+                    name
+                )
+                .map_err(|e| e.to_string())?;
+                weighing = Some((roll_id, name));
+            }
+        }
+        output.flush().map_err(|e| e.to_string())?;
+    }
+    Ok(handled)
+}
@@ -0,0 +1,382 @@
+use crate::audit;
+use crate::print_add;
+use crate::print_stats;
+use crate::print_structs::*;
+use crate::projects;
+use crate::watch_bambu;
+use rusqlite::Connection;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use tiny_http::{Header, Method, Request, Response, Server};
+use uuid::Uuid;
+
+//Requests handled at once, they still take turns on the database
+pub const WORKERS: usize = 4;
+
+type Reply = Result<(u16, Value), (u16, String)>;
+
+//The web interface, built into the binary so there is nothing else to install
+const ASSETS: [(&str, &str, &str); 3] = [
+    (
+        "/",
+        "text/html; charset=utf-8",
+        include_str!("web/index.html"),
+    ),
+    (
+        "/app.js",
+        "text/javascript; charset=utf-8",
+        include_str!("web/app.js"),
+    ),
+    (
+        "/style.css",
+        "text/css; charset=utf-8",
+        include_str!("web/style.css"),
+    ),
+];
+
+//Answers requests until the server is shut down, each worker waits its turn for the connection.
+//Changes made through the API are recorded as the user running the server
+pub fn serve(
+    server: Server,
+    db_path: &Path,
+    token: Option<String>,
+    user: &str,
+) -> Result<(), String> {
+    let conn = Connection::open(db_path).map_err(|e| e.to_string())?;
+    audit::start(&conn, user).map_err(|e| e.to_string())?;
+    let server = Arc::new(server);
+    let db = Arc::new(Mutex::new(conn));
+    let token = Arc::new(token);
+    let workers: Vec<_> = (0..WORKERS)
+        .map(|_| {
+            let (server, db, token) = (server.clone(), db.clone(), token.clone());
+            std::thread::spawn(move || {
+                for request in server.incoming_requests() {
+                    handle(request, &db, token.as_deref());
+                }
+            })
+        })
+        .collect();
+    for worker in workers {
+        let _ = worker.join();
+    }
+    Ok(())
+}
+
+fn handle(mut request: Request, db: &Mutex<Connection>, token: Option<&str>) {
+    //The page itself holds no data, so it is served without the token
+    if *request.method() == Method::Get && !request.url().starts_with("/api") {
+        let path = request.url().split('?').next().unwrap_or_default();
+        let response = match ASSETS.iter().find(|asset| asset.0 == path) {
+            Some((_, content_type, body)) => respond(200, content_type, body.to_string()),
+            None => respond(404, "text/plain", String::from("Not found")),
+        };
+        let _ = request.respond(response);
+        return;
+    }
+
+    let reply = if authorised(&request, token) {
+        let mut body = String::new();
+        match request.as_reader().read_to_string(&mut body) {
+            Ok(_) => {
+                let (path, query) = split_url(request.url());
+                //A request which panicked leaves the connection itself in a usable state
+                let conn = db.lock().unwrap_or_else(|e| e.into_inner());
+                route(&conn, request.method(), &path, &query, &body)
+            }
+            Err(e) => Err((400, e.to_string())),
+        }
+    } else {
+        Err((401, String::from("Missing or wrong bearer token")))
+    };
+    let (status, body) = match reply {
+        Ok(reply) => reply,
+        Err((status, message)) => (status, json!({ "error": message })),
+    };
+    let _ = request.respond(respond(status, "application/json", body.to_string()));
+}
+
+fn respond(status: u16, content_type: &str, body: String) -> Response<std::io::Cursor<Vec<u8>>> {
+    Response::from_string(body)
+        .with_status_code(status)
+        .with_header(Header::from_bytes(&b"Content-Type"[..], content_type.as_bytes()).unwrap())
+}
+
+fn authorised(request: &Request, token: Option<&str>) -> bool {
+    let token = match token {
+        Some(token) => token,
+        None => return true,
+    };
+    request.headers().iter().any(|header| {
+        header.field.equiv("Authorization")
+            && header
+                .value
+                .as_str()
+                .strip_prefix("Bearer ")
+                .is_some_and(|given| same_token(given, token))
+    })
+}
+
+//Looks at every byte of the token whatever the guess, so the time taken to answer
+//gives away nothing about how much of a guess was right
+fn same_token(given: &str, token: &str) -> bool {
+    let given = given.as_bytes();
+    let mut difference = given.len() ^ token.len();
+    for (i, byte) in token.bytes().enumerate() {
+        difference |= usize::from(given.get(i).copied().unwrap_or(0) ^ byte);
+    }
+    difference == 0
+}
+
+fn split_url(url: &str) -> (Vec<String>, HashMap<String, String>) {
+    let (path, query) = url.split_once('?').unwrap_or((url, ""));
+    let path = path
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .map(decode)
+        .collect();
+    let query = query
+        .split('&')
+        .filter_map(|pair| {
+            let (key, value) = pair.split_once('=')?;
+            Some((decode(key), decode(value)))
+        })
+        .collect();
+    (path, query)
+}
+
+//Undoes percent encoding, so spool names with spaces can be used in urls
+fn decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' if i + 2 < bytes.len() => {
+                let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).unwrap_or_default();
+                match u8::from_str_radix(hex, 16) {
+                    Ok(byte) => {
+                        decoded.push(byte);
+                        i += 2;
+                    }
+                    Err(_) => decoded.push(b'%'),
+                }
+            }
+            b'+' => decoded.push(b' '),
+            byte => decoded.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn route(
+    conn: &Connection,
+    method: &Method,
+    path: &[String],
+    query: &HashMap<String, String>,
+    body: &str,
+) -> Reply {
+    let path: Vec<&str> = path.iter().map(|segment| segment.as_str()).collect();
+    match (method, path.as_slice()) {
+        (Method::Get, ["api", "spools"]) => ok(print_stats::list_spools(conn)),
+        (Method::Post, ["api", "spools"]) => create_spool(conn, body),
+        (Method::Get, ["api", "spools", spool]) => {
+            let roll_id = find(conn, spool)?;
+            let spool = print_stats::list_spools(conn)
+                .map_err(internal)?
+                .into_iter()
+                .find(|found| found.roll_id == Some(roll_id));
+            Ok((200, json!(spool)))
+        }
+        (Method::Get, ["api", "spools", spool, "remaining"]) => remaining(conn, find(conn, spool)?),
+        (Method::Get, ["api", "prints"]) => {
+            let mut prints = print_stats::list_prints(conn).map_err(internal)?;
+            if let Some(spool) = query.get("spool") {
+                let roll_id = find(conn, spool)?;
+                prints.retain(|print| print.roll_id == Some(roll_id));
+            }
+            Ok((200, json!(prints)))
+        }
+        (Method::Post, ["api", "prints"]) => create_print(
+            conn,
+            query.get("printer").map(|printer| printer.as_str()),
+            body,
+        ),
+        (Method::Get, ["api", "inventory"]) => inventory(conn, query.get("tag")),
+        (Method::Get, ["api", "printers"]) => {
+            let printers = print_stats::loaded_printers(conn).map_err(internal)?;
+            let printers: Vec<Value> = printers
+                .into_iter()
+                .map(|(printer, roll_id)| json!({ "printer": printer, "roll_id": roll_id }))
+                .collect();
+            Ok((200, json!(printers)))
+        }
+        (Method::Post, ["api", "printers", printer, "load"]) => {
+            let request: LoadRequest = parse_body(body)?;
+            let spool = match &request.spool {
+                Some(spool) => spool,
+                None => return Err((400, String::from("spool is required"))),
+            };
+            let roll_id = find(conn, spool)?;
+            match parse_tray(&request)? {
+                Some(tray) => print_add::load_tray(conn, printer, tray, roll_id),
+                None => print_add::load_spool(conn, printer, roll_id),
+            }
+            .map_err(internal)?;
+            Ok((200, json!({ "printer": printer, "roll_id": roll_id })))
+        }
+        (Method::Post, ["api", "printers", printer, "unload"]) => {
+            let request: LoadRequest = parse_body(if body.is_empty() { "{}" } else { body })?;
+            match parse_tray(&request)? {
+                Some(tray) => print_add::unload_tray(conn, printer, tray),
+                None => print_add::unload_spool(conn, printer),
+            }
+            .map_err(internal)?;
+            Ok((200, json!({ "printer": printer })))
+        }
+        (Method::Get, ["api", "remaining"]) => {
+            let spool = match query.get("printer") {
+                Some(printer) => print_add::spool_for_printer(conn, printer),
+                None => print_add::get_current_spool(conn),
+            };
+            let spool = spool.map_err(|_| (404, String::from("No spools have been created")))?;
+            remaining(conn, spool.roll_id)
+        }
+        (Method::Get, ["api", "stats"]) => {
+            let (weight, length, time) = print_stats::lifetime_statistics(conn);
+            Ok((
+                200,
+                json!({ "total_weight": weight, "total_length": length, "total_time": time }),
+            ))
+        }
+        (_, ["api", ..]) => Err((404, format!("No endpoint at /{}", path.join("/")))),
+        _ => Err((404, String::from("Not found"))),
+    }
+}
+
+fn ok<T: serde::Serialize>(result: rusqlite::Result<T>) -> Reply {
+    Ok((200, json!(result.map_err(internal)?)))
+}
+
+fn internal(e: rusqlite::Error) -> (u16, String) {
+    (500, e.to_string())
+}
+
+fn find(conn: &Connection, spool: &str) -> Result<Uuid, (u16, String)> {
+    print_add::find_spool(conn, spool)
+        .map(|found| found.roll_id)
+        .map_err(|_| (404, format!("No spool called {}", spool)))
+}
+
+fn remaining(conn: &Connection, roll_id: Uuid) -> Reply {
+    let (weight, length) = print_stats::remaining_on_spool(conn, roll_id);
+    Ok((
+        200,
+        json!({ "roll_id": roll_id, "remaining_weight": weight, "remaining_length": length }),
+    ))
+}
+
+#[derive(serde::Deserialize)]
+struct LoadRequest {
+    spool: Option<String>,
+    tray: Option<String>,
+}
+
+fn parse_tray(request: &LoadRequest) -> Result<Option<u32>, (u16, String)> {
+    request
+        .tray
+        .as_deref()
+        .map(watch_bambu::parse_tray)
+        .transpose()
+        .map_err(|e| (400, e))
+}
+
+//Spools with what is left on them and where they are loaded, for the web interface,
+//only the spools with the tag when one is given
+fn inventory(conn: &Connection, tag: Option<&String>) -> Reply {
+    let printers = print_stats::loaded_printers(conn).map_err(internal)?;
+    let trays = print_stats::loaded_trays(conn).map_err(internal)?;
+    let spools: Vec<Value> = print_stats::spool_inventory(conn)
+        .map_err(internal)?
+        .into_iter()
+        .filter(|spool| tag.is_none_or(|tag| spool.tags.contains(&tag.to_lowercase())))
+        .map(|spool| {
+            let mut loaded: Vec<Value> = printers
+                .iter()
+                .filter(|(_, roll_id)| *roll_id == spool.roll_id)
+                .map(|(printer, _)| json!({ "printer": printer, "tray": null }))
+                .collect();
+            loaded.extend(
+                trays
+                    .iter()
+                    .filter(|(_, _, roll_id)| *roll_id == spool.roll_id)
+                    .map(|(printer, tray, _)| {
+                        json!({ "printer": printer, "tray": watch_bambu::tray_name(*tray) })
+                    }),
+            );
+            json!({
+                "roll_id": spool.roll_id,
+                "roll_name": spool.roll_name,
+                "roll_material": spool.roll_material,
+                "roll_weight": spool.roll_weight,
+                "used_weight": spool.used_weight,
+                "remaining_weight": spool.remaining_weight(),
+                "loaded": loaded,
+                "location": spool.location,
+                "tags": spool.tags,
+            })
+        })
+        .collect();
+    Ok((200, json!(spools)))
+}
+
+fn parse_body<T: serde::de::DeserializeOwned>(body: &str) -> Result<T, (u16, String)> {
+    serde_json::from_str(body).map_err(|e| (400, format!("Invalid body: {}", e)))
+}
+
+fn create_spool(conn: &Connection, body: &str) -> Reply {
+    let mut spool: Spool = parse_body(body)?;
+    if spool.roll_name.as_deref().is_none_or(str::is_empty) {
+        return Err((400, String::from("roll_name is required")));
+    }
+    spool.validate().map_err(|e| (400, e))?;
+    spool.roll_id = Some(Uuid::new_v4());
+    spool.timestamp.get_or_insert_with(crate::get_timestamp);
+    print_add::insert_spool(conn, &mut spool).map_err(internal)?;
+    Ok((201, json!(spool)))
+}
+
+//Prints go on the spool named in the body, else the printer's spool, else the newest spool
+fn create_print(conn: &Connection, printer: Option<&str>, body: &str) -> Reply {
+    let mut print: Filament = parse_body(body)?;
+    print.validate().map_err(|e| (400, e))?;
+    if let Some(project) = &print.print_project {
+        let found = projects::find_project(conn, project).map_err(internal)?;
+        match found {
+            Some(found) => print.print_project = Some(found),
+            None => return Err((400, format!("No project called {}", project))),
+        }
+    }
+    match print.roll_id {
+        Some(roll_id) => {
+            find(conn, &roll_id.to_string())?;
+        }
+        None => {
+            let spool = match printer {
+                Some(printer) => print_add::spool_for_printer(conn, printer),
+                None => print_add::get_current_spool(conn),
+            };
+            let spool = spool.map_err(|_| (404, String::from("No spools have been created")))?;
+            print.roll_id = Some(spool.roll_id);
+        }
+    }
+    print.print_id = Some(Uuid::new_v4());
+    print
+        .print_status
+        .get_or_insert_with(|| String::from(PRINT_SUCCESS));
+    print_add::insert_print(conn, &mut print).map_err(internal)?;
+    Ok((201, json!(print)))
+}
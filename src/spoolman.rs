@@ -0,0 +1,422 @@
+use crate::print_add;
+use crate::print_stats;
+use crate::print_structs::*;
+use crate::{format_iso_timestamp, get_timestamp, parse_timestamp};
+use clap::ValueEnum;
+use rusqlite::{Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::io::{Read, Write};
+use uuid::Uuid;
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum SpoolmanFormat {
+    Json,
+    Csv,
+}
+
+//Key in Spoolman's extra fields holding our id, so a round trip keeps the same spools
+const EXTRA_ID: &str = "remainder_id";
+//Columns of the Spoolman CSV export which hold numbers or flags rather than text
+const NUMBER_COLUMNS: [&str; 12] = [
+    "id",
+    "price",
+    "remaining_weight",
+    "initial_weight",
+    "spool_weight",
+    "used_weight",
+    "remaining_length",
+    "used_length",
+    "density",
+    "diameter",
+    "weight",
+    "archived",
+];
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct SpoolmanVendor {
+    pub id: i64,
+    pub name: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct SpoolmanFilament {
+    pub id: i64,
+    pub name: Option<String>,
+    pub vendor: Option<SpoolmanVendor>,
+    pub material: Option<String>,
+    pub price: Option<f64>,
+    #[serde(default)]
+    pub density: f64,
+    #[serde(default)]
+    pub diameter: f64,
+    pub weight: Option<f64>,
+    pub spool_weight: Option<f64>,
+    pub color_hex: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct SpoolmanSpool {
+    pub id: i64,
+    pub registered: Option<String>,
+    pub first_used: Option<String>,
+    pub last_used: Option<String>,
+    pub filament: SpoolmanFilament,
+    pub price: Option<f64>,
+    pub remaining_weight: Option<f64>,
+    pub initial_weight: Option<f64>,
+    pub spool_weight: Option<f64>,
+    #[serde(default)]
+    pub used_weight: f64,
+    pub remaining_length: Option<f64>,
+    #[serde(default)]
+    pub used_length: f64,
+    pub location: Option<String>,
+    pub comment: Option<String>,
+    #[serde(default)]
+    pub archived: bool,
+    #[serde(default)]
+    pub extra: BTreeMap<String, String>,
+}
+
+impl SpoolmanSpool {
+    //Our id when the spool came from here, otherwise one derived from the Spoolman id
+    fn roll_id(&self) -> Uuid {
+        self.extra
+            .get(EXTRA_ID)
+            .and_then(|id| serde_json::from_str::<String>(id).ok())
+            .and_then(|id| Uuid::parse_str(&id).ok())
+            .unwrap_or_else(|| {
+                Uuid::new_v5(
+                    &Uuid::NAMESPACE_OID,
+                    format!("spoolman:spool:{}", self.id).as_bytes(),
+                )
+            })
+    }
+
+    fn roll_name(&self) -> String {
+        let filament = &self.filament;
+        match (&filament.vendor, &filament.name) {
+            (Some(vendor), Some(name)) => format!("{} {}", vendor.name, name),
+            (None, Some(name)) => name.clone(),
+            (Some(vendor), None) => format!(
+                "{} {}",
+                vendor.name,
+                filament.material.as_deref().unwrap_or("filament")
+            ),
+            (None, None) => format!("Spoolman spool {}", self.id),
+        }
+    }
+
+    fn initial_weight(&self) -> Option<f64> {
+        self.initial_weight.or(self.filament.weight).or(self
+            .remaining_weight
+            .map(|remaining| remaining + self.used_weight))
+    }
+}
+
+pub struct SpoolmanSummary {
+    pub spools: usize,
+    pub usage_records: usize,
+    pub duplicates: Vec<Uuid>,
+}
+
+//Spoolman's model of every spool, with the used weight taken from the prints
+pub fn spoolman_spools(conn: &Connection) -> Result<Vec<SpoolmanSpool>, String> {
+    let spools = print_stats::list_spools(conn).map_err(|e| e.to_string())?;
+    let prints = print_stats::list_prints(conn).map_err(|e| e.to_string())?;
+    let mut usage: HashMap<Uuid, (Weight, Length, Option<i64>, Option<i64>)> = HashMap::new();
+    for print in prints {
+        let used = usage.entry(print.roll_id.unwrap()).or_default();
+        used.0 += print.print_weight.unwrap_or_default();
+        used.1 += print.print_length.unwrap_or_default();
+        if let Some(timestamp) = print.timestamp {
+            used.2 = Some(used.2.map_or(timestamp, |first| first.min(timestamp)));
+            used.3 = Some(used.3.map_or(timestamp, |last| last.max(timestamp)));
+        }
+    }
+
+    //1cm of filament is the cross section in cm2, which gives the density from the weight per metre
+    let area = std::f64::consts::PI * (FILAMENT_DIAMETER as f64 / 20.0).powi(2);
+    let density = round(GRAMS_PER_METRE as f64 / (area * 100.0));
+    let mut filaments: Vec<(Option<String>, Option<String>)> = Vec::new();
+    let mut exported = Vec::new();
+    for (index, spool) in spools.into_iter().enumerate() {
+        let roll_id = spool.roll_id.unwrap();
+        let (used_weight, used_length, first_used, last_used) =
+            usage.get(&roll_id).cloned().unwrap_or_default();
+        let key = (spool.roll_name.clone(), spool.roll_material.clone());
+        let filament_id = match filaments.iter().position(|filament| *filament == key) {
+            Some(position) => position,
+            None => {
+                filaments.push(key);
+                filaments.len() - 1
+            }
+        } as i64
+            + 1;
+        let initial_weight = f64::from(spool.roll_weight.unwrap_or_default());
+        let initial_length = f64::from(spool.roll_length.unwrap_or_default());
+        let used_weight = f64::from(used_weight);
+        let used_length = f64::from(used_length);
+        let mut extra = BTreeMap::new();
+        extra.insert(
+            EXTRA_ID.to_string(),
+            serde_json::to_string(&roll_id.to_string()).unwrap(),
+        );
+        let color_hex = spool.colour_hex();
+        exported.push(SpoolmanSpool {
+            id: index as i64 + 1,
+            registered: spool.timestamp.map(format_iso_timestamp),
+            first_used: first_used.map(format_iso_timestamp),
+            last_used: last_used.map(format_iso_timestamp),
+            filament: SpoolmanFilament {
+                id: filament_id,
+                name: spool.roll_name,
+                vendor: None,
+                material: spool.roll_material,
+                price: spool.roll_price.map(|price| round(price as f64)),
+                density,
+                diameter: spool.roll_diameter.unwrap_or(FILAMENT_DIAMETER) as f64,
+                weight: Some(round(initial_weight)),
+                spool_weight: spool.roll_tare.map(|tare| round(f64::from(tare))),
+                color_hex,
+            },
+            price: spool.roll_price.map(|price| round(price as f64)),
+            remaining_weight: Some(round((initial_weight - used_weight).max(0.0))),
+            initial_weight: Some(round(initial_weight)),
+            spool_weight: spool.roll_tare.map(|tare| round(f64::from(tare))),
+            used_weight: round(used_weight),
+            remaining_length: Some(((initial_length - used_length) * 1000.0).max(0.0).round()),
+            used_length: (used_length * 1000.0).round(),
+            location: None,
+            comment: spool.roll_notes,
+            archived: false,
+            extra,
+        });
+    }
+    Ok(exported)
+}
+
+//Our weights are only kept as f32, so anything past two decimals is noise
+fn round(value: f64) -> f64 {
+    (value * 100.0).round() / 100.0
+}
+
+pub fn export_spoolman<W: Write>(
+    conn: &Connection,
+    format: SpoolmanFormat,
+    mut writer: W,
+) -> Result<usize, String> {
+    let spools = spoolman_spools(conn)?;
+    match format {
+        SpoolmanFormat::Json => {
+            serde_json::to_writer_pretty(&mut writer, &spools).map_err(|e| e.to_string())?;
+            writeln!(writer).map_err(|e| e.to_string())?;
+        }
+        SpoolmanFormat::Csv => {
+            let rows: Vec<BTreeMap<String, String>> = spools
+                .iter()
+                .map(|spool| {
+                    let mut row = BTreeMap::new();
+                    flatten("", &serde_json::to_value(spool).unwrap(), &mut row);
+                    row
+                })
+                .collect();
+            let mut headers: Vec<String> =
+                rows.iter().flat_map(|row| row.keys().cloned()).collect();
+            headers.sort();
+            headers.dedup();
+            let mut writer = csv::Writer::from_writer(writer);
+            writer.write_record(&headers).map_err(|e| e.to_string())?;
+            for row in &rows {
+                let record: Vec<&str> = headers
+                    .iter()
+                    .map(|header| row.get(header).map(String::as_str).unwrap_or(""))
+                    .collect();
+                writer.write_record(record).map_err(|e| e.to_string())?;
+            }
+            writer.flush().map_err(|e| e.to_string())?;
+        }
+    }
+    Ok(spools.len())
+}
+
+//Nested objects become dotted column names, the way Spoolman writes its CSV export
+fn flatten(prefix: &str, value: &Value, row: &mut BTreeMap<String, String>) {
+    match value {
+        Value::Object(fields) => {
+            for (key, value) in fields {
+                let name = if prefix.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{}.{}", prefix, key)
+                };
+                flatten(&name, value, row);
+            }
+        }
+        Value::Null => {
+            row.insert(prefix.to_string(), String::new());
+        }
+        Value::String(text) => {
+            row.insert(prefix.to_string(), text.clone());
+        }
+        other => {
+            row.insert(prefix.to_string(), other.to_string());
+        }
+    }
+}
+
+//Turns a row of dotted CSV columns back into the nested JSON Spoolman uses
+fn unflatten(headers: &csv::StringRecord, record: &csv::StringRecord) -> Value {
+    let mut root = Map::new();
+    for (header, cell) in headers.iter().zip(record.iter()) {
+        let path: Vec<&str> = header.trim().split('.').collect();
+        let last = path[path.len() - 1];
+        let value = if cell.trim().is_empty() {
+            Value::Null
+        } else if NUMBER_COLUMNS.contains(&last) {
+            serde_json::from_str(cell.trim()).unwrap_or(Value::String(cell.to_string()))
+        } else {
+            Value::String(cell.to_string())
+        };
+        let mut object = &mut root;
+        for key in &path[..path.len() - 1] {
+            let entry = object
+                .entry(key.to_string())
+                .or_insert_with(|| Value::Object(Map::new()));
+            if !entry.is_object() {
+                *entry = Value::Object(Map::new());
+            }
+            object = entry.as_object_mut().unwrap();
+        }
+        object.insert(last.to_string(), value);
+    }
+    //An empty vendor is written as empty columns rather than left out
+    if let Some(Value::Object(filament)) = root.get_mut("filament") {
+        if let Some(Value::Object(vendor)) = filament.get("vendor") {
+            if vendor.values().all(Value::is_null) {
+                filament.insert(String::from("vendor"), Value::Null);
+            }
+        }
+    }
+    Value::Object(root)
+}
+
+pub fn read_spoolman<R: Read>(
+    format: SpoolmanFormat,
+    reader: R,
+) -> Result<Vec<SpoolmanSpool>, String> {
+    match format {
+        SpoolmanFormat::Json => serde_json::from_reader(reader).map_err(|e| e.to_string()),
+        SpoolmanFormat::Csv => {
+            let mut reader = csv::Reader::from_reader(reader);
+            let headers = reader.headers().map_err(|e| e.to_string())?.clone();
+            let mut spools = Vec::new();
+            for (index, record) in reader.records().enumerate() {
+                let record = record.map_err(|e| format!("line {}: {}", index + 2, e))?;
+                let spool = serde_json::from_value(unflatten(&headers, &record))
+                    .map_err(|e| format!("line {}: {}", index + 2, e))?;
+                spools.push(spool);
+            }
+            Ok(spools)
+        }
+    }
+}
+
+//Adds the spools in one transaction, what was already used of a spool is recorded
+//as a single print so the remaining amount carries over
+pub fn import_spoolman(
+    conn: &Connection,
+    spools: &[SpoolmanSpool],
+    dry_run: bool,
+) -> Result<SpoolmanSummary, String> {
+    let tx = conn.unchecked_transaction().map_err(|e| e.to_string())?;
+    let mut summary = SpoolmanSummary {
+        spools: 0,
+        usage_records: 0,
+        duplicates: Vec::new(),
+    };
+    for spoolman_spool in spools {
+        let roll_id = spoolman_spool.roll_id();
+        let exists = tx
+            .query_row("SELECT 1 FROM spool WHERE roll_id = ?1", [roll_id], |_| {
+                Ok(())
+            })
+            .optional()
+            .map_err(|e| e.to_string())?;
+        if exists.is_some() {
+            summary.duplicates.push(roll_id);
+            continue;
+        }
+        let initial_weight = spoolman_spool
+            .initial_weight()
+            .ok_or_else(|| format!("spool {} has no weight", spoolman_spool.id))?;
+        let registered = spoolman_spool
+            .registered
+            .as_deref()
+            .and_then(parse_timestamp);
+        let mut spool = Spool {
+            roll_id: Some(roll_id),
+            roll_name: Some(spoolman_spool.roll_name()),
+            roll_weight: Some(Weight::from(initial_weight)),
+            timestamp: Some(registered.unwrap_or_else(get_timestamp)),
+            roll_material: spoolman_spool.filament.material.clone(),
+            roll_price: spoolman_spool
+                .price
+                .or(spoolman_spool.filament.price)
+                .map(|price| price as f32),
+            roll_tare: spoolman_spool
+                .spool_weight
+                .or(spoolman_spool.filament.spool_weight)
+                .map(Weight::from),
+            roll_colour_hex: spoolman_spool
+                .filament
+                .color_hex
+                .as_deref()
+                .and_then(hex_colour)
+                .map(|hex| format!("#{}", hex)),
+            roll_diameter: Some(spoolman_spool.filament.diameter as f32)
+                .filter(|diameter| *diameter > 0.0 && *diameter != FILAMENT_DIAMETER),
+            roll_notes: spoolman_spool.comment.clone(),
+            ..Default::default()
+        };
+        print_add::insert_spool(&tx, &mut spool).map_err(|e| e.to_string())?;
+        summary.spools += 1;
+
+        if spoolman_spool.used_weight > 0.0 {
+            let used_length = if spoolman_spool.used_length > 0.0 {
+                Some(Length::from(spoolman_spool.used_length / 1000.0))
+            } else {
+                None
+            };
+            let mut usage = Filament {
+                print_id: Some(Uuid::new_v5(
+                    &roll_id,
+                    format!("spoolman:usage:{}", spoolman_spool.id).as_bytes(),
+                )),
+                print_weight: Some(Weight::from(spoolman_spool.used_weight)),
+                print_length: used_length,
+                print_time: Some(0),
+                roll_id: Some(roll_id),
+                timestamp: spoolman_spool
+                    .last_used
+                    .as_deref()
+                    .and_then(parse_timestamp)
+                    .or(registered),
+                print_name: Some(String::from("Used before import from Spoolman")),
+                print_status: Some(String::from(PRINT_SUCCESS)),
+                print_notes: None,
+                print_project: None,
+            };
+            print_add::insert_print(&tx, &mut usage).map_err(|e| e.to_string())?;
+            summary.usage_records += 1;
+        }
+    }
+    if dry_run {
+        tx.rollback().map_err(|e| e.to_string())?;
+    } else {
+        tx.commit().map_err(|e| e.to_string())?;
+    }
+    Ok(summary)
+}
@@ -0,0 +1,143 @@
+use crate::print_structs::*;
+use crate::sync_state::{self, SyncState, SyncSummary};
+use rusqlite::Connection;
+use serde::Deserialize;
+use uuid::Uuid;
+
+//Jobs asked for in each request to the history
+const PAGE_SIZE: usize = 100;
+
+#[derive(Deserialize, Debug)]
+pub struct MoonrakerJob {
+    pub job_id: String,
+    pub filename: String,
+    pub status: String,
+    pub start_time: f64,
+    pub end_time: Option<f64>,
+    #[serde(default)]
+    pub print_duration: f64,
+    //Millimetres of filament
+    #[serde(default)]
+    pub filament_used: f64,
+}
+
+#[derive(Deserialize)]
+struct HistoryResult {
+    jobs: Vec<MoonrakerJob>,
+}
+
+#[derive(Deserialize)]
+struct HistoryResponse {
+    result: HistoryResult,
+}
+
+impl MoonrakerJob {
+    fn print_status(&self) -> Option<&'static str> {
+        match self.status.as_str() {
+            "in_progress" => None,
+            "completed" => Some(PRINT_SUCCESS),
+            "cancelled" => Some(PRINT_CANCELLED),
+            _ => Some(PRINT_FAILED),
+        }
+    }
+
+    //Job ids are hex counters, compared as numbers so they stay ordered past ffffff
+    fn number(&self) -> Option<u64> {
+        u64::from_str_radix(&self.job_id, 16).ok()
+    }
+}
+
+fn source_name(url: &str) -> String {
+    format!("moonraker:{}", url.trim_end_matches('/'))
+}
+
+//Jobs started after the given time, oldest first
+pub fn fetch_jobs(url: &str, since: Option<f64>) -> Result<Vec<MoonrakerJob>, String> {
+    let mut jobs = Vec::new();
+    loop {
+        let mut request = ureq::get(&format!(
+            "{}/server/history/list",
+            url.trim_end_matches('/')
+        ))
+        .query("order", "asc")
+        .query("limit", &PAGE_SIZE.to_string())
+        .query("start", &jobs.len().to_string());
+        if let Some(since) = since {
+            request = request.query("since", &since.to_string());
+        }
+        let response: HistoryResponse = request
+            .call()
+            .map_err(|e| format!("Could not reach Moonraker: {}", e))?
+            .into_json()
+            .map_err(|e| format!("Unexpected reply from Moonraker: {}", e))?;
+        let count = response.result.jobs.len();
+        jobs.extend(response.result.jobs);
+        if count < PAGE_SIZE {
+            return Ok(jobs);
+        }
+    }
+}
+
+//Records every finished job newer than the last sync against the spool, stopping
+//at a job still printing so it is picked up once it is done
+pub fn import_jobs(
+    conn: &Connection,
+    url: &str,
+    jobs: &[MoonrakerJob],
+    roll_id: Uuid,
+) -> Result<SyncSummary, String> {
+    let source = source_name(url);
+    let tx = conn.unchecked_transaction().map_err(|e| e.to_string())?;
+    let mut state = sync_state::get_sync_state(&tx, &source).map_err(|e| e.to_string())?;
+    let last_number = state
+        .last_job_id
+        .as_deref()
+        .and_then(|id| u64::from_str_radix(id, 16).ok());
+    let mut summary = SyncSummary {
+        imported: 0,
+        skipped: 0,
+    };
+    for job in jobs {
+        if let (Some(last), Some(number)) = (last_number, job.number()) {
+            if number <= last {
+                summary.skipped += 1;
+                continue;
+            }
+        }
+        let status = match job.print_status() {
+            Some(status) => status,
+            None => break,
+        };
+        let mut print = Filament {
+            print_id: Some(Uuid::new_v5(
+                &Uuid::NAMESPACE_URL,
+                format!("{}/{}", source, job.job_id).as_bytes(),
+            )),
+            print_length: Some(Length::from_millimetres(job.filament_used.round() as i64)),
+            print_time: Some(job.print_duration.round() as i32),
+            roll_id: Some(roll_id),
+            timestamp: Some(job.end_time.unwrap_or(job.start_time + job.print_duration) as i64),
+            print_name: Some(job.filename.clone()),
+            print_status: Some(String::from(status)),
+            ..Default::default()
+        };
+        if sync_state::record_synced_print(&tx, &mut print).map_err(|e| e.to_string())? {
+            summary.imported += 1;
+        } else {
+            summary.skipped += 1;
+        }
+        state = SyncState {
+            last_job_id: Some(job.job_id.clone()),
+            last_time: Some(job.start_time),
+        };
+    }
+    sync_state::save_sync_state(&tx, &source, &state).map_err(|e| e.to_string())?;
+    tx.commit().map_err(|e| e.to_string())?;
+    Ok(summary)
+}
+
+pub fn sync_moonraker(conn: &Connection, url: &str, roll_id: Uuid) -> Result<SyncSummary, String> {
+    let state = sync_state::get_sync_state(conn, &source_name(url)).map_err(|e| e.to_string())?;
+    let jobs = fetch_jobs(url, state.last_time)?;
+    import_jobs(conn, url, &jobs, roll_id)
+}
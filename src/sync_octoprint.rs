@@ -0,0 +1,332 @@
+use crate::print_structs::*;
+use crate::sync_state::{self, SyncSummary};
+use rusqlite::Connection;
+use serde::Deserialize;
+use uuid::Uuid;
+
+#[derive(Deserialize, Debug, Default)]
+pub struct OctoPrintFile {
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub path: String,
+    #[serde(rename = "type", default)]
+    pub file_type: String,
+    #[serde(rename = "gcodeAnalysis")]
+    pub gcode_analysis: Option<GcodeAnalysis>,
+    pub prints: Option<PrintHistory>,
+    //Only filled in for folders
+    #[serde(default)]
+    pub children: Vec<OctoPrintFile>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct GcodeAnalysis {
+    #[serde(rename = "estimatedPrintTime")]
+    pub estimated_print_time: Option<f64>,
+    //Per extruder, length in millimetres and volume in cubic centimetres
+    #[serde(default)]
+    pub filament: std::collections::HashMap<String, ToolUsage>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct ToolUsage {
+    pub length: Option<f64>,
+    pub volume: Option<f64>,
+}
+
+//How many times the file was printed, OctoPrint only keeps the details of the last one
+#[derive(Deserialize, Debug, Default)]
+pub struct PrintHistory {
+    #[serde(default)]
+    pub success: u32,
+    #[serde(default)]
+    pub failure: u32,
+    pub last: Option<LastPrint>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct LastPrint {
+    //When the print finished
+    pub date: f64,
+    #[serde(rename = "printTime")]
+    pub print_time: Option<f64>,
+    pub success: bool,
+}
+
+//A job recorded by the Print History plugin, which keeps every print and not just the last
+#[derive(Deserialize, Debug, Default)]
+pub struct HistoryJob {
+    #[serde(rename = "fileName", default)]
+    pub file_name: String,
+    //When the job started
+    pub timestamp: f64,
+    #[serde(rename = "printTime")]
+    pub print_time: Option<f64>,
+    pub success: bool,
+    //Millimetres
+    #[serde(rename = "filamentLength")]
+    pub filament_length: Option<f64>,
+}
+
+#[derive(Deserialize)]
+struct HistoryResponse {
+    history: Vec<HistoryJob>,
+}
+
+#[derive(Deserialize)]
+struct FilesResponse {
+    files: Vec<OctoPrintFile>,
+}
+
+impl GcodeAnalysis {
+    //Metres of filament the whole file uses over every extruder, worked out from the
+    //volume when the slicer did not give a length
+    fn filament_length(&self) -> f64 {
+        let area = std::f64::consts::PI * (FILAMENT_DIAMETER as f64 / 2.0).powi(2);
+        self.filament
+            .values()
+            .map(|tool| match (tool.length, tool.volume) {
+                (Some(length), _) => length,
+                (None, Some(volume)) => volume * 1000.0 / area,
+                (None, None) => 0.0,
+            })
+            .sum::<f64>()
+            / 1000.0
+    }
+}
+
+fn source_name(url: &str) -> String {
+    format!("octoprint:{}", url.trim_end_matches('/'))
+}
+
+//A finished print from either the plugin or the file list, id_key tells it apart
+//from every other print of the file
+struct FinishedJob<'a> {
+    file: Option<&'a OctoPrintFile>,
+    name: String,
+    id_key: String,
+    finished: f64,
+    print_time: Option<f64>,
+    success: bool,
+    //Metres, when the plugin measured it
+    length: Option<f64>,
+}
+
+impl FinishedJob<'_> {
+    fn filament_length(&self) -> f64 {
+        let analysis = self.file.and_then(|file| file.gcode_analysis.as_ref());
+        let mut length = self
+            .length
+            .unwrap_or_else(|| analysis.map_or(0.0, |analysis| analysis.filament_length()));
+        //A failed print stopped part way, so only count the share of the file it got through
+        if !self.success {
+            let estimated = analysis.and_then(|analysis| analysis.estimated_print_time);
+            if let (Some(estimated), Some(print_time)) = (estimated, self.print_time) {
+                if estimated > 0.0 {
+                    length *= (print_time / estimated).min(1.0);
+                }
+            }
+        }
+        length
+    }
+}
+
+//Every gcode file on the printer with folders flattened out
+pub fn fetch_files(url: &str, api_key: &str) -> Result<Vec<OctoPrintFile>, String> {
+    let response: FilesResponse = ureq::get(&format!("{}/api/files", url.trim_end_matches('/')))
+        .set("X-Api-Key", api_key)
+        .query("recursive", "true")
+        .call()
+        .map_err(|e| format!("Could not reach OctoPrint: {}", e))?
+        .into_json()
+        .map_err(|e| format!("Unexpected reply from OctoPrint: {}", e))?;
+    let mut files = Vec::new();
+    let mut pending = response.files;
+    while let Some(mut file) = pending.pop() {
+        pending.append(&mut file.children);
+        if file.file_type == "machinecode" {
+            files.push(file);
+        }
+    }
+    Ok(files)
+}
+
+//Every job the Print History plugin recorded, None when the plugin is not installed
+pub fn fetch_history(url: &str, api_key: &str) -> Result<Option<Vec<HistoryJob>>, String> {
+    let response = ureq::get(&format!(
+        "{}/plugin/printhistory/history",
+        url.trim_end_matches('/')
+    ))
+    .set("X-Api-Key", api_key)
+    .call();
+    let response: HistoryResponse = match response {
+        Ok(response) => response
+            .into_json()
+            .map_err(|e| format!("Unexpected reply from OctoPrint: {}", e))?,
+        Err(ureq::Error::Status(404, _)) => return Ok(None),
+        Err(e) => return Err(format!("Could not reach OctoPrint: {}", e)),
+    };
+    Ok(Some(response.history))
+}
+
+//Each job the plugin recorded, known by its file and the time it finished
+fn history_jobs<'a>(
+    source: &str,
+    files: &'a [OctoPrintFile],
+    history: &[HistoryJob],
+) -> Vec<FinishedJob<'a>> {
+    history
+        .iter()
+        .map(|job| {
+            let file = files
+                .iter()
+                .find(|file| file.path == job.file_name || file.name == job.file_name);
+            let finished = job.timestamp + job.print_time.unwrap_or_default();
+            FinishedJob {
+                file,
+                name: file.map_or_else(|| job.file_name.clone(), |file| file.name.clone()),
+                id_key: format!(
+                    "{}/{}@{}",
+                    source,
+                    file.map_or(job.file_name.as_str(), |file| file.path.as_str()),
+                    finished
+                ),
+                finished,
+                print_time: job.print_time,
+                success: job.success,
+                length: job.filament_length.map(|length| length / 1000.0),
+            }
+        })
+        .collect()
+}
+
+//Without the plugin only the last print of a file has its details, the prints counted
+//since the last sync before it are added with the slicer's estimate. How far failed
+//ones got is not known, so they count no filament. The counts each file was synced at
+//are kept in sync_state as success/failure
+fn counted_jobs<'a>(
+    conn: &Connection,
+    source: &str,
+    files: &'a [OctoPrintFile],
+) -> Result<Vec<FinishedJob<'a>>, String> {
+    let mut jobs = Vec::new();
+    for file in files {
+        let Some(history) = &file.prints else {
+            continue;
+        };
+        let Some(last) = &history.last else {
+            continue;
+        };
+        let file_source = format!("{}/{}", source, file.path);
+        let mut state =
+            sync_state::get_sync_state(conn, &file_source).map_err(|e| e.to_string())?;
+        let (synced_success, synced_failure) = state
+            .last_job_id
+            .as_deref()
+            .and_then(|counts| counts.split_once('/'))
+            .and_then(|(success, failure)| Some((success.parse().ok()?, failure.parse().ok()?)))
+            .unwrap_or((0, 0));
+        let estimated = file
+            .gcode_analysis
+            .as_ref()
+            .and_then(|analysis| analysis.estimated_print_time);
+        //The last print is the newest of its kind, so it takes the highest count
+        let (last_success, last_failure) = if last.success {
+            (history.success, 0)
+        } else {
+            (0, history.failure)
+        };
+        for (success, synced, count, last_count) in [
+            (true, synced_success, history.success, last_success),
+            (false, synced_failure, history.failure, last_failure),
+        ] {
+            for index in (synced + 1)..=count {
+                if index == last_count {
+                    continue;
+                }
+                let status = if success { PRINT_SUCCESS } else { PRINT_FAILED };
+                jobs.push(FinishedJob {
+                    file: Some(file),
+                    name: file.name.clone(),
+                    id_key: format!("{}#{}{}", file_source, status, index),
+                    finished: last.date,
+                    print_time: if success { estimated } else { None },
+                    success,
+                    length: if success { None } else { Some(0.0) },
+                });
+            }
+        }
+        jobs.push(FinishedJob {
+            file: Some(file),
+            name: file.name.clone(),
+            id_key: format!("{}@{}", file_source, last.date),
+            finished: last.date,
+            print_time: last.print_time,
+            success: last.success,
+            length: None,
+        });
+        state.last_job_id = Some(format!("{}/{}", history.success, history.failure));
+        state.last_time = Some(last.date);
+        sync_state::save_sync_state(conn, &file_source, &state).map_err(|e| e.to_string())?;
+    }
+    Ok(jobs)
+}
+
+//Records every finished print against the spool, from the Print History plugin when it is
+//installed and from the counts on each file when it is not. A print is known by its file
+//and the time it finished so running this again adds nothing new
+pub fn import_files(
+    conn: &Connection,
+    url: &str,
+    files: &[OctoPrintFile],
+    history: Option<&[HistoryJob]>,
+    roll_id: Uuid,
+) -> Result<SyncSummary, String> {
+    let source = source_name(url);
+    let tx = conn.unchecked_transaction().map_err(|e| e.to_string())?;
+    let mut finished = match history {
+        Some(history) => history_jobs(&source, files, history),
+        None => counted_jobs(&tx, &source, files)?,
+    };
+    finished.sort_by(|a, b| a.finished.total_cmp(&b.finished));
+
+    let mut summary = SyncSummary {
+        imported: 0,
+        skipped: 0,
+    };
+    for job in finished {
+        let mut print = Filament {
+            print_id: Some(Uuid::new_v5(&Uuid::NAMESPACE_URL, job.id_key.as_bytes())),
+            print_length: Some(Length::from(job.filament_length())),
+            print_time: Some(job.print_time.unwrap_or_default().round() as i32),
+            roll_id: Some(roll_id),
+            timestamp: Some(job.finished as i64),
+            print_name: Some(job.name.clone()),
+            print_status: Some(String::from(if job.success {
+                PRINT_SUCCESS
+            } else {
+                PRINT_FAILED
+            })),
+            ..Default::default()
+        };
+        if sync_state::record_synced_print(&tx, &mut print).map_err(|e| e.to_string())? {
+            summary.imported += 1;
+        } else {
+            summary.skipped += 1;
+        }
+    }
+    tx.commit().map_err(|e| e.to_string())?;
+    Ok(summary)
+}
+
+pub fn sync_octoprint(
+    conn: &Connection,
+    url: &str,
+    api_key: &str,
+    roll_id: Uuid,
+) -> Result<SyncSummary, String> {
+    let files = fetch_files(url, api_key)?;
+    let history = fetch_history(url, api_key)?;
+    import_files(conn, url, &files, history.as_deref(), roll_id)
+}
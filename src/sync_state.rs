@@ -0,0 +1,50 @@
+use crate::print_add;
+use crate::print_structs::*;
+use rusqlite::{Connection, OptionalExtension, Result};
+
+//How far a print history source has been imported
+#[derive(Debug, Default, PartialEq)]
+pub struct SyncState {
+    pub last_job_id: Option<String>,
+    pub last_time: Option<f64>,
+}
+
+pub struct SyncSummary {
+    pub imported: usize,
+    pub skipped: usize,
+}
+
+pub fn get_sync_state(conn: &Connection, source: &str) -> Result<SyncState> {
+    let state_query = "SELECT last_job_id, last_time FROM sync_state WHERE sync_source = ?1";
+    let state_rt = conn
+        .query_row(state_query, [source], |row| {
+            Ok(SyncState {
+                last_job_id: row.get(0)?,
+                last_time: row.get(1)?,
+            })
+        })
+        .optional()?;
+    Ok(state_rt.unwrap_or_default())
+}
+
+pub fn save_sync_state(conn: &Connection, source: &str, state: &SyncState) -> Result<usize> {
+    conn.execute(
+        "INSERT INTO sync_state (sync_source, last_job_id, last_time) VALUES (?1, ?2, ?3)
+            ON CONFLICT(sync_source) DO UPDATE SET last_job_id = excluded.last_job_id,
+                last_time = excluded.last_time",
+        (source, &state.last_job_id, state.last_time),
+    )
+}
+
+//Adds a print from a sync unless a print with the same id is already stored
+pub fn record_synced_print(conn: &Connection, print: &mut Filament) -> Result<bool> {
+    let exists_query = "SELECT 1 FROM filament WHERE print_id = ?1";
+    let exists = conn
+        .query_row(exists_query, [print.print_id.unwrap()], |_| Ok(()))
+        .optional()?;
+    if exists.is_some() {
+        return Ok(false);
+    }
+    print_add::insert_print(conn, print)?;
+    Ok(true)
+}
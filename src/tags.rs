@@ -0,0 +1,139 @@
+use crate::print_stats;
+use crate::print_structs::{Length, Weight};
+use rusqlite::{Connection, Result};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+//Correlated query for the tags of spool s, joined with commas
+pub const SPOOL_TAGS: &str =
+    "(SELECT group_concat(t.tag, ',') FROM spool_tag t WHERE t.roll_id = s.roll_id)";
+
+//Filament and time used by the prints carrying a tag, either on the print itself
+//or on the spool it was printed from
+#[derive(Debug, PartialEq)]
+pub struct TagUsage {
+    pub tag: String,
+    pub prints: i32,
+    pub weight: f32,
+    pub length: f32,
+    pub time: i32,
+}
+
+//Tags are kept in lower case so customer:ACME and customer:acme are the same tag.
+//Commas separate tags when they are listed together so they cannot be in one
+pub fn parse_tag(tag: &str) -> Result<String, String> {
+    let tag = tag.trim().to_lowercase();
+    if tag.is_empty() {
+        return Err(String::from("tags cannot be empty"));
+    }
+    if tag.contains(',') || tag.chars().any(char::is_whitespace) {
+        return Err(format!("{} has a comma or space in it", tag));
+    }
+    Ok(tag)
+}
+
+//Splits what SPOOL_TAGS gives back, sorted so listings are stable
+pub fn split_tags(tags: Option<String>) -> Vec<String> {
+    let mut tags: Vec<String> = tags
+        .unwrap_or_default()
+        .split(',')
+        .filter(|tag| !tag.is_empty())
+        .map(str::to_string)
+        .collect();
+    tags.sort();
+    tags
+}
+
+pub fn tag_spool(conn: &Connection, roll_id: Uuid, tags: &[String]) -> Result<usize> {
+    let mut added = 0;
+    for tag in tags {
+        added += conn.execute(
+            "INSERT OR IGNORE INTO spool_tag (roll_id, tag) VALUES (?1, ?2)",
+            (roll_id, tag),
+        )?;
+    }
+    Ok(added)
+}
+
+pub fn untag_spool(conn: &Connection, roll_id: Uuid, tags: &[String]) -> Result<usize> {
+    let mut removed = 0;
+    for tag in tags {
+        removed += conn.execute(
+            "DELETE FROM spool_tag WHERE roll_id = ?1 AND tag = ?2",
+            (roll_id, tag),
+        )?;
+    }
+    Ok(removed)
+}
+
+pub fn tag_print(conn: &Connection, print_id: Uuid, tags: &[String]) -> Result<usize> {
+    let mut added = 0;
+    for tag in tags {
+        added += conn.execute(
+            "INSERT OR IGNORE INTO print_tag (print_id, tag) VALUES (?1, ?2)",
+            (print_id, tag),
+        )?;
+    }
+    Ok(added)
+}
+
+pub fn untag_print(conn: &Connection, print_id: Uuid, tags: &[String]) -> Result<usize> {
+    let mut removed = 0;
+    for tag in tags {
+        removed += conn.execute(
+            "DELETE FROM print_tag WHERE print_id = ?1 AND tag = ?2",
+            (print_id, tag),
+        )?;
+    }
+    Ok(removed)
+}
+
+//The tags of every print that has any
+pub fn print_tags(conn: &Connection) -> Result<HashMap<Uuid, Vec<String>>> {
+    let mut stmt = conn.prepare("SELECT print_id, tag FROM print_tag ORDER BY tag")?;
+    let mut tags: HashMap<Uuid, Vec<String>> = HashMap::new();
+    let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+    for row in rows {
+        let (print_id, tag) = row?;
+        tags.entry(print_id).or_default().push(tag);
+    }
+    Ok(tags)
+}
+
+//Every tag in use with how many spools and prints carry it
+pub fn list_tags(conn: &Connection) -> Result<Vec<(String, i32, i32)>> {
+    let tags_query = "SELECT tag, SUM(spools), SUM(prints) FROM (
+                SELECT tag, 1 AS spools, 0 AS prints FROM spool_tag
+                UNION ALL SELECT tag, 0, 1 FROM print_tag)
+            GROUP BY tag ORDER BY tag";
+    let mut stmt = conn.prepare(tags_query)?;
+    let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?;
+    rows.collect()
+}
+
+//Usage per tag, only for tags starting with the prefix when one is given, e.g. project:
+pub fn tag_usage(conn: &Connection, prefix: Option<&str>) -> Result<Vec<TagUsage>> {
+    let usage_query = format!(
+        "SELECT t.tag, count(*), COALESCE(SUM(f.print_weight), 0),
+                COALESCE(SUM(f.print_length), 0), COALESCE(SUM(f.print_time), 0)
+            FROM (SELECT print_id, tag FROM print_tag
+                UNION SELECT f.print_id, st.tag FROM filament f
+                    JOIN spool_tag st ON st.roll_id = f.roll_id) t
+            JOIN filament f ON f.print_id = t.print_id
+            WHERE (?1 IS NULL OR substr(t.tag, 1, length(?1)) = ?1) AND {}
+            GROUP BY t.tag ORDER BY SUM(f.print_weight) DESC, t.tag",
+        print_stats::COUNTED_PRINTS
+    );
+    let mut stmt = conn.prepare(&usage_query)?;
+    let prefix = prefix.map(str::to_lowercase);
+    let rows = stmt.query_map([prefix], |row| {
+        Ok(TagUsage {
+            tag: row.get(0)?,
+            prints: row.get(1)?,
+            weight: row.get::<_, Weight>(2)?.grams(),
+            length: row.get::<_, Length>(3)?.metres(),
+            time: row.get(4)?,
+        })
+    })?;
+    rows.collect()
+}
@@ -1,5 +1,108 @@
 use rusqlite::{Connection, Result};
 
+//Bumped whenever the tables change, so older databases get upgraded and
+//backups from a newer version of the program are refused
+pub const SCHEMA_VERSION: i32 = 15;
+
+//Databases from before this version kept weights in grams and lengths in metres as reals
+const FIXED_POINT_VERSION: i32 = 12;
+
+//Databases from before this version stored weigh-ins as successful prints
+const WEIGH_IN_VERSION: i32 = 15;
+
+//Weights are whole milligrams and lengths whole millimetres, see print_structs
+const SPOOL_TABLE: &str = "CREATE TABLE spool (
+    roll_id BLOB PRIMARY KEY,
+    roll_name TEXT,
+    roll_weight INTEGER,
+    roll_length INTEGER,
+    roll_timestamp INTEGER NOT NULL,
+    roll_material TEXT,
+    roll_price REAL,
+    roll_tare INTEGER,
+    roll_colour TEXT,
+    roll_diameter REAL,
+    roll_opened INTEGER,
+    roll_colour_hex TEXT,
+    roll_notes TEXT)";
+
+const FILAMENT_TABLE: &str = "CREATE TABLE filament(
+    print_id BLOB PRIMARY KEY,
+    print_weight INTEGER,
+    print_length INTEGER,
+    print_time INTEGER,
+    roll_id BLOB NOT NULL,
+    print_timestamp INTEGER,
+    print_name TEXT,
+    print_status TEXT,
+    print_notes TEXT,
+    print_project TEXT)";
+
+const PRODUCT_TABLE: &str = "CREATE TABLE product(
+    product_name TEXT PRIMARY KEY COLLATE NOCASE,
+    product_vendor TEXT,
+    product_material TEXT,
+    product_colour TEXT,
+    product_colour_hex TEXT,
+    product_diameter REAL,
+    product_density REAL,
+    product_weight INTEGER,
+    product_tare INTEGER,
+    product_price REAL)";
+
+pub fn setup_database(conn: &Connection) -> Result<(), &'static str> {
+    let upgrading = needs_migration(conn)?;
+    let version = schema_version(conn)?;
+    create_new_spool_tbl(conn)?;
+    create_new_filament_tbl(conn)?;
+    create_new_printer_tbl(conn)?;
+    create_new_tray_tbl(conn)?;
+    create_new_sync_tbl(conn)?;
+    create_new_product_tbl(conn)?;
+    create_new_location_tbl(conn)?;
+    create_new_move_tbl(conn)?;
+    create_new_drying_tbl(conn)?;
+    create_new_threshold_tbl(conn)?;
+    create_new_tag_tbls(conn)?;
+    create_new_project_tbl(conn)?;
+    create_new_audit_tbl(conn)?;
+    create_new_certificate_tbl(conn)?;
+    if upgrading && version < FIXED_POINT_VERSION {
+        convert_to_fixed_point(conn)?;
+    }
+    if upgrading && version < WEIGH_IN_VERSION {
+        mark_weigh_ins(conn)?;
+    }
+    if let Err(e) = conn.pragma_update(None, "user_version", SCHEMA_VERSION) {
+        eprintln!("Err: {}", e);
+        return Err("Err setting schema version");
+    }
+    Ok(())
+}
+
+pub fn schema_version(conn: &Connection) -> Result<i32, &'static str> {
+    match conn.query_row("PRAGMA user_version", [], |row| row.get(0)) {
+        Ok(version) => Ok(version),
+        Err(e) => {
+            eprintln!("Err: {}", e);
+            Err("Err reading schema version")
+        }
+    }
+}
+
+//True when the database has tables from an older version of the program
+pub fn needs_migration(conn: &Connection) -> Result<bool, &'static str> {
+    let check_query = "SELECT count(name) FROM sqlite_master WHERE type='table' AND name='spool'";
+    let exists: i32 = match conn.query_row(check_query, [], |row| row.get(0)) {
+        Ok(exists) => exists,
+        Err(e) => {
+            eprintln!("Err: {}", e);
+            return Err("Err with query");
+        }
+    };
+    Ok(exists == 1 && schema_version(conn)? < SCHEMA_VERSION)
+}
+
 pub fn create_new_spool_tbl(conn: &Connection) -> Result<(), &'static str> {
     let check_query = "SELECT count(name) FROM sqlite_master WHERE type='table' AND name='spool'";
     let exists_rt = conn.query_row(check_query, [], |row| row.get(0));
@@ -13,17 +116,19 @@ pub fn create_new_spool_tbl(conn: &Connection) -> Result<(), &'static str> {
     match exists {
         0 => {
             //println!("No spool table found, need to create one");
-            let create_query = "CREATE TABLE spool (
-                roll_id BLOB PRIMARY KEY,
-                roll_name TEXT,
-                roll_weight REAL,
-                roll_length REAL,
-                roll_timestamp INTEGER NOT NULL)";
-            conn.execute(create_query, ()).unwrap();
+            conn.execute(SPOOL_TABLE, ()).unwrap();
             //println!("Created Spool Table");
         }
         1 => {
             //println!("Spool table found")
+            add_missing_column(conn, "spool", "roll_material", "TEXT")?;
+            add_missing_column(conn, "spool", "roll_price", "REAL")?;
+            add_missing_column(conn, "spool", "roll_tare", "INTEGER")?;
+            add_missing_column(conn, "spool", "roll_colour", "TEXT")?;
+            add_missing_column(conn, "spool", "roll_diameter", "REAL")?;
+            add_missing_column(conn, "spool", "roll_opened", "INTEGER")?;
+            add_missing_column(conn, "spool", "roll_colour_hex", "TEXT")?;
+            add_missing_column(conn, "spool", "roll_notes", "TEXT")?;
         }
         _ => {
             eprintln!("Issue with finding table");
@@ -47,17 +152,16 @@ pub fn create_new_filament_tbl(conn: &Connection) -> Result<(), &'static str> {
     match exists {
         0 => {
             //println!("No filament table found, need to create one");
-            let create_query = "CREATE TABLE filament(
-                print_id BLOB PRIMARY KEY,
-                print_weight REAL,
-                print_length REAL,
-                print_time INTEGER,
-                roll_id BLOB NOT NULL)";
-            conn.execute(create_query, ()).unwrap();
+            conn.execute(FILAMENT_TABLE, ()).unwrap();
             //println!("Created filament Table");
         }
         1 => {
             //println!("Filament table found")
+            add_missing_column(conn, "filament", "print_timestamp", "INTEGER")?;
+            add_missing_column(conn, "filament", "print_name", "TEXT")?;
+            add_missing_column(conn, "filament", "print_status", "TEXT")?;
+            add_missing_column(conn, "filament", "print_notes", "TEXT")?;
+            add_missing_column(conn, "filament", "print_project", "TEXT")?;
         }
         _ => {
             println!("Issue with finding table");
@@ -66,3 +170,301 @@ pub fn create_new_filament_tbl(conn: &Connection) -> Result<(), &'static str> {
     }
     Ok(())
 }
+
+//Rebuilds the tables holding weights and lengths with integer columns, turning the
+//grams and metres older versions stored into milligrams and millimetres
+fn convert_to_fixed_point(conn: &Connection) -> Result<(), &'static str> {
+    let tx = match conn.unchecked_transaction() {
+        Ok(tx) => tx,
+        Err(e) => {
+            eprintln!("Err: {}", e);
+            return Err("Err starting transaction");
+        }
+    };
+    rebuild_table(
+        &tx,
+        "spool",
+        SPOOL_TABLE,
+        &["roll_weight", "roll_length", "roll_tare"],
+    )?;
+    rebuild_table(
+        &tx,
+        "filament",
+        FILAMENT_TABLE,
+        &["print_weight", "print_length"],
+    )?;
+    rebuild_table(
+        &tx,
+        "product",
+        PRODUCT_TABLE,
+        &["product_weight", "product_tare"],
+    )?;
+    if let Err(e) = tx.commit() {
+        eprintln!("Err: {}", e);
+        return Err("Err converting weights and lengths");
+    }
+    Ok(())
+}
+
+//Creates the table again and copies the rows across, multiplying the scaled columns by 1000
+fn rebuild_table(
+    conn: &Connection,
+    table: &str,
+    create_query: &str,
+    scaled: &[&str],
+) -> Result<(), &'static str> {
+    let columns_query = format!("SELECT name FROM pragma_table_info('{}')", table);
+    let columns: Vec<String> = match conn
+        .prepare(&columns_query)
+        .and_then(|mut stmt| stmt.query_map([], |row| row.get(0))?.collect())
+    {
+        Ok(columns) => columns,
+        Err(e) => {
+            eprintln!("Err: {}", e);
+            return Err("Err with query");
+        }
+    };
+    let values: Vec<String> = columns
+        .iter()
+        .map(|column| {
+            if scaled.contains(&column.as_str()) {
+                format!("CAST(round({} * 1000) AS INTEGER)", column)
+            } else {
+                column.clone()
+            }
+        })
+        .collect();
+    let rebuild_query = format!(
+        "ALTER TABLE {table} RENAME TO {table}_old;
+        {create_query};
+        INSERT INTO {table} ({}) SELECT {} FROM {table}_old;
+        DROP TABLE {table}_old;",
+        columns.join(", "),
+        values.join(", ")
+    );
+    if let Err(e) = conn.execute_batch(&rebuild_query) {
+        eprintln!("Err: {}", e);
+        return Err("Err converting weights and lengths");
+    }
+    Ok(())
+}
+
+//Gives the weigh-ins older versions added their own status, they were named Weigh-in
+//and took no time
+fn mark_weigh_ins(conn: &Connection) -> Result<(), &'static str> {
+    let mark_query = "UPDATE filament SET print_status = 'weigh-in'
+        WHERE print_name = 'Weigh-in' AND print_time = 0
+            AND (print_status IS NULL OR print_status = 'success')";
+    if let Err(e) = conn.execute(mark_query, ()) {
+        eprintln!("Err: {}", e);
+        return Err("Err marking weigh-ins");
+    }
+    Ok(())
+}
+
+//Adds a column to a table created by an older version of the program
+pub fn add_missing_column(
+    conn: &Connection,
+    table: &str,
+    column: &str,
+    definition: &str,
+) -> Result<(), &'static str> {
+    let check_query = format!(
+        "SELECT count(name) FROM pragma_table_info('{}') WHERE name = ?1",
+        table
+    );
+    let exists: i32 = match conn.query_row(&check_query, [column], |row| row.get(0)) {
+        Ok(exists) => exists,
+        Err(e) => {
+            eprintln!("Err: {}", e);
+            return Err("Err with query");
+        }
+    };
+    if exists == 0 {
+        let alter_query = format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition);
+        if let Err(e) = conn.execute(&alter_query, ()) {
+            eprintln!("Err: {}", e);
+            return Err("Err adding column");
+        }
+    }
+    Ok(())
+}
+
+//Printers and the spool loaded in each of them
+pub fn create_new_printer_tbl(conn: &Connection) -> Result<(), &'static str> {
+    create_table_if_missing(
+        conn,
+        "printer",
+        "CREATE TABLE printer(
+            printer_name TEXT PRIMARY KEY,
+            roll_id BLOB)",
+    )
+}
+
+//Spools loaded in the trays of a printer's AMS, numbered the way Bambu printers number them
+pub fn create_new_tray_tbl(conn: &Connection) -> Result<(), &'static str> {
+    create_table_if_missing(
+        conn,
+        "ams_tray",
+        "CREATE TABLE ams_tray(
+            printer_name TEXT,
+            tray_id INTEGER,
+            roll_id BLOB,
+            PRIMARY KEY (printer_name, tray_id))",
+    )
+}
+
+//Where each print history source got up to on its last sync
+pub fn create_new_sync_tbl(conn: &Connection) -> Result<(), &'static str> {
+    create_table_if_missing(
+        conn,
+        "sync_state",
+        "CREATE TABLE sync_state(
+            sync_source TEXT PRIMARY KEY,
+            last_job_id TEXT,
+            last_time REAL)",
+    )
+}
+
+//Filament products that new spools can be made from
+pub fn create_new_product_tbl(conn: &Connection) -> Result<(), &'static str> {
+    create_table_if_missing(conn, "product", PRODUCT_TABLE)
+}
+
+//Shelves, dryboxes and printers spools are kept in
+pub fn create_new_location_tbl(conn: &Connection) -> Result<(), &'static str> {
+    create_table_if_missing(
+        conn,
+        "location",
+        "CREATE TABLE location(
+            location_name TEXT PRIMARY KEY COLLATE NOCASE,
+            location_kind TEXT NOT NULL)",
+    )
+}
+
+//Every time a spool changed places, a NULL location is a spool taken out of a printer
+pub fn create_new_move_tbl(conn: &Connection) -> Result<(), &'static str> {
+    create_table_if_missing(
+        conn,
+        "spool_move",
+        "CREATE TABLE spool_move(
+            roll_id BLOB NOT NULL,
+            location_name TEXT,
+            move_timestamp INTEGER NOT NULL)",
+    )
+}
+
+//Each time a spool was dried, timestamped when it came out of the dryer
+pub fn create_new_drying_tbl(conn: &Connection) -> Result<(), &'static str> {
+    create_table_if_missing(
+        conn,
+        "spool_drying",
+        "CREATE TABLE spool_drying(
+            roll_id BLOB NOT NULL,
+            drying_timestamp INTEGER NOT NULL,
+            drying_temperature REAL NOT NULL,
+            drying_minutes INTEGER NOT NULL)",
+    )
+}
+
+//Days each material can be open before it needs drying, over the built in ones
+pub fn create_new_threshold_tbl(conn: &Connection) -> Result<(), &'static str> {
+    create_table_if_missing(
+        conn,
+        "drying_threshold",
+        "CREATE TABLE drying_threshold(
+            material TEXT PRIMARY KEY COLLATE NOCASE,
+            threshold_days REAL NOT NULL)",
+    )
+}
+
+//Free form tags such as customer:acme on spools and prints
+pub fn create_new_tag_tbls(conn: &Connection) -> Result<(), &'static str> {
+    create_table_if_missing(
+        conn,
+        "spool_tag",
+        "CREATE TABLE spool_tag(
+            roll_id BLOB NOT NULL,
+            tag TEXT NOT NULL,
+            PRIMARY KEY (roll_id, tag))",
+    )?;
+    create_table_if_missing(
+        conn,
+        "print_tag",
+        "CREATE TABLE print_tag(
+            print_id BLOB NOT NULL,
+            tag TEXT NOT NULL,
+            PRIMARY KEY (print_id, tag))",
+    )
+}
+
+//Projects and customers prints are charged to
+pub fn create_new_project_tbl(conn: &Connection) -> Result<(), &'static str> {
+    create_table_if_missing(
+        conn,
+        "project",
+        "CREATE TABLE project(
+            project_name TEXT PRIMARY KEY COLLATE NOCASE,
+            project_customer TEXT)",
+    )
+}
+
+//Every change to spools, prints and what is loaded in printers, see audit.
+//Rows can only be added, never changed or removed
+pub fn create_new_audit_tbl(conn: &Connection) -> Result<(), &'static str> {
+    create_table_if_missing(
+        conn,
+        "audit_log",
+        "CREATE TABLE audit_log(
+            audit_id INTEGER PRIMARY KEY AUTOINCREMENT,
+            audit_timestamp INTEGER NOT NULL,
+            audit_user TEXT NOT NULL,
+            audit_table TEXT NOT NULL,
+            audit_action TEXT NOT NULL,
+            roll_id BLOB,
+            audit_before TEXT,
+            audit_after TEXT)",
+    )?;
+    let append_only = "CREATE TRIGGER IF NOT EXISTS audit_log_no_update BEFORE UPDATE ON audit_log
+        BEGIN SELECT RAISE(ABORT, 'the audit log can only be added to'); END;
+        CREATE TRIGGER IF NOT EXISTS audit_log_no_delete BEFORE DELETE ON audit_log
+        BEGIN SELECT RAISE(ABORT, 'the audit log can only be added to'); END;";
+    if let Err(e) = conn.execute_batch(append_only) {
+        eprintln!("Err: {}", e);
+        return Err("Err creating table");
+    }
+    Ok(())
+}
+
+//The certificate each Bambu printer showed the first time it was watched, by serial
+pub fn create_new_certificate_tbl(conn: &Connection) -> Result<(), &'static str> {
+    create_table_if_missing(
+        conn,
+        "printer_certificate",
+        "CREATE TABLE printer_certificate(
+            printer_serial TEXT PRIMARY KEY,
+            certificate BLOB NOT NULL)",
+    )
+}
+
+fn create_table_if_missing(
+    conn: &Connection,
+    table: &str,
+    create_query: &str,
+) -> Result<(), &'static str> {
+    let check_query = "SELECT count(name) FROM sqlite_master WHERE type='table' AND name=?1";
+    let exists: i32 = match conn.query_row(check_query, [table], |row| row.get(0)) {
+        Ok(exists) => exists,
+        Err(e) => {
+            eprintln!("Err: {}", e);
+            return Err("Err with query");
+        }
+    };
+    if exists == 0 {
+        if let Err(e) = conn.execute(create_query, ()) {
+            eprintln!("Err: {}", e);
+            return Err("Err creating table");
+        }
+    }
+    Ok(())
+}
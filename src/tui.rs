@@ -0,0 +1,512 @@
+use crate::chart;
+use crate::drying;
+use crate::print_add;
+use crate::print_stats::{self, SpoolStatus};
+use crate::print_structs::*;
+use crate::units::{self, Units};
+use crate::{format_timestamp, get_timestamp};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{
+    Block, Cell, Clear, Gauge, List, ListItem, Paragraph, Row, Table, TableState,
+};
+use ratatui::{DefaultTerminal, Frame};
+use rusqlite::Connection;
+use uuid::Uuid;
+
+//Prints shown under the spools
+const RECENT_PRINTS: usize = 10;
+const GAUGE_WIDTH: usize = 20;
+//Spools with less than this share left are drawn in red
+const LOW_FRACTION: f32 = 0.15;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Action {
+    NewSpool,
+    AddPrint,
+    Load,
+    Weigh,
+}
+
+#[derive(Debug)]
+struct Form {
+    action: Action,
+    fields: Vec<(&'static str, String)>,
+    focus: usize,
+}
+
+impl Form {
+    fn new(action: Action) -> Form {
+        let labels: &[&'static str] = match action {
+            Action::NewSpool => &["Name", "Material", "Weight", "Empty spool"],
+            Action::AddPrint => &["Weight", "Time (min)", "Name"],
+            Action::Load => &["Printer"],
+            Action::Weigh => &["Scale reading"],
+        };
+        Form {
+            action,
+            fields: labels.iter().map(|label| (*label, String::new())).collect(),
+            focus: 0,
+        }
+    }
+
+    fn value(&self, label: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|field| field.0 == label)
+            .map(|field| field.1.trim())
+            .filter(|value| !value.is_empty())
+    }
+
+    //Checked with the same parsers as the command line
+    fn positive(&self, label: &str) -> Result<Option<f32>, String> {
+        self.value(label).map(units::parse_positive).transpose()
+    }
+
+    //A weight such as 250 or 3.2oz, plain numbers are grams like on the command line
+    fn weight(&self, label: &str) -> Result<Option<Weight>, String> {
+        self.value(label).map(units::parse_weight).transpose()
+    }
+
+    fn positive_weight(&self, label: &str) -> Result<Option<Weight>, String> {
+        self.value(label)
+            .map(units::parse_positive_weight)
+            .transpose()
+    }
+
+    fn title(&self) -> &'static str {
+        match self.action {
+            Action::NewSpool => "New spool",
+            Action::AddPrint => "Add print",
+            Action::Load => "Load spool",
+            Action::Weigh => "Weigh spool",
+        }
+    }
+}
+
+//Everything the dashboard shows, read again after each change
+pub struct App {
+    spools: Vec<SpoolStatus>,
+    loaded: Vec<(String, Uuid)>,
+    prints: Vec<Filament>,
+    stats: (Weight, Length, i32),
+    table: TableState,
+    form: Option<Form>,
+    message: String,
+    units: Units,
+    quit: bool,
+}
+
+impl App {
+    pub fn new(conn: &Connection, units: Units) -> Result<App, String> {
+        let mut app = App {
+            spools: Vec::new(),
+            loaded: Vec::new(),
+            prints: Vec::new(),
+            stats: (Weight::ZERO, Length::ZERO, 0),
+            table: TableState::default(),
+            form: None,
+            message: String::new(),
+            units,
+            quit: false,
+        };
+        app.refresh(conn)?;
+        //Start on the newest spool, which is the one prints go to by default
+        if !app.spools.is_empty() {
+            app.table.select(Some(app.spools.len() - 1));
+        }
+        Ok(app)
+    }
+
+    fn refresh(&mut self, conn: &Connection) -> Result<(), String> {
+        self.spools = print_stats::spool_inventory(conn).map_err(|e| e.to_string())?;
+        self.loaded = print_stats::loaded_printers(conn).map_err(|e| e.to_string())?;
+        let mut prints = print_stats::list_prints(conn).map_err(|e| e.to_string())?;
+        prints.reverse();
+        prints.truncate(RECENT_PRINTS);
+        self.prints = prints;
+        self.stats = print_stats::lifetime_statistics(conn);
+        if self
+            .table
+            .selected()
+            .is_some_and(|selected| selected >= self.spools.len())
+        {
+            self.table.select(self.spools.len().checked_sub(1));
+        }
+        Ok(())
+    }
+
+    fn selected(&self) -> Option<&SpoolStatus> {
+        self.table
+            .selected()
+            .and_then(|selected| self.spools.get(selected))
+    }
+
+    pub fn handle_key(&mut self, conn: &Connection, key: KeyEvent) {
+        if self.form.is_some() {
+            self.handle_form_key(conn, key);
+            return;
+        }
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => self.quit = true,
+            KeyCode::Down | KeyCode::Char('j') => self.table.select_next(),
+            KeyCode::Up | KeyCode::Char('k') => self.table.select_previous(),
+            KeyCode::Char('n') => self.form = Some(Form::new(Action::NewSpool)),
+            KeyCode::Char('a') => self.open_spool_form(Action::AddPrint),
+            KeyCode::Char('l') => self.open_spool_form(Action::Load),
+            KeyCode::Char('w') => self.open_spool_form(Action::Weigh),
+            KeyCode::Char('r') => {
+                if let Err(e) = self.refresh(conn) {
+                    self.message = e;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    //Forms which act on the selected spool
+    fn open_spool_form(&mut self, action: Action) {
+        if self.selected().is_some() {
+            self.form = Some(Form::new(action));
+        } else {
+            self.message = String::from("Create a spool first with n");
+        }
+    }
+
+    fn handle_form_key(&mut self, conn: &Connection, key: KeyEvent) {
+        let form = self.form.as_mut().unwrap();
+        match key.code {
+            KeyCode::Esc => self.form = None,
+            KeyCode::Tab | KeyCode::Down => form.focus = (form.focus + 1) % form.fields.len(),
+            KeyCode::BackTab | KeyCode::Up => {
+                form.focus = (form.focus + form.fields.len() - 1) % form.fields.len()
+            }
+            KeyCode::Backspace => {
+                form.fields[form.focus].1.pop();
+            }
+            KeyCode::Char(c) => form.fields[form.focus].1.push(c),
+            KeyCode::Enter if form.focus + 1 < form.fields.len() => form.focus += 1,
+            KeyCode::Enter => {
+                let form = self.form.take().unwrap();
+                self.message = match self.submit(conn, &form) {
+                    Ok(message) => message,
+                    Err(e) => {
+                        //Leave the form open so the mistake can be fixed
+                        self.form = Some(form);
+                        e
+                    }
+                };
+                if let Err(e) = self.refresh(conn) {
+                    self.message = e;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn submit(&mut self, conn: &Connection, form: &Form) -> Result<String, String> {
+        match form.action {
+            Action::NewSpool => {
+                let name = form.value("Name").ok_or("A name is needed")?;
+                let weight = form
+                    .positive_weight("Weight")?
+                    .ok_or("A weight is needed")?;
+                let mut spool = Spool {
+                    roll_id: Some(Uuid::new_v4()),
+                    roll_name: Some(name.to_string()),
+                    roll_weight: Some(weight),
+                    timestamp: Some(get_timestamp()),
+                    roll_material: form.value("Material").map(str::to_string),
+                    roll_tare: form.weight("Empty spool")?,
+                    ..Default::default()
+                };
+                spool.validate()?;
+                print_add::open_new_spool(conn, &mut spool).map_err(|e| e.to_string())?;
+                //Move to the new spool, it is listed last
+                self.table.select(Some(self.spools.len()));
+                Ok(format!("Created {}", name))
+            }
+            Action::AddPrint => {
+                let spool = self.selected().unwrap();
+                let weight = form
+                    .positive_weight("Weight")?
+                    .ok_or("A weight is needed")?;
+                let minutes = form
+                    .positive("Time (min)")?
+                    .ok_or("A print time is needed")?;
+                let mut print = Filament {
+                    print_id: Some(Uuid::new_v4()),
+                    print_weight: Some(weight),
+                    print_time: Some((minutes * 60.0).round() as i32),
+                    roll_id: Some(spool.roll_id),
+                    print_name: form.value("Name").map(str::to_string),
+                    print_status: Some(String::from(PRINT_SUCCESS)),
+                    ..Default::default()
+                };
+                print.validate()?;
+                let message = format!(
+                    "Added {} print to {}",
+                    self.units.weight(weight),
+                    spool.roll_name
+                );
+                let roll_id = spool.roll_id;
+                print_add::add_new_print(conn, &mut print).map_err(|e| e.to_string())?;
+                with_exposure_warning(conn, message, roll_id)
+            }
+            Action::Load => {
+                let spool = self.selected().unwrap();
+                let printer = form.value("Printer").ok_or("A printer is needed")?;
+                print_add::load_spool(conn, printer, spool.roll_id).map_err(|e| e.to_string())?;
+                let message = format!("Loaded {} into {}", spool.roll_name, printer);
+                with_exposure_warning(conn, message, spool.roll_id)
+            }
+            Action::Weigh => {
+                let spool = self.selected().unwrap();
+                let reading = form
+                    .weight("Scale reading")?
+                    .ok_or("A scale reading is needed")?;
+                let weigh_in = print_add::weigh_spool(conn, spool.roll_id, reading)
+                    .map_err(|e| e.to_string())?;
+                Ok(format!(
+                    "Adjusted {} by {}",
+                    spool.roll_name,
+                    self.units.weight(-weigh_in.print_weight.unwrap())
+                ))
+            }
+        }
+    }
+
+    pub fn render(&mut self, frame: &mut Frame) {
+        let [spools_area, gauge_area, bottom_area, status_area] = Layout::vertical([
+            Constraint::Min(5),
+            Constraint::Length(3),
+            Constraint::Length(RECENT_PRINTS as u16 + 2),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+        let [prints_area, stats_area] =
+            Layout::horizontal([Constraint::Percentage(65), Constraint::Percentage(35)])
+                .areas(bottom_area);
+
+        self.render_spools(frame, spools_area);
+        self.render_gauge(frame, gauge_area);
+        self.render_prints(frame, prints_area);
+        self.render_stats(frame, stats_area);
+
+        let status = if self.message.is_empty() {
+            "j/k move  n new spool  a add print  l load  w weigh  r refresh  q quit"
+        } else {
+            self.message.as_str()
+        };
+        frame.render_widget(Paragraph::new(status), status_area);
+
+        if let Some(form) = &self.form {
+            render_form(frame, form);
+        }
+    }
+
+    fn render_spools(&mut self, frame: &mut Frame, area: Rect) {
+        let rows = self.spools.iter().map(|spool| {
+            let fraction = if spool.roll_weight > Weight::ZERO {
+                spool.remaining_weight().grams() / spool.roll_weight.grams()
+            } else {
+                0.0
+            };
+            let colour = if fraction < LOW_FRACTION {
+                Color::Red
+            } else {
+                Color::Green
+            };
+            let printers: Vec<&str> = self
+                .loaded
+                .iter()
+                .filter(|(_, roll_id)| *roll_id == spool.roll_id)
+                .map(|(printer, _)| printer.as_str())
+                .collect();
+            let place = if printers.is_empty() {
+                spool.location.clone().unwrap_or_default()
+            } else {
+                printers.join(", ")
+            };
+            Row::new([
+                Cell::from(spool.roll_name.clone()),
+                Cell::from(spool.roll_material.clone().unwrap_or_default()),
+                Cell::from(format!(
+                    "{:>10}",
+                    self.units.weight(spool.remaining_weight())
+                )),
+                Cell::from(chart::gauge(fraction, GAUGE_WIDTH, false))
+                    .style(Style::default().fg(colour)),
+                Cell::from(place),
+            ])
+        });
+        let table = Table::new(
+            rows,
+            [
+                Constraint::Fill(2),
+                Constraint::Length(8),
+                Constraint::Length(12),
+                Constraint::Length(GAUGE_WIDTH as u16),
+                Constraint::Fill(1),
+            ],
+        )
+        .header(
+            Row::new(["Spool", "Material", "Left", "", "Where"])
+                .style(Style::default().add_modifier(Modifier::BOLD)),
+        )
+        .row_highlight_style(Style::default().add_modifier(Modifier::REVERSED))
+        .block(Block::bordered().title(" Spools "));
+        frame.render_stateful_widget(table, area, &mut self.table);
+    }
+
+    fn render_gauge(&self, frame: &mut Frame, area: Rect) {
+        let (title, ratio, label) = match self.selected() {
+            Some(spool) => {
+                let ratio = if spool.roll_weight > Weight::ZERO {
+                    (spool.remaining_weight().grams() / spool.roll_weight.grams()).clamp(0.0, 1.0)
+                } else {
+                    0.0
+                };
+                (
+                    format!(" {} ", spool.roll_name),
+                    ratio as f64,
+                    format!(
+                        "{} of {} left",
+                        self.units.weight(spool.remaining_weight()),
+                        self.units.weight(spool.roll_weight)
+                    ),
+                )
+            }
+            None => (String::from(" No spools "), 0.0, String::new()),
+        };
+        let gauge = Gauge::default()
+            .block(Block::bordered().title(title))
+            .gauge_style(Style::default().fg(Color::Green))
+            .ratio(ratio)
+            .label(label);
+        frame.render_widget(gauge, area);
+    }
+
+    fn render_prints(&self, frame: &mut Frame, area: Rect) {
+        let items: Vec<ListItem> = self
+            .prints
+            .iter()
+            .map(|print| {
+                let spool = self
+                    .spools
+                    .iter()
+                    .find(|spool| Some(spool.roll_id) == print.roll_id)
+                    .map(|spool| spool.roll_name.as_str())
+                    .unwrap_or_default();
+                let status = print.print_status.as_deref().unwrap_or(PRINT_SUCCESS);
+                let status_style = match status {
+                    PRINT_SUCCESS | PRINT_WEIGH_IN => Style::default(),
+                    _ => Style::default().fg(Color::Red),
+                };
+                ListItem::new(Line::from(vec![
+                    Span::raw(format!(
+                        "{}  {:<20} {:>10}  {:<16} ",
+                        print.timestamp.map(format_timestamp).unwrap_or_default(),
+                        print.print_name.as_deref().unwrap_or("-"),
+                        self.units.weight(print.print_weight.unwrap_or_default()),
+                        spool
+                    )),
+                    Span::styled(status.to_string(), status_style),
+                ]))
+            })
+            .collect();
+        frame.render_widget(
+            List::new(items).block(Block::bordered().title(" Recent prints ")),
+            area,
+        );
+    }
+
+    fn render_stats(&self, frame: &mut Frame, area: Rect) {
+        let (weight, length, time) = self.stats;
+        let lines = vec![
+            Line::from(format!("Filament used  {}", self.units.weight(weight))),
+            Line::from(format!("Length used    {}", self.units.length(length))),
+            Line::from(format!("Printing time  {} min", time / 60)),
+            Line::from(format!("Spools         {}", self.spools.len())),
+        ];
+        frame.render_widget(
+            Paragraph::new(lines).block(Block::bordered().title(" Lifetime ")),
+            area,
+        );
+    }
+}
+
+//The status line only has room for one line, so the warning goes on the end of it
+fn with_exposure_warning(
+    conn: &Connection,
+    message: String,
+    roll_id: Uuid,
+) -> Result<String, String> {
+    match drying::exposure_warning(conn, roll_id, get_timestamp()).map_err(|e| e.to_string())? {
+        Some(warning) => Ok(format!("{}. Warning: {}", message, warning)),
+        None => Ok(message),
+    }
+}
+
+fn render_form(frame: &mut Frame, form: &Form) {
+    let area = frame.area();
+    let height = form.fields.len() as u16 + 4;
+    let width = 50.min(area.width);
+    let popup = Rect {
+        x: area.x + (area.width - width) / 2,
+        y: area.y + area.height.saturating_sub(height) / 2,
+        width,
+        height: height.min(area.height),
+    };
+    let mut lines: Vec<Line> = form
+        .fields
+        .iter()
+        .enumerate()
+        .map(|(index, (label, value))| {
+            let style = if index == form.focus {
+                Style::default().add_modifier(Modifier::REVERSED)
+            } else {
+                Style::default()
+            };
+            Line::from(vec![
+                Span::raw(format!("{:<18}", label)),
+                Span::styled(format!("{:<24}", value), style),
+            ])
+        })
+        .collect();
+    lines.push(Line::from(""));
+    lines.push(Line::from("Enter next/save  Esc cancel"));
+    frame.render_widget(Clear, popup);
+    frame.render_widget(
+        Paragraph::new(lines).block(Block::bordered().title(format!(" {} ", form.title()))),
+        popup,
+    );
+}
+
+pub fn run(conn: &Connection, units: Units) -> Result<(), String> {
+    let mut app = App::new(conn, units)?;
+    let mut terminal = ratatui::init();
+    let result = event_loop(conn, &mut app, &mut terminal);
+    ratatui::restore();
+    result
+}
+
+fn event_loop(
+    conn: &Connection,
+    app: &mut App,
+    terminal: &mut DefaultTerminal,
+) -> Result<(), String> {
+    while !app.quit {
+        terminal
+            .draw(|frame| app.render(frame))
+            .map_err(|e| e.to_string())?;
+        if let Event::Key(key) = event::read().map_err(|e| e.to_string())? {
+            if key.kind == KeyEventKind::Press {
+                app.handle_key(conn, key);
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,226 @@
+use crate::print_structs::{Length, Weight};
+use clap::ValueEnum;
+use std::str::FromStr;
+
+const GRAMS_PER_OUNCE: f64 = 28.349523125;
+const GRAMS_PER_POUND: f64 = 453.59237;
+const METRES_PER_FOOT: f64 = 0.3048;
+const METRES_PER_INCH: f64 = 0.0254;
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+pub enum UnitSystem {
+    /// Grams and metres
+    #[default]
+    Metric,
+    /// Ounces and feet
+    Imperial,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum WeightUnit {
+    G,
+    Kg,
+    Oz,
+    Lb,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum LengthUnit {
+    M,
+    Mm,
+    Ft,
+    In,
+}
+
+impl WeightUnit {
+    fn grams(self) -> f64 {
+        match self {
+            WeightUnit::G => 1.0,
+            WeightUnit::Kg => 1000.0,
+            WeightUnit::Oz => GRAMS_PER_OUNCE,
+            WeightUnit::Lb => GRAMS_PER_POUND,
+        }
+    }
+
+    fn symbol(self) -> &'static str {
+        match self {
+            WeightUnit::G => "g",
+            WeightUnit::Kg => "kg",
+            WeightUnit::Oz => "oz",
+            WeightUnit::Lb => "lb",
+        }
+    }
+
+    //Decimals that still show a change of about a tenth of a gram
+    fn decimals(self) -> usize {
+        match self {
+            WeightUnit::G => 1,
+            WeightUnit::Oz => 2,
+            WeightUnit::Kg | WeightUnit::Lb => 3,
+        }
+    }
+
+    fn from_symbol(symbol: &str) -> Option<WeightUnit> {
+        match symbol {
+            "" | "g" | "gram" | "grams" => Some(WeightUnit::G),
+            "kg" | "kilo" | "kilos" | "kilogram" | "kilograms" => Some(WeightUnit::Kg),
+            "oz" | "ounce" | "ounces" => Some(WeightUnit::Oz),
+            "lb" | "lbs" | "pound" | "pounds" => Some(WeightUnit::Lb),
+            _ => None,
+        }
+    }
+}
+
+impl LengthUnit {
+    fn metres(self) -> f64 {
+        match self {
+            LengthUnit::M => 1.0,
+            LengthUnit::Mm => 0.001,
+            LengthUnit::Ft => METRES_PER_FOOT,
+            LengthUnit::In => METRES_PER_INCH,
+        }
+    }
+
+    fn symbol(self) -> &'static str {
+        match self {
+            LengthUnit::M => "m",
+            LengthUnit::Mm => "mm",
+            LengthUnit::Ft => "ft",
+            LengthUnit::In => "in",
+        }
+    }
+
+    fn decimals(self) -> usize {
+        match self {
+            LengthUnit::M => 2,
+            LengthUnit::Mm => 0,
+            LengthUnit::Ft | LengthUnit::In => 1,
+        }
+    }
+
+    fn from_symbol(symbol: &str) -> Option<LengthUnit> {
+        match symbol {
+            "" | "m" | "metre" | "metres" | "meter" | "meters" => Some(LengthUnit::M),
+            "mm" | "millimetre" | "millimetres" | "millimeter" | "millimeters" => {
+                Some(LengthUnit::Mm)
+            }
+            "ft" | "foot" | "feet" => Some(LengthUnit::Ft),
+            "in" | "inch" | "inches" => Some(LengthUnit::In),
+            _ => None,
+        }
+    }
+}
+
+//The units weights and lengths are shown in, the system's unless one is picked
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Units {
+    pub weight: WeightUnit,
+    pub length: LengthUnit,
+}
+
+impl Units {
+    pub fn new(
+        system: UnitSystem,
+        weight: Option<WeightUnit>,
+        length: Option<LengthUnit>,
+    ) -> Units {
+        let (system_weight, system_length) = match system {
+            UnitSystem::Metric => (WeightUnit::G, LengthUnit::M),
+            UnitSystem::Imperial => (WeightUnit::Oz, LengthUnit::Ft),
+        };
+        Units {
+            weight: weight.unwrap_or(system_weight),
+            length: length.unwrap_or(system_length),
+        }
+    }
+
+    pub fn weight(&self, weight: Weight) -> String {
+        format!(
+            "{:.*} {}",
+            self.weight.decimals(),
+            f64::from(weight) / self.weight.grams(),
+            self.weight.symbol()
+        )
+    }
+
+    pub fn length(&self, length: Length) -> String {
+        format!(
+            "{:.*} {}",
+            self.length.decimals(),
+            f64::from(length) / self.length.metres(),
+            self.length.symbol()
+        )
+    }
+}
+
+impl Default for Units {
+    fn default() -> Units {
+        Units::new(UnitSystem::Metric, None, None)
+    }
+}
+
+//Far more than any spool, but small enough that milligrams and millimetres fit an i64
+pub const MAX_QUANTITY: f64 = 1e9;
+
+//Splits 3.2oz or 3.2 oz into the number and the lower case unit after it
+fn split_unit(value: &str) -> Result<(f64, String), String> {
+    let value = value.trim();
+    let number = value.trim_end_matches(char::is_alphabetic);
+    let unit = value[number.len()..].to_lowercase();
+    match number.trim().parse::<f64>() {
+        Ok(number) if number > MAX_QUANTITY => Err(format!("{} is too large", value)),
+        Ok(number) if number.is_finite() && number >= 0.0 => Ok((number, unit)),
+        Ok(number) if number.is_finite() => Err(format!("{} cannot be negative", value)),
+        _ => Err(format!("{} is not a number", value)),
+    }
+}
+
+//A weight such as 250, 3.2oz or 1kg, plain numbers are grams
+pub fn parse_weight(value: &str) -> Result<Weight, String> {
+    let (number, unit) = split_unit(value)?;
+    let unit = WeightUnit::from_symbol(&unit)
+        .ok_or_else(|| format!("{} is not a unit of weight, use g, kg, oz or lb", unit))?;
+    Ok(Weight::from(number * unit.grams()))
+}
+
+//A length such as 330, 100ft or 1500mm, plain numbers are metres
+pub fn parse_length(value: &str) -> Result<Length, String> {
+    let (number, unit) = split_unit(value)?;
+    let unit = LengthUnit::from_symbol(&unit)
+        .ok_or_else(|| format!("{} is not a unit of length, use m, mm, ft or in", unit))?;
+    Ok(Length::from(number * unit.metres()))
+}
+
+//The weight of a spool or a print, which has to be more than nothing
+pub fn parse_positive_weight(value: &str) -> Result<Weight, String> {
+    let weight = parse_weight(value)?;
+    if weight <= Weight::ZERO {
+        return Err(format!("{} is not more than 0", value));
+    }
+    Ok(weight)
+}
+
+pub fn parse_positive_length(value: &str) -> Result<Length, String> {
+    let length = parse_length(value)?;
+    if length <= Length::ZERO {
+        return Err(format!("{} is not more than 0", value));
+    }
+    Ok(length)
+}
+
+//A number that has to be more than 0, such as a price or a print time
+pub fn parse_positive<T>(value: &str) -> Result<T, String>
+where
+    T: FromStr + Copy + Into<f64>,
+{
+    match value.trim().parse::<T>() {
+        Ok(number) if number.into().is_finite() && number.into() > 0.0 => Ok(number),
+        Ok(_) => Err(format!("{} is not more than 0", value)),
+        Err(_) => Err(format!("{} is not a number", value)),
+    }
+}
+
+//Filament diameter in millimetres
+pub fn parse_diameter(value: &str) -> Result<f32, String> {
+    parse_positive::<f32>(value).map_err(|_| format!("{} is not a diameter in millimetres", value))
+}
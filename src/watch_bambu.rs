@@ -0,0 +1,471 @@
+use crate::print_add;
+use crate::print_structs::*;
+use rumqttc::{Client, Event, MqttOptions, Packet, QoS, TlsConfiguration, Transport};
+use rusqlite::{Connection, OptionalExtension};
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::{DigitallySignedStruct, SignatureScheme};
+use serde::Deserialize;
+use std::collections::{BTreeSet, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use uuid::Uuid;
+
+//Tray number Bambu printers give the spool holder on the back of the printer
+pub const EXTERNAL_TRAY: u32 = 254;
+//Trays in each AMS unit
+const TRAYS_PER_AMS: u32 = 4;
+//print_error reported when the job was stopped from the screen or the app
+const CANCELLED_ERROR: i64 = 0x0300400C;
+//Full status reports are well over the default packet limit
+const MAX_PACKET_SIZE: usize = 1024 * 1024;
+
+pub struct BambuOptions {
+    pub host: String,
+    pub port: u16,
+    pub serial: String,
+    pub access_code: String,
+    //Skip TLS, for brokers such as a local Mosquitto bridge
+    pub plain: bool,
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct Report {
+    print: Option<PrintReport>,
+}
+
+//Reports after the first only carry the fields which changed
+#[derive(Deserialize, Debug, Default)]
+struct PrintReport {
+    gcode_state: Option<String>,
+    subtask_name: Option<String>,
+    print_error: Option<i64>,
+    ams: Option<AmsReport>,
+    vt_tray: Option<TrayReport>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct AmsReport {
+    #[serde(default)]
+    ams: Vec<AmsUnit>,
+    tray_now: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct AmsUnit {
+    id: String,
+    #[serde(default)]
+    tray: Vec<TrayReport>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct TrayReport {
+    id: String,
+    //Percent left, -1 when the spool has no tag to read it from
+    remain: Option<i32>,
+    //Grams when full
+    tray_weight: Option<String>,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct TrayLevel {
+    remain: Option<i32>,
+    full_weight: Option<f32>,
+}
+
+#[derive(Debug)]
+struct ActiveJob {
+    name: String,
+    started: i64,
+    start_levels: HashMap<u32, TrayLevel>,
+    trays: BTreeSet<u32>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum BambuEvent {
+    Started(String),
+    //Name, status and the prints recorded for it, one per tray used
+    Finished(String, &'static str, usize),
+}
+
+//Follows one printer's reports, spotting when jobs start and end and which trays they used
+#[derive(Debug, Default)]
+pub struct BambuTracker {
+    state: Option<String>,
+    name: Option<String>,
+    print_error: i64,
+    tray_now: Option<u32>,
+    levels: HashMap<u32, TrayLevel>,
+    job: Option<ActiveJob>,
+}
+
+//Names trays the way the printer's screen does, A1 to D4 and Ext
+pub fn tray_name(tray: u32) -> String {
+    if tray == EXTERNAL_TRAY {
+        return String::from("Ext");
+    }
+    let unit = (b'A' + (tray / TRAYS_PER_AMS) as u8) as char;
+    format!("{}{}", unit, tray % TRAYS_PER_AMS + 1)
+}
+
+pub fn parse_tray(name: &str) -> Result<u32, String> {
+    let upper = name.trim().to_uppercase();
+    if upper == "EXT" {
+        return Ok(EXTERNAL_TRAY);
+    }
+    let mut chars = upper.chars();
+    if let (Some(unit @ 'A'..='D'), Some(slot @ '1'..='4'), None) =
+        (chars.next(), chars.next(), chars.next())
+    {
+        return Ok((unit as u32 - 'A' as u32) * TRAYS_PER_AMS + (slot as u32 - '1' as u32));
+    }
+    Err(format!("{} is not a tray, use A1 to D4 or Ext", name))
+}
+
+fn is_printing(state: &str) -> bool {
+    matches!(state, "PREPARE" | "RUNNING" | "PAUSE")
+}
+
+impl BambuTracker {
+    //Takes in one report, recording the prints when a job ends
+    pub fn handle_report(
+        &mut self,
+        conn: &Connection,
+        printer: &str,
+        serial: &str,
+        payload: &[u8],
+    ) -> Result<Option<BambuEvent>, String> {
+        let report: Report = match serde_json::from_slice(payload) {
+            Ok(report) => report,
+            //Other messages share the topic, only print reports matter
+            Err(_) => return Ok(None),
+        };
+        let print = match report.print {
+            Some(print) => print,
+            None => return Ok(None),
+        };
+        self.update(print);
+
+        let state = self.state.clone().unwrap_or_default();
+        match &self.job {
+            None if is_printing(&state) => {
+                let name = self
+                    .name
+                    .clone()
+                    .unwrap_or_else(|| String::from("Bambu print"));
+                let mut trays = BTreeSet::new();
+                trays.extend(self.tray_now);
+                self.job = Some(ActiveJob {
+                    name: name.clone(),
+                    started: crate::get_timestamp(),
+                    start_levels: self.levels.clone(),
+                    trays,
+                });
+                Ok(Some(BambuEvent::Started(name)))
+            }
+            Some(_) if !is_printing(&state) => {
+                let status = match state.as_str() {
+                    "FINISH" => PRINT_SUCCESS,
+                    "FAILED" if self.print_error != CANCELLED_ERROR => PRINT_FAILED,
+                    _ => PRINT_CANCELLED,
+                };
+                let job = self.job.take().unwrap();
+                let recorded = self.record_job(conn, printer, serial, &job, status)?;
+                Ok(Some(BambuEvent::Finished(job.name, status, recorded)))
+            }
+            Some(_) => {
+                let tray_now = self.tray_now;
+                self.job.as_mut().unwrap().trays.extend(tray_now);
+                Ok(None)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn update(&mut self, print: PrintReport) {
+        if let Some(state) = print.gcode_state {
+            self.state = Some(state);
+        }
+        if let Some(name) = print.subtask_name {
+            self.name = Some(name);
+        }
+        if let Some(print_error) = print.print_error {
+            self.print_error = print_error;
+        }
+        let mut trays: Vec<(u32, TrayReport)> = Vec::new();
+        if let Some(ams) = print.ams {
+            if let Some(tray_now) = ams.tray_now {
+                //255 is reported when nothing is loaded
+                self.tray_now = tray_now.parse().ok().filter(|tray| *tray != 255);
+            }
+            for unit in ams.ams {
+                let unit_id: u32 = unit.id.parse().unwrap_or_default();
+                for tray in unit.tray {
+                    let tray_id: u32 = tray.id.parse().unwrap_or_default();
+                    trays.push((unit_id * TRAYS_PER_AMS + tray_id, tray));
+                }
+            }
+        }
+        if let Some(tray) = print.vt_tray {
+            trays.push((EXTERNAL_TRAY, tray));
+        }
+        for (id, tray) in trays {
+            let level = self.levels.entry(id).or_default();
+            if let Some(remain) = tray.remain {
+                level.remain = Some(remain).filter(|remain| *remain >= 0);
+            }
+            if let Some(weight) = tray.tray_weight.and_then(|weight| weight.parse().ok()) {
+                level.full_weight = Some(weight);
+            }
+        }
+    }
+
+    //Filament used from each tray is the drop in its remaining percent, trays which
+    //cannot report that are recorded with no filament used
+    fn record_job(
+        &self,
+        conn: &Connection,
+        printer: &str,
+        serial: &str,
+        job: &ActiveJob,
+        status: &str,
+    ) -> Result<usize, String> {
+        let mut trays = job.trays.clone();
+        for (id, level) in &self.levels {
+            let before = job.start_levels.get(id).and_then(|level| level.remain);
+            if let (Some(before), Some(after)) = (before, level.remain) {
+                if after < before {
+                    trays.insert(*id);
+                }
+            }
+        }
+        let finished = crate::get_timestamp();
+        let tx = conn.unchecked_transaction().map_err(|e| e.to_string())?;
+        let mut recorded = 0;
+        //A job with no AMS information still gets recorded against the printer's spool
+        let trays: Vec<Option<u32>> = if trays.is_empty() {
+            vec![None]
+        } else {
+            trays.into_iter().map(Some).collect()
+        };
+        for tray in trays {
+            let roll_id = match tray {
+                Some(tray) => print_add::spool_for_tray(&tx, printer, tray),
+                None => print_add::spool_for_printer(&tx, printer),
+            }
+            .map_err(|_| format!("No spool to record {} against", job.name))?
+            .roll_id;
+            let weight = tray.and_then(|tray| {
+                let before = job.start_levels.get(&tray)?.remain?;
+                let level = self.levels.get(&tray)?;
+                Some((before - level.remain?).max(0) as f32 / 100.0 * level.full_weight?)
+            });
+            if weight.is_none() {
+                eprintln!(
+                    "{} does not report how much filament is left, recorded {} with none used",
+                    tray.map_or(String::from("The printer"), tray_name),
+                    job.name
+                );
+            }
+            let mut print = Filament {
+                print_id: Some(Uuid::new_v5(
+                    &Uuid::NAMESPACE_URL,
+                    format!("bambu:{}/{}@{}/{:?}", serial, job.name, job.started, tray).as_bytes(),
+                )),
+                print_weight: Some(Weight::from_grams(weight.unwrap_or_default())),
+                print_time: Some((finished - job.started) as i32),
+                roll_id: Some(roll_id),
+                timestamp: Some(finished),
+                print_name: Some(job.name.clone()),
+                print_status: Some(String::from(status)),
+                ..Default::default()
+            };
+            print_add::insert_print(&tx, &mut print).map_err(|e| e.to_string())?;
+            recorded += 1;
+        }
+        tx.commit().map_err(|e| e.to_string())?;
+        Ok(recorded)
+    }
+}
+
+//The printers use a certificate signed by Bambu's own authority, which is not
+//published, so the certificate is pinned instead: the one a printer shows the first
+//time it is watched is kept by serial and any other is refused after that.
+//Nothing authenticates the printer on that first connection
+#[derive(Debug)]
+pub struct PrinterCertificate {
+    provider: Arc<rustls::crypto::CryptoProvider>,
+    pinned: Option<Vec<u8>>,
+    //The certificate shown when there was none pinned, to be pinned once connected
+    seen: Mutex<Option<Vec<u8>>>,
+}
+
+impl PrinterCertificate {
+    pub fn new(pinned: Option<Vec<u8>>) -> PrinterCertificate {
+        PrinterCertificate {
+            provider: Arc::new(rustls::crypto::ring::default_provider()),
+            pinned,
+            seen: Mutex::new(None),
+        }
+    }
+
+    pub fn check(&self, certificate: &[u8]) -> Result<(), String> {
+        match &self.pinned {
+            Some(pinned) if pinned != certificate => Err(String::from(
+                "The printer's certificate is not the one it had when first watched",
+            )),
+            Some(_) => Ok(()),
+            None => {
+                *self.seen.lock().unwrap_or_else(|e| e.into_inner()) = Some(certificate.to_vec());
+                Ok(())
+            }
+        }
+    }
+
+    //A certificate seen on this connection that still needs pinning
+    pub fn unpinned(&self) -> Option<Vec<u8>> {
+        self.seen.lock().unwrap_or_else(|e| e.into_inner()).take()
+    }
+}
+
+pub fn pinned_certificate(conn: &Connection, serial: &str) -> rusqlite::Result<Option<Vec<u8>>> {
+    conn.query_row(
+        "SELECT certificate FROM printer_certificate WHERE printer_serial = ?1",
+        [serial],
+        |row| row.get(0),
+    )
+    .optional()
+}
+
+pub fn pin_certificate(
+    conn: &Connection,
+    serial: &str,
+    certificate: &[u8],
+) -> rusqlite::Result<usize> {
+    conn.execute(
+        "INSERT OR REPLACE INTO printer_certificate (printer_serial, certificate) VALUES (?1, ?2)",
+        (serial, certificate),
+    )
+}
+
+//Forgets a printer's certificate, for when it was replaced by a firmware update
+pub fn unpin_certificate(conn: &Connection, serial: &str) -> rusqlite::Result<usize> {
+    conn.execute(
+        "DELETE FROM printer_certificate WHERE printer_serial = ?1",
+        [serial],
+    )
+}
+
+impl ServerCertVerifier for PrinterCertificate {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        self.check(end_entity.as_ref())
+            .map_err(rustls::Error::General)?;
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+fn mqtt_options(options: &BambuOptions, certificate: &Arc<PrinterCertificate>) -> MqttOptions {
+    let client_id = format!("remainder-{}", &Uuid::new_v4().simple().to_string()[..8]);
+    let mut mqtt = MqttOptions::new(client_id, options.host.clone(), options.port);
+    mqtt.set_credentials("bblp", options.access_code.clone())
+        .set_keep_alive(Duration::from_secs(30))
+        .set_max_packet_size(MAX_PACKET_SIZE, MAX_PACKET_SIZE);
+    if !options.plain {
+        let config = rustls::ClientConfig::builder()
+            .dangerous()
+            .with_custom_certificate_verifier(certificate.clone())
+            .with_no_client_auth();
+        mqtt.set_transport(Transport::tls_with_config(TlsConfiguration::Rustls(
+            Arc::new(config),
+        )));
+    }
+    mqtt
+}
+
+//Listens to the printer until the connection drops, asking for a full report first
+//so a job already running is picked up
+pub fn watch_bambu(
+    conn: &Connection,
+    printer: &str,
+    options: &BambuOptions,
+    tracker: &mut BambuTracker,
+) -> Result<(), String> {
+    let pinned = pinned_certificate(conn, &options.serial).map_err(|e| e.to_string())?;
+    let certificate = Arc::new(PrinterCertificate::new(pinned));
+    let (client, mut connection) = Client::new(mqtt_options(options, &certificate), 10);
+    client
+        .subscribe(format!("device/{}/report", options.serial), QoS::AtMostOnce)
+        .map_err(|e| e.to_string())?;
+    client
+        .publish(
+            format!("device/{}/request", options.serial),
+            QoS::AtMostOnce,
+            false,
+            r#"{"pushing": {"sequence_id": "0", "command": "pushall"}}"#,
+        )
+        .map_err(|e| e.to_string())?;
+
+    for notification in connection.iter() {
+        let publish = match notification.map_err(|e| e.to_string())? {
+            Event::Incoming(Packet::Publish(publish)) => publish,
+            //The printer took the access code, so its certificate is the one to keep
+            Event::Incoming(Packet::ConnAck(_)) => {
+                if let Some(seen) = certificate.unpinned() {
+                    pin_certificate(conn, &options.serial, &seen).map_err(|e| e.to_string())?;
+                }
+                continue;
+            }
+            _ => continue,
+        };
+        match tracker.handle_report(conn, printer, &options.serial, &publish.payload)? {
+            Some(BambuEvent::Started(name)) => println!("Started {}", name),
+            Some(BambuEvent::Finished(name, status, recorded)) => println!(
+                "Finished {} ({}), recorded {} prints",
+                name, status, recorded
+            ),
+            None => {}
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,171 @@
+"use strict";
+
+const TOKEN_KEY = "remainder-token";
+const LOW_FRACTION = 0.15;
+
+function showMessage(text, isError) {
+  const message = document.getElementById("message");
+  message.textContent = text;
+  message.className = isError ? "error" : "";
+  message.hidden = false;
+}
+
+async function api(path, options = {}) {
+  const headers = { "Content-Type": "application/json" };
+  const token = localStorage.getItem(TOKEN_KEY);
+  if (token) {
+    headers.Authorization = "Bearer " + token;
+  }
+  const response = await fetch("/api" + path, { ...options, headers });
+  const body = await response.json();
+  if (response.status === 401) {
+    document.getElementById("login").hidden = false;
+  }
+  if (!response.ok) {
+    throw new Error(body.error || response.statusText);
+  }
+  return body;
+}
+
+function placeName(place) {
+  return place.tray ? place.printer + " " + place.tray : place.printer;
+}
+
+function renderSpools(spools) {
+  const list = document.getElementById("spool-list");
+  const select = document.querySelector("#print-form select[name=spool]");
+  const template = document.getElementById("spool-template");
+  list.replaceChildren();
+  select.replaceChildren();
+
+  //Newest first, the same order people reach for them
+  for (const spool of [...spools].reverse()) {
+    const item = template.content.cloneNode(true);
+    const fraction = spool.roll_weight > 0 ? spool.remaining_weight / spool.roll_weight : 0;
+    item.querySelector(".name").textContent = spool.roll_name;
+    item.querySelector(".material").textContent = spool.roll_material || "";
+    const fill = item.querySelector(".fill");
+    fill.style.width = Math.max(0, Math.min(1, fraction)) * 100 + "%";
+    fill.classList.toggle("low", fraction < LOW_FRACTION);
+    item.querySelector(".left").textContent =
+      spool.remaining_weight.toFixed(1) + " g of " + spool.roll_weight.toFixed(0) + " g left";
+    if (spool.loaded.length) {
+      item.querySelector(".loaded").textContent = "in " + spool.loaded.map(placeName).join(", ");
+    } else if (spool.location) {
+      item.querySelector(".loaded").textContent = "in " + spool.location;
+    }
+    item.querySelector(".load").addEventListener("click", () => loadSpool(spool));
+    const unload = item.querySelector(".unload");
+    unload.hidden = spool.loaded.length === 0;
+    unload.addEventListener("click", () => unloadSpool(spool));
+    list.appendChild(item);
+
+    const option = document.createElement("option");
+    option.value = spool.roll_id;
+    option.textContent = spool.roll_name;
+    select.appendChild(option);
+  }
+  if (spools.length === 0) {
+    const empty = document.createElement("li");
+    empty.textContent = "No spools yet, create one with remainder create-spool";
+    list.appendChild(empty);
+  }
+}
+
+function renderStats(stats) {
+  const rows = [
+    ["Filament used", stats.total_weight.toFixed(1) + " g"],
+    ["Length used", stats.total_length.toFixed(1) + " m"],
+    ["Printing time", Math.round(stats.total_time / 60) + " min"],
+  ];
+  const list = document.getElementById("stat-list");
+  list.replaceChildren();
+  for (const [name, value] of rows) {
+    const term = document.createElement("dt");
+    term.textContent = name;
+    const detail = document.createElement("dd");
+    detail.textContent = value;
+    list.append(term, detail);
+  }
+}
+
+async function refresh() {
+  try {
+    const [spools, stats] = await Promise.all([api("/inventory"), api("/stats")]);
+    renderSpools(spools);
+    renderStats(stats);
+  } catch (error) {
+    showMessage(error.message, true);
+  }
+}
+
+async function loadSpool(spool) {
+  const printer = prompt("Load " + spool.roll_name + " into which printer?");
+  if (!printer) {
+    return;
+  }
+  try {
+    await api("/printers/" + encodeURIComponent(printer) + "/load", {
+      method: "POST",
+      body: JSON.stringify({ spool: spool.roll_id }),
+    });
+    showMessage("Loaded " + spool.roll_name + " into " + printer);
+    refresh();
+  } catch (error) {
+    showMessage(error.message, true);
+  }
+}
+
+async function unloadSpool(spool) {
+  let place = spool.loaded[0];
+  if (spool.loaded.length > 1) {
+    const names = spool.loaded.map(placeName);
+    const chosen = prompt("Unload from which of " + names.join(", ") + "?", names[0]);
+    place = spool.loaded[names.indexOf(chosen)];
+    if (!place) {
+      return;
+    }
+  }
+  try {
+    await api("/printers/" + encodeURIComponent(place.printer) + "/unload", {
+      method: "POST",
+      body: JSON.stringify({ tray: place.tray }),
+    });
+    showMessage("Unloaded " + spool.roll_name + " from " + placeName(place));
+    refresh();
+  } catch (error) {
+    showMessage(error.message, true);
+  }
+}
+
+document.getElementById("print-form").addEventListener("submit", async (event) => {
+  event.preventDefault();
+  const form = new FormData(event.target);
+  const number = (name) => (form.get(name) === "" ? null : Number(form.get(name)));
+  const print = {
+    roll_id: form.get("spool"),
+    print_name: form.get("print_name") || null,
+    print_weight: number("print_weight"),
+    print_length: number("print_length"),
+    print_time: Math.round(number("print_time") * 60),
+    print_status: form.get("print_status"),
+  };
+  try {
+    await api("/prints", { method: "POST", body: JSON.stringify(print) });
+    showMessage("Logged " + (print.print_name || "print"));
+    event.target.reset();
+    refresh();
+  } catch (error) {
+    showMessage(error.message, true);
+  }
+});
+
+document.getElementById("login").addEventListener("submit", (event) => {
+  event.preventDefault();
+  localStorage.setItem(TOKEN_KEY, new FormData(event.target).get("token"));
+  event.target.hidden = true;
+  document.getElementById("message").hidden = true;
+  refresh();
+});
+
+refresh();